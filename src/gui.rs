@@ -8,16 +8,24 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 #![allow(rustdoc::missing_crate_level_docs)]
 
-use crate::egui::{Color32, Pos2, Rect, Shape, Stroke, StrokeKind};
+use crate::egui::{Color32, Pos2, Rect, Shape, Stroke};
 use eframe::egui;
+use serde::Deserialize;
 use serde_json::Value;
 
-#[derive(Debug, Eq, Hash, PartialEq)]
+mod kicad_lib;
+mod spice;
+mod units;
+
+use units::{format_unit_value, parse_unit_value};
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 enum ComponentType {
     Capacitor,
     Diode,
     DiodeSchottky,
     DiodeZener,
+    Ground,
     Inductor,
     Led,
     OpAmp,
@@ -29,8 +37,8 @@ enum ComponentType {
     ResistorUS,
     Potentiometer,
     PotentiometerUS,
+    Power,
     VoltmeterDC,
-    Wire,
 }
 
 fn string_to_componenttype(n: &str) -> Option<ComponentType> {
@@ -39,6 +47,7 @@ fn string_to_componenttype(n: &str) -> Option<ComponentType> {
         "D" => Some(ComponentType::Diode),
         "D_Schottky" => Some(ComponentType::DiodeSchottky),
         "D_Zener" => Some(ComponentType::DiodeZener),
+        "GND" => Some(ComponentType::Ground),
         "L" => Some(ComponentType::Inductor),
         "LED" => Some(ComponentType::Led),
         "Opamp_Dual" => Some(ComponentType::OpAmp),
@@ -50,15 +59,37 @@ fn string_to_componenttype(n: &str) -> Option<ComponentType> {
         "R_Potentiometer" => Some(ComponentType::Potentiometer),
         "R_US" => Some(ComponentType::ResistorUS),
         "R_Potentiometer_US" => Some(ComponentType::PotentiometerUS),
+        "VCC" => Some(ComponentType::Power),
         "Voltmeter_DC" => Some(ComponentType::VoltmeterDC),
         &_ => None,
     }
 }
 
+/// Reference designator prefix shown on parts without their own label
+fn reference_prefix(c: ComponentType) -> &'static str {
+    match c {
+        ComponentType::Capacitor => "C",
+        ComponentType::Diode
+        | ComponentType::DiodeSchottky
+        | ComponentType::DiodeZener
+        | ComponentType::Led => "D",
+        ComponentType::Inductor => "L",
+        ComponentType::OpAmp => "U",
+        ComponentType::TransistorNPN
+        | ComponentType::TransistorNPNDarlington
+        | ComponentType::TransistorPNP
+        | ComponentType::TransistorPNPDarlington => "Q",
+        ComponentType::Resistor | ComponentType::ResistorUS => "R",
+        ComponentType::Potentiometer | ComponentType::PotentiometerUS => "RV",
+        ComponentType::VoltmeterDC => "MES",
+        _ => "",
+    }
+}
+
 /// Maps component type into draw instructions
 type ComponentDrawLibrary = std::collections::HashMap<ComponentType, Value>;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 struct GraphicalComponent {
     component_type: ComponentType,
     position: Pos2,
@@ -66,6 +97,14 @@ struct GraphicalComponent {
     angle: f32,
     flip_x: bool,
     flip_y: bool,
+    // net name for ground and power symbols (eg. "GND", "AGND", "VCC")
+    label: String,
+    // locked parts can't be dragged or deleted
+    locked: bool,
+    // disabled parts stay on the schematic but are left out of the circuit
+    enabled: bool,
+    // resistance, capacitance etc. in base units, None for parts without one
+    value: Option<f64>,
 }
 
 impl GraphicalComponent {
@@ -82,15 +121,291 @@ impl GraphicalComponent {
             angle,
             flip_x,
             flip_y,
+            label: String::new(),
+            locked: false,
+            enabled: true,
+            value: value_unit(component_type).map(|(v, _)| v),
+        }
+    }
+
+    /// Ground symbol connecting its pin to the named ground net
+    fn new_ground(name: &str, position: Pos2) -> Self {
+        Self {
+            label: name.into(),
+            ..Self::new(ComponentType::Ground, position, 0.0, false, false)
+        }
+    }
+
+    /// Power symbol, a supply rail between the named net and ground
+    fn new_power(name: &str, position: Pos2) -> Self {
+        Self {
+            label: name.into(),
+            ..Self::new(ComponentType::Power, position, 0.0, false, false)
+        }
+    }
+
+    /// Text shown at the symbol origin, the label or else the reference prefix
+    fn display_label(&self) -> &str {
+        if self.label.is_empty() {
+            reference_prefix(self.component_type)
+        } else {
+            &self.label
+        }
+    }
+
+    /// Transform from library coordinates to schematic coordinates
+    ///
+    /// Rotation and flips happen about the center of the drawing, so
+    /// the part turns in place rather than around the library origin.
+    fn transform(&self, draw_lib: &ComponentDrawLibrary) -> Transform {
+        let c = draw_center(&draw_lib[&self.component_type]);
+        Transform::new(1.0, 0.0, -c.x, -c.y, false, false).chain(&Transform::new(
+            1.0,
+            std::f32::consts::FRAC_PI_2 * self.angle,
+            self.position.x + c.x,
+            self.position.y + c.y,
+            self.flip_x,
+            self.flip_y,
+        ))
+    }
+}
+
+/// Wire segment, connects whatever pins sit at its two ends
+#[derive(Clone, Debug, PartialEq)]
+struct Wire {
+    start: Pos2,
+    end: Pos2,
+    // shielded cable length in meters, ends stay separate nets
+    cable: Option<f64>,
+    // current sense element, ends stay separate nets
+    sense: bool,
+}
+
+impl Wire {
+    fn new(start: Pos2, end: Pos2) -> Self {
+        Self {
+            start,
+            end,
+            cable: None,
+            sense: false,
+        }
+    }
+
+    /// Shielded cable connection, simulated with capacitance to ground
+    fn new_cable(start: Pos2, end: Pos2, length: f64) -> Self {
+        Self {
+            start,
+            end,
+            cable: Some(length),
+            sense: false,
+        }
+    }
+}
+
+/// Edge that selected parts line up on
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Align {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+/// Placed parts and wires, independent of the GUI
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Schematic {
+    parts: Vec<GraphicalComponent>,
+    wires: Vec<Wire>,
+}
+
+impl Schematic {
+    /// Place a part, returns its index
+    fn add_part(&mut self, part: GraphicalComponent) -> usize {
+        self.parts.push(part);
+        self.parts.len() - 1
+    }
+
+    /// First rail name from `POWER_NET` up that no power symbol uses yet
+    fn unused_rail_name(&self) -> String {
+        (1..)
+            .map(|n| match n {
+                1 => POWER_NET.to_string(),
+                n => format!("{}{}", POWER_NET, n),
+            })
+            .find(|name| {
+                !self
+                    .parts
+                    .iter()
+                    .any(|p| p.component_type == ComponentType::Power && p.label == *name)
+            })
+            .unwrap_or_default()
+    }
+
+    /// Turn a part by 90 degrees, returns false if it is locked
+    fn rotate_part(&mut self, index: usize) -> bool {
+        match self.parts.get_mut(index) {
+            Some(part) if !part.locked => {
+                part.angle = (part.angle + 1.0) % 4.0;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Mirror a part left to right (or top to bottom when not
+    /// `horizontal`), returns false if it is locked
+    fn flip_part(&mut self, index: usize, horizontal: bool) -> bool {
+        match self.parts.get_mut(index) {
+            Some(part) if !part.locked => {
+                if horizontal {
+                    part.flip_x = !part.flip_x;
+                } else {
+                    part.flip_y = !part.flip_y;
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Move a part by `delta`, returns false if it is locked
+    fn move_part(&mut self, index: usize, delta: egui::Vec2) -> bool {
+        match self.parts.get_mut(index) {
+            Some(part) if !part.locked => {
+                part.position += delta;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Remove a part, unless it is locked
+    fn remove_part(&mut self, index: usize) -> Option<GraphicalComponent> {
+        if self.parts.get(index)?.locked {
+            return None;
+        }
+        Some(self.parts.remove(index))
+    }
+
+    /// Line up part positions on the outermost one, locked parts stay put
+    fn align(&mut self, indices: &[usize], align: Align) {
+        let positions = indices
+            .iter()
+            .filter_map(|i| self.parts.get(*i))
+            .map(|p| p.position);
+        let target = match align {
+            Align::Left => positions.map(|p| p.x).fold(f32::INFINITY, f32::min),
+            Align::Right => positions.map(|p| p.x).fold(f32::NEG_INFINITY, f32::max),
+            Align::Top => positions.map(|p| p.y).fold(f32::INFINITY, f32::min),
+            Align::Bottom => positions.map(|p| p.y).fold(f32::NEG_INFINITY, f32::max),
+        };
+        for i in indices {
+            let Some(p) = self.parts.get(*i).map(|p| p.position) else {
+                continue;
+            };
+            let delta = match align {
+                Align::Left | Align::Right => egui::vec2(target - p.x, 0.0),
+                Align::Top | Align::Bottom => egui::vec2(0.0, target - p.y),
+            };
+            self.move_part(*i, delta);
+        }
+    }
+
+    /// Space parts evenly between the two outermost ones
+    fn distribute(&mut self, indices: &[usize], horizontal: bool) {
+        let coord = |p: Pos2| if horizontal { p.x } else { p.y };
+        let mut order: Vec<usize> = indices
+            .iter()
+            .copied()
+            .filter(|i| *i < self.parts.len())
+            .collect();
+        if order.len() < 3 {
+            return;
+        }
+        order.sort_by(|a, b| {
+            coord(self.parts[*a].position).total_cmp(&coord(self.parts[*b].position))
+        });
+        let first = coord(self.parts[order[0]].position);
+        let step =
+            (coord(self.parts[order[order.len() - 1]].position) - first) / (order.len() - 1) as f32;
+        for (n, i) in order.iter().enumerate() {
+            let d = first + step * n as f32 - coord(self.parts[*i].position);
+            let delta = if horizontal {
+                egui::vec2(d, 0.0)
+            } else {
+                egui::vec2(0.0, d)
+            };
+            self.move_part(*i, delta);
         }
     }
+
+    /// Connect two points with a plain wire
+    fn add_wire(&mut self, start: Pos2, end: Pos2) {
+        self.wires.push(Wire::new(start, end));
+    }
+
+    /// Connect two points with a shielded cable of given length in meters
+    fn add_cable(&mut self, start: Pos2, end: Pos2, length: f64) {
+        self.wires.push(Wire::new_cable(start, end, length));
+    }
+
+    /// Turn current sensing on or off for a wire, measuring from start to end
+    fn toggle_sense(&mut self, index: usize) {
+        if let Some(wire) = self.wires.get_mut(index) {
+            wire.sense = !wire.sense;
+        }
+    }
+}
+
+/// Most edits that can be undone
+const UNDO_DEPTH: usize = 100;
+
+/// Snapshots of the schematic for undo and redo
+#[derive(Debug, Default)]
+struct History {
+    undo: Vec<Schematic>,
+    redo: Vec<Schematic>,
+}
+
+impl History {
+    /// Remember the schematic from before an edit, forgets what could be redone
+    fn push(&mut self, before: Schematic) {
+        if self.undo.len() == UNDO_DEPTH {
+            self.undo.remove(0);
+        }
+        self.undo.push(before);
+        self.redo.clear();
+    }
+
+    /// Go back to before the last edit, returns false if there is none
+    fn undo(&mut self, schematic: &mut Schematic) -> bool {
+        let Some(before) = self.undo.pop() else {
+            return false;
+        };
+        self.redo.push(std::mem::replace(schematic, before));
+        true
+    }
+
+    /// Apply the last undone edit again, returns false if there is none
+    fn redo(&mut self, schematic: &mut Schematic) -> bool {
+        let Some(after) = self.redo.pop() else {
+            return false;
+        };
+        self.undo.push(std::mem::replace(schematic, after));
+        true
+    }
 }
 
+/// Name of the ground net that becomes the simulator's node 0
+const GROUND_NET: &str = "GND";
+
+/// Net name given to newly placed power symbols, then "VCC2", "VCC3"...
+const POWER_NET: &str = "VCC";
+
 fn main() -> Result<(), eframe::Error> {
     env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
 
-    let pos2 = Pos2::new(1.0, 0.0);
-    let t = Transform::new(0.3, 0.5 * 3.14159265, 10.0, 5.0, false, false);
+    let t = Transform::new(0.3, std::f32::consts::FRAC_PI_2, 10.0, 5.0, false, false);
     println!("t = {:?}", t);
 
     let options = eframe::NativeOptions {
@@ -109,32 +424,794 @@ fn main() -> Result<(), eframe::Error> {
     )
 }
 
+/// Colors for drawing the schematic
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Theme {
+    background: Color32,
+    component: Color32,
+    pad: Color32,
+    selected: Color32,
+    disabled: Color32,
+    grid: Color32,
+    // net under the pointer
+    highlight: Color32,
+}
+
+impl Theme {
+    /// Light on dark for the screen
+    const DARK: Theme = Theme {
+        background: Color32::from_gray(27),
+        component: Color32::WHITE,
+        pad: Color32::YELLOW,
+        selected: Color32::RED,
+        disabled: Color32::DARK_GRAY,
+        grid: Color32::from_gray(60),
+        highlight: Color32::LIGHT_BLUE,
+    };
+
+    /// Black on white for printing and documentation
+    const PRINT: Theme = Theme {
+        background: Color32::WHITE,
+        component: Color32::BLACK,
+        pad: Color32::BLACK,
+        selected: Color32::RED,
+        disabled: Color32::GRAY,
+        grid: Color32::from_gray(220),
+        highlight: Color32::BLUE,
+    };
+}
+
+/// Size of pads in library units
+const PAD_SIZE: f32 = 10.0;
+
+/// Camera for the schematic
+#[derive(Clone, Debug, PartialEq)]
+struct View {
+    scale: f32,
+    offset: egui::Vec2,
+}
+
+impl Default for View {
+    fn default() -> Self {
+        Self {
+            scale: 0.6,
+            offset: egui::Vec2::ZERO,
+        }
+    }
+}
+
+impl View {
+    /// Transform from schematic coordinates to screen coordinates
+    fn transform(&self) -> Transform {
+        Transform::new(self.scale, 0.0, self.offset.x, self.offset.y, false, false)
+    }
+
+    /// Screen position back to schematic coordinates
+    fn to_schematic(&self, p: Pos2) -> Pos2 {
+        ((p - self.offset).to_vec2() / self.scale).to_pos2()
+    }
+
+    /// Scale by `factor`, keeping the schematic point under `screen` in place
+    fn zoom_at(&mut self, screen: Pos2, factor: f32) {
+        let anchor = self.to_schematic(screen);
+        self.scale = (self.scale * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        self.offset = screen.to_vec2() - anchor.to_vec2() * self.scale;
+    }
+}
+
+/// Range of view scales, schematic units to screen points
+const MIN_ZOOM: f32 = 0.05;
+const MAX_ZOOM: f32 = 20.0;
+
+/// Zoom factor per point of mouse wheel scrolling
+const ZOOM_PER_SCROLL: f32 = 1.002;
+
+/// Grid spacing parts and wires snap to, in schematic units
+const DEFAULT_GRID: f32 = 50.0;
+
+/// Grid dots closer than this on screen are not drawn, in pixels
+const MIN_GRID_SPACING: f32 = 8.0;
+
+/// Nearest grid point, or `p` itself when the grid is off (0)
+fn snap_to_grid(p: Pos2, grid: f32) -> Pos2 {
+    if grid <= 0.0 {
+        return p;
+    }
+    Pos2::new((p.x / grid).round() * grid, (p.y / grid).round() * grid)
+}
+
+/// Screen positions of the grid points inside `rect`, none when too dense
+fn grid_dots(view: &View, rect: Rect, grid: f32) -> Vec<Pos2> {
+    if grid * view.scale < MIN_GRID_SPACING {
+        return vec![];
+    }
+    let (min, max) = (view.to_schematic(rect.min), view.to_schematic(rect.max));
+    let (x0, y0) = ((min.x / grid).ceil() as i32, (min.y / grid).ceil() as i32);
+    let (x1, y1) = ((max.x / grid).floor() as i32, (max.y / grid).floor() as i32);
+    let transform = view.transform();
+    (y0..=y1)
+        .flat_map(|y| (x0..=x1).map(move |x| Pos2::new(x as f32 * grid, y as f32 * grid)))
+        .map(|p| transform.apply(&p))
+        .collect()
+}
+
+/// Parts offered in the palette, in order
+const PALETTE: [ComponentType; 18] = [
+    ComponentType::Resistor,
+    ComponentType::ResistorUS,
+    ComponentType::Potentiometer,
+    ComponentType::PotentiometerUS,
+    ComponentType::Capacitor,
+    ComponentType::Inductor,
+    ComponentType::Diode,
+    ComponentType::DiodeSchottky,
+    ComponentType::DiodeZener,
+    ComponentType::Led,
+    ComponentType::TransistorNPN,
+    ComponentType::TransistorPNP,
+    ComponentType::TransistorNPNDarlington,
+    ComponentType::TransistorPNPDarlington,
+    ComponentType::OpAmp,
+    ComponentType::VoltmeterDC,
+    ComponentType::Power,
+    ComponentType::Ground,
+];
+
+/// Part picked from the palette, placed by the next click on the schematic
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Placement {
+    component_type: ComponentType,
+    // in 90 degree chunks
+    angle: f32,
+}
+
+impl Placement {
+    fn new(component_type: ComponentType) -> Self {
+        Self {
+            component_type,
+            angle: 0.0,
+        }
+    }
+
+    /// Turn by 90 degrees before placing
+    fn rotate(&mut self) {
+        self.angle = (self.angle + 1.0) % 4.0;
+    }
+
+    /// The part placed at screen position `p`, snapped to `grid`
+    ///
+    /// Power symbols start out on a rail of their own in `schematic`.
+    fn part_at(
+        &self,
+        view: &View,
+        p: Pos2,
+        grid: f32,
+        schematic: &Schematic,
+    ) -> GraphicalComponent {
+        let position = snap_to_grid(view.to_schematic(p), grid);
+        let part = match self.component_type {
+            ComponentType::Ground => GraphicalComponent::new_ground(GROUND_NET, position),
+            ComponentType::Power => {
+                GraphicalComponent::new_power(&schematic.unused_rail_name(), position)
+            }
+            t => GraphicalComponent::new(t, position, 0.0, false, false),
+        };
+        GraphicalComponent {
+            angle: self.angle,
+            ..part
+        }
+    }
+}
+
+/// Measurement line in schematic coordinates
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Ruler {
+    start: Pos2,
+    end: Pos2,
+}
+
+/// Length of end ticks on the ruler, in pixels
+const RULER_TICK: f32 = 6.0;
+
+impl Ruler {
+    /// Ruler between two screen positions
+    fn from_screen(view: &View, start: Pos2, end: Pos2) -> Self {
+        Self {
+            start: view.to_schematic(start),
+            end: view.to_schematic(end),
+        }
+    }
+
+    /// Length in schematic units
+    fn length(&self) -> f32 {
+        self.start.distance(self.end)
+    }
+
+    /// Line with ticks across both ends, in screen coordinates
+    fn shapes(&self, transform: &Transform, color: Color32) -> Vec<Shape> {
+        let (a, b) = (transform.apply(&self.start), transform.apply(&self.end));
+        let stroke = Stroke::new(1.0, color);
+        let mut res = vec![Shape::line_segment([a, b], stroke)];
+        if a != b {
+            let across = (b - a).normalized().rot90() * RULER_TICK;
+            for p in [a, b] {
+                res.push(Shape::line_segment([p - across, p + across], stroke));
+            }
+        }
+        res
+    }
+}
+
+/// Size of each of the magnitude and phase plots of the Bode window
+const BODE_SIZE: egui::Vec2 = egui::vec2(480.0, 160.0);
+
+/// Log axis ticks between two frequencies, true for decades
+fn log_ticks(f_min: f64, f_max: f64) -> Vec<(f64, bool)> {
+    let mut res = vec![];
+    let (lo, hi) = (f_min.log10().floor() as i32, f_max.log10().ceil() as i32);
+    for d in lo..=hi {
+        for k in 1..=9 {
+            let f = k as f64 * 10f64.powi(d);
+            // some slack for roundoff in the decade powers
+            if f >= f_min * (1.0 - 1e-9) && f <= f_max * (1.0 + 1e-9) {
+                res.push((f, k == 1));
+            }
+        }
+    }
+    res
+}
+
+/// Frequency for axis labels, eg. "100" or "10k"
+fn freq_label(f: f64) -> String {
+    if f >= 1e3 {
+        format!("{}k", f / 1e3)
+    } else {
+        format!("{}", f)
+    }
+}
+
+/// Range rounded out to whole decades, at least one decade wide
+fn decade_range(f_min: f64, f_max: f64) -> (f64, f64) {
+    let (lo, hi) = (f_min.log10().floor(), f_max.log10().ceil());
+    (10f64.powf(lo), 10f64.powf(hi.max(lo + 1.0)))
+}
+
+/// Range rounded out to multiples of `step`, at least one step wide
+fn step_range(lo: f64, hi: f64, step: f64) -> (f64, f64) {
+    let (lo, hi) = ((lo / step).floor() * step, (hi / step).ceil() * step);
+    if hi > lo {
+        (lo, hi)
+    } else {
+        (lo - step, hi + step)
+    }
+}
+
+/// Most gridlines a plot axis draws
+const MAX_GRIDLINES: usize = 100;
+
+/// Multiples of `step` from `lo` to `hi`, none for a range that isn't finite
+fn step_ticks(lo: f64, hi: f64, step: f64) -> Vec<f64> {
+    let n = ((hi - lo) / step).round();
+    if !n.is_finite() || n < 0.0 || step <= 0.0 {
+        return vec![];
+    }
+    (0..=(n as usize).min(MAX_GRIDLINES))
+        .map(|i| lo + i as f64 * step)
+        .collect()
+}
+
+/// Bode points of the first probe in an AC run's CSV output
+///
+/// The sim writes a `freq` column then `mag(n)` and `phase(n)` for each
+/// probe, or `gain(n)` in dB when the run has a drive node. Gain comes
+/// back in dB.
+fn parse_ac_csv(text: &str) -> Result<Vec<(f64, f64, f64)>, String> {
+    let mut lines = text.lines();
+    let header: Vec<&str> = lines.next().unwrap_or("").split(',').collect();
+    let in_db = header.get(1).is_some_and(|h| h.starts_with("gain("));
+    if header.len() < 3 || header[0] != "freq" || !(in_db || header[1].starts_with("mag(")) {
+        return Err(String::from(
+            "not an AC result, expected freq,mag(n),phase(n)",
+        ));
+    }
+    let mut res = vec![];
+    for (i, line) in lines.enumerate() {
+        let fields: Vec<&str> = line.split(',').collect();
+        let number = |k: usize| {
+            fields
+                .get(k)
+                .and_then(|v| v.trim().parse::<f64>().ok())
+                .ok_or_else(|| format!("line {}: expected number in column {}", i + 2, k + 1))
+        };
+        let gain = match in_db {
+            true => number(1)?,
+            false => 20.0 * number(1)?.log10(),
+        };
+        res.push((number(0)?, gain, number(2)?));
+    }
+    Ok(res)
+}
+
+/// Transient run from the sim's CSV output, a column per probed net
+#[derive(Debug, Default)]
+struct Waveforms {
+    // net name of each column
+    names: Vec<String>,
+    time: Vec<f64>,
+    // one row per step with a value per column
+    rows: Vec<Vec<f64>>,
+}
+
+impl Waveforms {
+    /// Values of a net over the run, None if it wasn't probed
+    fn trace(&self, name: &str) -> Option<Vec<f64>> {
+        let col = self.names.iter().position(|n| n == name)?;
+        Some(self.rows.iter().map(|row| row[col]).collect())
+    }
+}
+
+/// Waveforms in a transient run's CSV output
+///
+/// The sim writes a `time [s]` column then one for each probe, named
+/// after the net of the exported model with its unit, eg. `out [V]`.
+/// Traces go by the net name, so they still find their column after
+/// nets get renumbered.
+fn parse_transient_csv(text: &str) -> Result<Waveforms, String> {
+    let mut lines = text.lines();
+    let header: Vec<&str> = lines.next().unwrap_or("").split(',').collect();
+    if header[0] != "time [s]" {
+        return Err(String::from("not a transient result, expected time [s]"));
+    }
+    let names = header[1..]
+        .iter()
+        .map(|h| h.rsplit_once(" [").map_or(*h, |(name, _)| name).to_string())
+        .collect();
+    let mut waveforms = Waveforms {
+        names,
+        ..Waveforms::default()
+    };
+    for (i, line) in lines.enumerate() {
+        let fields: Vec<&str> = line.split(',').collect();
+        let number = |k: usize| {
+            fields
+                .get(k)
+                .and_then(|v| v.trim().parse::<f64>().ok())
+                .ok_or_else(|| format!("line {}: expected number in column {}", i + 2, k + 1))
+        };
+        waveforms.time.push(number(0)?);
+        let row = (1..header.len()).map(number).collect::<Result<_, _>>()?;
+        waveforms.rows.push(row);
+    }
+    Ok(waveforms)
+}
+
+/// Two circuit variants and their difference, for the first probe
+#[derive(Debug, Default)]
+struct Overlay {
+    // "time" or "freq", frequencies go on a log axis
+    x_name: String,
+    name: String,
+    x: Vec<f64>,
+    a: Vec<f64>,
+    b: Vec<f64>,
+    diff: Vec<f64>,
+}
+
+/// Overlay of the first probe in the sim's comparison CSV output
+///
+/// The sim writes the x column, then `a:`, `b:` and `diff:` columns with a
+/// name for each probe. Empty values (missing on one side) are NaN.
+fn parse_compare_csv(text: &str) -> Result<Overlay, String> {
+    let mut lines = text.lines();
+    let header: Vec<&str> = lines.next().unwrap_or("").split(',').collect();
+    let probes = header.len().saturating_sub(1) / 3;
+    let name = header.get(1).and_then(|h| h.strip_prefix("a:"));
+    let Some(name) = name.filter(|name| {
+        header.len() == 1 + 3 * probes
+            && header[1 + probes] == format!("b:{}", name)
+            && header[1 + 2 * probes] == format!("diff:{}", name)
+    }) else {
+        return Err(String::from(
+            "not a comparison, expected x,a:name,b:name,diff:name",
+        ));
+    };
+    let mut overlay = Overlay {
+        x_name: header[0].to_string(),
+        name: name.to_string(),
+        ..Overlay::default()
+    };
+    for (i, line) in lines.enumerate() {
+        let fields: Vec<&str> = line.split(',').collect();
+        let number = |k: usize| {
+            let field = fields.get(k).map(|v| v.trim());
+            match field {
+                Some("") if k > 0 => Ok(f64::NAN),
+                _ => field
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .ok_or_else(|| format!("line {}: expected number in column {}", i + 2, k + 1)),
+            }
+        };
+        overlay.x.push(number(0)?);
+        overlay.a.push(number(1)?);
+        overlay.b.push(number(1 + probes)?);
+        overlay.diff.push(number(1 + 2 * probes)?);
+    }
+    Ok(overlay)
+}
+
+/// Traces against a shared x axis in one plot, log axis with decade gridlines
+///
+/// Points that aren't finite (or not positive on a log axis) are left out.
+fn overlay_plot(ui: &mut egui::Ui, x: &[f64], traces: &[(&[f64], Color32)], log_x: bool) {
+    let (response, painter) = ui.allocate_painter(BODE_SIZE, egui::Sense::hover());
+    let rect = response.rect;
+    painter.rect_filled(rect, 0.0, Color32::BLACK);
+    let shown = |x: f64, v: f64| x.is_finite() && v.is_finite() && (!log_x || x > 0.0);
+    let points = traces
+        .iter()
+        .flat_map(|(values, _)| x.iter().zip(values.iter()).filter(|(x, v)| shown(**x, **v)));
+    let bounds = |(lo, hi): (f64, f64), v: f64| (lo.min(v), hi.max(v));
+    let empty = (f64::INFINITY, f64::NEG_INFINITY);
+    let (x_lo, x_hi) = points.clone().map(|p| *p.0).fold(empty, bounds);
+    let (lo, hi) = points.map(|p| *p.1).fold(empty, bounds);
+    if x_lo > x_hi {
+        return;
+    }
+    let (x_lo, x_hi) = if log_x {
+        decade_range(x_lo, x_hi)
+    } else {
+        (x_lo, x_hi)
+    };
+    let scale = |v: f64| if log_x { v.log10() } else { v };
+    let x_span = scale(x_hi) - scale(x_lo);
+    let x_pos = |x: f64| {
+        let t = if x_span > 0.0 {
+            (scale(x) - scale(x_lo)) / x_span
+        } else {
+            0.5
+        };
+        rect.left() + rect.width() * t as f32
+    };
+    let span = if hi > lo { hi - lo } else { 1.0 };
+    let y_pos = |v: f64| rect.bottom() - rect.height() * ((v - lo) / span) as f32;
+    if log_x {
+        for (f, decade) in log_ticks(x_lo, x_hi) {
+            let grid = if decade {
+                Color32::GRAY
+            } else {
+                Color32::DARK_GRAY
+            };
+            painter.vline(x_pos(f), rect.y_range(), Stroke::new(1.0, grid));
+        }
+    }
+    if lo < 0.0 && hi > 0.0 {
+        painter.hline(
+            rect.x_range(),
+            y_pos(0.0),
+            Stroke::new(1.0, Color32::DARK_GRAY),
+        );
+    }
+    for (values, color) in traces {
+        let line = x
+            .iter()
+            .zip(values.iter())
+            .filter(|(x, v)| shown(**x, **v))
+            .map(|(x, v)| Pos2::new(x_pos(*x), y_pos(*v)));
+        painter.add(Shape::line(line.collect(), Stroke::new(1.5, *color)));
+    }
+}
+
+/// One Bode plot of `values` against log frequency, with a labeled marker level
+fn bode_plot(
+    ui: &mut egui::Ui,
+    freqs: &[f64],
+    values: &[f64],
+    step: f64,
+    marker: (f64, &str),
+    color: Color32,
+) {
+    let (response, painter) = ui.allocate_painter(BODE_SIZE, egui::Sense::hover());
+    let rect = response.rect;
+    painter.rect_filled(rect, 0.0, Color32::BLACK);
+    // points a log axis can show
+    let data: Vec<(f64, f64)> = freqs
+        .iter()
+        .zip(values)
+        .map(|(f, v)| (*f, *v))
+        .filter(|(f, v)| f.is_finite() && *f > 0.0 && v.is_finite())
+        .collect();
+    if data.is_empty() {
+        return;
+    }
+    let bounds = |(lo, hi): (f64, f64), v: f64| (lo.min(v), hi.max(v));
+    let empty = (f64::INFINITY, f64::NEG_INFINITY);
+    let (f_lo, f_hi) = data.iter().map(|p| p.0).fold(empty, bounds);
+    let (f_lo, f_hi) = decade_range(f_lo, f_hi);
+    let (lo, hi) = data.iter().map(|p| p.1).fold(empty, bounds);
+    let (lo, hi) = step_range(lo, hi, step);
+    let x = |f: f64| {
+        let t = (f.log10() - f_lo.log10()) / (f_hi.log10() - f_lo.log10());
+        rect.left() + rect.width() * t as f32
+    };
+    let y = |v: f64| rect.bottom() - rect.height() * ((v - lo) / (hi - lo)) as f32;
+    let font = egui::FontId::proportional(11.0);
+    for (f, decade) in log_ticks(f_lo, f_hi) {
+        let grid = if decade {
+            Color32::GRAY
+        } else {
+            Color32::DARK_GRAY
+        };
+        painter.vline(x(f), rect.y_range(), Stroke::new(1.0, grid));
+        if decade {
+            let pos = Pos2::new(x(f), rect.bottom());
+            painter.text(
+                pos,
+                egui::Align2::LEFT_BOTTOM,
+                freq_label(f),
+                font.clone(),
+                grid,
+            );
+        }
+    }
+    for v in step_ticks(lo, hi, step) {
+        painter.hline(rect.x_range(), y(v), Stroke::new(1.0, Color32::DARK_GRAY));
+        painter.text(
+            Pos2::new(rect.left(), y(v)),
+            egui::Align2::LEFT_TOP,
+            format!("{}", v),
+            font.clone(),
+            Color32::GRAY,
+        );
+    }
+    let (level, text) = marker;
+    if level > lo && level < hi {
+        let line = [
+            Pos2::new(rect.left(), y(level)),
+            Pos2::new(rect.right(), y(level)),
+        ];
+        painter.extend(Shape::dashed_line(
+            &line,
+            Stroke::new(1.0, Color32::GOLD),
+            6.0,
+            4.0,
+        ));
+        let pos = Pos2::new(rect.right(), y(level));
+        painter.text(pos, egui::Align2::RIGHT_BOTTOM, text, font, Color32::GOLD);
+    }
+    let points = data.iter().map(|(f, v)| Pos2::new(x(*f), y(*v)));
+    painter.add(Shape::line(points.collect(), Stroke::new(1.5, color)));
+}
+
+/// Length of new cables in meters, a typical instrument cable
+const DEFAULT_CABLE_LENGTH: f64 = 3.0;
+
+/// Height of the waveform panel in pixels
+const PLOT_HEIGHT: f32 = 160.0;
+
+/// Colors of waveform traces, reused in order
+const TRACE_COLORS: [Color32; 4] = [
+    Color32::LIGHT_BLUE,
+    Color32::LIGHT_GREEN,
+    Color32::GOLD,
+    Color32::LIGHT_RED,
+];
+
 struct MyApp {
     draw_lib: ComponentDrawLibrary,
-    graphical_parts: Vec<GraphicalComponent>,
+    schematic: Schematic,
+    // net name that is simulator ground
+    ground_net: String,
+    view: View,
+    theme: Theme,
+    // results of the last transient run, from `transient_path`, and why
+    // the last load failed
+    sim_results: Waveforms,
+    transient_path: String,
+    transient_error: Option<String>,
+    // names of the nets shown in the waveform panel
+    traces: Vec<String>,
+    // AC response as (frequency, gain dB, phase degrees), shown as Bode plot
+    bode: Vec<(f64, f64, f64)>,
+    // AC result file for the Bode plot, and why the last load failed
+    ac_path: String,
+    ac_error: Option<String>,
+    // two variants compared by the sim, shown overlaid, from `compare_path`
+    overlay: Option<Overlay>,
+    compare_path: String,
+    compare_error: Option<String>,
+    // net being dragged from the schematic to the waveform panel
+    probing: Option<usize>,
+    // library file to load, and why the last load failed (on the status line)
+    library_path: String,
+    library_error: Option<String>,
+    // in measure mode dragging draws the ruler instead of moving parts
+    measuring: bool,
+    ruler: Option<Ruler>,
+    // in wire mode dragging lays down a wire from `wire_start`, in cable
+    // mode a cable of `cable_length` meters
+    wiring: bool,
+    cabling: bool,
+    cable_length: f64,
+    wire_start: Option<Pos2>,
+    // parts picked with S for align and distribute
+    selection: Vec<usize>,
+    // palette part waiting to be placed
+    placing: Option<Placement>,
+    history: History,
+    // spacing parts and wires snap to, 0 for none
+    grid: f32,
+    // position of the dragged part when the drag started
+    drag_origin: Option<Pos2>,
     // Edit state
     part_selected: usize,
+    // value text being edited in the side panel, with its part
+    value_text: Option<(usize, String)>,
+    // why the value text isn't a value
+    value_error: Option<String>,
+}
+
+/// One symbol of the JSON library, `["DEF", [["F0", ...], ["DRAW", [...]], ...]]`
+#[derive(Debug, Deserialize)]
+struct LibraryEntry(String, Vec<Value>);
+
+/// Parse JSON library into symbol names and their DRAW sections
+///
+/// Errors say which entry (and DRAW line) is malformed.
+fn parse_library(bytes: &[u8]) -> Result<Vec<(String, Value)>, String> {
+    let entries: Vec<Value> =
+        serde_json::from_slice(bytes).map_err(|e| format!("library is not a list: {}", e))?;
+    library_draws(entries)
+}
+
+/// Symbol names and DRAW sections of library entries in their JSON shape
+fn library_draws(entries: Vec<Value>) -> Result<Vec<(String, Value)>, String> {
+    let mut res = vec![];
+    for (i, entry) in entries.into_iter().enumerate() {
+        let LibraryEntry(keyword, lines) =
+            serde_json::from_value(entry).map_err(|e| format!("library entry {}: {}", i, e))?;
+        if keyword != "DEF" {
+            return Err(format!(
+                "library entry {}: expected DEF, found {}",
+                i, keyword
+            ));
+        }
+        // name is the value field, eg. ["F1", "R", ...]
+        let name = lines
+            .iter()
+            .find(|line| line[0] == "F1")
+            .and_then(|line| line[1].as_str())
+            .ok_or(format!("library entry {}: no name in F1 field", i))?
+            .to_string();
+        let draw = find_draw(&Value::Array(lines))
+            .and_then(|draw| draw.as_array().cloned())
+            .ok_or(format!("library entry {} ({}): no DRAW section", i, name))?;
+        for (j, line) in draw.iter().enumerate() {
+            if !line[0].is_string() {
+                return Err(format!(
+                    "library entry {} ({}): DRAW line {} has no tag",
+                    i, name, j
+                ));
+            }
+            check_draw_line(line.as_array().map_or(&[], |a| a))
+                .map_err(|e| format!("library entry {} ({}): DRAW line {}: {}", i, name, j, e))?;
+        }
+        res.push((name, Value::Array(draw)));
+    }
+    Ok(res)
+}
+
+/// Check a DRAW line has every field its tag is drawn from
+///
+/// Unknown tags pass, they are drawn as markers.
+fn check_draw_line(a: &[Value]) -> Result<(), String> {
+    let tag = a.first().and_then(|t| t.as_str()).unwrap_or_default();
+    let numbers = |fields: &[usize]| match fields
+        .iter()
+        .find(|k| a.get(**k).and_then(parse_number).is_none())
+    {
+        Some(k) => Err(format!("{} needs a number in field {}", tag, k)),
+        None => Ok(()),
+    };
+    let text = |k: usize| {
+        a.get(k)
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| format!("{} needs text in field {}", tag, k))
+    };
+    match tag {
+        "A" => numbers(&[1, 2, 3, 4, 5, 8])?,
+        "C" => {
+            numbers(&[1, 2, 3, 6])?;
+            text(7)?;
+        }
+        "P" => {
+            let n = point_count(tag, a)?;
+            numbers(&(4..5 + 2 * n).collect::<Vec<_>>())?;
+            text(5 + 2 * n)?;
+        }
+        "B" => {
+            let n = point_count(tag, a)?;
+            numbers(&(4..5 + 2 * n).collect::<Vec<_>>())?;
+        }
+        "S" => numbers(&[1, 2, 3, 4, 7])?,
+        "X" => {
+            numbers(&[3, 4, 5, 9])?;
+            let direction = text(6)?;
+            if !matches!(direction, "U" | "D" | "L" | "R") {
+                return Err(format!(
+                    "X needs U, D, L or R in field 6, found {}",
+                    direction
+                ));
+            }
+        }
+        "T" => {
+            numbers(&[1, 2, 3, 4, 5])?;
+            a.get(8).ok_or("T needs text in field 8")?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Point count of a polyline-like DRAW line, checked against its length
+fn point_count(tag: &str, a: &[Value]) -> Result<usize, String> {
+    let n = match a.get(1).and_then(parse_number) {
+        Some(n) if n >= 1.0 && n.fract() == 0.0 => n as usize,
+        _ => return Err(format!("{} needs a point count in field 1", tag)),
+    };
+    // coordinates start at field 5
+    if n > a.len().saturating_sub(5) / 2 {
+        return Err(format!(
+            "{} has {} fields, too few for {} points",
+            tag,
+            a.len(),
+            n
+        ));
+    }
+    Ok(n)
+}
+
+/// Load the bundled component library
+fn load_draw_library() -> ComponentDrawLibrary {
+    let mut draw_lib = ComponentDrawLibrary::new();
+    let bytes = include_bytes!("./circuit.json");
+    let lib = parse_library(bytes).expect("Could not parse bundled library");
+    for (name, draw) in lib {
+        let comp = string_to_componenttype(&name).expect("Unknown component type");
+        println!("{:?}", comp);
+        draw_lib.insert(comp, draw);
+    }
+    draw_lib
+}
+
+/// Load a JSON or KiCad `.lib` library from disk, symbols the GUI doesn't know are skipped
+fn load_library(path: &str) -> Result<ComponentDrawLibrary, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+    let lib = if path.ends_with(".lib") {
+        let text = String::from_utf8_lossy(&bytes);
+        kicad_lib::parse_lib(&text).and_then(|defs| {
+            library_draws(
+                defs.into_iter()
+                    .map(|(_, lines)| serde_json::json!(["DEF", lines]))
+                    .collect(),
+            )
+        })
+    } else {
+        parse_library(&bytes)
+    }
+    .map_err(|e| format!("{}: {}", path, e))?;
+    let mut draw_lib = ComponentDrawLibrary::new();
+    for (name, draw) in lib {
+        if let Some(comp) = string_to_componenttype(&name) {
+            draw_lib.insert(comp, draw);
+        }
+    }
+    Ok(draw_lib)
 }
 
 impl Default for MyApp {
     fn default() -> Self {
-        let mut draw_lib = ComponentDrawLibrary::new();
-        let bytes = include_bytes!("./circuit.json");
-        let lib: Value = serde_json::from_slice(bytes).unwrap();
-        for elem in lib.as_array().unwrap() {
-            let name = elem[1][1][1]
-                .as_str()
-                .expect("Could not parse string in library");
-            let comp = string_to_componenttype(name).expect("Unknown component type");
-            println!("{:?}", comp);
-            draw_lib.insert(
-                comp,
-                find_draw(&elem[1])
-                    .expect("Could not find DRAW line in library")
-                    .clone(),
-            );
-        }
-        let graphical_parts = vec![
+        let draw_lib = load_draw_library();
+        let parts = vec![
             GraphicalComponent::new(
                 ComponentType::Capacitor,
                 Pos2::new(200.0, 200.0),
@@ -168,13 +1245,61 @@ impl Default for MyApp {
 
         Self {
             draw_lib,
-            graphical_parts,
+            schematic: Schematic {
+                parts,
+                wires: vec![],
+            },
+            ground_net: GROUND_NET.into(),
+            view: View::default(),
+            theme: Theme::DARK,
+            sim_results: Waveforms::default(),
+            transient_path: String::from("transient.csv"),
+            transient_error: None,
+            traces: vec![],
+            bode: vec![],
+            ac_path: String::from("ac.csv"),
+            ac_error: None,
+            overlay: None,
+            compare_path: String::from("compare.csv"),
+            compare_error: None,
+            probing: None,
+            library_path: String::from("circuit.json"),
+            library_error: None,
+            measuring: false,
+            ruler: None,
+            wiring: false,
+            cabling: false,
+            cable_length: DEFAULT_CABLE_LENGTH,
+            wire_start: None,
+            selection: vec![],
+            placing: None,
+            history: History::default(),
+            grid: DEFAULT_GRID,
+            drag_origin: None,
             part_selected,
+            value_text: None,
+            value_error: None,
         }
     }
 }
 
 impl MyApp {
+    /// Put the camera back to where it started, leaves the circuit alone
+    fn reset_view(&mut self) {
+        self.view = View::default();
+    }
+
+    /// Forget loaded transient, AC and comparison results and their load
+    /// errors so the next run starts fresh, probed traces stay
+    fn reset_sim(&mut self) {
+        self.sim_results = Waveforms::default();
+        self.transient_error = None;
+        self.bode.clear();
+        self.ac_error = None;
+        self.overlay = None;
+        self.compare_error = None;
+    }
+
     fn new(cc: &eframe::CreationContext<'_>) -> Self {
         let mut fonts = egui::FontDefinitions::default();
         fonts.font_data.insert(
@@ -205,20 +1330,17 @@ fn heading(text: &str) -> egui::Label {
 
 /// Given JSON library chunk of a component, extract part that is DRAW if found
 fn find_draw(v: &Value) -> Option<&Value> {
-    for i in 0..v.as_array().unwrap().len() {
-        if v[i][0] == serde_json::Value::String("DRAW".into()) {
-            return Some(&v[i][1]);
+    for line in v.as_array()? {
+        if line[0] == serde_json::Value::String("DRAW".into()) {
+            return Some(&line[1]);
         }
     }
-    return None;
+    None
 }
 
 /// Given a JSON value, try to parse as a f32 number
 fn parse_number(v: &Value) -> Option<f32> {
-    match v.as_number() {
-        Some(n) => Some(n.as_f64().unwrap() as f32),
-        None => None,
-    }
+    v.as_f64().map(|n| n as f32)
 }
 
 /// Order is: flip, scale, rotate, translate
@@ -253,13 +1375,13 @@ impl SingleTransform {
         let y = if self.flip_y { -a.y } else { a.y };
         let c = self.rotate.cos();
         let s = self.rotate.sin();
-        return Pos2::new(
+        Pos2::new(
             (x * c - y * s) * self.scale + self.translate.x,
             (x * s + y * c) * self.scale + self.translate.y,
-        );
+        )
     }
     fn apply_scalar(&self, a: f32) -> f32 {
-        return self.scale * a;
+        self.scale * a
     }
 }
 
@@ -295,30 +1417,77 @@ impl Transform {
         Self { transforms }
     }
     fn apply(&self, a: &Pos2) -> Pos2 {
-        let mut p = a.clone();
+        let mut p = *a;
         for t in &self.transforms {
             p = t.apply(&p);
         }
-        return p;
+        p
     }
     fn apply_scalar(&self, a: f32) -> f32 {
         let mut res = a;
         for t in &self.transforms {
             res = t.apply_scalar(res);
         }
-        return res;
+        res
+    }
+    /// Direction angle (radians, clockwise on screen) after rotations and flips
+    fn apply_angle(&self, a: f32) -> f32 {
+        let mut res = a;
+        for t in &self.transforms {
+            if t.flip_x != t.flip_y {
+                res = -res;
+            }
+            res += t.rotate;
+        }
+        res
+    }
+}
+
+/// Default text size of labels, in library units
+const LABEL_SIZE: f32 = 50.0;
+
+/// Text centered at `center`, turned to stay readable (never upside down)
+fn text_shape(
+    fonts: &egui::text::Fonts,
+    center: Pos2,
+    angle: f32,
+    text: &str,
+    size: f32,
+    color: Color32,
+) -> Shape {
+    use std::f32::consts::{FRAC_PI_2, PI, TAU};
+    let mut angle = angle.rem_euclid(TAU);
+    if angle > PI {
+        angle -= TAU;
+    }
+    if angle >= FRAC_PI_2 {
+        angle -= PI;
+    } else if angle < -FRAC_PI_2 {
+        angle += PI;
     }
+    let galley = fonts.layout_no_wrap(text.to_string(), egui::FontId::proportional(size), color);
+    // galley turns around its top left corner
+    let half = galley.size() / 2.0;
+    let pos = center - egui::emath::Rot2::from_angle(angle) * half;
+    Shape::Text(egui::epaint::TextShape::new(pos, galley, color).with_angle(angle))
+}
+
+/// Center of possibly rotated text
+fn text_center(text: &egui::epaint::TextShape) -> Pos2 {
+    text.pos + egui::emath::Rot2::from_angle(text.angle) * (text.galley.size() / 2.0)
 }
 
 /// Helper function for draw_to_shape
 // Turns one line of DRAW section into a (Shape, Shape)
 // Pair is base layer, then pad layer (on top)
+// Fields were checked by check_draw_line when the library loaded
 fn drawline_to_shape(
     v: &Value,
     transform: &Transform,
     color: Color32,
     pad_color: Color32,
     pad_size: f32,
+    fonts: Option<&egui::text::Fonts>,
 ) -> (Option<Shape>, Option<Shape>) {
     let a = v.as_array().unwrap();
     let tag = &a[0];
@@ -337,8 +1506,8 @@ fn drawline_to_shape(
                 y = -parse_number(&a[2]).unwrap();
                 r = parse_number(&a[3]).unwrap();
                 // Angles measured in 1/10s of degrees
-                angle_start = parse_number(&a[4]).unwrap() / 10.0 / 360.0 * 2.0 * 3.14159265;
-                angle_end = parse_number(&a[5]).unwrap() / 10.0 / 360.0 * 2.0 * 3.14159265;
+                angle_start = (parse_number(&a[4]).unwrap() / 10.0).to_radians();
+                angle_end = (parse_number(&a[5]).unwrap() / 10.0).to_radians();
                 w = parse_number(&a[8]).unwrap().max(w_fine_orig);
                 let w = transform.apply_scalar(w);
                 let mut v: std::vec::Vec<Pos2> = vec![];
@@ -350,6 +1519,14 @@ fn drawline_to_shape(
                     let c = transform.apply(&Pos2::new(xx, yy));
                     v.push(c);
                 }
+                if a.get(9).and_then(|f| f.as_str()) == Some("F") {
+                    // Filled sector, closed through the center
+                    v.push(transform.apply(&Pos2::new(x, y)));
+                    return (
+                        Some(Shape::convex_polygon(v, color, Stroke::default())),
+                        None,
+                    );
+                }
                 return (Some(Shape::line(v, Stroke::new(w, color))), None);
             }
             "C" => {
@@ -359,14 +1536,18 @@ fn drawline_to_shape(
                 y = -parse_number(&a[2]).unwrap();
                 r = parse_number(&a[3]).unwrap();
                 w = parse_number(&a[6]).unwrap();
-                if a[7].as_str().unwrap() == "N" {
-                    let c = transform.apply(&Pos2::new(x, y));
-                    let r = transform.apply_scalar(r);
-                    let w = transform.apply_scalar(w);
-                    return (
-                        Some(Shape::circle_stroke(c, r, Stroke::new(w, color))),
-                        None,
-                    );
+                let c = transform.apply(&Pos2::new(x, y));
+                let r = transform.apply_scalar(r);
+                let w = transform.apply_scalar(w);
+                match a[7].as_str().unwrap() {
+                    "N" => {
+                        return (
+                            Some(Shape::circle_stroke(c, r, Stroke::new(w, color))),
+                            None,
+                        );
+                    }
+                    "F" => return (Some(Shape::circle_filled(c, r, color)), None),
+                    _ => {}
                 }
             }
             "P" => {
@@ -390,6 +1571,8 @@ fn drawline_to_shape(
                         Some(Shape::convex_polygon(v, color, Stroke::default())),
                         None,
                     );
+                } else if let Some(res) = styled_line(&v, a.get(6 + 2 * n), w, color, transform) {
+                    return (Some(Shape::Vec(res)), None);
                 } else {
                     let mut res = vec![];
                     // Add individual line segments connecting pairs.
@@ -400,6 +1583,25 @@ fn drawline_to_shape(
                     return (Some(Shape::Vec(res)), None);
                 }
             }
+            "B" => {
+                // Bezier curve, control points laid out like a polyline
+                let (n, w);
+                n = parse_number(&a[1]).unwrap() as usize;
+                w = parse_number(&a[4]).unwrap().max(w_fine_orig);
+                let w = transform.apply_scalar(w);
+                let control: Vec<Pos2> = (0..n)
+                    .map(|i| {
+                        let x = parse_number(&a[5 + 2 * i]).unwrap();
+                        let y = -parse_number(&a[6 + 2 * i]).unwrap();
+                        Pos2::new(x, y)
+                    })
+                    .collect();
+                let v: Vec<Pos2> = bezier_points(&control, BEZIER_SEGMENTS)
+                    .iter()
+                    .map(|p| transform.apply(p))
+                    .collect();
+                return (Some(Shape::line(v, Stroke::new(w, color))), None);
+            }
             "S" => {
                 // Rectangle
                 let (sx, sy, ex, ey, w);
@@ -418,6 +1620,9 @@ fn drawline_to_shape(
                     transform.apply(&Pos2::new(sx, ey)),
                     transform.apply(&Pos2::new(sx, sy)),
                 ];
+                if let Some(res) = styled_line(&v, a.get(9), w, color, transform) {
+                    return (Some(Shape::Vec(res)), None);
+                }
                 // Add individual line segments connecting pairs.
                 // This avoids jagged connectors that extend beyond radius of line bend.
                 let mut res = vec![];
@@ -452,10 +1657,113 @@ fn drawline_to_shape(
                     Some(Shape::circle_filled(c1, pad_size, pad_color)),
                 );
             }
-            &_ => return (None, None),
+            "T" => {
+                // Text, needs fonts to lay out
+                let (angle, x, y, size, hidden);
+                // Angle measured in 1/10s of degrees, counterclockwise
+                angle = -parse_number(&a[1]).unwrap() / 10.0 / 360.0 * std::f32::consts::TAU;
+                x = parse_number(&a[2]).unwrap();
+                y = -parse_number(&a[3]).unwrap();
+                size = parse_number(&a[4]).unwrap();
+                hidden = parse_number(&a[5]).unwrap() != 0.0;
+                // "~" stands for a space in library text
+                let text = match &a[8] {
+                    Value::String(t) => t.replace('~', " "),
+                    other => other.to_string(),
+                };
+                if let (Some(fonts), false) = (fonts, hidden) {
+                    let c = transform.apply(&Pos2::new(x, y));
+                    let size = transform.apply_scalar(size);
+                    let angle = transform.apply_angle(angle);
+                    return (Some(text_shape(fonts, c, angle, &text, size, color)), None);
+                }
+            }
+            &_ => return (unknown_tag_marker(ts, a, transform), None),
         }
     }
-    return (None, None);
+    (None, None)
+}
+
+/// Number of line segments a Bezier curve is drawn with
+const BEZIER_SEGMENTS: usize = 16;
+
+/// Points along a Bezier curve of any degree (quadratic, cubic, ...)
+///
+/// Evaluated with de Casteljau's algorithm at `segments + 1` evenly spaced
+/// parameter values, so the first and last points are the curve ends.
+fn bezier_points(control: &[Pos2], segments: usize) -> Vec<Pos2> {
+    if control.len() < 2 {
+        return control.to_vec();
+    }
+    (0..=segments)
+        .map(|i| {
+            let t = i as f32 / segments as f32;
+            let mut p = control.to_vec();
+            while p.len() > 1 {
+                p = p.windows(2).map(|pair| pair[0].lerp(pair[1], t)).collect();
+            }
+            p[0]
+        })
+        .collect()
+}
+
+/// Dash and gap lengths for dashed strokes, in library units
+const DASH_LENGTH: f32 = 30.0;
+const DASH_GAP: f32 = 20.0;
+/// Distance between dots of dotted strokes, in library units
+const DOT_SPACING: f32 = 15.0;
+
+/// Gapped segments for a dashed or dotted line, None for solid lines
+///
+/// Style comes from an optional field after the fill flag, "DASH" or "DOT".
+fn styled_line(
+    v: &[Pos2],
+    style: Option<&Value>,
+    w: f32,
+    color: Color32,
+    transform: &Transform,
+) -> Option<Vec<Shape>> {
+    let style = style?.as_str()?.to_ascii_uppercase();
+    match style.as_str() {
+        "DASH" => Some(Shape::dashed_line(
+            v,
+            Stroke::new(w, color),
+            transform.apply_scalar(DASH_LENGTH),
+            transform.apply_scalar(DASH_GAP),
+        )),
+        "DOT" => Some(Shape::dotted_line(
+            v,
+            color,
+            transform.apply_scalar(DOT_SPACING),
+            w / 2.0,
+        )),
+        _ => None,
+    }
+}
+
+/// Size of marker for unknown draw tags, in library units
+const UNKNOWN_MARKER_SIZE: f32 = 20.0;
+
+/// Draw unknown tag as magenta cross at its first coordinate pair, log tag once
+fn unknown_tag_marker(tag: &str, a: &[Value], transform: &Transform) -> Option<Shape> {
+    static LOGGED: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(vec![]);
+    let mut logged = LOGGED.lock().unwrap();
+    if !logged.iter().any(|t| t == tag) {
+        eprintln!("Unknown draw tag {:?}", tag);
+        logged.push(tag.into());
+    }
+    let (x, y) = a[1..]
+        .windows(2)
+        .find_map(|w| Some((parse_number(&w[0])?, parse_number(&w[1])?)))?;
+    let d = UNKNOWN_MARKER_SIZE / 2.0;
+    let w = transform.apply_scalar(2.0);
+    let stroke = Stroke::new(w, Color32::MAGENTA);
+    let corners = [(-d, -d), (d, d), (-d, d), (d, -d)]
+        .map(|(dx, dy)| transform.apply(&Pos2::new(x + dx, -y + dy)));
+    Some(Shape::Vec(vec![
+        Shape::line_segment([corners[0], corners[1]], stroke),
+        Shape::line_segment([corners[2], corners[3]], stroke),
+    ]))
 }
 
 /// Given DRAW JSON value, turn section into single Shape for drawing (including pads)
@@ -465,12 +1773,13 @@ fn draw_to_shape(
     color: Color32,
     pad_color: Color32,
     pad_size: f32,
+    fonts: Option<&egui::text::Fonts>,
 ) -> Shape {
     let mut lower_shapes = vec![];
     let mut upper_shapes = vec![];
     let n = v.as_array().unwrap().len();
     for i in 0..n {
-        let shape = drawline_to_shape(&v[i], &transform, color, pad_color, pad_size);
+        let shape = drawline_to_shape(&v[i], transform, color, pad_color, pad_size, fonts);
         if let (Some(s), _) = shape {
             lower_shapes.push(s);
         }
@@ -479,81 +1788,2738 @@ fn draw_to_shape(
         }
     }
     lower_shapes.append(&mut upper_shapes);
-    return Shape::Vec(lower_shapes);
+    Shape::Vec(lower_shapes)
 }
 
-impl eframe::App for MyApp {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        egui::CentralPanel::default().show(ctx, |ui| {
-            if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Delete)) {
-                if self.graphical_parts.len() > 0 {
-                    self.graphical_parts.remove(self.part_selected);
-                    if self.graphical_parts.len() > 0
-                        && self.part_selected > self.graphical_parts.len() - 1
-                    {
-                        self.part_selected = self.graphical_parts.len() - 1;
+/// Shapes for all parts of the schematic, in the colors of the theme
+fn schematic_shapes(
+    schematic: &Schematic,
+    draw_lib: &ComponentDrawLibrary,
+    global_transform: &Transform,
+    theme: &Theme,
+    selected: Option<usize>,
+    fonts: Option<&egui::text::Fonts>,
+) -> Vec<Shape> {
+    // wires go under the parts
+    let stroke = Stroke::new(global_transform.apply_scalar(WIRE_WIDTH), theme.component);
+    let mut shapes: Vec<Shape> = schematic
+        .wires
+        .iter()
+        .map(|w| {
+            let points = [w.start, w.end].map(|p| global_transform.apply(&p));
+            Shape::line_segment(points, stroke)
+        })
+        .collect();
+    let radius = global_transform.apply_scalar(JUNCTION_RADIUS);
+    for p in junctions(schematic, draw_lib) {
+        shapes.push(Shape::circle_filled(
+            global_transform.apply(&p),
+            radius,
+            theme.component,
+        ));
+    }
+    for (index, component) in schematic.parts.iter().enumerate() {
+        let draw_instr = &draw_lib[&component.component_type];
+        // swap order of transforms
+        let transform = component.transform(draw_lib).chain(global_transform);
+        let color = if selected == Some(index) {
+            theme.selected
+        } else if !component.enabled {
+            theme.disabled
+        } else {
+            theme.component
+        };
+        shapes.push(draw_to_shape(
+            draw_instr, &transform, color, theme.pad, PAD_SIZE, fonts,
+        ));
+        let label = component.display_label();
+        if let (Some(fonts), false) = (fonts, label.is_empty()) {
+            shapes.push(text_shape(
+                fonts,
+                transform.apply(&Pos2::ZERO),
+                transform.apply_angle(0.0),
+                label,
+                transform.apply_scalar(LABEL_SIZE),
+                color,
+            ));
+        }
+        if selected == Some(index) {
+            shapes.extend(selection_shapes(
+                component,
+                draw_lib,
+                global_transform,
+                theme.selected,
+            ));
+        }
+    }
+    shapes
+}
+
+/// Size of selection handles in screen pixels
+const HANDLE_SIZE: f32 = 8.0;
+/// Length of the orientation arrow, in library units
+const ARROW_LENGTH: f32 = 80.0;
+
+/// Corners of the part's bounds in screen coordinates, where handles go
+fn selection_handles(
+    part: &GraphicalComponent,
+    draw_lib: &ComponentDrawLibrary,
+    global_transform: &Transform,
+) -> [Pos2; 4] {
+    let bounds = draw_bounds(&draw_lib[&part.component_type]);
+    let transform = part.transform(draw_lib).chain(global_transform);
+    [
+        bounds.left_top(),
+        bounds.right_top(),
+        bounds.right_bottom(),
+        bounds.left_bottom(),
+    ]
+    .map(|p| transform.apply(&p))
+}
+
+/// Corner handles plus an arrow along the part's "up"
+///
+/// The arrow head only has a barb on the part's right side, so flips
+/// show up as well as rotation.
+fn selection_shapes(
+    part: &GraphicalComponent,
+    draw_lib: &ComponentDrawLibrary,
+    global_transform: &Transform,
+    color: Color32,
+) -> Vec<Shape> {
+    let mut res: Vec<Shape> = selection_handles(part, draw_lib, global_transform)
+        .iter()
+        .map(|p| {
+            let rect = Rect::from_center_size(*p, egui::vec2(HANDLE_SIZE, HANDLE_SIZE));
+            Shape::rect_filled(rect, 0.0, color)
+        })
+        .collect();
+    let c = draw_center(&draw_lib[&part.component_type]);
+    let transform = part.transform(draw_lib).chain(global_transform);
+    let tip = c + egui::vec2(0.0, -ARROW_LENGTH);
+    let barb = tip + egui::vec2(ARROW_LENGTH / 4.0, ARROW_LENGTH / 4.0);
+    let stroke = Stroke::new(2.0, color);
+    let [c, tip, barb] = [c, tip, barb].map(|p| transform.apply(&p));
+    res.push(Shape::line_segment([c, tip], stroke));
+    res.push(Shape::line_segment([tip, barb], stroke));
+    res
+}
+
+fn svg_color(c: Color32) -> String {
+    format!("#{:02x}{:02x}{:02x}", c.r(), c.g(), c.b())
+}
+
+fn svg_stroke(color: Color32, width: f32) -> String {
+    format!("stroke=\"{}\" stroke-width=\"{}\"", svg_color(color), width)
+}
+
+/// Append SVG elements for shape, only the kinds of shape the library draws
+fn shape_to_svg(shape: &Shape, out: &mut Vec<String>) {
+    match shape {
+        Shape::Vec(shapes) => {
+            for s in shapes {
+                shape_to_svg(s, out);
+            }
+        }
+        Shape::LineSegment { points, stroke } => out.push(format!(
+            "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" {} stroke-linecap=\"round\"/>",
+            points[0].x,
+            points[0].y,
+            points[1].x,
+            points[1].y,
+            svg_stroke(stroke.color, stroke.width)
+        )),
+        Shape::Path(path) => {
+            let points: Vec<String> = path
+                .points
+                .iter()
+                .map(|p| format!("{},{}", p.x, p.y))
+                .collect();
+            let stroke = match &path.stroke.color {
+                egui::epaint::ColorMode::Solid(c) if path.stroke.width > 0.0 => {
+                    svg_stroke(*c, path.stroke.width)
+                }
+                _ => String::from("stroke=\"none\""),
+            };
+            let fill = if path.fill == Color32::TRANSPARENT {
+                String::from("none")
+            } else {
+                svg_color(path.fill)
+            };
+            let tag = if path.closed { "polygon" } else { "polyline" };
+            out.push(format!(
+                "<{} points=\"{}\" fill=\"{}\" {}/>",
+                tag,
+                points.join(" "),
+                fill,
+                stroke
+            ));
+        }
+        Shape::Circle(circle) => {
+            let fill = if circle.fill == Color32::TRANSPARENT {
+                String::from("none")
+            } else {
+                svg_color(circle.fill)
+            };
+            let stroke = if circle.stroke.width > 0.0 {
+                svg_stroke(circle.stroke.color, circle.stroke.width)
+            } else {
+                String::from("stroke=\"none\"")
+            };
+            out.push(format!(
+                "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\" {}/>",
+                circle.center.x, circle.center.y, circle.radius, fill, stroke
+            ));
+        }
+        Shape::Text(text) => {
+            let center = text_center(text);
+            out.push(format!(
+                "<text x=\"{}\" y=\"{}\" font-size=\"{}\" fill=\"{}\" text-anchor=\"middle\" dominant-baseline=\"central\" transform=\"rotate({} {} {})\">{}</text>",
+                center.x,
+                center.y,
+                text.galley.size().y,
+                svg_color(text.fallback_color),
+                text.angle.to_degrees(),
+                center.x,
+                center.y,
+                text.galley.text()
+                    .replace('&', "&amp;")
+                    .replace('<', "&lt;")
+                    .replace('>', "&gt;")
+            ));
+        }
+        _ => {}
+    }
+}
+
+/// SVG document with the shapes drawn over the background color
+fn shapes_to_svg(shapes: &[Shape], background: Color32) -> String {
+    let bounds = shapes
+        .iter()
+        .map(|s| s.visual_bounding_rect())
+        .fold(Rect::NOTHING, |a, b| a.union(b))
+        .expand(PAD_SIZE);
+    let mut out = vec![format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">",
+        bounds.min.x,
+        bounds.min.y,
+        bounds.width(),
+        bounds.height()
+    )];
+    out.push(format!(
+        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+        bounds.min.x,
+        bounds.min.y,
+        bounds.width(),
+        bounds.height(),
+        svg_color(background)
+    ));
+    for shape in shapes {
+        shape_to_svg(shape, &mut out);
+    }
+    out.push(String::from("</svg>"));
+    out.join("\n") + "\n"
+}
+
+/// Center of bounding box of DRAW JSON value, in untransformed coordinates
+fn draw_center(v: &Value) -> Pos2 {
+    let bounds = draw_bounds(v);
+    if bounds.is_positive() {
+        bounds.center()
+    } else {
+        Pos2::ZERO
+    }
+}
+
+/// Bounding box of drawing in library coordinates
+fn draw_bounds(v: &Value) -> Rect {
+    let identity = Transform::new(1.0, 0.0, 0.0, 0.0, false, false);
+    // text is left out, it depends on fonts
+    let shape = draw_to_shape(v, &identity, Color32::WHITE, Color32::WHITE, PAD_SIZE, None);
+    shape.visual_bounding_rect()
+}
+
+/// Given DRAW JSON value, find positions of pins (pads) of the first unit
+fn draw_to_padpos(v: &Value, transform: &Transform) -> Vec<Pos2> {
+    let mut res = vec![];
+    for line in v.as_array().unwrap() {
+        let a = line.as_array().unwrap();
+        if a[0] != "X" {
+            continue;
+        }
+        // Unit 0 is common to all units, other units would duplicate pins
+        let unit = parse_number(&a[9]).unwrap();
+        if unit > 1.0 {
+            continue;
+        }
+        let x = parse_number(&a[3]).unwrap();
+        let y = -parse_number(&a[4]).unwrap();
+        res.push(transform.apply(&Pos2::new(x, y)));
+    }
+    res
+}
+
+/// Electrical connectivity of a schematic
+#[derive(Debug)]
+struct Netlist {
+    // net of every pin, indexed by part then pad
+    part_nets: Vec<Vec<usize>>,
+    // name of every net, net 0 is the ground net
+    net_names: Vec<String>,
+    // cable connections as (net, net, length)
+    cables: Vec<(usize, usize, f64)>,
+    // net of every wire (the start of a cable)
+    wire_nets: Vec<usize>,
+    // current sense wires as (from net, to net)
+    senses: Vec<(usize, usize)>,
+}
+
+impl Netlist {
+    /// Number of distinct nets that something connects to
+    fn net_count(&self) -> usize {
+        let mut used = vec![false; self.net_names.len()];
+        for net in self.part_nets.iter().flatten() {
+            used[*net] = true;
+        }
+        for (a, b, _) in &self.cables {
+            used[*a] = true;
+            used[*b] = true;
+        }
+        for (a, b) in &self.senses {
+            used[*a] = true;
+            used[*b] = true;
+        }
+        used.iter().filter(|u| **u).count()
+    }
+}
+
+/// Summary of schematic size, eg. "3 parts (2 Resistor, 1 Ground), 2 nets"
+fn status_line(schematic: &Schematic, netlist: &Netlist) -> String {
+    let mut counts: Vec<(&ComponentType, usize)> = vec![];
+    for part in &schematic.parts {
+        match counts.iter_mut().find(|(t, _)| **t == part.component_type) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((&part.component_type, 1)),
+        }
+    }
+    let by_type: Vec<String> = counts
+        .iter()
+        .map(|(t, n)| format!("{} {:?}", n, t))
+        .collect();
+    format!(
+        "{} parts ({}), {} nets",
+        schematic.parts.len(),
+        by_type.join(", "),
+        netlist.net_count()
+    )
+}
+
+/// Power rails wired straight to a ground net, eg. "VCC is shorted to GND"
+///
+/// A rail source across zero impedance makes the circuit matrix singular,
+/// so this should be checked before trying to solve.
+fn power_shorts(schematic: &Schematic, netlist: &Netlist) -> Vec<String> {
+    let ground_nets: Vec<usize> = schematic
+        .parts
+        .iter()
+        .zip(&netlist.part_nets)
+        .filter(|(part, _)| part.component_type == ComponentType::Ground)
+        .flat_map(|(_, nets)| nets.iter().copied())
+        .chain([0])
+        .collect();
+    let mut res = vec![];
+    for (part, nets) in schematic.parts.iter().zip(&netlist.part_nets) {
+        if part.component_type != ComponentType::Power {
+            continue;
+        }
+        if let Some(net) = nets.iter().find(|net| ground_nets.contains(net)) {
+            let msg = format!("{} is shorted to {}", part.label, netlist.net_names[*net]);
+            if !res.contains(&msg) {
+                res.push(msg);
+            }
+        }
+    }
+    res
+}
+
+/// Union-find over point indices
+struct UnionFind {
+    parent: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new() -> Self {
+        Self { parent: vec![] }
+    }
+    fn add(&mut self) -> usize {
+        self.parent.push(self.parent.len());
+        self.parent.len() - 1
+    }
+    fn find(&mut self, i: usize) -> usize {
+        let mut root = i;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        // compress path
+        let mut i = i;
+        while self.parent[i] != root {
+            let next = self.parent[i];
+            self.parent[i] = root;
+            i = next;
+        }
+        root
+    }
+    fn union(&mut self, a: usize, b: usize) {
+        let (ra, rb) = (self.find(a), self.find(b));
+        self.parent[ra] = rb;
+    }
+}
+
+/// Points closer than a unit (after rounding) are the same connection point
+fn point_key(p: Pos2) -> (i64, i64) {
+    (p.x.round() as i64, p.y.round() as i64)
+}
+
+/// Work out which pins are connected, through wires and same-named grounds
+///
+/// Ground symbols named `ground_net` become net 0, other ground names
+/// (eg. "AGND" vs "DGND") stay separate nets unless something joins them.
+/// Power symbols join by name the same way.
+fn extract_netlist(
+    schematic: &Schematic,
+    draw_lib: &ComponentDrawLibrary,
+    ground_net: &str,
+) -> Netlist {
+    let mut uf = UnionFind::new();
+    let mut points = std::collections::HashMap::new();
+    let mut point_index =
+        |uf: &mut UnionFind, p: Pos2| *points.entry(point_key(p)).or_insert_with(|| uf.add());
+    let (parts, wires) = (&schematic.parts, &schematic.wires);
+    // disabled parts have no pads, so they connect nothing
+    let part_points: Vec<Vec<usize>> = parts
+        .iter()
+        .map(|part| {
+            if !part.enabled {
+                return vec![];
+            }
+            draw_to_padpos(&draw_lib[&part.component_type], &part.transform(draw_lib))
+                .into_iter()
+                .map(|p| point_index(&mut uf, p))
+                .collect()
+        })
+        .collect();
+    let mut cable_points = vec![];
+    let mut sense_points = vec![];
+    let mut wire_points = vec![];
+    for wire in wires {
+        let (a, b) = (
+            point_index(&mut uf, wire.start),
+            point_index(&mut uf, wire.end),
+        );
+        wire_points.push(a);
+        match wire.cable {
+            Some(length) => cable_points.push((a, b, length)),
+            None if wire.sense => sense_points.push((a, b)),
+            None => uf.union(a, b),
+        }
+    }
+    // same-named grounds (and power rails) are one net
+    let mut grounds: Vec<(&str, usize)> = vec![];
+    for (part, pads) in parts.iter().zip(&part_points) {
+        let global = matches!(
+            part.component_type,
+            ComponentType::Ground | ComponentType::Power
+        );
+        if !global || pads.is_empty() {
+            continue;
+        }
+        match grounds.iter().find(|(name, _)| *name == part.label) {
+            Some((_, p)) => uf.union(*p, pads[0]),
+            None => grounds.push((&part.label, pads[0])),
+        }
+    }
+    // number nets, ground first
+    let mut net_of_root = std::collections::HashMap::new();
+    let mut net_names = vec![ground_net.to_string()];
+    if let Some((_, p)) = grounds.iter().find(|(name, _)| *name == ground_net) {
+        net_of_root.insert(uf.find(*p), 0);
+    }
+    for (name, p) in &grounds {
+        let root = uf.find(*p);
+        net_of_root.entry(root).or_insert_with(|| {
+            net_names.push(name.to_string());
+            net_names.len() - 1
+        });
+    }
+    let mut net_of = |p: usize| {
+        let root = uf.find(p);
+        *net_of_root.entry(root).or_insert_with(|| {
+            net_names.push(format!("n{}", net_names.len()));
+            net_names.len() - 1
+        })
+    };
+    let part_nets = part_points
+        .iter()
+        .map(|pads| pads.iter().map(|p| net_of(*p)).collect())
+        .collect();
+    let cables = cable_points
+        .into_iter()
+        .map(|(a, b, length)| (net_of(a), net_of(b), length))
+        .collect();
+    let wire_nets = wire_points.into_iter().map(&mut net_of).collect();
+    let senses = sense_points
+        .into_iter()
+        .map(|(a, b)| (net_of(a), net_of(b)))
+        .collect();
+    Netlist {
+        part_nets,
+        net_names,
+        cables,
+        wire_nets,
+        senses,
+    }
+}
+
+/// Values parts start with until they are edited
+const DEFAULT_RESISTANCE: f64 = 10e3;
+const DEFAULT_CAPACITANCE: f64 = 100e-9;
+const DEFAULT_INDUCTANCE: f64 = 10e-3;
+const DEFAULT_RAIL_VOLTAGE: f64 = 9.0;
+
+/// Starting value and unit of parts that have an editable value
+fn value_unit(component_type: ComponentType) -> Option<(f64, &'static str)> {
+    match component_type {
+        ComponentType::Resistor
+        | ComponentType::ResistorUS
+        | ComponentType::Potentiometer
+        | ComponentType::PotentiometerUS => Some((DEFAULT_RESISTANCE, "Ω")),
+        ComponentType::Capacitor => Some((DEFAULT_CAPACITANCE, "F")),
+        ComponentType::Inductor => Some((DEFAULT_INDUCTANCE, "H")),
+        ComponentType::Power => Some((DEFAULT_RAIL_VOLTAGE, "V")),
+        _ => None,
+    }
+}
+
+/// Value typed for a part, resistance, capacitance and inductance above zero
+fn part_value(component_type: ComponentType, text: &str) -> Result<f64, String> {
+    let v = parse_unit_value(text).ok_or_else(|| format!("{:?} is not a value", text))?;
+    if !v.is_finite() {
+        return Err(format!("{} is not finite", text));
+    }
+    if v <= 0.0 && component_type != ComponentType::Power {
+        return Err(format!("{} has to be above zero", text));
+    }
+    Ok(v)
+}
+
+/// Circuit model for the simulator (`sim model.json`), in its JSON format
+///
+/// Parts the simulator has no model for (Darlingtons, meters) are left
+/// out. Sense wires become ammeters and cables become cable sections. Each rail gets one source
+/// however many power symbols are on it; a rail shorted to ground or
+/// symbols on one rail asking for different voltages are errors.
+fn build_netlist(schematic: &Schematic, netlist: &Netlist) -> Result<Value, String> {
+    if let Some(short) = power_shorts(schematic, netlist).into_iter().next() {
+        return Err(short);
+    }
+    let mut components = vec![];
+    let mut rails: Vec<(usize, Option<f64>)> = vec![];
+    for (part, nets) in schematic.parts.iter().zip(&netlist.part_nets) {
+        let value = part.value;
+        let component = match (part.component_type, nets.as_slice()) {
+            (ComponentType::Resistor | ComponentType::ResistorUS, [a, b]) => {
+                serde_json::json!({ "type": "Resistor", "r": value, "nets": [a, b] })
+            }
+            (ComponentType::Potentiometer | ComponentType::PotentiometerUS, [a, w, b]) => {
+                serde_json::json!({
+                    "type": "Potentiometer",
+                    "total_r": value,
+                    "wiper": 0.5,
+                    "taper": "Linear",
+                    "nets": [a, w, b]
+                })
+            }
+            (ComponentType::Capacitor, [a, b]) => {
+                serde_json::json!({ "type": "Capacitor", "c": value, "nets": [a, b] })
+            }
+            (ComponentType::Inductor, [a, b]) => {
+                serde_json::json!({ "type": "Inductor", "l": value, "nets": [a, b] })
+            }
+            // pads are cathode then anode
+            (ComponentType::Diode, [k, a]) => {
+                serde_json::json!({ "type": "Diode", "nets": [a, k] })
+            }
+            (ComponentType::DiodeSchottky, [k, a]) => {
+                serde_json::json!({ "type": "Schottky", "nets": [a, k] })
+            }
+            (ComponentType::Led, [k, a]) => serde_json::json!({ "type": "Led", "nets": [a, k] }),
+            (ComponentType::DiodeZener, [k, a]) => {
+                serde_json::json!({ "type": "Zener", "nets": [a, k] })
+            }
+            (ComponentType::TransistorNPN, [b, c, e]) => {
+                serde_json::json!({ "type": "BJT", "nets": [b, c, e] })
+            }
+            (ComponentType::TransistorPNP, [b, c, e]) => serde_json::json!({
+                "type": "BJT",
+                "nets": [b, c, e],
+                "params": { "transistor_type": "PNP" }
+            }),
+            // pads are output, inverting, non-inverting
+            (ComponentType::OpAmp, [out, n, p]) => {
+                serde_json::json!({ "type": "OpAmp", "nets": [p, n, out] })
+            }
+            (ComponentType::Power, [rail]) => {
+                match rails.iter().find(|(net, _)| net == rail) {
+                    Some((_, v)) if *v == value => continue,
+                    Some(_) => {
+                        let name = &netlist.net_names[*rail];
+                        return Err(format!(
+                            "{} has power symbols with different voltages",
+                            name
+                        ));
                     }
+                    None => rails.push((*rail, value)),
                 }
+                serde_json::json!({ "type": "VoltageSource", "v": value, "nets": [rail, 0] })
             }
-            if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::P)) {
-                // Previous
-                self.part_selected = if self.part_selected > 0 {
-                    self.part_selected - 1
-                } else {
-                    0
-                };
-                println!(
-                    "- part_selected = {:?} / {:?}",
-                    self.part_selected,
-                    self.graphical_parts.len()
-                );
+            _ => continue,
+        };
+        components.push(component);
+    }
+    for (a, b) in &netlist.senses {
+        components.push(serde_json::json!({ "type": "Ammeter", "nets": [a, b] }));
+    }
+    for (a, b, length) in &netlist.cables {
+        components.push(serde_json::json!({ "type": "Cable", "length": length, "nets": [a, b] }));
+    }
+    Ok(serde_json::json!({ "nets": netlist.net_names, "components": components }))
+}
+
+/// SPICE netlist of the schematic, to take it into ngspice or LTspice
+fn to_spice(schematic: &Schematic, netlist: &Netlist) -> Result<String, String> {
+    Ok(spice::model_to_spice(&build_netlist(schematic, netlist)?))
+}
+
+/// How close to a pad or wire a probe has to be, in schematic units
+const PROBE_RADIUS: f32 = 20.0;
+
+/// Distance from point to line segment
+fn segment_distance(p: Pos2, a: Pos2, b: Pos2) -> f32 {
+    let ab = b - a;
+    let t = if ab.length_sq() > 0.0 {
+        ((p - a).dot(ab) / ab.length_sq()).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    p.distance(a + t * ab)
+}
+
+/// Net under a schematic point, looking at pads first and then wires
+fn net_at(
+    schematic: &Schematic,
+    draw_lib: &ComponentDrawLibrary,
+    netlist: &Netlist,
+    p: Pos2,
+) -> Option<usize> {
+    for (part, nets) in schematic.parts.iter().zip(&netlist.part_nets) {
+        let pads = draw_to_padpos(&draw_lib[&part.component_type], &part.transform(draw_lib));
+        // disabled parts have no nets
+        for (pad, net) in pads.iter().zip(nets) {
+            if pad.distance(p) <= PROBE_RADIUS {
+                return Some(*net);
             }
-            if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::N)) {
-                // Next
-                if self.graphical_parts.len() > 0 {
-                    self.part_selected = if self.part_selected < self.graphical_parts.len() - 1 {
-                        self.part_selected + 1
-                    } else {
-                        self.graphical_parts.len() - 1
-                    };
+        }
+    }
+    wire_at(schematic, p).map(|i| netlist.wire_nets[i])
+}
+
+/// Wires (by index) and pin positions on a net
+fn net_members(
+    schematic: &Schematic,
+    draw_lib: &ComponentDrawLibrary,
+    netlist: &Netlist,
+    net: usize,
+) -> (Vec<usize>, Vec<Pos2>) {
+    let wires = (0..schematic.wires.len())
+        .filter(|i| netlist.wire_nets[*i] == net)
+        .collect();
+    let mut pins = vec![];
+    for (part, nets) in schematic.parts.iter().zip(&netlist.part_nets) {
+        let pads = draw_to_padpos(&draw_lib[&part.component_type], &part.transform(draw_lib));
+        // disabled parts have no nets
+        for (pad, pad_net) in pads.iter().zip(nets) {
+            if *pad_net == net {
+                pins.push(*pad);
+            }
+        }
+    }
+    (wires, pins)
+}
+
+/// Width of wires, in library units
+const WIRE_WIDTH: f32 = 2.0;
+
+/// How close to a pin or wire end a new wire end snaps to it, in schematic units
+const SNAP_RADIUS: f32 = 30.0;
+
+/// Nearest pin or wire end within `SNAP_RADIUS` of a schematic point, or the point itself
+fn snap_to_pin(schematic: &Schematic, draw_lib: &ComponentDrawLibrary, p: Pos2) -> Pos2 {
+    let pads = schematic.parts.iter().flat_map(|part| {
+        draw_to_padpos(&draw_lib[&part.component_type], &part.transform(draw_lib))
+    });
+    let ends = schematic.wires.iter().flat_map(|w| [w.start, w.end]);
+    pads.chain(ends)
+        .filter(|q| q.distance(p) <= SNAP_RADIUS)
+        .min_by(|a, b| a.distance(p).total_cmp(&b.distance(p)))
+        .unwrap_or(p)
+}
+
+/// Index of the wire under a schematic point
+fn wire_at(schematic: &Schematic, p: Pos2) -> Option<usize> {
+    schematic
+        .wires
+        .iter()
+        .position(|w| segment_distance(p, w.start, w.end) <= PROBE_RADIUS)
+}
+
+/// Radius of the dots where wires join, in library units
+const JUNCTION_RADIUS: f32 = 8.0;
+
+/// Points where three or more wire ends and pins meet, in schematic units
+///
+/// Wires only connect at their ends, so wires crossing somewhere in the
+/// middle are not a junction.
+fn junctions(schematic: &Schematic, draw_lib: &ComponentDrawLibrary) -> Vec<Pos2> {
+    let pads = schematic
+        .parts
+        .iter()
+        .filter(|part| part.enabled)
+        .flat_map(|part| {
+            draw_to_padpos(&draw_lib[&part.component_type], &part.transform(draw_lib))
+        });
+    let ends = schematic.wires.iter().flat_map(|w| [w.start, w.end]);
+    let mut counts: Vec<((i64, i64), Pos2, usize)> = vec![];
+    for p in ends.chain(pads) {
+        match counts.iter_mut().find(|(key, _, _)| *key == point_key(p)) {
+            Some((_, _, n)) => *n += 1,
+            None => counts.push((point_key(p), p, 1)),
+        }
+    }
+    counts
+        .into_iter()
+        .filter(|(_, _, n)| *n >= 3)
+        .map(|(_, p, _)| p)
+        .collect()
+}
+
+impl MyApp {
+    /// Switch to the library at `library_path`, keeping the current one on errors
+    fn load_library(&mut self) {
+        let result = load_library(&self.library_path).and_then(|lib| {
+            // every placed part needs a symbol
+            match self
+                .schematic
+                .parts
+                .iter()
+                .find(|p| !lib.contains_key(&p.component_type))
+            {
+                Some(p) => Err(format!(
+                    "{}: no symbol for {:?}",
+                    self.library_path, p.component_type
+                )),
+                None => Ok(lib),
+            }
+        });
+        match result {
+            Ok(lib) => {
+                self.draw_lib = lib;
+                self.library_error = None;
+            }
+            Err(e) => self.library_error = Some(e),
+        }
+    }
+
+    /// Lay down a wire, or a cable in cable mode, as one edit
+    fn lay_wire(&mut self, start: Pos2, end: Pos2) {
+        self.history.push(self.schematic.clone());
+        if self.cabling {
+            self.schematic.add_cable(start, end, self.cable_length);
+        } else {
+            self.schematic.add_wire(start, end);
+        }
+    }
+
+    /// Show the AC result at `ac_path` in the Bode window
+    fn load_ac(&mut self) {
+        let result = std::fs::read_to_string(&self.ac_path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| parse_ac_csv(&text));
+        match result {
+            Ok(bode) => {
+                self.bode = bode;
+                self.ac_error = None;
+            }
+            Err(e) => self.ac_error = Some(format!("{}: {}", self.ac_path, e)),
+        }
+    }
+
+    /// Show the transient result at `transient_path` in the waveform panel
+    fn load_transient(&mut self) {
+        let result = std::fs::read_to_string(&self.transient_path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| parse_transient_csv(&text));
+        match result {
+            Ok(waveforms) => {
+                self.sim_results = waveforms;
+                self.transient_error = None;
+            }
+            Err(e) => self.transient_error = Some(format!("{}: {}", self.transient_path, e)),
+        }
+    }
+
+    /// Show the comparison at `compare_path` in the overlay window
+    fn load_compare(&mut self) {
+        let result = std::fs::read_to_string(&self.compare_path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| parse_compare_csv(&text));
+        match result {
+            Ok(overlay) => {
+                self.overlay = Some(overlay);
+                self.compare_error = None;
+            }
+            Err(e) => self.compare_error = Some(format!("{}: {}", self.compare_path, e)),
+        }
+    }
+
+    /// Side panel with the value of the selected part
+    ///
+    /// The text is read back when editing ends. A text that isn't a valid
+    /// value stays for fixing, with the reason under it.
+    fn value_panel(&mut self, ctx: &egui::Context) {
+        let Some(part) = self.schematic.parts.get_mut(self.part_selected) else {
+            return;
+        };
+        let (Some(value), Some((_, unit))) = (part.value, value_unit(part.component_type)) else {
+            return;
+        };
+        if !matches!(&self.value_text, Some((i, _)) if *i == self.part_selected) {
+            self.value_text = Some((self.part_selected, format_unit_value(value, unit)));
+            self.value_error = None;
+        }
+        let Some((_, text)) = &mut self.value_text else {
+            return;
+        };
+        let mut edited = false;
+        egui::SidePanel::right("value").show(ctx, |ui| {
+            ui.label(part.display_label());
+            if ui.text_edit_singleline(text).lost_focus() {
+                match part_value(part.component_type, text) {
+                    Ok(v) => {
+                        edited = part.value != Some(v);
+                        part.value = Some(v);
+                        *text = format_unit_value(v, unit);
+                        self.value_error = None;
+                    }
+                    Err(e) => self.value_error = Some(e),
                 }
-                println!(
-                    "+ part_selected = {:?} / {:?}",
-                    self.part_selected,
-                    self.graphical_parts.len()
-                );
             }
-            ui.add(heading("Circuit"));
-            let painter = ui.painter();
-            let color = Color32::WHITE;
-            let pad_color = Color32::YELLOW;
-            let pad_size = 10.0;
-            let global_transform = Transform::new(0.6, 0.0, 0.0, 0.0, false, false);
-            for (index, component) in self.graphical_parts.iter().enumerate() {
-                let draw_instr = &self.draw_lib[&component.component_type];
-                // swap order of transforms
-                let transform = Transform::new(
-                    1.0,
-                    3.14159 * 0.5 * component.angle,
-                    component.position.x,
-                    component.position.y,
-                    component.flip_x,
-                    component.flip_y,
-                )
-                .chain(&global_transform);
-                let color = if index == self.part_selected {
-                    Color32::RED
-                } else {
-                    color
-                };
-                painter.add(draw_to_shape(
-                    &draw_instr,
-                    &transform,
-                    color,
-                    pad_color,
-                    pad_size,
-                ));
+            if let Some(error) = &self.value_error {
+                ui.colored_label(Color32::RED, error);
             }
         });
+        if edited {
+            let mut before = self.schematic.clone();
+            before.parts[self.part_selected].value = Some(value);
+            self.history.push(before);
+        }
+    }
+
+    /// Keep the selection valid after the schematic was swapped by undo or redo
+    fn restored(&mut self) {
+        self.part_selected = self
+            .part_selected
+            .min(self.schematic.parts.len().saturating_sub(1));
+        self.selection.clear();
+        self.value_text = None;
+    }
+
+    /// Bode window with magnitude and phase of the last AC response
+    fn bode_window(&self, ctx: &egui::Context) {
+        if self.bode.is_empty() {
+            return;
+        }
+        let freqs: Vec<f64> = self.bode.iter().map(|p| p.0).collect();
+        let gain: Vec<f64> = self.bode.iter().map(|p| p.1).collect();
+        let phase: Vec<f64> = self.bode.iter().map(|p| p.2).collect();
+        egui::Window::new("Bode").show(ctx, |ui| {
+            ui.label("Magnitude (dB)");
+            bode_plot(
+                ui,
+                &freqs,
+                &gain,
+                10.0,
+                (-3.0, "-3 dB"),
+                Color32::LIGHT_BLUE,
+            );
+            ui.label("Phase (degrees)");
+            bode_plot(
+                ui,
+                &freqs,
+                &phase,
+                45.0,
+                (-45.0, "-45°"),
+                Color32::LIGHT_GREEN,
+            );
+        });
+    }
+
+    /// Window with both compared variants and their difference in one plot
+    fn compare_window(&self, ctx: &egui::Context) {
+        let Some(overlay) = &self.overlay else {
+            return;
+        };
+        let traces = [
+            (
+                &overlay.a[..],
+                TRACE_COLORS[0],
+                format!("a: {}", overlay.name),
+            ),
+            (
+                &overlay.b[..],
+                TRACE_COLORS[1],
+                format!("b: {}", overlay.name),
+            ),
+            (&overlay.diff[..], TRACE_COLORS[3], String::from("a - b")),
+        ];
+        egui::Window::new("Compare").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                for (_, color, label) in &traces {
+                    ui.colored_label(*color, label);
+                }
+            });
+            let lines: Vec<(&[f64], Color32)> = traces.iter().map(|t| (t.0, t.1)).collect();
+            overlay_plot(ui, &overlay.x, &lines, overlay.x_name == "freq");
+            ui.label(&overlay.x_name);
+        });
+    }
+
+    /// Waveform panel, returns its area so probes can be dropped on it
+    ///
+    /// Dragging a trace's legend entry out of the panel removes the trace.
+    fn plot_panel(&mut self, ui: &mut egui::Ui) -> Rect {
+        let rect = ui.max_rect();
+        let painter = ui.painter().clone();
+        painter.rect_filled(rect, 0.0, self.theme.background);
+        let values: Vec<Option<Vec<f64>>> = self
+            .traces
+            .iter()
+            .map(|name| self.sim_results.trace(name))
+            .collect();
+        let (lo, hi) = values
+            .iter()
+            .flatten()
+            .flatten()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| {
+                (lo.min(*v), hi.max(*v))
+            });
+        let span = if hi > lo { hi - lo } else { 1.0 };
+        let steps = self.sim_results.rows.len().max(2) - 1;
+        let mut removed = None;
+        ui.horizontal(|ui| {
+            for (n, (name, trace)) in self.traces.iter().zip(&values).enumerate() {
+                let color = TRACE_COLORS[n % TRACE_COLORS.len()];
+                let points: Vec<Pos2> = trace
+                    .iter()
+                    .flatten()
+                    .enumerate()
+                    .map(|(i, v)| {
+                        Pos2::new(
+                            rect.left() + rect.width() * i as f32 / steps as f32,
+                            rect.bottom() - rect.height() * ((v - lo) / span) as f32,
+                        )
+                    })
+                    .collect();
+                painter.add(Shape::line(points, Stroke::new(1.5, color)));
+                let label = egui::Label::new(egui::RichText::new(name).color(color))
+                    .sense(egui::Sense::drag());
+                let response = ui.add(label);
+                let outside = ui
+                    .ctx()
+                    .pointer_interact_pos()
+                    .is_some_and(|p| !rect.contains(p));
+                if response.drag_stopped() && outside {
+                    removed = Some(n);
+                }
+            }
+        });
+        if let Some(n) = removed {
+            self.traces.remove(n);
+        }
+        rect
+    }
+}
+
+impl eframe::App for MyApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let plot_rect = egui::TopBottomPanel::bottom("plot")
+            .exact_height(PLOT_HEIGHT)
+            .show(ctx, |ui| self.plot_panel(ui))
+            .inner;
+        self.bode_window(ctx);
+        self.compare_window(ctx);
+        self.value_panel(ctx);
+        // typed values shouldn't trigger the key shortcuts
+        let typing = ctx.wants_keyboard_input();
+        egui::CentralPanel::default().show(ctx, |ui| {
+            if !typing && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Delete))
+            {
+                let before = self.schematic.clone();
+                if self.schematic.remove_part(self.part_selected).is_some() {
+                    self.history.push(before);
+                    // indices after the removed part have shifted
+                    self.selection.clear();
+                    self.value_text = None;
+                }
+                if !self.schematic.parts.is_empty()
+                    && self.part_selected > self.schematic.parts.len() - 1
+                {
+                    self.part_selected = self.schematic.parts.len() - 1;
+                }
+            }
+            if !typing && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::L)) {
+                // Lock / unlock
+                let before = self.schematic.clone();
+                if let Some(part) = self.schematic.parts.get_mut(self.part_selected) {
+                    self.history.push(before);
+                    part.locked = !part.locked;
+                }
+            }
+            if !typing && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::E)) {
+                // Enable / disable
+                let before = self.schematic.clone();
+                if let Some(part) = self.schematic.parts.get_mut(self.part_selected) {
+                    self.history.push(before);
+                    part.enabled = !part.enabled;
+                }
+            }
+            if !typing && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::S)) {
+                // Add to / remove from selection
+                match self.selection.iter().position(|i| *i == self.part_selected) {
+                    Some(n) => {
+                        self.selection.remove(n);
+                    }
+                    None => self.selection.push(self.part_selected),
+                }
+            }
+            if !typing && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::I)) {
+                // Current sense on / off for the wire under the pointer
+                let p = ctx.pointer_hover_pos().map(|p| self.view.to_schematic(p));
+                if let Some(index) = p.and_then(|p| wire_at(&self.schematic, p)) {
+                    self.history.push(self.schematic.clone());
+                    self.schematic.toggle_sense(index);
+                }
+            }
+            if !typing && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::W)) {
+                // Wire mode on / off
+                self.wiring = !self.wiring;
+                self.cabling = false;
+                self.wire_start = None;
+            }
+            if !typing && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::C)) {
+                // Cable mode on / off
+                self.cabling = !self.cabling;
+                self.wiring = self.cabling;
+                self.wire_start = None;
+            }
+            if !typing && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::M)) {
+                // Measure mode on / off
+                self.measuring = !self.measuring;
+                self.ruler = None;
+            }
+            if !typing && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::P)) {
+                // Previous
+                self.part_selected = if self.part_selected > 0 {
+                    self.part_selected - 1
+                } else {
+                    0
+                };
+                println!(
+                    "- part_selected = {:?} / {:?}",
+                    self.part_selected,
+                    self.schematic.parts.len()
+                );
+            }
+            if !typing && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::N)) {
+                // Next
+                if !self.schematic.parts.is_empty() {
+                    self.part_selected = if self.part_selected < self.schematic.parts.len() - 1 {
+                        self.part_selected + 1
+                    } else {
+                        self.schematic.parts.len() - 1
+                    };
+                }
+                println!(
+                    "+ part_selected = {:?} / {:?}",
+                    self.part_selected,
+                    self.schematic.parts.len()
+                );
+            }
+            if !typing && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::R)) {
+                // Rotate the part about to be placed, or else the selected one
+                match &mut self.placing {
+                    Some(placement) => placement.rotate(),
+                    None => {
+                        let before = self.schematic.clone();
+                        if self.schematic.rotate_part(self.part_selected) {
+                            self.history.push(before);
+                        }
+                    }
+                }
+            }
+            if !typing && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::X)) {
+                // Flip left to right
+                let before = self.schematic.clone();
+                if self.schematic.flip_part(self.part_selected, true) {
+                    self.history.push(before);
+                }
+            }
+            if !typing && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Y)) {
+                // Flip top to bottom
+                let before = self.schematic.clone();
+                if self.schematic.flip_part(self.part_selected, false) {
+                    self.history.push(before);
+                }
+            }
+            if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Escape)) {
+                self.placing = None;
+            }
+            // text fields have their own undo
+            let undo = egui::Modifiers::COMMAND;
+            let redo = undo | egui::Modifiers::SHIFT;
+            if !typing && ctx.input_mut(|i| i.consume_key(redo, egui::Key::Z)) {
+                if self.history.redo(&mut self.schematic) {
+                    self.restored();
+                }
+            } else if !typing
+                && ctx.input_mut(|i| i.consume_key(undo, egui::Key::Z))
+                && self.history.undo(&mut self.schematic)
+            {
+                self.restored();
+            }
+            ui.add(heading("Circuit"));
+            let netlist = extract_netlist(&self.schematic, &self.draw_lib, &self.ground_net);
+            ui.label(status_line(&self.schematic, &netlist));
+            for short in power_shorts(&self.schematic, &netlist) {
+                ui.colored_label(Color32::RED, short);
+            }
+            let errors = [
+                &self.library_error,
+                &self.transient_error,
+                &self.ac_error,
+                &self.compare_error,
+            ];
+            for error in errors.into_iter().flatten() {
+                ui.colored_label(Color32::RED, error);
+            }
+            for (a, b) in &netlist.senses {
+                ui.label(format!(
+                    "I({} -> {})",
+                    netlist.net_names[*a], netlist.net_names[*b]
+                ));
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Reset view").clicked() {
+                    self.reset_view();
+                }
+                if ui.button("Reset simulation").clicked() {
+                    self.reset_sim();
+                }
+                ui.add(
+                    egui::DragValue::new(&mut self.grid)
+                        .range(0.0..=500.0)
+                        .prefix("Grid "),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut self.cable_length)
+                        .range(0.1..=1000.0)
+                        .prefix("Cable ")
+                        .suffix(" m"),
+                );
+                let mut print = self.theme == Theme::PRINT;
+                if ui.checkbox(&mut print, "Print theme").changed() {
+                    self.theme = if print { Theme::PRINT } else { Theme::DARK };
+                }
+                ui.text_edit_singleline(&mut self.library_path);
+                if ui.button("Load library").clicked() {
+                    self.load_library();
+                }
+                ui.text_edit_singleline(&mut self.transient_path);
+                if ui.button("Load transient").clicked() {
+                    self.load_transient();
+                }
+                ui.text_edit_singleline(&mut self.ac_path);
+                if ui.button("Load AC").clicked() {
+                    self.load_ac();
+                }
+                ui.text_edit_singleline(&mut self.compare_path);
+                if ui.button("Load compare").clicked() {
+                    self.load_compare();
+                }
+                if ui.button("Export model").clicked() {
+                    let written = build_netlist(&self.schematic, &netlist).and_then(|model| {
+                        std::fs::write("model.json", model.to_string()).map_err(|e| e.to_string())
+                    });
+                    if let Err(e) = written {
+                        eprintln!("Could not export model.json: {}", e);
+                    }
+                }
+                if ui.button("Export SPICE").clicked() {
+                    let written = to_spice(&self.schematic, &netlist).and_then(|text| {
+                        std::fs::write("circuit.cir", text).map_err(|e| e.to_string())
+                    });
+                    if let Err(e) = written {
+                        eprintln!("Could not export circuit.cir: {}", e);
+                    }
+                }
+                if ui.button("Export SVG").clicked() {
+                    let shapes = ctx.fonts(|fonts| {
+                        schematic_shapes(
+                            &self.schematic,
+                            &self.draw_lib,
+                            &Transform::new(1.0, 0.0, 0.0, 0.0, false, false),
+                            &self.theme,
+                            None,
+                            Some(fonts),
+                        )
+                    });
+                    let svg = shapes_to_svg(&shapes, self.theme.background);
+                    if let Err(e) = std::fs::write("schematic.svg", svg) {
+                        eprintln!("Could not export schematic.svg: {}", e);
+                    }
+                }
+            });
+            ui.horizontal_wrapped(|ui| {
+                let available = PALETTE
+                    .into_iter()
+                    .filter(|t| self.draw_lib.contains_key(t));
+                for component_type in available {
+                    let armed = self
+                        .placing
+                        .is_some_and(|p| p.component_type == component_type);
+                    let name = format!("{:?}", component_type);
+                    if ui.selectable_label(armed, name).clicked() {
+                        self.placing = (!armed).then(|| Placement::new(component_type));
+                    }
+                }
+            });
+            if let Some(placement) = self.placing {
+                ui.label(format!(
+                    "Click to place {:?}, R rotates, Esc cancels",
+                    placement.component_type
+                ));
+            }
+            if self.selection.len() > 1 {
+                ui.horizontal(|ui| {
+                    for (name, align) in [
+                        ("Align left", Align::Left),
+                        ("Align right", Align::Right),
+                        ("Align top", Align::Top),
+                        ("Align bottom", Align::Bottom),
+                    ] {
+                        if ui.button(name).clicked() {
+                            self.history.push(self.schematic.clone());
+                            self.schematic.align(&self.selection, align);
+                        }
+                    }
+                    if ui.button("Distribute horizontally").clicked() {
+                        self.history.push(self.schematic.clone());
+                        self.schematic.distribute(&self.selection, true);
+                    }
+                    if ui.button("Distribute vertically").clicked() {
+                        self.history.push(self.schematic.clone());
+                        self.schematic.distribute(&self.selection, false);
+                    }
+                });
+            }
+            // canvas takes what is left below the toolbar and palette
+            let canvas = ui.interact(
+                ui.available_rect_before_wrap(),
+                ui.id().with("canvas"),
+                egui::Sense::click_and_drag(),
+            );
+            let painter = ui.painter_at(canvas.rect);
+            // middle-drag pans, mouse wheel zooms around the pointer
+            if canvas.dragged_by(egui::PointerButton::Middle) {
+                self.view.offset += canvas.drag_delta();
+            }
+            if let Some(p) = canvas.hover_pos() {
+                let scroll = ctx.input(|i| i.smooth_scroll_delta.y);
+                if scroll != 0.0 {
+                    self.view.zoom_at(p, ZOOM_PER_SCROLL.powf(scroll));
+                }
+            }
+            let global_transform = self.view.transform();
+            // wire end under the pointer, snapped to pins or else the grid
+            let wire_end = canvas.interact_pointer_pos().map(|p| {
+                let p = self.view.to_schematic(p);
+                let pin = snap_to_pin(&self.schematic, &self.draw_lib, p);
+                if pin == p {
+                    snap_to_grid(p, self.grid)
+                } else {
+                    pin
+                }
+            });
+            if let Some(placement) = self.placing {
+                if canvas.clicked_by(egui::PointerButton::Primary)
+                    && let Some(p) = canvas.interact_pointer_pos()
+                {
+                    let part = placement.part_at(&self.view, p, self.grid, &self.schematic);
+                    self.history.push(self.schematic.clone());
+                    self.part_selected = self.schematic.add_part(part);
+                    self.placing = None;
+                }
+            } else if self.wiring {
+                if canvas.drag_started_by(egui::PointerButton::Primary) {
+                    self.wire_start = wire_end;
+                }
+                if canvas.drag_stopped_by(egui::PointerButton::Primary)
+                    && let (Some(start), Some(end)) = (self.wire_start.take(), wire_end)
+                    && start != end
+                {
+                    self.lay_wire(start, end);
+                }
+            } else if self.measuring && canvas.dragged_by(egui::PointerButton::Primary) {
+                let start = ctx.input(|i| i.pointer.press_origin());
+                if let (Some(start), Some(end)) = (start, canvas.interact_pointer_pos()) {
+                    self.ruler = Some(Ruler::from_screen(&self.view, start, end));
+                }
+            } else if canvas.dragged_by(egui::PointerButton::Primary) {
+                // the whole drag is one edit
+                if canvas.drag_started() {
+                    self.history.push(self.schematic.clone());
+                    self.drag_origin = self
+                        .schematic
+                        .parts
+                        .get(self.part_selected)
+                        .map(|p| p.position);
+                }
+                // follow the pointer from where the part started, on the grid
+                let start = ctx.input(|i| i.pointer.press_origin());
+                if let (Some(origin), Some(start), Some(end), Some(part)) = (
+                    self.drag_origin,
+                    start,
+                    canvas.interact_pointer_pos(),
+                    self.schematic.parts.get(self.part_selected),
+                ) {
+                    let moved = (end - start) / global_transform.apply_scalar(1.0);
+                    let delta = snap_to_grid(origin + moved, self.grid) - part.position;
+                    self.schematic.move_part(self.part_selected, delta);
+                }
+            }
+            // right-drag from a net into the waveform panel adds a trace
+            if canvas.drag_started_by(egui::PointerButton::Secondary) {
+                self.probing = canvas.interact_pointer_pos().and_then(|p| {
+                    net_at(
+                        &self.schematic,
+                        &self.draw_lib,
+                        &netlist,
+                        self.view.to_schematic(p),
+                    )
+                });
+            }
+            if canvas.drag_stopped_by(egui::PointerButton::Secondary) {
+                let dropped = ctx
+                    .pointer_interact_pos()
+                    .is_some_and(|p| plot_rect.contains(p));
+                let probed = self.probing.take().map(|net| &netlist.net_names[net]);
+                if let Some(name) = probed.filter(|name| dropped && !self.traces.contains(name)) {
+                    self.traces.push(name.clone());
+                }
+            }
+            painter.rect_filled(canvas.rect, 0.0, self.theme.background);
+            for p in grid_dots(&self.view, canvas.rect, self.grid) {
+                painter.circle_filled(p, 1.0, self.theme.grid);
+            }
+            painter.extend(ctx.fonts(|fonts| {
+                schematic_shapes(
+                    &self.schematic,
+                    &self.draw_lib,
+                    &global_transform,
+                    &self.theme,
+                    Some(self.part_selected),
+                    Some(fonts),
+                )
+            }));
+            // everything connected to the pin or wire under the pointer
+            let hovered = canvas.hover_pos().and_then(|p| {
+                let p = self.view.to_schematic(p);
+                net_at(&self.schematic, &self.draw_lib, &netlist, p)
+            });
+            if let Some(net) = hovered {
+                let (wires, pins) = net_members(&self.schematic, &self.draw_lib, &netlist, net);
+                let width = global_transform.apply_scalar(WIRE_WIDTH);
+                for wire in wires.iter().map(|i| &self.schematic.wires[*i]) {
+                    painter.line_segment(
+                        [wire.start, wire.end].map(|p| global_transform.apply(&p)),
+                        Stroke::new(width, self.theme.highlight),
+                    );
+                }
+                for pin in pins {
+                    painter.circle_filled(
+                        global_transform.apply(&pin),
+                        global_transform.apply_scalar(PAD_SIZE),
+                        self.theme.highlight,
+                    );
+                }
+            }
+            if let (Some(start), Some(end)) = (self.wire_start, wire_end) {
+                painter.line_segment(
+                    [global_transform.apply(&start), global_transform.apply(&end)],
+                    Stroke::new(
+                        global_transform.apply_scalar(WIRE_WIDTH),
+                        self.theme.selected,
+                    ),
+                );
+            }
+            if let Some(ruler) = &self.ruler {
+                painter.extend(ruler.shapes(&global_transform, self.theme.selected));
+                painter.text(
+                    global_transform.apply(&ruler.end),
+                    egui::Align2::LEFT_BOTTOM,
+                    format!("{:.1}", ruler.length()),
+                    egui::FontId::proportional(14.0),
+                    self.theme.selected,
+                );
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ground_nets() -> Result<(), String> {
+        let draw_lib = load_draw_library();
+        // Resistors are vertical with pads at y -150 and +150
+        let parts = vec![
+            GraphicalComponent::new(
+                ComponentType::Resistor,
+                Pos2::new(0.0, 0.0),
+                0.0,
+                false,
+                false,
+            ),
+            GraphicalComponent::new(
+                ComponentType::Resistor,
+                Pos2::new(500.0, 0.0),
+                0.0,
+                false,
+                false,
+            ),
+            GraphicalComponent::new(
+                ComponentType::Resistor,
+                Pos2::new(1000.0, 0.0),
+                0.0,
+                false,
+                false,
+            ),
+            GraphicalComponent::new_ground("AGND", Pos2::new(0.0, 150.0)),
+            GraphicalComponent::new_ground("DGND", Pos2::new(500.0, 150.0)),
+            GraphicalComponent::new_ground("AGND", Pos2::new(1000.0, 150.0)),
+        ];
+        let mut schematic = Schematic {
+            parts,
+            wires: vec![],
+        };
+        let netlist = extract_netlist(&schematic, &draw_lib, GROUND_NET);
+        let nets = &netlist.part_nets;
+        // same-named grounds unify, different names stay apart
+        assert_eq!(nets[0][1], nets[2][1]);
+        assert_ne!(nets[0][1], nets[1][1]);
+        assert_eq!(netlist.net_names[nets[0][1]], "AGND");
+        assert_eq!(netlist.net_names[nets[1][1]], "DGND");
+        // neither of them is the simulator ground
+        assert_ne!(nets[0][1], 0);
+        assert_ne!(nets[1][1], 0);
+        // unless configured that way
+        let netlist = extract_netlist(&schematic, &draw_lib, "AGND");
+        assert_eq!(netlist.part_nets[2][1], 0);
+        assert_ne!(netlist.part_nets[1][1], 0);
+        // a wire joins AGND and DGND
+        schematic.add_wire(Pos2::new(0.0, 150.0), Pos2::new(500.0, 150.0));
+        let netlist = extract_netlist(&schematic, &draw_lib, "AGND");
+        assert_eq!(netlist.part_nets[1][1], 0);
+        // top pads all float on their own nets
+        schematic.parts.truncate(3);
+        schematic.wires.clear();
+        let netlist = extract_netlist(&schematic, &draw_lib, GROUND_NET);
+        assert_eq!(netlist.net_names.len(), 7);
+        // a cable keeps both ends apart but records the connection
+        let (a, b) = (Pos2::new(0.0, -150.0), Pos2::new(500.0, -150.0));
+        schematic.add_cable(a, b, 3.0);
+        let netlist = extract_netlist(&schematic, &draw_lib, GROUND_NET);
+        let nets = &netlist.part_nets;
+        assert_ne!(nets[0][0], nets[1][0]);
+        assert_eq!(netlist.cables, vec![(nets[0][0], nets[1][0], 3.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_placement() -> Result<(), String> {
+        let view = View {
+            scale: 2.0,
+            offset: egui::vec2(100.0, 50.0),
+        };
+        let mut placement = Placement::new(ComponentType::Resistor);
+        placement.rotate();
+        let mut schematic = Schematic::default();
+        let part = placement.part_at(&view, Pos2::new(300.0, 250.0), 0.0, &schematic);
+        assert_eq!(part.component_type, ComponentType::Resistor);
+        assert_eq!(part.position, Pos2::new(100.0, 100.0));
+        assert_eq!(part.angle, 1.0);
+        assert_eq!(part.value, Some(DEFAULT_RESISTANCE));
+        // four turns come back around
+        for _ in 0..3 {
+            placement.rotate();
+        }
+        assert_eq!(placement.angle, 0.0);
+        // supply symbols get their nets
+        let ground = Placement::new(ComponentType::Ground);
+        let part = ground.part_at(&view, Pos2::new(100.0, 50.0), 0.0, &schematic);
+        assert_eq!(part.label, GROUND_NET);
+        assert_eq!(part.position, Pos2::ZERO);
+        // each power symbol placed gets its own rail
+        let power = Placement::new(ComponentType::Power);
+        for name in ["VCC", "VCC2", "VCC3"] {
+            let part = power.part_at(&view, Pos2::new(100.0, 50.0), 0.0, &schematic);
+            assert_eq!(part.label, name);
+            schematic.add_part(part);
+        }
+        schematic.parts.remove(0);
+        let part = power.part_at(&view, Pos2::new(100.0, 50.0), 0.0, &schematic);
+        assert_eq!(part.label, "VCC");
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotate_flip() -> Result<(), String> {
+        let draw_lib = load_draw_library();
+        let mut schematic = Schematic::default();
+        let index = schematic.add_part(GraphicalComponent::new(
+            ComponentType::Capacitor,
+            Pos2::new(200.0, 100.0),
+            0.0,
+            false,
+            false,
+        ));
+        let pads = |schematic: &Schematic| {
+            let part = &schematic.parts[index];
+            draw_to_padpos(&draw_lib[&part.component_type], &part.transform(&draw_lib))
+        };
+        let before = pads(&schematic);
+        // a quarter turn clockwise on screen about the drawing center
+        let center =
+            Pos2::new(200.0, 100.0) + draw_center(&draw_lib[&ComponentType::Capacitor]).to_vec2();
+        assert!(schematic.rotate_part(index));
+        for (p, q) in before.iter().zip(pads(&schematic)) {
+            let d = *p - center;
+            let expected = center + egui::vec2(-d.y, d.x);
+            assert!(expected.distance(q) < 1e-3, "{:?} {:?}", expected, q);
+        }
+        // four turns and two flips each way come back around
+        for _ in 0..3 {
+            schematic.rotate_part(index);
+        }
+        for horizontal in [true, true, false, false] {
+            schematic.flip_part(index, horizontal);
+        }
+        for (p, q) in before.iter().zip(pads(&schematic)) {
+            assert!(p.distance(q) < 1e-3);
+        }
+        // one flip swaps the pads of the vertical capacitor
+        schematic.flip_part(index, false);
+        let flipped = pads(&schematic);
+        assert!(flipped[0].distance(before[1]) < 1e-3);
+        // locked parts stay put
+        schematic.parts[index].locked = true;
+        assert!(!schematic.rotate_part(index));
+        assert!(!schematic.flip_part(index, true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_grid_snap() -> Result<(), String> {
+        let view = View {
+            scale: 0.5,
+            offset: egui::vec2(10.0, 20.0),
+        };
+        // cursor at schematic (130, -80) lands on (150, -100)
+        let part = Placement::new(ComponentType::Capacitor).part_at(
+            &view,
+            Pos2::new(75.0, -20.0),
+            50.0,
+            &Schematic::default(),
+        );
+        assert_eq!(part.position, Pos2::new(150.0, -100.0));
+        assert_eq!(
+            snap_to_grid(Pos2::new(24.0, 26.0), 50.0),
+            Pos2::new(0.0, 50.0)
+        );
+        // no grid leaves the point alone
+        assert_eq!(
+            snap_to_grid(Pos2::new(24.0, 26.0), 0.0),
+            Pos2::new(24.0, 26.0)
+        );
+        // dots cover the visible area, schematic 0..200 by 0..100 here
+        let rect = Rect::from_min_max(Pos2::new(10.0, 20.0), Pos2::new(110.0, 70.0));
+        let dots = grid_dots(&view, rect, 50.0);
+        assert_eq!(dots.len(), 5 * 3);
+        assert_eq!(dots[0], Pos2::new(10.0, 20.0));
+        // too dense to draw when zoomed out
+        assert!(grid_dots(&view, rect, 10.0).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_undo_redo() -> Result<(), String> {
+        let mut schematic = Schematic::default();
+        schematic.add_part(GraphicalComponent::new_ground(GROUND_NET, Pos2::ZERO));
+        let original = schematic.clone();
+        let mut history = History::default();
+        // place, then delete the first part
+        history.push(schematic.clone());
+        let placed = Placement::new(ComponentType::Resistor).part_at(
+            &View::default(),
+            Pos2::ZERO,
+            DEFAULT_GRID,
+            &schematic,
+        );
+        schematic.add_part(placed);
+        history.push(schematic.clone());
+        schematic.remove_part(0);
+        let edited = schematic.clone();
+        assert!(history.undo(&mut schematic));
+        assert!(history.undo(&mut schematic));
+        assert_eq!(schematic, original);
+        assert!(!history.undo(&mut schematic));
+        // redo both, a new edit drops what was undone
+        assert!(history.redo(&mut schematic));
+        assert!(history.redo(&mut schematic));
+        assert_eq!(schematic, edited);
+        history.undo(&mut schematic);
+        history.push(schematic.clone());
+        assert!(!history.redo(&mut schematic));
+        // only the last UNDO_DEPTH edits are kept
+        for _ in 0..2 * UNDO_DEPTH {
+            history.push(schematic.clone());
+        }
+        assert_eq!(history.undo.len(), UNDO_DEPTH);
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_netlist() -> Result<(), String> {
+        let draw_lib = load_draw_library();
+        // VCC -> R1 -> R2 -> GND divider, R2 off to the side on a wire
+        let mut schematic = Schematic::default();
+        schematic.add_part(GraphicalComponent::new_power("VCC", Pos2::new(0.0, -150.0)));
+        for x in [0.0, 300.0] {
+            schematic.add_part(GraphicalComponent::new(
+                ComponentType::Resistor,
+                Pos2::new(x, 0.0),
+                0.0,
+                false,
+                false,
+            ));
+        }
+        schematic.add_part(GraphicalComponent::new_ground(
+            GROUND_NET,
+            Pos2::new(300.0, 150.0),
+        ));
+        schematic.add_wire(Pos2::new(0.0, 150.0), Pos2::new(300.0, -150.0));
+        let netlist = extract_netlist(&schematic, &draw_lib, GROUND_NET);
+        assert_eq!(netlist.net_count(), 3);
+        let model = build_netlist(&schematic, &netlist)?;
+        assert_eq!(model["nets"], serde_json::json!(["GND", "VCC", "n2"]));
+        let components = model["components"].as_array().ok_or("no components")?;
+        let summary: Vec<(&str, &Value)> = components
+            .iter()
+            .map(|c| (c["type"].as_str().unwrap_or_default(), &c["nets"]))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("VoltageSource", &serde_json::json!([1, 0])),
+                ("Resistor", &serde_json::json!([1, 2])),
+                ("Resistor", &serde_json::json!([2, 0])),
+            ]
+        );
+        assert_eq!(components[1]["r"], DEFAULT_RESISTANCE);
+        // edited values go into the model
+        schematic.parts[2].value = parse_unit_value("4.7k");
+        let model = build_netlist(&schematic, &netlist)?;
+        assert_eq!(model["components"][2]["r"], 4700.0);
+        // same circuit as a SPICE netlist
+        let spice = to_spice(&schematic, &netlist)?;
+        let lines: Vec<&str> = spice.lines().skip(1).collect();
+        assert_eq!(
+            lines,
+            vec!["V1 1 0 DC 9", "R1 1 2 10k", "R2 2 0 4.7k", ".end"]
+        );
+        // a second VCC symbol on the rail adds no second source
+        schematic.add_part(GraphicalComponent::new_power("VCC", Pos2::new(600.0, 0.0)));
+        let netlist = extract_netlist(&schematic, &draw_lib, GROUND_NET);
+        let model = build_netlist(&schematic, &netlist)?;
+        let sources = model["components"]
+            .as_array()
+            .ok_or("no components")?
+            .iter()
+            .filter(|c| c["type"] == "VoltageSource")
+            .count();
+        assert_eq!(sources, 1);
+        // unless it asks for another voltage
+        schematic.parts[4].value = Some(5.0);
+        let err = build_netlist(&schematic, &netlist).err();
+        assert_eq!(
+            err.as_deref(),
+            Some("VCC has power symbols with different voltages")
+        );
+        // a rail on ground has no solution
+        schematic.parts[4] = GraphicalComponent::new_power(GROUND_NET, Pos2::new(600.0, 0.0));
+        let netlist = extract_netlist(&schematic, &draw_lib, GROUND_NET);
+        let err = build_netlist(&schematic, &netlist).err();
+        assert_eq!(err.as_deref(), Some("GND is shorted to GND"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_cable_export() -> Result<(), String> {
+        // 10k resistor driving a resistor at the far end of a cable
+        let mut app = MyApp {
+            schematic: Schematic::default(),
+            ..MyApp::default()
+        };
+        for x in [0.0, 300.0] {
+            app.schematic.add_part(GraphicalComponent::new(
+                ComponentType::Resistor,
+                Pos2::new(x, 0.0),
+                0.0,
+                false,
+                false,
+            ));
+        }
+        app.cabling = true;
+        app.cable_length = 10.0;
+        app.lay_wire(Pos2::new(0.0, 150.0), Pos2::new(300.0, -150.0));
+        assert_eq!(app.schematic.wires[0].cable, Some(10.0));
+        assert!(app.history.undo(&mut app.schematic));
+        assert!(app.schematic.wires.is_empty());
+        app.lay_wire(Pos2::new(0.0, 150.0), Pos2::new(300.0, -150.0));
+        let netlist = extract_netlist(&app.schematic, &app.draw_lib, GROUND_NET);
+        let nets = &netlist.part_nets;
+        assert_ne!(nets[0][1], nets[1][0]);
+        // the cable between the resistors is simulated
+        let model = build_netlist(&app.schematic, &netlist)?;
+        let components = model["components"].as_array().ok_or("no components")?;
+        assert_eq!(components.len(), 3);
+        assert_eq!(
+            components[2],
+            serde_json::json!({ "type": "Cable", "length": 10.0, "nets": [nets[0][1], nets[1][0]] })
+        );
+        // plain wires again out of cable mode
+        app.cabling = false;
+        app.lay_wire(Pos2::new(0.0, -150.0), Pos2::new(300.0, 150.0));
+        assert_eq!(app.schematic.wires[1].cable, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_power_short() -> Result<(), String> {
+        let draw_lib = load_draw_library();
+        // VCC through a resistor to ground is fine
+        let mut schematic = Schematic::default();
+        schematic.add_part(GraphicalComponent::new(
+            ComponentType::Resistor,
+            Pos2::new(0.0, 0.0),
+            0.0,
+            false,
+            false,
+        ));
+        schematic.add_part(GraphicalComponent::new_power("VCC", Pos2::new(0.0, -150.0)));
+        schematic.add_part(GraphicalComponent::new_ground(
+            GROUND_NET,
+            Pos2::new(0.0, 150.0),
+        ));
+        let netlist = extract_netlist(&schematic, &draw_lib, GROUND_NET);
+        assert_eq!(netlist.net_names[netlist.part_nets[0][0]], "VCC");
+        assert!(power_shorts(&schematic, &netlist).is_empty());
+        // another VCC symbol wired straight to ground is not
+        schematic.add_part(GraphicalComponent::new_power("VCC", Pos2::new(500.0, 0.0)));
+        schematic.add_wire(Pos2::new(500.0, 0.0), Pos2::new(0.0, 150.0));
+        let netlist = extract_netlist(&schematic, &draw_lib, GROUND_NET);
+        assert_eq!(
+            power_shorts(&schematic, &netlist),
+            vec![String::from("VCC is shorted to GND")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_schematic_headless() -> Result<(), String> {
+        let draw_lib = load_draw_library();
+        // divider: R1 from top net to middle, R2 wired from middle to ground
+        let mut schematic = Schematic::default();
+        let r1 = schematic.add_part(GraphicalComponent::new(
+            ComponentType::Resistor,
+            Pos2::new(0.0, 0.0),
+            0.0,
+            false,
+            false,
+        ));
+        let r2 = schematic.add_part(GraphicalComponent::new(
+            ComponentType::Resistor,
+            Pos2::new(400.0, 300.0),
+            0.0,
+            false,
+            false,
+        ));
+        let gnd = schematic.add_part(GraphicalComponent::new_ground(
+            GROUND_NET,
+            Pos2::new(400.0, 450.0),
+        ));
+        schematic.add_wire(Pos2::new(0.0, 150.0), Pos2::new(400.0, 150.0));
+        let netlist = extract_netlist(&schematic, &draw_lib, GROUND_NET);
+        let nets = &netlist.part_nets;
+        assert_eq!(nets[r1][1], nets[r2][0]);
+        assert_eq!(nets[r2][1], 0);
+        assert_eq!(nets[gnd][0], 0);
+        assert_ne!(nets[r1][0], nets[r1][1]);
+        assert_eq!(netlist.net_count(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_wire_snap() -> Result<(), String> {
+        let draw_lib = load_draw_library();
+        // resistor with pads at y -150 and +150
+        let mut schematic = Schematic::default();
+        schematic.add_part(GraphicalComponent::new(
+            ComponentType::Resistor,
+            Pos2::new(0.0, 0.0),
+            0.0,
+            false,
+            false,
+        ));
+        let snap = |schematic: &Schematic, p| snap_to_pin(schematic, &draw_lib, p);
+        assert_eq!(
+            snap(&schematic, Pos2::new(10.0, 140.0)),
+            Pos2::new(0.0, 150.0)
+        );
+        assert_eq!(
+            snap(&schematic, Pos2::new(300.0, 0.0)),
+            Pos2::new(300.0, 0.0)
+        );
+        // wire ends snap too
+        schematic.add_wire(Pos2::new(0.0, 150.0), Pos2::new(400.0, 150.0));
+        assert_eq!(
+            snap(&schematic, Pos2::new(390.0, 160.0)),
+            Pos2::new(400.0, 150.0)
+        );
+        // wires are drawn first, under the part
+        let identity = Transform::new(1.0, 0.0, 0.0, 0.0, false, false);
+        let shapes = schematic_shapes(&schematic, &draw_lib, &identity, &Theme::DARK, None, None);
+        assert_eq!(shapes.len(), 2);
+        match &shapes[0] {
+            Shape::LineSegment { points, .. } => {
+                assert_eq!(*points, [Pos2::new(0.0, 150.0), Pos2::new(400.0, 150.0)])
+            }
+            other => return Err(format!("expected wire, got {:?}", other)),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_junctions() -> Result<(), String> {
+        let draw_lib = load_draw_library();
+        // T of three wires
+        let mut schematic = Schematic::default();
+        let center = Pos2::new(100.0, 100.0);
+        for end in [(0.0, 100.0), (200.0, 100.0), (100.0, 200.0)] {
+            schematic.add_wire(center, Pos2::new(end.0, end.1));
+        }
+        assert_eq!(junctions(&schematic, &draw_lib), vec![center]);
+        // plus of four wires is still one junction
+        schematic.add_wire(center, Pos2::new(100.0, 0.0));
+        assert_eq!(junctions(&schematic, &draw_lib), vec![center]);
+        let identity = Transform::new(1.0, 0.0, 0.0, 0.0, false, false);
+        let shapes = schematic_shapes(&schematic, &draw_lib, &identity, &Theme::DARK, None, None);
+        let dots = shapes
+            .iter()
+            .filter(|s| matches!(s, Shape::Circle(c) if c.center == center))
+            .count();
+        assert_eq!(dots, 1);
+        // two wires crossing in the middle don't connect
+        let mut crossing = Schematic::default();
+        crossing.add_wire(Pos2::new(0.0, 100.0), Pos2::new(200.0, 100.0));
+        crossing.add_wire(Pos2::new(100.0, 0.0), Pos2::new(100.0, 200.0));
+        assert!(junctions(&crossing, &draw_lib).is_empty());
+        // a pin with two wires on it is a junction too
+        let mut pin = Schematic::default();
+        pin.add_part(GraphicalComponent::new(
+            ComponentType::Resistor,
+            Pos2::new(0.0, 0.0),
+            0.0,
+            false,
+            false,
+        ));
+        pin.add_wire(Pos2::new(0.0, 150.0), Pos2::new(200.0, 150.0));
+        assert!(junctions(&pin, &draw_lib).is_empty());
+        pin.add_wire(Pos2::new(0.0, 150.0), Pos2::new(0.0, 300.0));
+        assert_eq!(junctions(&pin, &draw_lib), vec![Pos2::new(0.0, 150.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_probe_net() -> Result<(), String> {
+        let draw_lib = load_draw_library();
+        // resistor with pads at y -150 and +150, wired from its bottom to the right
+        let mut schematic = Schematic::default();
+        schematic.add_part(GraphicalComponent::new(
+            ComponentType::Resistor,
+            Pos2::new(0.0, 0.0),
+            0.0,
+            false,
+            false,
+        ));
+        schematic.add_wire(Pos2::new(0.0, 150.0), Pos2::new(400.0, 150.0));
+        let netlist = extract_netlist(&schematic, &draw_lib, GROUND_NET);
+        let nets = &netlist.part_nets[0];
+        let probe = |p| net_at(&schematic, &draw_lib, &netlist, p);
+        assert_eq!(probe(Pos2::new(5.0, -145.0)), Some(nets[0]));
+        assert_eq!(probe(Pos2::new(0.0, 150.0)), Some(nets[1]));
+        // anywhere along the wire
+        assert_eq!(probe(Pos2::new(250.0, 160.0)), Some(nets[1]));
+        assert_eq!(probe(Pos2::new(250.0, 0.0)), None);
+        // screen positions go through the view first
+        let view = View {
+            scale: 0.5,
+            offset: egui::vec2(100.0, 50.0),
+        };
+        let screen = view.transform().apply(&Pos2::new(250.0, 150.0));
+        assert_eq!(view.to_schematic(screen), Pos2::new(250.0, 150.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_net_members() -> Result<(), String> {
+        let draw_lib = load_draw_library();
+        // two resistors in series, pads at y -150 and +150, and a loose wire
+        let mut schematic = Schematic::default();
+        for x in [0.0, 400.0] {
+            schematic.add_part(GraphicalComponent::new(
+                ComponentType::Resistor,
+                Pos2::new(x, 0.0),
+                0.0,
+                false,
+                false,
+            ));
+        }
+        schematic.add_wire(Pos2::new(0.0, 150.0), Pos2::new(200.0, 150.0));
+        schematic.add_wire(Pos2::new(200.0, 150.0), Pos2::new(400.0, 150.0));
+        schematic.add_wire(Pos2::new(0.0, 400.0), Pos2::new(400.0, 400.0));
+        let netlist = extract_netlist(&schematic, &draw_lib, GROUND_NET);
+        // hovering the bottom pin of the first resistor
+        let net = net_at(&schematic, &draw_lib, &netlist, Pos2::new(0.0, 150.0))
+            .ok_or("no net at pin")?;
+        let (wires, pins) = net_members(&schematic, &draw_lib, &netlist, net);
+        assert_eq!(wires, vec![0, 1]);
+        assert_eq!(pins, vec![Pos2::new(0.0, 150.0), Pos2::new(400.0, 150.0)]);
+        // same as the netlist says
+        let expected: Vec<usize> = (0..schematic.wires.len())
+            .filter(|i| netlist.wire_nets[*i] == netlist.part_nets[0][1])
+            .collect();
+        assert_eq!(wires, expected);
+        // hovering the middle of a wire gives the same net
+        let middle = net_at(&schematic, &draw_lib, &netlist, Pos2::new(300.0, 150.0));
+        assert_eq!(middle, Some(net));
+        // the loose wire is on its own
+        let loose = net_at(&schematic, &draw_lib, &netlist, Pos2::new(200.0, 400.0))
+            .ok_or("no net at wire")?;
+        let (wires, pins) = net_members(&schematic, &draw_lib, &netlist, loose);
+        assert_eq!((wires, pins), (vec![2], vec![]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sense_wire() -> Result<(), String> {
+        let draw_lib = load_draw_library();
+        // two resistors in series, joined by a wire
+        let mut schematic = Schematic::default();
+        for x in [0.0, 400.0] {
+            schematic.add_part(GraphicalComponent::new(
+                ComponentType::Resistor,
+                Pos2::new(x, 0.0),
+                0.0,
+                false,
+                false,
+            ));
+        }
+        schematic.add_wire(Pos2::new(0.0, 150.0), Pos2::new(400.0, 150.0));
+        let netlist = extract_netlist(&schematic, &draw_lib, GROUND_NET);
+        assert_eq!(netlist.part_nets[0][1], netlist.part_nets[1][1]);
+        assert!(netlist.senses.is_empty());
+        assert_eq!(wire_at(&schematic, Pos2::new(200.0, 155.0)), Some(0));
+        // sensing splits the wire into two nets
+        schematic.toggle_sense(0);
+        let netlist = extract_netlist(&schematic, &draw_lib, GROUND_NET);
+        let (a, b) = (netlist.part_nets[0][1], netlist.part_nets[1][1]);
+        assert_ne!(a, b);
+        assert_eq!(netlist.senses, vec![(a, b)]);
+        assert_eq!(netlist.net_count(), 4);
+        schematic.toggle_sense(0);
+        let netlist = extract_netlist(&schematic, &draw_lib, GROUND_NET);
+        assert!(netlist.senses.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ruler() -> Result<(), String> {
+        let view = View {
+            scale: 0.5,
+            offset: egui::vec2(10.0, 20.0),
+        };
+        // 50 pixels on screen is 100 schematic units at half scale
+        let ruler = Ruler::from_screen(&view, Pos2::new(10.0, 20.0), Pos2::new(40.0, 60.0));
+        assert_eq!(ruler.length(), 100.0);
+        let transform = view.transform();
+        let shapes = ruler.shapes(&transform, Color32::WHITE);
+        assert_eq!(shapes.len(), 3);
+        let Shape::LineSegment { points, .. } = shapes[0] else {
+            return Err("ruler is not a line".into());
+        };
+        assert_eq!(points[0], transform.apply(&ruler.start));
+        assert_eq!(points[1], transform.apply(&ruler.end));
+        let screen_length = points[0].distance(points[1]);
+        assert!((ruler.length() - screen_length / transform.apply_scalar(1.0)).abs() < 1e-4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_disabled_part() -> Result<(), String> {
+        let draw_lib = load_draw_library();
+        // two resistors in series, bottom one to ground
+        let mut schematic = Schematic::default();
+        for y in [0.0, 300.0] {
+            schematic.add_part(GraphicalComponent::new(
+                ComponentType::Resistor,
+                Pos2::new(0.0, y),
+                0.0,
+                false,
+                false,
+            ));
+        }
+        schematic.add_part(GraphicalComponent::new_ground(
+            GROUND_NET,
+            Pos2::new(0.0, 450.0),
+        ));
+        schematic.parts[1].enabled = false;
+        let netlist = extract_netlist(&schematic, &draw_lib, GROUND_NET);
+        assert_eq!(schematic.parts.len(), 3);
+        assert!(netlist.part_nets[1].is_empty());
+        // top resistor now floats, ground is on its own
+        assert_eq!(netlist.net_count(), 3);
+        assert_ne!(netlist.part_nets[0][1], 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_theme_svg() -> Result<(), String> {
+        let draw_lib = load_draw_library();
+        let mut schematic = Schematic::default();
+        for component_type in [ComponentType::Resistor, ComponentType::Capacitor] {
+            schematic.add_part(GraphicalComponent::new(
+                component_type,
+                Pos2::new(0.0, 0.0),
+                0.0,
+                false,
+                false,
+            ));
+        }
+        let transform = Transform::new(1.0, 0.0, 0.0, 0.0, false, false);
+        let theme = Theme::PRINT;
+        let shapes = schematic_shapes(&schematic, &draw_lib, &transform, &theme, None, None);
+        let svg = shapes_to_svg(&shapes, theme.background);
+        let lines: Vec<&str> = svg.lines().collect();
+        assert!(lines[1].starts_with("<rect") && lines[1].ends_with("fill=\"#ffffff\"/>"));
+        let strokes: Vec<&str> = svg
+            .split("stroke=\"")
+            .skip(1)
+            .map(|s| &s[..s.find('"').unwrap()])
+            .collect();
+        assert!(!strokes.is_empty());
+        assert!(strokes.iter().all(|s| *s == "#000000" || *s == "none"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotate_in_place() -> Result<(), String> {
+        let draw_lib = load_draw_library();
+        let bounds_center = |component_type: ComponentType, angle: f32| {
+            let part = GraphicalComponent::new(
+                component_type,
+                Pos2::new(300.0, 200.0),
+                angle,
+                false,
+                false,
+            );
+            let draw = &draw_lib[&part.component_type];
+            draw_to_shape(
+                draw,
+                &part.transform(&draw_lib),
+                Color32::WHITE,
+                Color32::WHITE,
+                PAD_SIZE,
+                None,
+            )
+            .visual_bounding_rect()
+            .center()
+        };
+        for component_type in [ComponentType::TransistorNPN, ComponentType::Diode] {
+            let c0 = bounds_center(component_type, 0.0);
+            let c1 = bounds_center(component_type, 1.0);
+            assert!(c0.distance(c1) < 1e-3, "{:?} moved to {:?}", c0, c1);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_align_distribute() -> Result<(), String> {
+        let mut schematic = Schematic::default();
+        for (x, y) in [(300.0, 0.0), (100.0, 500.0), (250.0, 100.0)] {
+            schematic.add_part(GraphicalComponent::new(
+                ComponentType::Resistor,
+                Pos2::new(x, y),
+                0.0,
+                false,
+                false,
+            ));
+        }
+        schematic.align(&[0, 1, 2], Align::Left);
+        let xs: Vec<f32> = schematic.parts.iter().map(|p| p.position.x).collect();
+        assert_eq!(xs, vec![100.0, 100.0, 100.0]);
+        schematic.distribute(&[0, 1, 2], false);
+        let ys: Vec<f32> = schematic.parts.iter().map(|p| p.position.y).collect();
+        assert_eq!(ys, vec![0.0, 500.0, 250.0]);
+        // locked parts keep their place
+        schematic.parts[2].locked = true;
+        schematic.align(&[0, 1, 2], Align::Bottom);
+        assert_eq!(schematic.parts[0].position.y, 500.0);
+        assert_eq!(schematic.parts[2].position.y, 250.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_selection_handles() -> Result<(), String> {
+        let draw_lib = load_draw_library();
+        let global = View::default().transform();
+        let mut part = GraphicalComponent::new(
+            ComponentType::Capacitor,
+            Pos2::new(200.0, 100.0),
+            1.0,
+            false,
+            false,
+        );
+        let bounds = draw_bounds(&draw_lib[&ComponentType::Capacitor]);
+        let transform = part.transform(&draw_lib).chain(&global);
+        let handles = selection_handles(&part, &draw_lib, &global);
+        assert_eq!(handles[0], transform.apply(&bounds.left_top()));
+        assert_eq!(handles[2], transform.apply(&bounds.right_bottom()));
+        // a quarter turn puts the box on its side
+        let size = (handles[2] - handles[0]).abs();
+        let bounds_size = bounds.size() * global.apply_scalar(1.0);
+        assert!((size.x - bounds_size.y).abs() < 1e-3);
+        assert!((size.y - bounds_size.x).abs() < 1e-3);
+        // flipping changes the arrow, not the handles
+        let arrow = selection_shapes(&part, &draw_lib, &global, Color32::RED);
+        part.flip_x = true;
+        let flipped = selection_shapes(&part, &draw_lib, &global, Color32::RED);
+        assert_eq!(arrow.len(), 6);
+        assert_ne!(arrow[5], flipped[5]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_locked_part() -> Result<(), String> {
+        let mut schematic = Schematic::default();
+        let rail = schematic.add_part(GraphicalComponent::new(
+            ComponentType::Resistor,
+            Pos2::new(100.0, 100.0),
+            0.0,
+            false,
+            false,
+        ));
+        let delta = egui::Vec2::new(50.0, 0.0);
+        assert!(schematic.move_part(rail, delta));
+        assert_eq!(schematic.parts[rail].position, Pos2::new(150.0, 100.0));
+        schematic.parts[rail].locked = true;
+        assert!(!schematic.move_part(rail, delta));
+        assert_eq!(schematic.parts[rail].position, Pos2::new(150.0, 100.0));
+        assert!(schematic.remove_part(rail).is_none());
+        assert_eq!(schematic.parts.len(), 1);
+        // unlocked again it can go
+        schematic.parts[rail].locked = false;
+        assert!(schematic.remove_part(rail).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_view_and_sim() -> Result<(), String> {
+        let mut app = MyApp::default();
+        let parts = app.schematic.parts.len();
+        app.view.scale = 2.0;
+        app.view.offset = egui::Vec2::new(30.0, -10.0);
+        // results as the load buttons leave them
+        let dir = std::env::temp_dir();
+        let files = [
+            (
+                "circuit_test_reset_transient.csv",
+                "time [s],out [V]\n0.001,1\n",
+            ),
+            (
+                "circuit_test_reset_ac.csv",
+                "freq,mag(1),phase(1)\n10,1,0\n",
+            ),
+            (
+                "circuit_test_reset_compare.csv",
+                "time,a:v(1),b:v(1),diff:v(1)\n0.001,1,1,0\n",
+            ),
+        ];
+        let paths: Vec<String> = files
+            .iter()
+            .map(|(name, text)| {
+                let path = dir.join(name);
+                std::fs::write(&path, text).map_err(|e| e.to_string())?;
+                Ok(path.to_string_lossy().into())
+            })
+            .collect::<Result<_, String>>()?;
+        app.transient_path = paths[0].clone();
+        app.ac_path = paths[1].clone();
+        app.compare_path = paths[2].clone();
+        app.load_transient();
+        app.load_ac();
+        app.load_compare();
+        app.traces = vec![app.sim_results.names[0].clone()];
+        app.library_path = String::from("no/such/library.json");
+        app.load_library();
+        app.reset_view();
+        assert_eq!(app.view, View::default());
+        assert_eq!(app.schematic.parts.len(), parts);
+        assert_eq!(app.sim_results.rows.len(), 1);
+        assert_eq!(app.bode.len(), 1);
+        assert!(app.overlay.is_some());
+        app.view.scale = 2.0;
+        app.ac_path = String::from("no/such/ac.csv");
+        app.load_ac();
+        app.reset_sim();
+        assert!(app.sim_results.rows.is_empty() && app.sim_results.names.is_empty());
+        assert!(app.bode.is_empty() && app.overlay.is_none());
+        assert_eq!(app.ac_error, None);
+        // probes, the view, the circuit and other errors are left alone
+        assert_eq!(app.traces.len(), 1);
+        assert!(app.library_error.is_some());
+        assert_eq!(app.view.scale, 2.0);
+        assert_eq!(app.schematic.parts.len(), parts);
+        Ok(())
+    }
+
+    #[test]
+    fn test_view_zoom() -> Result<(), String> {
+        let mut view = View {
+            offset: egui::Vec2::new(100.0, 50.0),
+            ..View::default()
+        };
+        // the point under the cursor stays put
+        let cursor = Pos2::new(400.0, 300.0);
+        let under = view.to_schematic(cursor);
+        view.zoom_at(cursor, 2.0);
+        assert!((view.scale - 1.2).abs() < 1e-6);
+        let p = view.transform().apply(&under);
+        assert!(p.distance(cursor) < 1e-3);
+        // zoom is clamped at both ends
+        view.zoom_at(cursor, 1e6);
+        assert_eq!(view.scale, MAX_ZOOM);
+        view.zoom_at(cursor, 1e-9);
+        assert_eq!(view.scale, MIN_ZOOM);
+        assert!(view.transform().apply(&under).distance(cursor) < 1e-2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_tag_marker() -> Result<(), String> {
+        let transform = Transform::new(1.0, 0.0, 0.0, 0.0, false, false);
+        let line = serde_json::json!(["Q", "x", 100, 50, 1]);
+        let (shape, pad) = drawline_to_shape(
+            &line,
+            &transform,
+            Color32::WHITE,
+            Color32::YELLOW,
+            10.0,
+            None,
+        );
+        assert!(pad.is_none());
+        let Some(Shape::Vec(segments)) = shape else {
+            return Err("no marker".into());
+        };
+        assert_eq!(segments.len(), 2);
+        let Shape::LineSegment { points, stroke } = &segments[0] else {
+            return Err("marker is not lines".into());
+        };
+        assert_eq!(stroke.color, Color32::MAGENTA);
+        // centered on (100, -50) in screen coordinates
+        assert_eq!(points[0].lerp(points[1], 0.5), Pos2::new(100.0, -50.0));
+        // nothing to place marker at
+        let line = serde_json::json!(["Q", "x"]);
+        let (shape, _) = drawline_to_shape(
+            &line,
+            &transform,
+            Color32::WHITE,
+            Color32::YELLOW,
+            10.0,
+            None,
+        );
+        assert!(shape.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_element() -> Result<(), String> {
+        let fonts = egui::text::Fonts::new(1.0, 1024, egui::FontDefinitions::default());
+        fn texts(shape: &Shape) -> Vec<(String, Pos2, f32)> {
+            match shape {
+                Shape::Vec(shapes) => shapes.iter().flat_map(texts).collect(),
+                Shape::Text(t) => vec![(t.galley.text().to_string(), text_center(t), t.angle)],
+                _ => vec![],
+            }
+        }
+        // voltmeter "V", centered on the symbol origin
+        let transform = Transform::new(0.5, 0.0, 100.0, 100.0, false, false);
+        let line = serde_json::json!(["T", 0, 0, 0, 100, 0, 0, 0, "V", "Normal", 0, "C", "C"]);
+        let draw = |line: &Value, transform: &Transform, fonts| {
+            drawline_to_shape(
+                line,
+                transform,
+                Color32::WHITE,
+                Color32::YELLOW,
+                10.0,
+                fonts,
+            )
+        };
+        let (shape, pad) = draw(&line, &transform, Some(&fonts));
+        assert!(pad.is_none());
+        let found = texts(&shape.ok_or("no text")?);
+        assert_eq!(found.len(), 1);
+        let (text, center, angle) = &found[0];
+        assert_eq!(text, "V");
+        assert!(center.distance(Pos2::new(100.0, 100.0)) < 1e-3);
+        assert_eq!(*angle, 0.0);
+        // without fonts (eg. for bounds) text is skipped
+        assert!(draw(&line, &transform, None).0.is_none());
+        // vertical text stays vertical in a mirrored symbol, reading upwards
+        let vertical =
+            serde_json::json!(["T", 900, 0, 0, 100, 0, 0, 0, "V", "Normal", 0, "C", "C"]);
+        let mirrored = Transform::new(0.5, 0.0, 100.0, 100.0, true, false);
+        let (shape, _) = draw(&vertical, &mirrored, Some(&fonts));
+        let angle = texts(&shape.ok_or("no text")?)[0].2;
+        assert!((angle + std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+        // a placed resistor reads "R"
+        let draw_lib = load_draw_library();
+        let mut schematic = Schematic::default();
+        schematic.add_part(GraphicalComponent::new(
+            ComponentType::Resistor,
+            Pos2::new(200.0, 200.0),
+            1.0,
+            false,
+            false,
+        ));
+        let identity = Transform::new(1.0, 0.0, 0.0, 0.0, false, false);
+        let theme = Theme::DARK;
+        let shapes = schematic_shapes(&schematic, &draw_lib, &identity, &theme, None, Some(&fonts));
+        let found: Vec<_> = shapes.iter().flat_map(texts).collect();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, "R");
+        let origin = schematic.parts[0].transform(&draw_lib).apply(&Pos2::ZERO);
+        assert!(found[0].1.distance(origin) < 1e-3);
+        // turned with the part but still readable
+        assert!((found[0].2 + std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dashed_rectangle() -> Result<(), String> {
+        let transform = Transform::new(1.0, 0.0, 0.0, 0.0, false, false);
+        let solid = serde_json::json!(["S", -100, -100, 100, 100, 0, 1, 0, "N"]);
+        let (shape, _) = drawline_to_shape(
+            &solid,
+            &transform,
+            Color32::WHITE,
+            Color32::YELLOW,
+            10.0,
+            None,
+        );
+        let Some(Shape::Vec(solid)) = shape else {
+            return Err("no rectangle".into());
+        };
+        let dashed = serde_json::json!(["S", -100, -100, 100, 100, 0, 1, 0, "N", "DASH"]);
+        let (shape, _) = drawline_to_shape(
+            &dashed,
+            &transform,
+            Color32::WHITE,
+            Color32::YELLOW,
+            10.0,
+            None,
+        );
+        let Some(Shape::Vec(dashes)) = shape else {
+            return Err("no dashed rectangle".into());
+        };
+        // many short segments instead of four sides
+        assert!(dashes.len() > solid.len());
+        for dash in &dashes {
+            let Shape::LineSegment { points, .. } = dash else {
+                return Err("dash is not a line segment".into());
+            };
+            assert!(points[0].distance(points[1]) <= DASH_LENGTH + 0.01);
+        }
+        // dotted polyline is all dots
+        let dotted = serde_json::json!(["P", 2, 0, 1, 0, 0, 0, 200, 0, "N", "DOT"]);
+        let (shape, _) = drawline_to_shape(
+            &dotted,
+            &transform,
+            Color32::WHITE,
+            Color32::YELLOW,
+            10.0,
+            None,
+        );
+        let Some(Shape::Vec(dots)) = shape else {
+            return Err("no dotted line".into());
+        };
+        assert!(dots.len() > 2);
+        assert!(dots.iter().all(|d| matches!(d, Shape::Circle(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_filled_arc_circle() -> Result<(), String> {
+        let transform = Transform::new(1.0, 0.0, 0.0, 0.0, false, false);
+        let shape_of = |v: Value| {
+            drawline_to_shape(&v, &transform, Color32::WHITE, Color32::YELLOW, 10.0, None).0
+        };
+        let filled = shape_of(serde_json::json!(["C", 0, 0, 50, 0, 1, 0, "F"]));
+        let Some(Shape::Circle(circle)) = filled else {
+            return Err("no filled circle".into());
+        };
+        assert_eq!(circle.fill, Color32::WHITE);
+        assert_eq!(circle.radius, 50.0);
+        let outline = shape_of(serde_json::json!(["C", 0, 0, 50, 0, 1, 10, "N"]));
+        let Some(Shape::Circle(circle)) = outline else {
+            return Err("no circle outline".into());
+        };
+        assert_eq!(circle.fill, Color32::TRANSPARENT);
+        // filled arc is a sector polygon, unfilled stays a line
+        let arc = |fill| serde_json::json!(["A", 0, 0, 50, 0, 900, 0, 1, 0, fill, 50, 0, 0, 50]);
+        let Some(Shape::Path(sector)) = shape_of(arc("F")) else {
+            return Err("no filled arc".into());
+        };
+        assert!(sector.closed);
+        assert_eq!(sector.fill, Color32::WHITE);
+        assert_eq!(sector.points.last(), Some(&Pos2::ZERO));
+        let Some(Shape::Path(line)) = shape_of(arc("N")) else {
+            return Err("no arc line".into());
+        };
+        assert!(!line.closed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bezier() -> Result<(), String> {
+        let transform = Transform::new(0.5, 0.0, 100.0, 100.0, false, false);
+        let cubic = serde_json::json!(["B", 4, 0, 1, 0, 0, 0, 100, 200, 300, 200, 400, 0, "N"]);
+        let (shape, pad) = drawline_to_shape(
+            &cubic,
+            &transform,
+            Color32::WHITE,
+            Color32::YELLOW,
+            10.0,
+            None,
+        );
+        assert!(pad.is_none());
+        let Some(Shape::Path(path)) = shape else {
+            return Err("no bezier".into());
+        };
+        assert_eq!(path.points.len(), BEZIER_SEGMENTS + 1);
+        // ends on the first and last control points, y flipped
+        assert_eq!(path.points[0], transform.apply(&Pos2::new(0.0, 0.0)));
+        assert_eq!(
+            path.points[BEZIER_SEGMENTS],
+            transform.apply(&Pos2::new(400.0, 0.0))
+        );
+        // halfway along a symmetric cubic is 3/4 of the way up
+        assert_eq!(
+            path.points[BEZIER_SEGMENTS / 2],
+            transform.apply(&Pos2::new(200.0, -150.0))
+        );
+        // quadratic midpoint is halfway to the middle control point
+        let quad = [Pos2::ZERO, Pos2::new(100.0, 100.0), Pos2::new(200.0, 0.0)];
+        assert_eq!(bezier_points(&quad, 2)[1], Pos2::new(100.0, 50.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_library() -> Result<(), String> {
+        let lib = parse_library(include_bytes!("./circuit.json"))?;
+        assert!(lib.iter().any(|(name, _)| name == "R"));
+        let err = parse_library(br#"[["DEF"]]"#).unwrap_err();
+        assert!(err.starts_with("library entry 0:"), "{}", err);
+        let err = parse_library(br#"[["DEF", [["F0", "R"], ["F1"]]]]"#).unwrap_err();
+        assert_eq!(err, "library entry 0: no name in F1 field");
+        let err = parse_library(br#"[["DEF", [["F1", "R"]]]]"#).unwrap_err();
+        assert_eq!(err, "library entry 0 (R): no DRAW section");
+        let text =
+            br#"[["DEF", [["F1", "R"], ["DRAW", [["P", 2, 0, 1, 0, 0, 0, 0, 1, "N"], 5]]]]]"#;
+        let err = parse_library(text).unwrap_err();
+        assert_eq!(err, "library entry 0 (R): DRAW line 1 has no tag");
+        // fields are checked too, instead of panicking when drawn
+        let draw_error = |line: &str| {
+            let text = format!(r#"[["DEF", [["F1", "R"], ["DRAW", [{}]]]]]"#, line);
+            parse_library(text.as_bytes()).unwrap_err()
+        };
+        assert_eq!(
+            draw_error(r#"["C", 0, 0, 10]"#),
+            "library entry 0 (R): DRAW line 0: C needs a number in field 6"
+        );
+        assert_eq!(
+            draw_error(r#"["S", 0, "x", 10, 10, 0, 1, 0, "N"]"#),
+            "library entry 0 (R): DRAW line 0: S needs a number in field 2"
+        );
+        assert_eq!(
+            draw_error(r#"["P", 3, 0, 1, 0, 0, 0, 10, 10, "N"]"#),
+            "library entry 0 (R): DRAW line 0: P has 10 fields, too few for 3 points"
+        );
+        assert_eq!(
+            draw_error(r#"["P", 2, 0, 1, 0, 0, 0, 10, 10]"#),
+            "library entry 0 (R): DRAW line 0: P needs text in field 9"
+        );
+        assert_eq!(
+            draw_error(r#"["X", "~", 1, 0, 0, 50, "Q", 50, 50, 1, 1, "P"]"#),
+            "library entry 0 (R): DRAW line 0: X needs U, D, L or R in field 6, found Q"
+        );
+        // Bezier control points are counted like polyline points
+        assert_eq!(
+            draw_error(r#"["B", 4, 0, 1, 0, 0, 0, 100, 300, 300, 300]"#),
+            "library entry 0 (R): DRAW line 0: B has 11 fields, too few for 4 points"
+        );
+        assert_eq!(
+            draw_error(r#"["B", 1e9, 0, 1, 0, 0, 0]"#),
+            "library entry 0 (R): DRAW line 0: B has 7 fields, too few for 1000000000 points"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_library() -> Result<(), String> {
+        let err = load_library("no/such/library.json").unwrap_err();
+        assert!(
+            err.starts_with("could not read no/such/library.json:"),
+            "{}",
+            err
+        );
+        // the app keeps its library and reports the problem
+        let mut app = MyApp {
+            library_path: String::from("no/such/library.json"),
+            ..MyApp::default()
+        };
+        app.load_library();
+        assert!(app.library_error.is_some());
+        assert!(app.draw_lib.contains_key(&ComponentType::Resistor));
+        // the bundled file loads from disk too, also in KiCad form
+        app.library_path = String::from("src/circuit.json");
+        app.load_library();
+        assert_eq!(app.library_error, None);
+        app.library_path = String::from("src/circuit.lib");
+        app.load_library();
+        assert_eq!(app.library_error, None);
+        // a broken symbol on disk is an error, not a panic when drawn
+        let path = std::env::temp_dir().join("circuit_test_broken.lib");
+        let text = "DEF R R 0 0 N Y 1 F N\nF1 \"R\" 0 0 50 V V C CNN\nDRAW\nX ~ 1 0 150\nENDDRAW\nENDDEF\n";
+        std::fs::write(&path, text).map_err(|e| e.to_string())?;
+        app.library_path = path.to_string_lossy().into();
+        app.load_library();
+        let err = app.library_error.clone().unwrap_or_default();
+        assert!(
+            err.ends_with("library entry 0 (R): DRAW line 0: X needs a number in field 5"),
+            "{}",
+            err
+        );
+        assert!(app.draw_lib.contains_key(&ComponentType::Resistor));
+        Ok(())
+    }
+
+    #[test]
+    fn test_log_ticks() -> Result<(), String> {
+        let ticks = log_ticks(10.0, 100e3);
+        let decades: Vec<f64> = ticks.iter().filter(|t| t.1).map(|t| t.0).collect();
+        assert_eq!(decades, vec![10.0, 100.0, 1e3, 10e3, 100e3]);
+        // 2..9 times each decade in between
+        assert_eq!(ticks.len(), 5 + 4 * 8);
+        assert_eq!(ticks[1], (20.0, false));
+        assert_eq!(ticks[8], (90.0, false));
+        assert_eq!(ticks[ticks.len() - 2], (90e3, false));
+        // autoscale rounds out to whole decades
+        assert_eq!(decade_range(20.0, 20e3), (10.0, 100e3));
+        assert_eq!(step_range(-43.0, 2.0, 10.0), (-50.0, 10.0));
+        assert_eq!(freq_label(10e3), "10k");
+        assert_eq!(decade_range(100.0, 100.0), (100.0, 1e3));
+        // gridlines stop, whatever the range
+        assert_eq!(step_ticks(-20.0, 10.0, 10.0), vec![-20.0, -10.0, 0.0, 10.0]);
+        assert!(step_ticks(0.0, f64::INFINITY, 10.0).is_empty());
+        assert!(step_ticks(f64::NAN, 0.0, 10.0).is_empty());
+        assert!(step_ticks(0.0, 10.0, 0.0).is_empty());
+        assert!(step_ticks(10.0, 0.0, 10.0).is_empty());
+        assert_eq!(step_ticks(0.0, 1e300, 1.0).len(), MAX_GRIDLINES + 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_part_value() -> Result<(), String> {
+        assert_eq!(part_value(ComponentType::Resistor, "4.7k")?, 4700.0);
+        assert_eq!(part_value(ComponentType::Capacitor, "100nF")?, 100e-9);
+        // passive values have to be positive, rails can be negative
+        assert_eq!(
+            part_value(ComponentType::Resistor, "0"),
+            Err(String::from("0 has to be above zero"))
+        );
+        assert!(part_value(ComponentType::Capacitor, "-1u").is_err());
+        assert!(part_value(ComponentType::Inductor, "-10mH").is_err());
+        assert!(part_value(ComponentType::Resistor, "inf").is_err());
+        assert!(part_value(ComponentType::Resistor, "ten").is_err());
+        assert_eq!(part_value(ComponentType::Power, "-15V")?, -15.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_ac_csv() -> Result<(), String> {
+        let text = "freq,mag(2),phase(2)\n10,1,-0.5\n100,0.1,-45\n";
+        let bode = parse_ac_csv(text)?;
+        assert_eq!(bode, vec![(10.0, 0.0, -0.5), (100.0, -20.0, -45.0)]);
+        // transfer functions come in dB already
+        let bode = parse_ac_csv("freq,gain(2),phase(2)\n10,-3,-45\n")?;
+        assert_eq!(bode, vec![(10.0, -3.0, -45.0)]);
+        assert!(parse_ac_csv("time,v(2)\n0,1\n").is_err());
+        let err = parse_ac_csv("freq,mag(2),phase(2)\n10,1\n").unwrap_err();
+        assert_eq!(err, "line 2: expected number in column 3");
+        // loading fills the Bode plot, failures say why
+        let path = std::env::temp_dir().join("circuit_test_ac.csv");
+        std::fs::write(&path, text).map_err(|e| e.to_string())?;
+        let mut app = MyApp {
+            ac_path: path.to_string_lossy().into(),
+            ..MyApp::default()
+        };
+        app.load_ac();
+        assert_eq!((app.bode.len(), &app.ac_error), (2, &None));
+        app.ac_path = String::from("no/such/ac.csv");
+        app.load_ac();
+        assert!(app.ac_error.is_some());
+        assert_eq!(app.bode.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_transient_csv() -> Result<(), String> {
+        let text = "time [s],out [V],VCC [V]\n0.001,1,5\n0.002,2,5\n";
+        let waveforms = parse_transient_csv(text)?;
+        assert_eq!(waveforms.names, vec!["out", "VCC"]);
+        assert_eq!(waveforms.time, vec![0.001, 0.002]);
+        // traces go by net name, whatever column the net is in
+        assert_eq!(waveforms.trace("out"), Some(vec![1.0, 2.0]));
+        assert_eq!(waveforms.trace("VCC"), Some(vec![5.0, 5.0]));
+        assert_eq!(waveforms.trace("GND"), None);
+        assert!(parse_transient_csv("freq,mag(2),phase(2)\n").is_err());
+        let err = parse_transient_csv("time [s],out [V]\n0.001,x\n").unwrap_err();
+        assert_eq!(err, "line 2: expected number in column 2");
+        // loading fills the waveform panel, failures say why
+        let mut app = MyApp::default();
+        let path = std::env::temp_dir().join("circuit_test_transient.csv");
+        std::fs::write(&path, "time [s],out [V]\n0.001,1.5\n").map_err(|e| e.to_string())?;
+        app.transient_path = path.to_string_lossy().into();
+        app.load_transient();
+        assert_eq!(app.transient_error, None);
+        assert_eq!(app.sim_results.trace("out"), Some(vec![1.5]));
+        app.transient_path = String::from("no/such/transient.csv");
+        app.load_transient();
+        assert!(app.transient_error.is_some());
+        assert_eq!(app.sim_results.rows.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_compare_csv() -> Result<(), String> {
+        let text = "time,a:v(2),a:v(3),b:v(2),b:v(3),diff:v(2),diff:v(3)\n\
+                    0.001,1,5,0.5,5,0.5,0\n\
+                    0.002,2,5,,,,\n";
+        let overlay = parse_compare_csv(text)?;
+        assert_eq!(
+            (overlay.x_name.as_str(), overlay.name.as_str()),
+            ("time", "v(2)")
+        );
+        assert_eq!(overlay.x, vec![0.001, 0.002]);
+        assert_eq!(overlay.a, vec![1.0, 2.0]);
+        assert_eq!(overlay.b[0], 0.5);
+        assert!(overlay.b[1].is_nan() && overlay.diff[1].is_nan());
+        assert!(parse_compare_csv("freq,mag(2),phase(2)\n10,1,0\n").is_err());
+        assert!(parse_compare_csv("time,a:v(2),b:v(3),diff:v(2)\n").is_err());
+        let err = parse_compare_csv("time,a:x,b:x,diff:x\n,1,1,0\n").unwrap_err();
+        assert_eq!(err, "line 2: expected number in column 1");
+        // loading opens the overlay, failures say why
+        let path = std::env::temp_dir().join("circuit_test_compare_gui.csv");
+        std::fs::write(&path, text).map_err(|e| e.to_string())?;
+        let mut app = MyApp {
+            compare_path: path.to_string_lossy().into(),
+            ..MyApp::default()
+        };
+        app.load_compare();
+        let loaded = app.overlay.as_ref().ok_or("no overlay")?;
+        assert_eq!((&loaded.x, &loaded.a), (&overlay.x, &overlay.a));
+        app.compare_path = String::from("no/such/compare.csv");
+        app.load_compare();
+        assert!(app.compare_error.is_some() && app.overlay.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_status_line() -> Result<(), String> {
+        let draw_lib = load_draw_library();
+        // two resistors in series to ground
+        let mut schematic = Schematic::default();
+        for y in [0.0, 300.0] {
+            schematic.add_part(GraphicalComponent::new(
+                ComponentType::Resistor,
+                Pos2::new(0.0, y),
+                0.0,
+                false,
+                false,
+            ));
+        }
+        schematic.add_part(GraphicalComponent::new_ground(
+            GROUND_NET,
+            Pos2::new(0.0, 450.0),
+        ));
+        let netlist = extract_netlist(&schematic, &draw_lib, GROUND_NET);
+        assert_eq!(netlist.net_count(), 3);
+        assert_eq!(
+            status_line(&schematic, &netlist),
+            "3 parts (2 Resistor, 1 Ground), 3 nets"
+        );
+        Ok(())
     }
 }
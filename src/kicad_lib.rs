@@ -0,0 +1,169 @@
+//! Reader for classic KiCad symbol libraries (`.lib`)
+//!
+//! Gives the same nested structure as `script/lib2json.py`, so symbols can
+//! be loaded straight from KiCad files instead of the pre-baked JSON.
+
+use serde_json::Value;
+
+/// Keywords that open a nested section, with the keyword that closes it
+const PAIRS: [(&str, &str); 3] = [
+    ("DEF", "ENDDEF"),
+    ("DRAW", "ENDDRAW"),
+    ("$FPLIST", "$ENDFPLIST"),
+];
+
+/// Open section while parsing
+struct Section<'a> {
+    keyword: &'a str,
+    // line number of the opening keyword, for errors
+    line: usize,
+    // first argument, the symbol name for DEF
+    name: &'a str,
+    lines: Vec<Value>,
+}
+
+/// Token as a number if it parses as one, otherwise as a string without quotes
+fn parse_token(token: &str) -> Value {
+    if let Some(n) = token
+        .parse::<f64>()
+        .ok()
+        .and_then(serde_json::Number::from_f64)
+    {
+        return Value::Number(n);
+    }
+    Value::String(token.trim_matches('"').to_string())
+}
+
+/// Parse the `DEF ... DRAW ... ENDDRAW ... ENDDEF` text format
+///
+/// Returns the name from each DEF line along with the lines inside DEF,
+/// where nested sections are `["DRAW", [...]]` like in the JSON library.
+/// Comments, blank lines and the `EESchema-LIBRARY` header are skipped.
+pub fn parse_lib(text: &str) -> Result<Vec<(String, Value)>, String> {
+    let mut stack: Vec<Section> = vec![];
+    let mut res = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let number = i + 1;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let Some(first) = tokens.first().copied() else {
+            continue;
+        };
+        if first.starts_with('#') || first == "EESchema-LIBRARY" {
+            continue;
+        }
+        if let Some((keyword, _)) = PAIRS.iter().find(|(open, _)| *open == first) {
+            if (*keyword == "DEF") != stack.is_empty() {
+                return Err(format!("line {}: unexpected {}", number, keyword));
+            }
+            stack.push(Section {
+                keyword,
+                line: number,
+                name: tokens.get(1).copied().unwrap_or_default(),
+                lines: vec![],
+            });
+            continue;
+        }
+        let closes = stack.last().is_some_and(|section| {
+            PAIRS
+                .iter()
+                .any(|(open, close)| *open == section.keyword && *close == first)
+        });
+        match stack.last_mut() {
+            Some(_) if closes => {
+                let section = stack.pop().expect("open section");
+                match stack.last_mut() {
+                    Some(parent) => parent
+                        .lines
+                        .push(serde_json::json!([section.keyword, section.lines])),
+                    None => res.push((
+                        section.name.trim_matches('"').to_string(),
+                        Value::Array(section.lines),
+                    )),
+                }
+            }
+            Some(section) => section
+                .lines
+                .push(Value::Array(tokens.into_iter().map(parse_token).collect())),
+            None => return Err(format!("line {}: {} outside of DEF", number, first)),
+        }
+    }
+    match stack.last() {
+        Some(section) => Err(format!(
+            "line {}: {} is never closed",
+            section.line, section.keyword
+        )),
+        None => Ok(res),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lib() -> Result<(), String> {
+        let text = r##"EESchema-LIBRARY Version 2.4
+#
+# R
+#
+DEF R R 0 0 N Y 1 F N
+F0 "R" 80 0 50 V V C CNN
+F1 "R" 0 0 50 V V C CNN
+$FPLIST
+ R_*
+$ENDFPLIST
+DRAW
+S -40 -100 40 100 0 1 10 N
+X ~ 1 0 150 50 D 50 50 1 1 P
+X ~ 2 0 -150 50 U 50 50 1 1 P
+ENDDRAW
+ENDDEF
+#
+# GND
+#
+DEF GND #PWR 0 0 Y Y 1 F P
+F0 "#PWR" 0 -250 50 H I C CNN
+F1 "GND" 0 -150 50 H V C CNN
+DRAW
+P 6 0 1 0 0 0 0 -50 50 -50 0 -100 -50 -50 0 -50 N
+X GND 1 0 0 0 D 50 50 1 1 W N
+ENDDRAW
+ENDDEF
+#End Library
+"##;
+        let lib = parse_lib(text)?;
+        let names: Vec<&str> = lib.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["R", "GND"]);
+        let expected = serde_json::json!([
+            ["F0", "R", 80.0, 0.0, 50.0, "V", "V", "C", "CNN"],
+            ["F1", "R", 0.0, 0.0, 50.0, "V", "V", "C", "CNN"],
+            ["$FPLIST", [["R_*"]]],
+            [
+                "DRAW",
+                [
+                    ["S", -40.0, -100.0, 40.0, 100.0, 0.0, 1.0, 10.0, "N"],
+                    [
+                        "X", "~", 1.0, 0.0, 150.0, 50.0, "D", 50.0, 50.0, 1.0, 1.0, "P"
+                    ],
+                    [
+                        "X", "~", 2.0, 0.0, -150.0, 50.0, "U", 50.0, 50.0, 1.0, 1.0, "P"
+                    ]
+                ]
+            ]
+        ]);
+        assert_eq!(lib[0].1, expected);
+        // the bundled library matches its pre-baked JSON
+        let lib = parse_lib(include_str!("./circuit.lib"))?;
+        let json: Vec<(String, Value)> =
+            serde_json::from_slice(include_bytes!("./circuit.json")).map_err(|e| e.to_string())?;
+        assert_eq!(lib.len(), json.len());
+        for ((_, a), (keyword, b)) in lib.iter().zip(&json) {
+            assert_eq!(keyword, "DEF");
+            assert_eq!(a, b);
+        }
+        // unbalanced sections say where they start
+        let err = parse_lib("DEF R R 0 0 N Y 1 F N\nDRAW\nENDDEF\n").unwrap_err();
+        assert_eq!(err, "line 2: DRAW is never closed");
+        Ok(())
+    }
+}
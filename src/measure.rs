@@ -0,0 +1,81 @@
+//! Scalar measurements of transient output, eg. level and distortion
+
+use num_complex::Complex;
+
+/// Root mean square of the samples, 0 when there are none
+pub fn rms(samples: &[f64]) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|x| x * x).sum::<f64>() / samples.len() as f64).sqrt()
+}
+
+/// Difference between the highest and lowest sample
+pub fn peak_to_peak(samples: &[f64]) -> f64 {
+    let max = samples.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let min = samples.iter().cloned().fold(f64::INFINITY, f64::min);
+    if samples.is_empty() { 0.0 } else { max - min }
+}
+
+/// Amplitude of the component at `freq`, from a single DFT bin
+fn amplitude(samples: &[f64], freq: f64, sample_rate: f64) -> f64 {
+    let w = -2.0 * std::f64::consts::PI * freq / sample_rate;
+    let sum: Complex<f64> = samples
+        .iter()
+        .enumerate()
+        .map(|(n, x)| Complex::from_polar(*x, w * n as f64))
+        .sum();
+    2.0 * sum.norm() / samples.len() as f64
+}
+
+/// Total harmonic distortion, the RMS of the harmonics over the fundamental
+///
+/// Harmonics up to the Nyquist frequency are counted. Only whole periods of
+/// the fundamental are used so the bins don't leak into each other.
+pub fn thd(samples: &[f64], fundamental_hz: f64, sample_rate: f64) -> f64 {
+    let period = sample_rate / fundamental_hz;
+    let periods = (samples.len() as f64 / period).floor();
+    let samples = match (periods * period).round() as usize {
+        0 => samples,
+        n => &samples[..n.min(samples.len())],
+    };
+    let fundamental = amplitude(samples, fundamental_hz, sample_rate);
+    if fundamental == 0.0 {
+        return 0.0;
+    }
+    let harmonics: f64 = (2..)
+        .map(|k| k as f64 * fundamental_hz)
+        .take_while(|f| *f < sample_rate / 2.0)
+        .map(|f| amplitude(samples, f, sample_rate).powi(2))
+        .sum();
+    harmonics.sqrt() / fundamental
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_cmp::approx_eq;
+
+    #[test]
+    fn test_measure() -> Result<(), String> {
+        // 1kHz sine sampled at 48kHz for 10 periods
+        let rate = 48000.0;
+        let sine: Vec<f64> = (0..480)
+            .map(|n| (2.0 * std::f64::consts::PI * 1000.0 * n as f64 / rate).sin())
+            .collect();
+        assert!(approx_eq!(f64, rms(&sine), 0.5f64.sqrt(), epsilon = 1e-9));
+        assert!(approx_eq!(f64, peak_to_peak(&sine), 2.0, epsilon = 1e-9));
+        assert!(thd(&sine, 1000.0, rate) < 1e-9);
+        // hard clipping at half the amplitude adds odd harmonics
+        let clipped: Vec<f64> = sine.iter().map(|x| x.clamp(-0.5, 0.5)).collect();
+        assert!(approx_eq!(f64, peak_to_peak(&clipped), 1.0, epsilon = 1e-9));
+        let distortion = thd(&clipped, 1000.0, rate);
+        assert!(distortion > 0.1, "thd {}", distortion);
+        // partial periods at the end are left out
+        let longer: Vec<f64> = sine.iter().chain(&sine[..20]).cloned().collect();
+        assert!(thd(&longer, 1000.0, rate) < 1e-9);
+        assert_eq!(rms(&[]), 0.0);
+        assert_eq!(peak_to_peak(&[]), 0.0);
+        Ok(())
+    }
+}
@@ -0,0 +1,391 @@
+//! Reader for simplified SPICE netlists
+//!
+//! The first line is the title, then one element per line:
+//!
+//! ```text
+//! RC low pass
+//! * source
+//! V1 in 0 DC 5 AC 1
+//! R1 in out 4.7k
+//! C1 out 0 100n
+//! D1 out 0 1N4148
+//! Q1 c b e 2N3904
+//! .model 1N4148 D(IS=2.52n RS=0.568 N=1.752)
+//! .end
+//! ```
+//!
+//! Values are read like SPICE does: scale suffixes are case insensitive,
+//! so "M" is milli and mega is "meg", and letters after them are units
+//! that are ignored ("10uF"). Node "0" is ground, other node names are
+//! numbered in order of appearance.
+
+use crate::{
+    BJTParameters, Bjt, Capacitor, Component, CurrentSource, Diode, DiodeParameters, Inductor,
+    MNANodeInfo, MNASystem, Resistor, TransistorType, VoltageSource,
+};
+use std::collections::HashMap;
+
+/// Device model from a `.model` line
+enum SpiceModel {
+    Diode(DiodeParameters),
+    Transistor(BJTParameters),
+}
+
+/// Power of ten of SPICE suffixes, checked in order so "meg" wins over "m"
+const SPICE_SUFFIXES: [(&str, i32); 9] = [
+    ("meg", 6),
+    ("f", -15),
+    ("p", -12),
+    ("n", -9),
+    ("u", -6),
+    ("m", -3),
+    ("k", 3),
+    ("g", 9),
+    ("t", 12),
+];
+
+/// Value of a netlist token the way SPICE reads it, eg. "4.7k" or "10uF"
+fn spice_value(token: &str, line: usize) -> Result<f64, String> {
+    let bad = || format!("line {}: bad value {}", line, token);
+    let lower = token.to_ascii_lowercase();
+    // longest number at the start, which rules out "inf" and "nan"
+    if !lower.starts_with(|c: char| c.is_ascii_digit() || "+-.".contains(c)) {
+        return Err(bad());
+    }
+    let (number, value) = (1..=lower.len())
+        .rev()
+        .filter(|n| lower.is_char_boundary(*n))
+        .find_map(|n| Some((n, lower[..n].parse::<f64>().ok()?)))
+        .ok_or_else(bad)?;
+    let rest = &lower[number..];
+    let (rest, exponent) = SPICE_SUFFIXES
+        .iter()
+        .find_map(|(suffix, exponent)| Some((rest.strip_prefix(suffix)?, *exponent)))
+        .unwrap_or((rest, 0));
+    // whatever letters are left are the unit
+    if !rest.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(bad());
+    }
+    // dividing by an exact power of ten rounds once, so "10u" is 1e-5
+    Ok(if exponent < 0 {
+        value / 10f64.powi(-exponent)
+    } else {
+        value * 10f64.powi(exponent)
+    })
+}
+
+/// Parse `.model NAME TYPE(KEY=VALUE ...)`, unknown keys are ignored
+fn parse_model(rest: &str, line: usize) -> Result<(String, SpiceModel), String> {
+    let rest = rest.replace(['(', ')', ','], " ");
+    let mut tokens = rest.split_whitespace();
+    let (Some(name), Some(kind)) = (tokens.next(), tokens.next()) else {
+        return Err(format!("line {}: incomplete .model", line));
+    };
+    let mut params = vec![];
+    for token in tokens {
+        let Some((key, value)) = token.split_once('=') else {
+            return Err(format!("line {}: bad model parameter {}", line, token));
+        };
+        params.push((key.to_ascii_lowercase(), spice_value(value, line)?));
+    }
+    let model = match kind.to_ascii_uppercase().as_str() {
+        "D" => {
+            let mut p = DiodeParameters::default();
+            for (key, value) in params {
+                match key.as_str() {
+                    "is" => p.is = value,
+                    "n" => p.n = value,
+                    "rs" => p.rs = value,
+                    _ => {}
+                }
+            }
+            SpiceModel::Diode(p)
+        }
+        kind @ ("NPN" | "PNP") => {
+            let mut p = BJTParameters::default();
+            if kind == "PNP" {
+                p.transistor_type = TransistorType::Pnp;
+            }
+            for (key, value) in params {
+                match key.as_str() {
+                    "is" => p.is = value,
+                    "bf" => p.bf = value,
+                    "br" => p.br = value,
+                    "nf" => p.n = value,
+                    "rb" => p.rb = value,
+                    "re" => p.re = value,
+                    "rc" => p.rc = value,
+                    "cje" => p.cje = value,
+                    "cjc" => p.cjc = value,
+                    "tf" => p.tf = value,
+                    "tr" => p.tr = value,
+                    _ => {}
+                }
+            }
+            SpiceModel::Transistor(p)
+        }
+        _ => return Err(format!("line {}: unknown model type {}", line, kind)),
+    };
+    Ok((name.to_ascii_uppercase(), model))
+}
+
+/// Parse a netlist into a system sized for its nodes and its components
+///
+/// Components are not reserved in the system yet, see
+/// `Simulator::from_netlist`. Like in SPICE the first line is the title.
+/// Lines starting with `*` are comments, dot commands other than `.model`
+/// and `.control` blocks are skipped. Diodes and transistors without a
+/// model name get the default parameters.
+pub fn parse_spice(text: &str) -> Result<(MNASystem, Vec<Box<dyn Component>>), String> {
+    let mut control = false;
+    let lines: Vec<(usize, Vec<&str>)> = text
+        .lines()
+        .enumerate()
+        .skip(1)
+        .map(|(i, line)| (i + 1, line.split_whitespace().collect::<Vec<_>>()))
+        .filter(|(_, tokens)| tokens.first().is_some_and(|t| !t.starts_with('*')))
+        .filter(|(_, tokens)| {
+            // simulator commands for ngspice
+            let keyword = tokens[0].to_ascii_lowercase();
+            control = (control || keyword == ".control") && keyword != ".endc";
+            !control && keyword != ".endc"
+        })
+        .collect();
+    // models can come after the elements using them
+    let mut models = HashMap::new();
+    for (number, tokens) in &lines {
+        if tokens[0].eq_ignore_ascii_case(".model") {
+            let (name, model) = parse_model(&tokens[1..].join(" "), *number)?;
+            models.insert(name, model);
+        }
+    }
+    let mut names: Vec<String> = vec!["0".to_string()];
+    let mut components: Vec<Box<dyn Component>> = vec![];
+    for (number, tokens) in &lines {
+        let number = *number;
+        let element = tokens[0];
+        if element.starts_with('.') {
+            continue;
+        }
+        let pins = match element.chars().next().map(|c| c.to_ascii_uppercase()) {
+            Some('Q') => 3,
+            Some('R' | 'C' | 'L' | 'V' | 'I' | 'D') => 2,
+            _ => return Err(format!("line {}: unknown element {}", number, element)),
+        };
+        if tokens.len() < 1 + pins {
+            return Err(format!("line {}: {} needs {} nodes", number, element, pins));
+        }
+        let nets: Vec<usize> = tokens[1..1 + pins]
+            .iter()
+            .map(|name| match names.iter().position(|n| n == name) {
+                Some(i) => i,
+                None => {
+                    names.push(name.to_string());
+                    names.len() - 1
+                }
+            })
+            .collect();
+        let args = &tokens[1 + pins..];
+        let model = match args.first() {
+            Some(name) => models.get(&name.to_ascii_uppercase()),
+            None => None,
+        };
+        let value = || match args.first() {
+            Some(v) => spice_value(v, number),
+            None => Err(format!("line {}: {} needs a value", number, element)),
+        };
+        let component: Box<dyn Component> = match element.as_bytes()[0].to_ascii_uppercase() {
+            b'R' => Box::new(Resistor::new(value()?, nets[0], nets[1])),
+            b'C' => Box::new(Capacitor::new(value()?, nets[0], nets[1])),
+            b'L' => Box::new(Inductor::new(value()?, nets[0], nets[1])),
+            b'V' | b'I' => {
+                // "DC" keyword is optional, "AC" magnitude follows the DC value
+                let (mut dc, mut ac) = (None, 0.0);
+                let mut i = 0;
+                while i < args.len() {
+                    let keyword = args[i].to_ascii_uppercase();
+                    match (keyword.as_str(), args.get(i + 1)) {
+                        ("DC", Some(v)) => dc = Some(spice_value(v, number)?),
+                        ("AC", Some(v)) => ac = spice_value(v, number)?,
+                        _ => {
+                            dc = Some(spice_value(args[i], number)?);
+                            i += 1;
+                            continue;
+                        }
+                    }
+                    i += 2;
+                }
+                let dc = dc.unwrap_or(0.0);
+                if element.as_bytes()[0].eq_ignore_ascii_case(&b'V') {
+                    Box::new(VoltageSource::with_ac(dc, ac, nets[0], nets[1]))
+                } else {
+                    // SPICE current flows through the source from n+ to n-
+                    Box::new(CurrentSource::new(dc, nets[1], nets[0]))
+                }
+            }
+            b'D' => match (args.first(), model) {
+                (None, _) => Box::new(Diode::new(nets[0], nets[1], DiodeParameters::default())),
+                (Some(_), Some(SpiceModel::Diode(p))) => {
+                    Box::new(Diode::new(nets[0], nets[1], p.clone()))
+                }
+                (Some(name), _) => {
+                    return Err(format!("line {}: no diode model {}", number, name));
+                }
+            },
+            _ => {
+                // collector, base, emitter in SPICE order
+                let params = match (args.first(), model) {
+                    (None, _) => BJTParameters::default(),
+                    (Some(_), Some(SpiceModel::Transistor(p))) => p.clone(),
+                    (Some(name), _) => {
+                        return Err(format!("line {}: no transistor model {}", number, name));
+                    }
+                };
+                Box::new(Bjt::new(nets[1], nets[0], nets[2], params))
+            }
+        };
+        components.push(component);
+    }
+    let mut system = MNASystem::default();
+    system.set_size(names.len());
+    for (node, name) in system.nodes.iter_mut().zip(&names) {
+        *node = MNANodeInfo::new_voltage_with_name(name);
+    }
+    Ok((system, components))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Model, Simulator};
+    use float_cmp::approx_eq;
+
+    #[test]
+    fn test_parse_spice() -> Result<(), String> {
+        let text = "RC divider
+V1 in 0 DC 10
+R1 in out 3k
+R2 out 0 1k
+C1 out 0 100n
+.end
+";
+        let (system, components) = parse_spice(text)?;
+        assert_eq!(system.net_size, 3);
+        assert_eq!(components.len(), 4);
+        let mut sim = Simulator::from_netlist(system, components);
+        assert_eq!(sim.system.nodes[2].name, "out");
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        assert!(approx_eq!(f64, sim.system.b[2].lu, 2.5, epsilon = 1e-9));
+        // models can follow the parts using them, node "0" is ground
+        let text = "transistor bias
+Q1 c b 0 small
+V1 c 0 5
+I1 0 b 10u
+.model small NPN(BF=50 IS=1e-14)
+";
+        let (system, components) = parse_spice(text)?;
+        let mut sim = Simulator::from_netlist(system, components);
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        // 10uA pushed into the base turns the transistor on
+        let vbe = sim.system.b[2].lu;
+        assert!(vbe > 0.5 && vbe < 0.8, "vbe {}", vbe);
+        let err = parse_spice("title\nD1 a 0 missing\n").err();
+        assert_eq!(err.as_deref(), Some("line 2: no diode model missing"));
+        assert!(parse_spice("title\nR1 a 0 4.7x%\n").is_err());
+        // divider from the ngspice examples, 1V over 1k and 2k
+        let (system, components) = parse_spice(include_str!("../test/t1.cir"))?;
+        let mut sim = Simulator::from_netlist(system, components);
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        assert!(approx_eq!(
+            f64,
+            sim.system.b[2].lu,
+            2.0 / 3.0,
+            epsilon = 1e-9
+        ));
+        // control blocks are for ngspice
+        let text = "title\nR1 a 0 1k\n.control\ntran 1u 1m\n.endc\n.end\n";
+        assert_eq!(parse_spice(text)?.1.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_spice_value() -> Result<(), String> {
+        // suffixes in any case, "M" is milli
+        for (token, expected) in [
+            ("10K", 10e3),
+            ("10k", 10e3),
+            ("1M", 1e-3),
+            ("1F", 1e-15),
+            ("1Meg", 1e6),
+            ("2.2MEG", 2.2e6),
+            ("100p", 100e-12),
+            ("3N", 3e-9),
+            ("4.7u", 4.7e-6),
+            ("1G", 1e9),
+            ("2T", 2e12),
+            ("1e-3", 1e-3),
+            ("-5", -5.0),
+            (".5", 0.5),
+        ] {
+            let v = spice_value(token, 1)?;
+            assert!(
+                (v - expected).abs() <= 1e-12 * expected.abs(),
+                "{}: {}",
+                token,
+                v
+            );
+        }
+        // units after the suffix are ignored
+        assert_eq!(spice_value("10uF", 1)?, 10e-6);
+        assert_eq!(spice_value("4.7kOhm", 1)?, 4.7e3);
+        assert_eq!(spice_value("5V", 1)?, 5.0);
+        for token in ["", "k", "nan", "inf", "1k%", "4.7x%"] {
+            assert!(spice_value(token, 1).is_err(), "{}", token);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_spice_round_trip() -> Result<(), String> {
+        // divider with a sense ammeter, loaded by a diode
+        let model = Model::from_json(
+            r#"{
+                "nets": ["GND", "in", "out", "mid"],
+                "components": [
+                    { "type": "VoltageSource", "v": 5.0, "nets": [1, 0] },
+                    { "type": "Resistor", "r": 1e3, "nets": [1, 2] },
+                    { "type": "Resistor", "r": 2.2e6, "nets": [2, 0] },
+                    { "type": "Ammeter", "nets": [2, 3] },
+                    { "type": "Diode", "nets": [3, 0], "params": { "is": 1e-14, "n": 1.5 } }
+                ]
+            }"#,
+        )
+        .map_err(|e| e.to_string())?;
+        let json: serde_json::Value =
+            serde_json::from_str(&model.to_json().map_err(|e| e.to_string())?)
+                .map_err(|e| e.to_string())?;
+        let text = crate::spice::model_to_spice(&json);
+        assert!(text.contains("R2 2 0 2.2Meg"));
+        let (system, components) = parse_spice(&text)?;
+        let mut copy = Simulator::from_netlist(system, components);
+        let mut sim = model.build().map_err(|e| e.to_string())?;
+        for s in [&mut sim, &mut copy] {
+            s.build_system();
+            s.solve_dc().map_err(|e| e.to_string())?;
+        }
+        for n in 1..4 {
+            let (a, b) = (sim.system.b[n].lu, copy.system.b[n].lu);
+            assert!(
+                approx_eq!(f64, a, b, epsilon = 1e-9),
+                "node {}: {} {}",
+                n,
+                a,
+                b
+            );
+        }
+        Ok(())
+    }
+}
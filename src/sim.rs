@@ -1,5 +1,21 @@
+use num_complex::Complex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::ops::{Index, IndexMut};
+
+mod measure;
+mod netlist;
+mod sparse;
+#[cfg(test)]
+mod spice;
+mod units;
+mod wav;
+use sparse::{PivotOrder, SparseMatrix};
+use units::{format_unit_value, parse_unit_value};
+
 /// Show pivot details in LU factorization
-const VERBOSE_LU: bool = true;
+const VERBOSE_LU: bool = false;
 
 /// Stepsize for linearization of non-linear components
 const G_MIN: f64 = 1e-12;
@@ -10,9 +26,67 @@ const V_TOLERANCE: f64 = 5e-5;
 /// Thermal voltage for diode and transistor model
 const V_THERMAL: f64 = 0.026;
 
+/// Elementary charge in coulombs, for shot and thermal noise
+const Q_ELECTRON: f64 = 1.602176634e-19;
+
+/// Smallest system size that is checked for a banded structure
+const BANDED_MIN_SIZE: usize = 16;
+
+/// Banded LU is used when the band (with pivoting fill) is at most
+/// this fraction of the system size
+const BANDED_MAX_FRACTION: f64 = 0.25;
+
+/// Systems of this size and up are solved with the sparse LU
+const SPARSE_MIN_SIZE: usize = 100;
+
 /// Maximum number of iterations in main netlist loop
 const MAX_ITER: u32 = 200;
 
+/// Conductance tying a pinned node to its voltage, makes it act like a source
+const G_PIN: f64 = 1e9;
+
+/// Solver settings that can be changed without recompiling
+///
+/// Defaults are the constants above.
+#[derive(Clone, Debug, PartialEq)]
+struct SimOptions {
+    // Newton iterations allowed per solve
+    max_iter: u32,
+    // voltage change below which Newton counts as settled
+    v_tolerance: f64,
+    // leakage conductance across nonlinear junctions
+    g_min: f64,
+    // thermal voltage for diode and transistor junctions
+    v_thermal: f64,
+}
+
+impl Default for SimOptions {
+    fn default() -> Self {
+        Self {
+            max_iter: MAX_ITER,
+            v_tolerance: V_TOLERANCE,
+            g_min: G_MIN,
+            v_thermal: V_THERMAL,
+        }
+    }
+}
+
+/// Longest Newton cycle recognized as DC oscillation
+const DC_CYCLE_MAX_PERIOD: usize = 8;
+
+/// Timesteps per shortest period when suggesting a timestep
+const STEPS_PER_PERIOD: f64 = 20.0;
+
+/// Relative size below which the current part of a capacitor state is roundoff
+const STATE_ROUNDOFF: f64 = 64.0 * f64::EPSILON;
+
+/// Finite difference step for checking Jacobians, relative to n*Vt
+/// for junctions and in volts for MOSFETs
+const JACOBIAN_STEP: f64 = 1e-4;
+
+/// Finite difference step in volts for linearizing behavioral sources
+const BEHAVIORAL_STEP: f64 = 1e-6;
+
 //
 // General overview
 // ----------------
@@ -69,24 +143,29 @@ impl MNACell {
     }
 
     /// Restore matrix state and update dynamic values
-    fn update_pre(&mut self, vars: &Vec<f64>) {
-        self.lu = self.pre_lu;
+    fn update_pre(&mut self, vars: &[f64]) {
+        self.lu = self.value(vars);
+    }
+
+    /// Assembled value of cell (pre_lu cache plus dynamic values)
+    fn value(&self, vars: &[f64]) -> f64 {
+        let mut v = self.pre_lu;
         for index in self.g_dyn.iter() {
-            self.lu += vars[*index];
+            v += vars[*index];
         }
+        v
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 enum InfoType {
-    VOLTAGE,
-    CURRENT,
-    COUNT,
+    Voltage,
+    Current,
 }
 
 // this is for keeping track of node information
 // for the purposes of more intelligent plotting
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 struct MNANodeInfo {
     // one auto-range per unit-type
     info_type: InfoType,
@@ -99,43 +178,132 @@ struct MNANodeInfo {
 impl MNANodeInfo {
     fn new_voltage(n: usize) -> Self {
         Self {
-            info_type: InfoType::VOLTAGE,
+            info_type: InfoType::Voltage,
             scale: 1.0,
             name: format!("v{}", n),
         }
     }
     fn new_voltage_with_name(name: &str) -> Self {
         Self {
-            info_type: InfoType::VOLTAGE,
+            info_type: InfoType::Voltage,
             scale: 1.0,
             name: name.into(),
         }
     }
     fn new_voltage_with_name_and_scale(name: &str, scale: f64) -> Self {
         Self {
-            info_type: InfoType::VOLTAGE,
+            info_type: InfoType::Voltage,
             scale,
             name: name.into(),
         }
     }
     fn new_current(name: &str) -> Self {
         Self {
-            info_type: InfoType::CURRENT,
+            info_type: InfoType::Current,
             scale: 1.0,
             name: name.into(),
         }
     }
     fn new_current_with_scale(name: &str, scale: f64) -> Self {
         Self {
-            info_type: InfoType::CURRENT,
+            info_type: InfoType::Current,
             scale,
             name: name.into(),
         }
     }
 }
+/// Value of every matrix cell that isn't stored
+static EMPTY_CELL: MNACell = MNACell {
+    g: 0.0,
+    g_timed: 0.0,
+    g_dyn: Vec::new(),
+    lu: 0.0,
+    pre_lu: 0.0,
+    txt: String::new(),
+};
+
+/// Matrix row that only stores the cells written to, sorted by column
+//
+// Reading a cell that isn't stored gives an empty cell, writing to one
+// stores it. Memory goes with the stamps (and LU fill) rather than the
+// square of the system size.
+#[derive(Debug, Default)]
+struct MNARow {
+    width: usize,
+    cells: Vec<(usize, MNACell)>,
+}
+
+impl MNARow {
+    fn resize(&mut self, n: usize) {
+        self.width = n;
+        self.cells.retain(|(c, _)| *c < n);
+    }
+
+    /// Stored cells and their columns
+    fn stored(&self) -> impl Iterator<Item = (usize, &MNACell)> {
+        self.cells.iter().map(|(c, cell)| (*c, cell))
+    }
+
+    fn stored_mut(&mut self) -> impl Iterator<Item = &mut MNACell> {
+        self.cells.iter_mut().map(|(_, cell)| cell)
+    }
+}
+
+impl Index<usize> for MNARow {
+    type Output = MNACell;
+
+    fn index(&self, c: usize) -> &MNACell {
+        assert!(c < self.width, "column {} outside row of {}", c, self.width);
+        match self.cells.binary_search_by_key(&c, |(col, _)| *col) {
+            Ok(i) => &self.cells[i].1,
+            Err(_) => &EMPTY_CELL,
+        }
+    }
+}
+
+impl IndexMut<usize> for MNARow {
+    fn index_mut(&mut self, c: usize) -> &mut MNACell {
+        assert!(c < self.width, "column {} outside row of {}", c, self.width);
+        let i = match self.cells.binary_search_by_key(&c, |(col, _)| *col) {
+            Ok(i) => i,
+            Err(i) => {
+                self.cells.insert(i, (c, MNACell::default()));
+                i
+            }
+        };
+        &mut self.cells[i].1
+    }
+}
+
 // Store matrix as a vector of rows for easy pivots
 type MNAVector = Vec<MNACell>;
-type MNAMatrix = Vec<MNAVector>;
+type MNAMatrix = Vec<MNARow>;
+
+/// Which LU factorization a solve went through
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum LuPath {
+    #[default]
+    General,
+    /// Reordered band with lower and upper half bandwidths
+    Banded { lower: usize, upper: usize },
+    /// Sparse LU, with the entries elimination added
+    Sparse { fill: usize },
+}
+
+/// Counters for the work done by the linear solver
+#[derive(Debug, Default, PartialEq)]
+struct SimulationStats {
+    general_solves: usize,
+    banded_solves: usize,
+    sparse_solves: usize,
+    // sparse solves that had to search for a new pivot order
+    pivot_searches: usize,
+    // sparse solves that had to take the structure of A again
+    sparse_patterns: usize,
+    // banded solves that had to order the nets again
+    band_plans: usize,
+    last_path: LuPath,
+}
 
 // Stores A and b for A*x - b = 0, where x is the solution.
 //
@@ -147,8 +315,48 @@ struct MNASystem {
     a_matrix: MNAMatrix,
     b: MNAVector,
     time: f64,
+    // 1/dt of the current step, 0 for DC
+    step_scale: f64,
     net_size: usize,
     vars: Vec<f64>,
+    // small-signal excitation for AC analysis, by node
+    ac: Vec<f64>,
+    // original row of each (pivoted) row of A and b
+    rows: Vec<usize>,
+    stats: SimulationStats,
+    // user names of probed nodes, with the node each one reads
+    probes: Vec<(String, usize)>,
+    // pivots of the last sparse LU, reused while the structure is the same
+    pivot_order: Option<PivotOrder>,
+    // structure of A for the sparse LU, kept between solves
+    sparse_pattern: Option<SparsePattern>,
+    // net order and band of A for the banded LU, kept between solves
+    band_plan: Option<BandPlan>,
+}
+
+/// Net order for the banded LU, valid until a net or cell is added
+#[derive(Debug)]
+struct BandPlan {
+    // size of the system and cells stored in A when the order was taken
+    size: usize,
+    stored: usize,
+    // nets 1.. in band order, and the place of each net in it
+    order: Vec<usize>,
+    place: Vec<usize>,
+    // half bandwidths of A in that order
+    lower: usize,
+    upper: usize,
+}
+
+/// Stored cells of A as a sparse matrix, valid until a net or cell is added
+#[derive(Debug)]
+struct SparsePattern {
+    // size of the system and cells stored in A when the pattern was taken
+    size: usize,
+    stored: usize,
+    // (original row, column) of each matrix value, in value order
+    cells: Vec<(usize, usize)>,
+    matrix: SparseMatrix,
 }
 
 impl Default for MNASystem {
@@ -158,8 +366,16 @@ impl Default for MNASystem {
             a_matrix: MNAMatrix::default(),
             b: MNAVector::default(),
             time: 0.0,
+            step_scale: 0.0,
             net_size: 0,
             vars: vec![],
+            ac: vec![],
+            rows: vec![],
+            stats: SimulationStats::default(),
+            probes: vec![],
+            pivot_order: None,
+            sparse_pattern: None,
+            band_plan: None,
         }
     }
 }
@@ -168,9 +384,11 @@ impl MNASystem {
     fn set_size(&mut self, n: usize) {
         self.a_matrix.resize_with(n, Default::default);
         self.b.resize_with(n, Default::default);
+        self.ac.resize(n, 0.0);
+        self.rows = (0..n).collect();
         self.nodes.clear();
         for i in 0..n {
-            self.a_matrix[i].resize_with(n, Default::default);
+            self.a_matrix[i].resize(n);
             self.nodes.push(MNANodeInfo::new_voltage(i));
         }
         self.net_size = n;
@@ -186,19 +404,58 @@ impl MNASystem {
         self.a_matrix[r][c].txt += txt;
     }
 
+    /// Position of original row `r`, wherever pivoting moved it
+    fn row_pos(&self, r: usize) -> usize {
+        self.rows
+            .iter()
+            .position(|x| *x == r)
+            .expect("row in system")
+    }
+
+    /// Right hand side entry for original row `r`
+    fn b_row(&mut self, r: usize) -> &mut MNACell {
+        let pos = self.row_pos(r);
+        &mut self.b[pos]
+    }
+
+    /// Matrix entry for original row `r` and column `c`
+    fn a_cell(&mut self, r: usize, c: usize) -> &mut MNACell {
+        let pos = self.row_pos(r);
+        &mut self.a_matrix[pos][c]
+    }
+
+    /// Add small-signal excitation on the right hand side for AC analysis
+    fn stamp_ac(&mut self, value: f64, r: usize) {
+        self.ac[r] += value;
+    }
+
     /// Reserve a fresh net position for a component's internal use
     fn reserve(&mut self) -> usize {
         let sz = self.net_size;
         self.net_size += 1;
         self.set_size(self.net_size);
-        return sz;
+        sz
+    }
+
+    /// Give node a probe name, replacing any probe of that name
+    fn register_probe(&mut self, name: &str, node: usize) {
+        self.probes.retain(|(n, _)| n != name);
+        self.probes.push((name.to_string(), node));
+    }
+
+    /// Node of the probe with this name
+    fn probe(&self, name: &str) -> Option<usize> {
+        self.probes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, node)| *node)
     }
 
     /// Reserve a fresh dynamic variable for a component's state tracking
     fn reserve_dynamic(&mut self) -> usize {
         let sz = self.vars.len();
         self.vars.push(0.);
-        return sz;
+        sz
     }
 
     /// Let component update dynamic value that is referenced in cells
@@ -215,6 +472,565 @@ impl MNASystem {
         self.a_matrix[r][c].g_dyn.push(index);
         self.a_matrix[r][c].txt = String::from(text);
     }
+
+    /// Where each original row of A and b is after pivoting
+    fn row_positions(&self) -> Vec<usize> {
+        let mut pos: Vec<usize> = (0..self.net_size).collect();
+        for (p, r) in self.rows.iter().enumerate() {
+            pos[*r] = p;
+        }
+        pos
+    }
+
+    /// Assembled diagonal and rhs of an original row, wherever it is pivoted
+    fn assembled_row(&self, r: usize) -> (f64, f64) {
+        let p = self.row_positions()[r];
+        (self.assembled_a(r, r), self.b[p].value(&self.vars))
+    }
+
+    /// Assembled entry of A in an original row, wherever it is pivoted
+    fn assembled_a(&self, r: usize, c: usize) -> f64 {
+        self.a_matrix[self.row_positions()[r]][c].value(&self.vars)
+    }
+
+    /// Initialize matrix for LU and save it to cache
+    fn init_lu(&mut self, step_scale: f64) {
+        self.step_scale = step_scale;
+        for i in 0..self.net_size {
+            self.b[i].init_lu(step_scale);
+            for cell in self.a_matrix[i].stored_mut() {
+                cell.init_lu(step_scale);
+            }
+        }
+    }
+
+    /// Restore matrix state from cache and add dynamic values
+    fn update_pre(&mut self) {
+        for i in 0..self.net_size {
+            self.b[i].update_pre(&self.vars);
+            for cell in self.a_matrix[i].stored_mut() {
+                cell.update_pre(&self.vars);
+            }
+        }
+    }
+
+    /// Solve the system using the current `lu` values, leaving solution in `b[i].lu`
+    //
+    // Row and column 0 are ground and are skipped.
+    fn solve(&mut self) -> Result<(), SimError> {
+        if let Some(path) = self.solve_sparse() {
+            self.stats.sparse_solves += 1;
+            self.stats.last_path = path;
+            return Ok(());
+        }
+        if let Some(path) = self.solve_banded() {
+            self.stats.banded_solves += 1;
+            self.stats.last_path = path;
+            return Ok(());
+        }
+        self.lu_factor()?;
+        self.lu_forward();
+        self.lu_solve();
+        self.stats.general_solves += 1;
+        self.stats.last_path = LuPath::General;
+        Ok(())
+    }
+
+    /// Order nets for the banded LU from the cells stored in A
+    //
+    // Nets 1.. go in reverse Cuthill-McKee order, keeping connected nets
+    // close. The structure only changes when nets or cells are added, so
+    // this runs once per `build_system` and the solves reuse it.
+    fn plan_band(&mut self) {
+        let n = self.net_size;
+        let mut pos = vec![0; n];
+        for (p, r) in self.rows.iter().enumerate() {
+            pos[*r] = p;
+        }
+        let stored = self.a_matrix.iter().map(|row| row.cells.len()).sum();
+        let mut adjacent = vec![vec![]; n];
+        for r in 1..n {
+            for (c, _) in self.a_matrix[pos[r]].stored() {
+                if c > 0 && c < n && c != r {
+                    adjacent[r].push(c);
+                    adjacent[c].push(r);
+                }
+            }
+        }
+        for a in adjacent.iter_mut() {
+            a.sort_unstable();
+            a.dedup();
+        }
+        let mut order = vec![];
+        let mut visited = vec![false; n];
+        if n > 0 {
+            visited[0] = true;
+        }
+        while order.len() + 1 < n {
+            // start every connected part from its least connected net
+            let start = (1..n)
+                .filter(|i| !visited[*i])
+                .min_by_key(|i| adjacent[*i].len())
+                .expect("unvisited net");
+            visited[start] = true;
+            let mut queue = VecDeque::from([start]);
+            while let Some(i) = queue.pop_front() {
+                order.push(i);
+                let mut next: Vec<usize> = adjacent[i]
+                    .iter()
+                    .copied()
+                    .filter(|j| !visited[*j])
+                    .collect();
+                next.sort_by_key(|j| adjacent[*j].len());
+                for j in next {
+                    visited[j] = true;
+                    queue.push_back(j);
+                }
+            }
+        }
+        order.reverse();
+        let mut place = vec![0; n];
+        for (k, net) in order.iter().enumerate() {
+            place[*net] = k;
+        }
+        let (mut lower, mut upper) = (0, 0);
+        for (r, a) in adjacent.iter().enumerate().skip(1) {
+            for c in a {
+                lower = lower.max(place[r].saturating_sub(place[*c]));
+                upper = upper.max(place[*c].saturating_sub(place[r]));
+            }
+        }
+        self.stats.band_plans += 1;
+        self.band_plan = Some(BandPlan {
+            size: n,
+            stored,
+            order,
+            place,
+            lower,
+            upper,
+        });
+    }
+
+    /// Solve with a banded LU when reordering gives a narrow band
+    //
+    // Works on a band copy of the `lu` values and only writes the
+    // solution back into `b[i].lu`, so A and the row order are untouched.
+    // Returns None (leaving everything as it was) when the band is too
+    // wide or pivoting inside the band fails, the general LU takes over.
+    fn solve_banded(&mut self) -> Option<LuPath> {
+        let n = self.net_size;
+        if n < BANDED_MIN_SIZE {
+            return None;
+        }
+        let stored: usize = self.a_matrix.iter().map(|row| row.cells.len()).sum();
+        if self
+            .band_plan
+            .as_ref()
+            .is_none_or(|p| (p.size, p.stored) != (n, stored))
+        {
+            self.plan_band();
+        }
+        let plan = self.band_plan.as_ref()?;
+        let (lower, upper) = (plan.lower, plan.upper);
+        // row swaps push the upper band out by up to `lower`
+        let width = 2 * lower + upper + 1;
+        if width as f64 > BANDED_MAX_FRACTION * n as f64 {
+            return None;
+        }
+        let mut pos = vec![0; n];
+        for (p, r) in self.rows.iter().enumerate() {
+            pos[*r] = p;
+        }
+        // each row keeps `w` columns from `starts[r]`, which moves up to the
+        // pivot column once the row is inside the pivoting window
+        let m = plan.order.len();
+        let w = lower + upper + 1;
+        let mut starts: Vec<usize> = (0..m).map(|r| r.saturating_sub(lower)).collect();
+        let mut a = vec![vec![0.0; w]; m];
+        for (r, net) in plan.order.iter().enumerate() {
+            for (c, cell) in self.a_matrix[pos[*net]].stored() {
+                if c == 0 || cell.lu == 0.0 {
+                    continue;
+                }
+                let k = plan.place[c].checked_sub(starts[r]).filter(|k| *k < w)?;
+                a[r][k] = cell.lu;
+            }
+        }
+        let mut x: Vec<f64> = plan.order.iter().map(|r| self.b[pos[*r]].lu).collect();
+        for p in 0..m {
+            let last = m.min(p + lower + 1);
+            for r in p..last {
+                let shift = p - starts[r];
+                a[r].rotate_left(shift);
+                a[r][w - shift..].fill(0.0);
+                starts[r] = p;
+            }
+            let pr = (p..last).max_by(|i, j| a[*i][0].abs().total_cmp(&a[*j][0].abs()))?;
+            if VERBOSE_LU {
+                eprintln!(
+                    "banded LU pivot {} (net {}) = {:e}",
+                    p, plan.order[pr], a[pr][0]
+                );
+            }
+            if a[pr][0] == 0.0 {
+                return None;
+            }
+            a.swap(p, pr);
+            x.swap(p, pr);
+            for r in p + 1..last {
+                let f = a[r][0] / a[p][0];
+                if f == 0.0 {
+                    continue;
+                }
+                let (above, below) = a.split_at_mut(r);
+                for (x, y) in below[0][1..].iter_mut().zip(&above[p][1..]) {
+                    *x -= f * y;
+                }
+                x[r] -= f * x[p];
+            }
+        }
+        for r in (0..m).rev() {
+            let end = w.min(m - r);
+            let sum: f64 = (1..end).map(|k| a[r][k] * x[r + k]).sum();
+            x[r] = (x[r] - sum) / a[r][0];
+        }
+        // solution goes by net index, like the general path
+        for (k, net) in plan.order.iter().enumerate() {
+            self.b[*net].lu = x[k];
+        }
+        Some(LuPath::Banded { lower, upper })
+    }
+
+    /// Put the stored `lu` values of A into the sparse pattern, returns b
+    //
+    // Rows are in original order, without ground. The pattern has every
+    // stored cell, zero or not, so it only changes when cells are added
+    // (by new stamps or general LU fill) and Newton iterations reuse it.
+    fn update_sparse(&mut self) -> Vec<f64> {
+        let n = self.net_size;
+        let mut pos = vec![0; n];
+        for (p, r) in self.rows.iter().enumerate() {
+            pos[*r] = p;
+        }
+        let stored = self.a_matrix.iter().map(|row| row.cells.len()).sum();
+        if self
+            .sparse_pattern
+            .as_ref()
+            .is_none_or(|p| (p.size, p.stored) != (n, stored))
+        {
+            let cells: Vec<(usize, usize)> = (1..n)
+                .flat_map(|r| {
+                    self.a_matrix[pos[r]]
+                        .stored()
+                        .filter(|(c, _)| *c > 0)
+                        .map(move |(c, _)| (r, c))
+                })
+                .collect();
+            let entries = cells.iter().map(|(r, c)| (r - 1, c - 1, 0.0));
+            let matrix = SparseMatrix::from_entries(n - 1, entries);
+            self.stats.sparse_patterns += 1;
+            self.sparse_pattern = Some(SparsePattern {
+                size: n,
+                stored,
+                cells,
+                matrix,
+            });
+        }
+        let pattern = self.sparse_pattern.as_mut().expect("sparse pattern");
+        for (v, (r, c)) in pattern.matrix.values_mut().iter_mut().zip(&pattern.cells) {
+            *v = self.a_matrix[pos[*r]][*c].lu;
+        }
+        (1..n).map(|r| self.b[pos[r]].lu).collect()
+    }
+
+    /// Solve large systems with a sparse LU of the stored `lu` values
+    //
+    // Like the banded path A and the row order are untouched, only the
+    // solution is written back into `b[i].lu`. Returns None for small or
+    // singular systems, the other paths take over.
+    //
+    // The pivot order is searched for once and reused as long as the
+    // structure of A stays the same and the pivots stay large enough.
+    fn solve_sparse(&mut self) -> Option<LuPath> {
+        if self.net_size < SPARSE_MIN_SIZE {
+            return None;
+        }
+        let rhs = self.update_sparse();
+        let a = &self.sparse_pattern.as_ref()?.matrix;
+        let cached = self
+            .pivot_order
+            .as_ref()
+            .and_then(|order| a.refactor(order));
+        let lu = match cached {
+            Some(lu) => lu,
+            None => {
+                let lu = a.factor()?;
+                if VERBOSE_LU {
+                    for (k, (r, c)) in lu.pivots().iter().enumerate() {
+                        eprintln!("sparse LU pivot {} (row {}, net {})", k, r + 1, c + 1);
+                    }
+                }
+                self.stats.pivot_searches += 1;
+                self.pivot_order = Some(lu.order().clone());
+                lu
+            }
+        };
+        for (net, x) in lu.solve(&rhs).into_iter().enumerate() {
+            self.b[net + 1].lu = x;
+        }
+        Some(LuPath::Sparse { fill: lu.fill() })
+    }
+
+    fn lu_factor(&mut self) -> Result<(), SimError> {
+        let n = self.net_size;
+        for p in 1..n {
+            // find pivot
+            let mut pr = p;
+            for r in p..n {
+                if self.a_matrix[r][p].lu.abs() > self.a_matrix[pr][p].lu.abs() {
+                    pr = r;
+                }
+            }
+            // swap if necessary
+            if pr != p {
+                self.a_matrix.swap(p, pr);
+                self.b.swap(p, pr);
+                self.rows.swap(p, pr);
+            }
+            if VERBOSE_LU {
+                eprintln!(
+                    "LU pivot {} (row {}) = {:e}",
+                    p, self.rows[p], self.a_matrix[p][p].lu
+                );
+            }
+            if self.a_matrix[p][p].lu == 0.0 {
+                return Err(SimError::SingularMatrix(p));
+            }
+            // take reciprocal for D entry
+            self.a_matrix[p][p].lu = 1.0 / self.a_matrix[p][p].lu;
+            // perform reduction on rows below
+            for r in p + 1..n {
+                if self.a_matrix[r][p].lu == 0.0 {
+                    continue;
+                }
+                self.a_matrix[r][p].lu *= self.a_matrix[p][p].lu;
+                for c in p + 1..n {
+                    if self.a_matrix[p][c].lu == 0.0 {
+                        continue;
+                    }
+                    self.a_matrix[r][c].lu -= self.a_matrix[p][c].lu * self.a_matrix[r][p].lu;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Forward substitution for the solution vector
+    fn lu_forward(&mut self) {
+        let n = self.net_size;
+        for p in 1..n {
+            for r in p + 1..n {
+                self.b[r].lu -= self.b[p].lu * self.a_matrix[r][p].lu;
+            }
+        }
+    }
+
+    /// Backward substitution, solving nodes from last to first
+    fn lu_solve(&mut self) {
+        let n = self.net_size;
+        for r in (1..n).rev() {
+            for s in r + 1..n {
+                self.b[r].lu -= self.b[s].lu * self.a_matrix[r][s].lu;
+            }
+            self.b[r].lu *= self.a_matrix[r][r].lu;
+        }
+    }
+
+    /// Solve small-signal system at angular frequency w, around current linearization
+    //
+    // Reactive stamps are evaluated at step scale t = jw/2 which turns the
+    // trapezoidal companion models into their exact admittances (eg. jwC).
+    // Dynamic values on the right hand side (state, linearization currents)
+    // are large-signal only, so just the given excitation (by node) drives the system.
+    fn solve_ac(&self, w: f64, excitation: &[f64]) -> Result<Vec<Complex<f64>>, SimError> {
+        let n = self.net_size;
+        let t = Complex::new(0.0, w / 2.0);
+        let mut a: Vec<Vec<Complex<f64>>> = self
+            .a_matrix
+            .iter()
+            .map(|row| {
+                (0..n)
+                    .map(|c| {
+                        let cell = &row[c];
+                        let mut g = cell.g;
+                        for index in cell.g_dyn.iter() {
+                            g += self.vars[*index];
+                        }
+                        t * cell.g_timed + g
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut x: Vec<Complex<f64>> = self
+            .rows
+            .iter()
+            .map(|r| Complex::new(excitation[*r], 0.0))
+            .collect();
+        // same elimination as the real LU, but in one pass
+        for p in 1..n {
+            let mut pr = p;
+            for r in p..n {
+                if a[r][p].norm() > a[pr][p].norm() {
+                    pr = r;
+                }
+            }
+            a.swap(p, pr);
+            x.swap(p, pr);
+            if a[p][p].norm() == 0.0 {
+                return Err(SimError::SingularMatrix(p));
+            }
+            let (top, rest) = a.split_at_mut(p + 1);
+            let pivot_row = &top[p];
+            for (i, row) in rest.iter_mut().enumerate() {
+                let f = row[p] / pivot_row[p];
+                if f.norm() == 0.0 {
+                    continue;
+                }
+                for (cell, v) in row[p..].iter_mut().zip(&pivot_row[p..]) {
+                    *cell -= f * v;
+                }
+                let v = x[p];
+                x[p + 1 + i] -= f * v;
+            }
+        }
+        for r in (1..n).rev() {
+            for s in r + 1..n {
+                let v = x[s];
+                x[r] -= a[r][s] * v;
+            }
+            x[r] /= a[r][r];
+        }
+        x[0] = Complex::new(0.0, 0.0);
+        Ok(x)
+    }
+
+    /// Current solution vector (including ground at index 0)
+    fn solution(&self) -> Vec<f64> {
+        self.b.iter().map(|cell| cell.lu).collect()
+    }
+
+    /// Write assembled A matrix and b vector in Matrix Market format
+    //
+    // Ground is dropped, so net i is row/column i in the 1-based files.
+    // Values are what the dynamic variables give right now, taken from
+    // the sparse pattern, which keeps pivoted rows in variable order.
+    fn write_mtx<W: Write>(&mut self, a: &mut W, b: &mut W) -> io::Result<()> {
+        let n = self.net_size;
+        self.update_pre();
+        let rhs = self.update_sparse();
+        let matrix = &self.sparse_pattern.as_ref().expect("sparse pattern").matrix;
+        let entries: Vec<_> = matrix.entries().filter(|(_, _, v)| *v != 0.0).collect();
+        writeln!(a, "%%MatrixMarket matrix coordinate real general")?;
+        self.write_mtx_names(a)?;
+        writeln!(a, "{} {} {}", n - 1, n - 1, entries.len())?;
+        for (r, c, v) in entries {
+            writeln!(a, "{} {} {:e}", r + 1, c + 1, v)?;
+        }
+        writeln!(b, "%%MatrixMarket matrix array real general")?;
+        self.write_mtx_names(b)?;
+        writeln!(b, "{} 1", n - 1)?;
+        for v in rhs {
+            writeln!(b, "{:e}", v)?;
+        }
+        Ok(())
+    }
+
+    /// Node names as Matrix Market comments
+    fn write_mtx_names<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for i in 1..self.net_size {
+            writeln!(w, "% {} {}", i, self.nodes[i].name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Errors reported by the simulation drivers
+#[derive(Debug, PartialEq)]
+enum SimError {
+    /// LU factorization found no usable pivot for this row
+    SingularMatrix(usize),
+    /// Newton loop ran out of iterations without settling
+    NoConvergence(u32),
+    /// Newton loop is cycling between states with this period,
+    /// so there is no stable DC operating point to find
+    NoDcSolution(usize),
+    /// Run configuration is missing something or has the wrong type
+    BadConfig(String),
+    /// Results could not be written
+    Output(String),
+    /// Circuit model JSON is malformed or uses something unsupported
+    BadModel(String),
+}
+
+impl std::fmt::Display for SimError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SimError::SingularMatrix(row) => write!(f, "singular matrix at row {}", row),
+            SimError::NoConvergence(iter) => {
+                write!(f, "no convergence after {} iterations", iter)
+            }
+            SimError::NoDcSolution(period) => write!(
+                f,
+                "no stable DC solution (Newton cycles with period {}), try transient analysis",
+                period
+            ),
+            SimError::BadConfig(msg) => write!(f, "bad run config: {}", msg),
+            SimError::Output(msg) => write!(f, "could not write output: {}", msg),
+            SimError::BadModel(msg) => write!(f, "bad circuit model: {}", msg),
+        }
+    }
+}
+
+/// Where one Newton solve stopped without settling
+#[derive(Clone, Debug, PartialEq)]
+struct NewtonFailure {
+    // simulation time of the step, 0 for DC
+    time: f64,
+    iterations: u32,
+    // index of the component whose newton returned false last
+    component: Option<usize>,
+    // worst node of that component by name, with its residual
+    node: Option<(String, f64)>,
+    // largest change of any voltage node in the last iteration
+    max_delta: f64,
+}
+
+/// Failed Newton solves since the system was built
+#[derive(Clone, Debug, Default, PartialEq)]
+struct ConvergenceReport {
+    failures: Vec<NewtonFailure>,
+}
+
+impl std::fmt::Display for ConvergenceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for failure in &self.failures {
+            write!(
+                f,
+                "t={}: {} iterations, max dv {}",
+                format_unit_value(failure.time, "s"),
+                failure.iterations,
+                format_unit_value(failure.max_delta, "V")
+            )?;
+            if let Some(component) = failure.component {
+                write!(f, ", component {} not settled", component)?;
+            }
+            if let Some((name, residual)) = &failure.node {
+                write!(f, " at {} (residual {})", name, residual)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
 }
 
 #[allow(unused)]
@@ -235,35 +1051,91 @@ trait Component {
         self.update_dynamic(m);
     }
 
+    // follow the simulation time, called after time advances and before
+    // the new step is solved so time dependent sources are up to date
+    fn advance(&mut self, m: &mut MNASystem) {}
+
     // return true if we're done - will keep iterating
     // until all the components are happy
-    fn newton(&mut self, m: &mut MNASystem) -> bool {
+    fn newton(&mut self, m: &mut MNASystem, options: &SimOptions) -> bool {
         true
     }
 
     // time-step change, fix their state-variables (used for caps)
     fn scale_time(&mut self, m: &mut MNASystem, _t_old_per_new: f64) {}
-}
 
-const UNIT_VALUE_OFFSET: i32 = 4;
-const UNIT_VALUE_MAX: i32 = 8;
-const UNIT_VALUE_SUFFIXES: [&'static str; UNIT_VALUE_MAX as usize] =
-    ["p", "n", "u", "m", "", "k", "M", "G"];
+    // highest frequency this component drives into the circuit, if any
+    fn max_frequency(&self) -> Option<f64> {
+        None
+    }
+
+    // rough time constant of a reactive component, once stamped
+    fn time_constant(&self, m: &MNASystem) -> Option<f64> {
+        None
+    }
+
+    // warnings for a solved operating point that is outside
+    // of what the component model is good for
+    fn range_warnings(&self, m: &MNASystem) -> Vec<String> {
+        vec![]
+    }
+
+    // for nonlinear components, relative mismatch of the rows stamped
+    // into the system against a numerical Jacobian of the device
+    fn jacobian_error(&self, m: &MNASystem) -> Option<f64> {
+        None
+    }
+
+    // scale output of an independent source relative to its nominal
+    // value, returns false for components that are not sources
+    fn scale_source(&mut self, m: &mut MNASystem, scale: f64) -> bool {
+        false
+    }
+
+    // change the nominal value of an independent source in an already
+    // stamped system, returns false for components that are not sources
+    fn set_source(&mut self, m: &mut MNASystem, value: f64) -> bool {
+        false
+    }
+
+    // energy held by reactive parts at the last update, by name
+    fn stored_energy(&self) -> Vec<(String, f64)> {
+        vec![]
+    }
+
+    // worst node of a nonlinear component after newton, with how far the
+    // solution there is from where the component is now linearized
+    fn newton_residual(&self, m: &MNASystem) -> Option<(usize, f64)> {
+        None
+    }
+
+    // move a potentiometer wiper in an already stamped system,
+    // returns false for components without one
+    fn move_wiper(&mut self, m: &mut MNASystem, frac: f64) -> bool {
+        false
+    }
+
+    // open or close a switch in an already stamped system,
+    // returns false for components that aren't switches
+    fn set_closed(&mut self, m: &mut MNASystem, closed: bool) -> bool {
+        false
+    }
 
-fn format_unit_value(v: f64, unit: &str) -> String {
-    let mut suff: i32 = UNIT_VALUE_OFFSET + (v.log10() as i32) / 3;
-    if v < 1.0 {
-        suff -= 1;
+    // white noise current sources at the operating point, as
+    // (from, to, spectral density in A^2/Hz)
+    fn noise_sources(&self, m: &MNASystem, options: &SimOptions) -> Vec<(usize, usize, f64)> {
+        vec![]
     }
-    if suff < 0 {
-        suff = 0;
+
+    // plain voltage sources expose themselves for series merging
+    fn voltage_source(&self) -> Option<&VoltageSource> {
+        None
     }
-    if suff > UNIT_VALUE_MAX {
-        suff = UNIT_VALUE_MAX;
+
+    // description for the JSON circuit model, None if not supported
+    fn model(&self) -> Option<ModelComponent> {
+        None
     }
-    let vr = v / f64::powf(10.0, 3.0 * ((suff - UNIT_VALUE_OFFSET) as f64));
-    // Use as many decimals as needed, or none if not needed
-    return format!("{:.}{}{}", vr, UNIT_VALUE_SUFFIXES[suff as usize], unit);
 }
 
 // Components stamp themselves onto MNASystem as they are created.
@@ -273,17 +1145,48 @@ struct Resistor {
     r: f64,
     l0: usize,
     l1: usize,
+    // parasitic series inductance, 0 for an ideal resistor
+    l: f64,
+    // inductor from internal net to l1, when l is not 0
+    series: Option<Inductor>,
 }
 
 impl Resistor {
     fn new(r: f64, l0: usize, l1: usize) -> Self {
-        Self { r, l0, l1 }
+        Self {
+            r,
+            l0,
+            l1,
+            l: 0.0,
+            series: None,
+        }
+    }
+
+    /// Resistor with series lead inductance, for high frequency accuracy
+    fn with_inductance(r: f64, l: f64, l0: usize, l1: usize) -> Self {
+        Self {
+            l,
+            ..Self::new(r, l0, l1)
+        }
     }
 }
 
 impl Component for Resistor {
+    fn reserve(&mut self, m: &mut MNASystem) {
+        if self.l != 0.0 {
+            let mut series = Inductor::new(self.l, m.reserve(), self.l1);
+            series.reserve(m);
+            self.series = Some(series);
+        }
+    }
+
     fn stamp(&self, m: &mut MNASystem) {
-        let (r, l0, l1) = (self.r, self.l0, self.l1);
+        // resistor part ends at the inductor, if there is one
+        let l1 = self.series.as_ref().map_or(self.l1, |s| s.l0);
+        if let Some(series) = &self.series {
+            series.stamp(m);
+        }
+        let (r, l0) = (self.r, self.l0);
         let g = 1.0 / r;
         let txt = format!("R{}", format_unit_value(r, ""));
         m.stamp_static(g, l0, l0, &format!("+{}", txt));
@@ -291,33 +1194,330 @@ impl Component for Resistor {
         m.stamp_static(-g, l1, l0, &format!("-{}", txt));
         m.stamp_static(g, l1, l1, &format!("+{}", txt));
     }
+
+    fn update(&mut self, m: &mut MNASystem) {
+        if let Some(series) = &mut self.series {
+            series.update(m);
+        }
+    }
+
+    fn scale_time(&mut self, m: &mut MNASystem, t_old_per_new: f64) {
+        if let Some(series) = &mut self.series {
+            series.scale_time(m, t_old_per_new);
+        }
+    }
+
+    fn stored_energy(&self) -> Vec<(String, f64)> {
+        self.series.as_ref().map_or(vec![], |l| l.stored_energy())
+    }
+
+    fn noise_sources(&self, _m: &MNASystem, options: &SimOptions) -> Vec<(usize, usize, f64)> {
+        // Johnson-Nyquist 4kT/R, with kT = q * thermal voltage
+        let l1 = self.series.as_ref().map_or(self.l1, |s| s.l0);
+        vec![(self.l0, l1, 4.0 * Q_ELECTRON * options.v_thermal / self.r)]
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::Resistor {
+            r: self.r,
+            l: self.l,
+            nets: [self.l0, self.l1],
+        })
+    }
 }
 
-#[derive(Debug)]
-struct CapacitorReserved {
-    l2: usize,
-    dyn_index: usize,
+/// Resistance law of a potentiometer over its mechanical travel
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+enum Taper {
+    Linear,
+    // audio taper, exponential with the midpoint at LOG_TAPER_MIDPOINT
+    Log,
+}
+
+/// Fraction of resistance at half travel for log taper (A-taper is 10%)
+const LOG_TAPER_MIDPOINT: f64 = 0.1;
+
+/// Smallest resistance of either side of a pot, keeps the ends from shorting
+const POT_R_MIN: f64 = 1e-3;
+
+impl Taper {
+    /// Fraction of resistance for a mechanical position in 0..1
+    fn apply(self, frac: f64) -> f64 {
+        match self {
+            Taper::Linear => frac,
+            Taper::Log => {
+                let m = LOG_TAPER_MIDPOINT;
+                let b = ((1.0 - m) / m).powi(2);
+                (b.powf(frac) - 1.0) / (b - 1.0)
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
-struct Capacitor {
-    c: f64,
+struct Potentiometer {
+    total_r: f64,
+    // mechanical position, 0 at l0 and 1 at l1
+    wiper: f64,
+    taper: Taper,
     l0: usize,
+    lwiper: usize,
     l1: usize,
-    state_var: f64,
-    voltage: f64,
-    reserved: Option<CapacitorReserved>,
 }
 
-impl Capacitor {
-    fn new(c: f64, l0: usize, l1: usize) -> Self {
+impl Potentiometer {
+    fn new(total_r: f64, taper: Taper, l0: usize, lwiper: usize, l1: usize) -> Self {
         Self {
-            c,
+            total_r,
+            wiper: 0.5,
+            taper,
             l0,
+            lwiper,
             l1,
-            state_var: 0.,
-            voltage: 0.,
-            reserved: None,
+        }
+    }
+
+    /// Move wiper to a mechanical position in 0..1
+    fn set_wiper(&mut self, frac: f64) {
+        self.wiper = frac.clamp(0.0, 1.0);
+    }
+
+    /// Resistance from l0 to wiper and from wiper to l1
+    fn resistances(&self) -> (f64, f64) {
+        let k = self.taper.apply(self.wiper);
+        (
+            f64::max(self.total_r * k, POT_R_MIN),
+            f64::max(self.total_r * (1.0 - k), POT_R_MIN),
+        )
+    }
+}
+
+impl Component for Potentiometer {
+    fn stamp(&self, m: &mut MNASystem) {
+        let (r0, r1) = self.resistances();
+        Resistor::new(r0, self.l0, self.lwiper).stamp(m);
+        Resistor::new(r1, self.lwiper, self.l1).stamp(m);
+    }
+
+    fn move_wiper(&mut self, m: &mut MNASystem, frac: f64) -> bool {
+        let (old0, old1) = self.resistances();
+        self.set_wiper(frac);
+        let (r0, r1) = self.resistances();
+        // only the conductances change, swap old values for new ones
+        for (g, a, b) in [
+            (1.0 / r0 - 1.0 / old0, self.l0, self.lwiper),
+            (1.0 / r1 - 1.0 / old1, self.lwiper, self.l1),
+        ] {
+            m.a_cell(a, a).g += g;
+            m.a_cell(a, b).g -= g;
+            m.a_cell(b, a).g -= g;
+            m.a_cell(b, b).g += g;
+        }
+        true
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::Potentiometer {
+            total_r: self.total_r,
+            wiper: self.wiper,
+            taper: self.taper,
+            nets: [self.l0, self.lwiper, self.l1],
+        })
+    }
+}
+
+/// Default resistance of a closed switch
+const SWITCH_R_ON: f64 = 1e-3;
+/// Default resistance of an open switch
+const SWITCH_R_OFF: f64 = 1e9;
+
+/// Switch that can be opened and closed while simulating, eg. a push button
+#[derive(Debug)]
+struct Switch {
+    closed: bool,
+    r_on: f64,
+    r_off: f64,
+    l0: usize,
+    l1: usize,
+}
+
+impl Switch {
+    fn new(closed: bool, l0: usize, l1: usize) -> Self {
+        Self {
+            closed,
+            r_on: SWITCH_R_ON,
+            r_off: SWITCH_R_OFF,
+            l0,
+            l1,
+        }
+    }
+
+    /// Conductance in the current state
+    fn conductance(&self) -> f64 {
+        1.0 / if self.closed { self.r_on } else { self.r_off }
+    }
+}
+
+impl Component for Switch {
+    fn stamp(&self, m: &mut MNASystem) {
+        let (g, l0, l1) = (self.conductance(), self.l0, self.l1);
+        let txt = if self.closed { "S:on" } else { "S:off" };
+        m.stamp_static(g, l0, l0, &format!("+{}", txt));
+        m.stamp_static(-g, l0, l1, &format!("-{}", txt));
+        m.stamp_static(-g, l1, l0, &format!("-{}", txt));
+        m.stamp_static(g, l1, l1, &format!("+{}", txt));
+    }
+
+    fn set_closed(&mut self, m: &mut MNASystem, closed: bool) -> bool {
+        // swap the old conductance for the new one, like a pot wiper
+        let old = self.conductance();
+        self.closed = closed;
+        let g = self.conductance() - old;
+        let (l0, l1) = (self.l0, self.l1);
+        m.a_cell(l0, l0).g += g;
+        m.a_cell(l0, l1).g -= g;
+        m.a_cell(l1, l0).g -= g;
+        m.a_cell(l1, l1).g += g;
+        true
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::Switch {
+            closed: self.closed,
+            r_on: self.r_on,
+            r_off: self.r_off,
+            nets: [self.l0, self.l1],
+        })
+    }
+}
+
+#[derive(Debug)]
+struct VoltageControlledSwitchReserved {
+    // dynamic conductance, and its negative for the cross terms
+    dyn_g: usize,
+    dyn_neg_g: usize,
+}
+
+/// Switch driven by the voltage from `ctrl_p` to `ctrl_n`, eg. a relay
+///
+/// Closes when the control voltage rises above `v_threshold` plus half the
+/// `hysteresis` and opens when it falls below the threshold minus half of
+/// it. The state changes between time steps, in `update`.
+#[derive(Debug)]
+struct VoltageControlledSwitch {
+    ctrl_p: usize,
+    ctrl_n: usize,
+    v_threshold: f64,
+    hysteresis: f64,
+    switch: Switch,
+    reserved: Option<VoltageControlledSwitchReserved>,
+}
+
+impl VoltageControlledSwitch {
+    fn new(
+        ctrl_p: usize,
+        ctrl_n: usize,
+        v_threshold: f64,
+        hysteresis: f64,
+        l0: usize,
+        l1: usize,
+    ) -> Self {
+        Self {
+            ctrl_p,
+            ctrl_n,
+            v_threshold,
+            hysteresis,
+            switch: Switch::new(false, l0, l1),
+            reserved: None,
+        }
+    }
+}
+
+impl Component for VoltageControlledSwitch {
+    fn reserve(&mut self, m: &mut MNASystem) {
+        self.reserved = Some(VoltageControlledSwitchReserved {
+            dyn_g: m.reserve_dynamic(),
+            dyn_neg_g: m.reserve_dynamic(),
+        });
+    }
+
+    fn stamp(&self, m: &mut MNASystem) {
+        // conductance is dynamic so it can change without restamping
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let (l0, l1) = (self.switch.l0, self.switch.l1);
+        m.add_dynamic_a(l0, l0, reserved.dyn_g, "+g:S");
+        m.add_dynamic_a(l0, l1, reserved.dyn_neg_g, "-g:S");
+        m.add_dynamic_a(l1, l0, reserved.dyn_neg_g, "-g:S");
+        m.add_dynamic_a(l1, l1, reserved.dyn_g, "+g:S");
+        self.update_dynamic(m);
+    }
+
+    fn update_dynamic(&self, m: &mut MNASystem) {
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let g = self.switch.conductance();
+        m.set_dynamic(reserved.dyn_g, g);
+        m.set_dynamic(reserved.dyn_neg_g, -g);
+    }
+
+    fn update(&mut self, m: &mut MNASystem) {
+        let v = m.b[self.ctrl_p].lu - m.b[self.ctrl_n].lu;
+        let half = self.hysteresis / 2.0;
+        if v > self.v_threshold + half {
+            self.switch.closed = true;
+        } else if v < self.v_threshold - half {
+            self.switch.closed = false;
+        }
+        self.update_dynamic(m);
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::VoltageControlledSwitch {
+            v_threshold: self.v_threshold,
+            hysteresis: self.hysteresis,
+            closed: self.switch.closed,
+            r_on: self.switch.r_on,
+            r_off: self.switch.r_off,
+            nets: [self.switch.l0, self.switch.l1, self.ctrl_p, self.ctrl_n],
+        })
+    }
+}
+
+#[derive(Debug)]
+struct CapacitorReserved {
+    l2: usize,
+    dyn_index: usize,
+}
+
+#[derive(Debug)]
+struct Capacitor {
+    c: f64,
+    l0: usize,
+    l1: usize,
+    state_var: f64,
+    voltage: f64,
+    // voltage to start transient analysis from, instead of the DC solution
+    ic: Option<f64>,
+    reserved: Option<CapacitorReserved>,
+}
+
+impl Capacitor {
+    fn new(c: f64, l0: usize, l1: usize) -> Self {
+        Self {
+            c,
+            l0,
+            l1,
+            state_var: 0.,
+            voltage: 0.,
+            ic: None,
+            reserved: None,
+        }
+    }
+
+    /// Capacitor charged to `v0` when transient analysis starts
+    fn with_ic(c: f64, v0: f64, l0: usize, l1: usize) -> Self {
+        Self {
+            ic: Some(v0),
+            ..Self::new(c, l0, l1)
         }
     }
 }
@@ -369,7 +1569,7 @@ impl Component for Capacitor {
         m.stamp_timed(-g, l1, l1, &format!("-t*{}", txt));
         m.stamp_static(2. * g, l2, l0, &format!("+2*{}", txt));
         m.stamp_static(-2. * g, l2, l1, &format!("-2*{}", txt));
-        m.stamp_static(-1., l2, l2, &"-1");
+        m.stamp_static(-1., l2, l2, "-1");
 
         m.add_dynamic_b(l2, dyn_index, &format!("q:C:{},{}", l0, l1));
 
@@ -405,705 +1605,8429 @@ impl Component for Capacitor {
         // so we subtract out the voltage, scale current
         // and then add the voltage back to get new state
         //
-        // note that this also works if the old rate is infinite
-        // (ie. t0=0) when going from DC analysis to transient
+        // i/t is current times the timestep, so it shrinks along
+        // with the step and the ratio of steps divides it
+        //
+        // when the old rate is infinite (ie. t0=0) going from DC
+        // analysis to transient there's no current part to keep,
+        // same if the ratio is so extreme it won't stay finite,
+        // and that is where an initial condition replaces the
+        // DC voltage
         //
+        // after very short steps the current part can be lost in
+        // the roundoff of the state, and a longer step would blow
+        // that roundoff up into a bogus current, so it is dropped
+        //
+        if t_old_per_new == 0.0
+            && let Some(v0) = self.ic
+        {
+            self.voltage = v0;
+        }
         let qq = 2. * self.c * self.voltage;
-        self.state_var = qq + (self.state_var - qq) * t_old_per_new;
+        let resolved = (self.state_var - qq).abs() > STATE_ROUNDOFF * qq.abs();
+        let current_part = (self.state_var - qq) / t_old_per_new;
+        self.state_var = if t_old_per_new > 0.0 && current_part.is_finite() && resolved {
+            qq + current_part
+        } else {
+            qq
+        };
         self.update_dynamic(m);
     }
-}
 
-#[derive(Debug)]
-struct VoltageSourceReserved {
-    l2: usize,
+    fn time_constant(&self, m: &MNASystem) -> Option<f64> {
+        // estimate with the static conductance seen at the pins,
+        // which is exact for a single RC and errs on the fast side
+        let g: f64 = [self.l0, self.l1]
+            .iter()
+            .filter(|l| **l != 0)
+            .map(|l| m.a_matrix[*l][*l].g)
+            .sum();
+        if g > 0.0 { Some(self.c / g) } else { None }
+    }
+
+    fn stored_energy(&self) -> Vec<(String, f64)> {
+        let name = format!("C:{},{}", self.l0, self.l1);
+        vec![(name, 0.5 * self.c * self.voltage * self.voltage)]
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::Capacitor {
+            c: self.c,
+            ic: self.ic,
+            nets: [self.l0, self.l1],
+        })
+    }
 }
 
+/// Charge storage with a charge linear in any solution variables
+///
+/// Carries current dq/dt from `l0` to `l1` where q is the sum of k * x
+/// over `terms`, integrated like `Capacitor`, which is the case of
+/// q = c * (v0 - v1). Transistor junctions use it, since their diffusion
+/// charge follows the junction current rather than a voltage.
 #[derive(Debug)]
-struct VoltageSource {
-    v: f64,
+struct ChargeStorage {
+    terms: Vec<(usize, f64)>,
     l0: usize,
     l1: usize,
-    reserved: Option<VoltageSourceReserved>,
+    name: String,
+    state_var: f64,
+    charge: f64,
+    reserved: Option<CapacitorReserved>,
 }
 
-impl VoltageSource {
-    fn new(v: f64, l0: usize, l1: usize) -> Self {
+impl ChargeStorage {
+    fn new(terms: Vec<(usize, f64)>, l0: usize, l1: usize, name: &str) -> Self {
         Self {
-            v,
+            terms,
             l0,
             l1,
+            name: name.to_string(),
+            state_var: 0.,
+            charge: 0.,
             reserved: None,
         }
     }
 }
 
-impl Component for VoltageSource {
+impl Component for ChargeStorage {
     fn reserve(&mut self, m: &mut MNASystem) {
-        self.reserved = Some(VoltageSourceReserved { l2: m.reserve() });
+        self.reserved = Some(CapacitorReserved {
+            l2: m.reserve(),
+            dyn_index: m.reserve_dynamic(),
+        });
     }
 
     fn stamp(&self, m: &mut MNASystem) {
+        // same as the capacitor with c*(v0 - v1) swapped for q:
+        //
+        // | -2*k*t  +t | l0
+        // | +2*k*t  -t | l1
+        // |  +4*k   -1 | state
+        //
+        // with one column of k for every term of the charge
         let reserved = self.reserved.as_ref().expect("need reserved");
-        let (v, l0, l1, l2) = (self.v, self.l0, self.l1, reserved.l2);
-        m.stamp_static(-1., l0, l2, &"-1");
-        m.stamp_static(1., l1, l2, &"+1");
-        m.stamp_static(1., l2, l0, &"+1");
-        m.stamp_static(-1., l2, l1, &"-1");
+        let (l0, l1, l2) = (self.l0, self.l1, reserved.l2);
+        m.stamp_timed(1., l0, l2, "+t");
+        m.stamp_timed(-1., l1, l2, "-t");
+        for (x, k) in &self.terms {
+            let txt = format!("{:.}", k);
+            m.stamp_timed(-2. * k, l0, *x, &format!("-t*2*{}", txt));
+            m.stamp_timed(2. * k, l1, *x, &format!("+t*2*{}", txt));
+            m.stamp_static(4. * k, l2, *x, &format!("+4*{}", txt));
+        }
+        m.stamp_static(-1., l2, l2, "-1");
+        m.add_dynamic_b(l2, reserved.dyn_index, &format!("q:{}", self.name));
+        m.nodes[l2] = MNANodeInfo::new_voltage_with_name(&format!("q:{}", self.name));
+        self.update_dynamic(m);
+    }
 
-        m.b[l2].g = v;
-        m.b[l2].txt = String::from(format!("{:.}V", v));
+    fn update_dynamic(&self, m: &mut MNASystem) {
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        m.set_dynamic(reserved.dyn_index, self.state_var);
+    }
 
-        m.nodes[l2] = MNANodeInfo::new_current(&format!("i:V({:.}:{},{})", v, l0, l1));
+    fn update(&mut self, m: &mut MNASystem) {
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        self.state_var = m.b[reserved.l2].lu;
+        self.charge = self.terms.iter().map(|(x, k)| k * m.b[*x].lu).sum();
+        // show the charge instead of the state, like the capacitor
+        m.b[reserved.l2].lu = self.charge;
+        self.update_dynamic(m);
+    }
+
+    fn scale_time(&mut self, m: &mut MNASystem, t_old_per_new: f64) {
+        // state is 2*q - i/t0, see the capacitor
+        let qq = 2. * self.charge;
+        let current_part = (self.state_var - qq) / t_old_per_new;
+        self.state_var = if t_old_per_new > 0.0 && current_part.is_finite() {
+            qq + current_part
+        } else {
+            qq
+        };
+        self.update_dynamic(m);
     }
 }
 
 #[derive(Debug)]
-struct VoltageProbeReserved {
+struct InductorReserved {
     l2: usize,
+    dyn_index: usize,
 }
 
 #[derive(Debug)]
-struct VoltageProbe {
-    // probe a differential voltage
-    // also forces this voltage to actually get solved :)
+struct Inductor {
+    l: f64,
     l0: usize,
     l1: usize,
-    reserved: Option<VoltageProbeReserved>,
+    // current from l0 to l1 and voltage across, at the last step
+    current: f64,
+    voltage: f64,
+    // current to start transient analysis from, instead of the DC solution
+    ic: Option<f64>,
+    reserved: Option<InductorReserved>,
 }
 
-impl VoltageProbe {
-    fn new(l0: usize, l1: usize) -> Self {
+impl Inductor {
+    fn new(l: f64, l0: usize, l1: usize) -> Self {
         Self {
+            l,
             l0,
             l1,
+            current: 0.0,
+            voltage: 0.0,
+            ic: None,
             reserved: None,
         }
     }
+
+    /// Inductor carrying `i0` from l0 to l1 when transient analysis starts
+    fn with_ic(l: f64, i0: f64, l0: usize, l1: usize) -> Self {
+        Self {
+            ic: Some(i0),
+            ..Self::new(l, l0, l1)
+        }
+    }
 }
 
-impl Component for VoltageProbe {
+impl Component for Inductor {
     fn reserve(&mut self, m: &mut MNASystem) {
-        self.reserved = Some(VoltageProbeReserved { l2: m.reserve() });
+        self.reserved = Some(InductorReserved {
+            l2: m.reserve(),
+            dyn_index: m.reserve_dynamic(),
+        });
     }
+
     fn stamp(&self, m: &mut MNASystem) {
+        // the extra net is the current through the inductor,
+        // and its row is trapezoidal integration of v = L*di/dt:
+        //
+        // |  0     0    +1   | v+
+        // |  0     0    -1   | v-
+        // | +1    -1  -2*L*t | i
+        //
+        // with right hand side -(2*L*t*i0 + v0) from last step, as
+        //  i1 - i0 = (v1 + v0)/(2*L*t), where t = 1/T
+        //
+        // at DC (t=0) this is a short with the current solved
+        // directly, and for AC the timed part becomes -jwL
         let reserved = self.reserved.as_ref().expect("need reserved");
-        let (l0, l1, l2) = (self.l0, self.l1, reserved.l2);
-
-        // vp + vn - vd = 0     so      vp = vd - vn
+        let (l, l0, l1, l2, dyn_index) =
+            (self.l, self.l0, self.l1, reserved.l2, reserved.dyn_index);
+        let txt = format_unit_value(l, "H");
+        m.stamp_static(1., l0, l2, "+1");
+        m.stamp_static(-1., l1, l2, "-1");
         m.stamp_static(1., l2, l0, "+1");
         m.stamp_static(-1., l2, l1, "-1");
-        m.stamp_static(-1., l2, l2, "-1");
-        m.nodes[l2] = MNANodeInfo::new_voltage_with_name(&"v:probe");
+        m.stamp_timed(-2. * l, l2, l2, &format!("-t*2*{}", txt));
+
+        m.add_dynamic_b(l2, dyn_index, &format!("phi:L:{},{}", l0, l1));
+
+        m.nodes[l2] = MNANodeInfo::new_current(&format!("i:L:{},{}", l0, l1));
+        self.update_dynamic(m);
+    }
+
+    fn update_dynamic(&self, m: &mut MNASystem) {
+        // state is kept as plain current and voltage, so
+        // it only depends on the step through step_scale
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let state = 2. * self.l * m.step_scale * self.current + self.voltage;
+        m.set_dynamic(reserved.dyn_index, -state);
+    }
+
+    fn update(&mut self, m: &mut MNASystem) {
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        self.current = m.b[reserved.l2].lu;
+        self.voltage = m.b[self.l0].lu - m.b[self.l1].lu;
+        self.update_dynamic(m);
+    }
+
+    fn scale_time(&mut self, m: &mut MNASystem, t_old_per_new: f64) {
+        // flux is current times inductance, which doesn't depend
+        // on the step, so the state just picks up the new step_scale
+        //
+        // going from DC to transient an initial condition replaces
+        // the DC current, the voltage is unknown until the first step
+        if t_old_per_new == 0.0
+            && let Some(i0) = self.ic
+        {
+            self.current = i0;
+            self.voltage = 0.0;
+        }
+        self.update_dynamic(m);
+    }
+
+    fn stored_energy(&self) -> Vec<(String, f64)> {
+        let name = format!("L:{},{}", self.l0, self.l1);
+        vec![(name, 0.5 * self.l * self.current * self.current)]
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::Inductor {
+            l: self.l,
+            ic: self.ic,
+            nets: [self.l0, self.l1],
+        })
     }
 }
 
 #[derive(Debug)]
-struct VoltageFunctionReserved {
-    dyn_index: usize,
+struct VoltageSourceReserved {
     l2: usize,
 }
 
 #[derive(Debug)]
-struct VoltageFunction {
-    // probe a differential voltage
-    // also forces this voltage to actually get solved :)
+struct VoltageSource {
     v: f64,
-    f: fn(f64) -> f64,
+    // small-signal amplitude for AC analysis
+    ac: f64,
     l0: usize,
     l1: usize,
-    reserved: Option<VoltageFunctionReserved>,
+    // inner nodes of merged series sources, with voltage above l1
+    taps: Vec<(usize, f64)>,
+    reserved: Option<VoltageSourceReserved>,
 }
 
-impl VoltageFunction {
-    fn new(f: fn(f64) -> f64, l0: usize, l1: usize) -> Self {
-        let v = f(0.0);
+impl VoltageSource {
+    fn new(v: f64, l0: usize, l1: usize) -> Self {
         Self {
             v,
-            f,
+            ac: 0.0,
             l0,
             l1,
+            taps: vec![],
             reserved: None,
         }
     }
+
+    /// Combine with a source sharing node `m` into one source across the outer nodes
+    ///
+    /// `m` stays in the circuit as a tap held at its old voltage, so only
+    /// one branch current is needed for the chain.
+    fn merge_series(&self, other: &VoltageSource, m: usize) -> Option<VoltageSource> {
+        // voltage of each outer node above m
+        let above_m = |s: &VoltageSource| {
+            if s.l1 == m {
+                Some((s.l0, s.v))
+            } else if s.l0 == m {
+                Some((s.l1, -s.v))
+            } else {
+                None
+            }
+        };
+        let (p, sp) = above_m(self)?;
+        let (q, sq) = above_m(other)?;
+        if p == q || self.ac != 0.0 || other.ac != 0.0 {
+            return None;
+        }
+        // potentials are now taken above q
+        let potential = |n: usize| {
+            if n == q {
+                0.0
+            } else if n == m {
+                -sq
+            } else {
+                sp - sq
+            }
+        };
+        let mut taps = vec![(m, -sq)];
+        for s in [self, other] {
+            let base = potential(s.l1);
+            taps.extend(s.taps.iter().map(|(t, v)| (*t, v + base)));
+        }
+        Some(VoltageSource {
+            taps,
+            ..VoltageSource::new(sp - sq, p, q)
+        })
+    }
+
+    /// Voltage source that also drives AC analysis with amplitude `ac`
+    fn with_ac(v: f64, ac: f64, l0: usize, l1: usize) -> Self {
+        Self {
+            ac,
+            ..Self::new(v, l0, l1)
+        }
+    }
 }
 
-impl Component for VoltageFunction {
+impl Component for VoltageSource {
     fn reserve(&mut self, m: &mut MNASystem) {
-        self.reserved = Some(VoltageFunctionReserved {
-            l2: m.reserve(),
-            dyn_index: m.reserve_dynamic(),
-        });
+        self.reserved = Some(VoltageSourceReserved { l2: m.reserve() });
     }
 
     fn stamp(&self, m: &mut MNASystem) {
         let reserved = self.reserved.as_ref().expect("need reserved");
-        let (dyn_index, l0, l1, l2) = (
-            reserved.dyn_index,
-            self.l0,
-            self.l1,
-            reserved.l2,
-        );
+        let (v, l0, l1, l2) = (self.v, self.l0, self.l1, reserved.l2);
+        m.stamp_static(-1., l0, l2, "-1");
+        m.stamp_static(1., l1, l2, "+1");
+        m.stamp_static(1., l2, l0, "+1");
+        m.stamp_static(-1., l2, l1, "-1");
 
-        // this is identical to voltage source
-        // except voltage is dynanic
-        m.stamp_static(-1., l0, l2, &"-1");
-        m.stamp_static(1., l1, l2, &"+1");
-        m.stamp_static(1., l2, l0, &"+1");
-        m.stamp_static(-1., l2, l1, &"-1");
+        m.b[l2].g = v;
+        m.b[l2].txt = format!("{:.}V", v);
+        m.stamp_ac(self.ac, l2);
 
-        m.add_dynamic_b(l2, dyn_index, &format!("Vfn:{},{}", l0, l1));
+        m.nodes[l2] = MNANodeInfo::new_current(&format!("i:V({:.}:{},{})", v, l0, l1));
 
-        m.nodes[l2] = MNANodeInfo::new_current(&format!("i:Vfn:{},{}", l0, l1));
-        self.update_dynamic(m);
+        // taps replace their own KCL row, no current flows out of them
+        for (t, vt) in &self.taps {
+            m.stamp_static(1., *t, *t, "+1");
+            m.stamp_static(-1., *t, l1, "-1");
+            m.b[*t].g = *vt;
+            m.b[*t].txt = format!("{:.}V", vt);
+        }
     }
 
-    fn update_dynamic(&self, m: &mut MNASystem) {
+    fn scale_source(&mut self, m: &mut MNASystem, scale: f64) -> bool {
         let reserved = self.reserved.as_ref().expect("need reserved");
-        m.set_dynamic(reserved.dyn_index, self.v);
+        m.b_row(reserved.l2).g = self.v * scale;
+        for (t, vt) in &self.taps {
+            m.b_row(*t).g = vt * scale;
+        }
+        true
     }
-    fn update(&mut self, m: &mut MNASystem) {
-        self.v = (self.f)(m.time);
-        // Update dynamic variable since we changed state_var
-        self.update_dynamic(m);
+
+    fn set_source(&mut self, m: &mut MNASystem, value: f64) -> bool {
+        // taps of merged sources keep their voltages
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        m.b_row(reserved.l2).g = value;
+        self.v = value;
+        true
+    }
+
+    fn voltage_source(&self) -> Option<&VoltageSource> {
+        Some(self)
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::VoltageSource {
+            v: self.v,
+            ac: self.ac,
+            nets: [self.l0, self.l1],
+            taps: self.taps.clone(),
+        })
     }
 }
 
 #[derive(Debug)]
-struct JunctionPN {
-    // variables
-    geq: f64,
-    ieq: f64,
-    veq: f64,
-    // parameters
-    is: f64,
-    nvt: f64,
-    rnvt: f64,
-    vcrit: f64,
+struct CurrentSource {
+    // current pushed into l0, drawn out of l1
+    i: f64,
+    l0: usize,
+    l1: usize,
+    // fraction of i currently stamped, for source stepping
+    scale: f64,
 }
 
-impl JunctionPN {
-    fn new(is: f64, n: f64) -> Self {
-        let nvt = n * V_THERMAL;
-        // initial state is linearized at v=0
+impl CurrentSource {
+    fn new(i: f64, l0: usize, l1: usize) -> Self {
         Self {
-            geq: is / (n * V_THERMAL) + G_MIN,
-            ieq: 0.0,
-            veq: 0.0,
-            is,
-            nvt,
-            rnvt: 1. / nvt,
-            vcrit: nvt * f64::ln(nvt / (is * f64::sqrt(2.0))),
+            i,
+            l0,
+            l1,
+            scale: 1.0,
         }
     }
+}
 
-    fn linearize(&mut self, v: f64) {
-        // linearize junction at the specified voltage
-        //
-        // ideally we could handle series resistance here as well
-        // to avoid putting it on a separate node, but not sure how
-        // to make that work as it looks like we'd need Lambert-W then
-        let e = self.is * f64::exp(v * self.rnvt);
-        let i = e - self.is + G_MIN * v;
-        let g = e * self.rnvt + G_MIN;
+impl Component for CurrentSource {
+    fn stamp(&self, m: &mut MNASystem) {
+        // no extra net, the current goes straight into the KCL rows
+        let i = self.i * self.scale;
+        m.b[self.l0].g += i;
+        m.b[self.l1].g -= i;
+        m.b[self.l0].txt += &format!("+{:.}A", self.i);
+        m.b[self.l1].txt += &format!("-{:.}A", self.i);
+    }
 
-        self.geq = g;
-        self.ieq = v * g - i;
-        self.veq = v;
+    fn scale_source(&mut self, m: &mut MNASystem, scale: f64) -> bool {
+        // other components may share the rows, so only change our part
+        let di = self.i * (scale - self.scale);
+        m.b_row(self.l0).g += di;
+        m.b_row(self.l1).g -= di;
+        self.scale = scale;
+        true
     }
 
-    // returns true if junction is good enough
-    fn newton(&mut self, v: f64) -> bool {
-        let dv = v - self.veq;
-        if f64::abs(dv) < V_TOLERANCE {
-            return true;
-        }
-        // check critical voltage and adjust voltage if over
-        let vv = if v > self.vcrit {
-            // this formula comes from Qucs documentation
-            // https://qucs.sourceforge.net/tech/node16.html#SECTION00431000000000000000
-            self.veq + self.nvt * f64::ln(f64::max(self.is, 1.0 + dv * self.rnvt))
-        } else {
-            v
-        };
-        self.linearize(vv);
-        return false;
+    fn set_source(&mut self, m: &mut MNASystem, value: f64) -> bool {
+        let di = (value - self.i) * self.scale;
+        m.b_row(self.l0).g += di;
+        m.b_row(self.l1).g -= di;
+        self.i = value;
+        true
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::CurrentSource {
+            i: self.i,
+            nets: [self.l0, self.l1],
+        })
     }
 }
 
-#[derive(Debug)]
-struct DiodeParameters {
-    // Series resistor in model
-    rs: f64,
-    // Reverse bias saturation current
-    is: f64,
-    // Ideality factor
-    n: f64,
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct CableParameters {
+    // Capacitance to ground per meter
+    c_per_m: f64,
+    // Conductor resistance per meter
+    r_per_m: f64,
 }
 
-impl Default for DiodeParameters {
+impl Default for CableParameters {
     fn default() -> Self {
-        // Default diode approximates 1N4148
+        // Typical instrument cable
         Self {
-            rs: 10.0,
-            is: 35.0e-12,
-            n: 1.24,
+            c_per_m: 100e-12,
+            r_per_m: 0.05,
         }
     }
 }
 
-#[derive(Debug)]
-struct DiodeReserved {
-    l2: usize,
-    l3: usize,
-    dyn_index0: usize,
-    dyn_index1: usize,
-}
+/// Shortest conductor resistance for cables, keeps zero length finite
+const CABLE_R_MIN: f64 = 1e-3;
 
 #[derive(Debug)]
-struct Diode {
-    l0: usize,
-    l1: usize,
-    pn: JunctionPN,
-    rs: f64,
-    reserved: Option<DiodeReserved>,
+struct Cable {
+    // length in meters
+    length: f64,
+    params: CableParameters,
+    // Cable is a pi-section: conductor resistance between the ends
+    // with half of the shield capacitance on each end
+    conductor: Resistor,
+    shield: [Capacitor; 2],
 }
 
-impl Diode {
-    fn new(l0: usize, l1: usize, params: DiodeParameters) -> Self {
-        let pn = JunctionPN::new(params.is, params.n);
+impl Cable {
+    fn new(length: f64, l0: usize, l1: usize, params: CableParameters) -> Self {
+        let r = f64::max(params.r_per_m * length, CABLE_R_MIN);
+        let c = params.c_per_m * length / 2.0;
         Self {
-            l0,
-            l1,
-            rs: params.rs,
-            pn,
-            reserved: None,
+            length,
+            params,
+            conductor: Resistor::new(r, l0, l1),
+            shield: [Capacitor::new(c, l0, 0), Capacitor::new(c, l1, 0)],
         }
     }
 }
 
-impl Component for Diode {
+impl Component for Cable {
     fn reserve(&mut self, m: &mut MNASystem) {
-        self.reserved = Some(DiodeReserved {
-            l2: m.reserve(),
-            l3: m.reserve(),
-            dyn_index0: m.reserve_dynamic(),
-            dyn_index1: m.reserve_dynamic(),
-        });
+        for c in &mut self.shield {
+            c.reserve(m);
+        }
     }
 
     fn stamp(&self, m: &mut MNASystem) {
-        let reserved = self.reserved.as_ref().expect("need reserved");
-        let (l0, l1, l2, l3) = (self.l0, self.l1, reserved.l2, reserved.l3);
+        self.conductor.stamp(m);
+        for c in &self.shield {
+            c.stamp(m);
+        }
+    }
 
-        // Diode could be built with 3 extra nodes:
-        //
-        // |  .  .    .       . +1 | V+
-        // |  .  .    .       . -1 | V-
-        // |  .  .  grs    -grs -1 | v:D
-        // |  .  . -grs grs+geq  . | v:pn = ieq
-        // | -1 +1   +1       .  . | i:pn
-        //
-        // Here grs is the 1/rs series conductance.
-        //
-        // This gives us the junction voltage (v:pn) and
-        // current (i:pn) and the composite voltage (v:D).
-        //
-        // The i:pn row is an ideal transformer connecting
-        // the floating diode to the ground-referenced v:D
-        // where we connect the series resistance to v:pn
-        // that solves the diode equation with Newton.
-        //
-        // We can then add the 3rd row to the bottom 2 with
-        // multipliers 1 and -rs = -1/grs and drop it:
-        //
-        // |  .  .   . +1 | V+
-        // |  .  .   . -1 | V-
-        // |  .  . geq -1 | v:pn = ieq
-        // | -1 +1  +1 rs | i:pn
-        //
-        // Note that only the v:pn row here is non-linear.
-        //
-        // We could even do away without the separate row for
-        // the current, which would lead to the following:
-        //
-        // | +grs -grs     -grs |
-        // | -grs +grs     +grs |
-        // | -grs +grs +grs+geq | = ieq
-        //
-        // In practice we keep the current row since it's
-        // nice to have it as an output anyway.
-        //
-        m.stamp_static(-1.0, l3, l0, "-1");
-        m.stamp_static(1.0, l3, l1, "+1");
-        m.stamp_static(1.0, l3, l2, "+1");
-        m.stamp_static(1.0, l0, l3, "+1");
-        m.stamp_static(-1.0, l1, l3, "-1");
-        m.stamp_static(-1.0, l2, l3, "-1");
-        m.stamp_static(self.rs, l3, l3, "rs:pn");
-        m.add_dynamic_a(l2, l2, reserved.dyn_index0, &format!("gm:D"));
-        m.add_dynamic_b(l2, reserved.dyn_index1, &format!("i0:D:{},{}", l0, l1));
-        m.nodes[l2] = MNANodeInfo::new_voltage_with_name(&format!("v:D:{},{}", l0, l1));
-        m.nodes[l3] = MNANodeInfo::new_current(&format!("i:D:{},{}", l0, l1));
-        self.update_dynamic(m);
+    fn update(&mut self, m: &mut MNASystem) {
+        for c in &mut self.shield {
+            c.update(m);
+        }
     }
 
-    fn update_dynamic(&self, m: &mut MNASystem) {
-        let reserved = self.reserved.as_ref().expect("need reserved");
-        m.set_dynamic(reserved.dyn_index0, self.pn.geq);
-        m.set_dynamic(reserved.dyn_index1, self.pn.ieq);
+    fn scale_time(&mut self, m: &mut MNASystem, t_old_per_new: f64) {
+        for c in &mut self.shield {
+            c.scale_time(m, t_old_per_new);
+        }
     }
 
-    fn newton(&mut self, m: &mut MNASystem) -> bool {
-        let reserved = self.reserved.as_ref().expect("need reserved");
-        self.pn.newton(m.b[reserved.l2].lu)
+    fn time_constant(&self, m: &MNASystem) -> Option<f64> {
+        self.shield
+            .iter()
+            .filter_map(|c| c.time_constant(m))
+            .reduce(f64::min)
     }
-}
 
-#[derive(Debug, PartialEq)]
-enum TransistorType {
-    NPN,
-    PNP,
+    fn stored_energy(&self) -> Vec<(String, f64)> {
+        self.shield.iter().flat_map(|c| c.stored_energy()).collect()
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::Cable {
+            length: self.length,
+            params: self.params.clone(),
+            nets: [self.conductor.l0, self.conductor.l1],
+        })
+    }
 }
 
-#[derive(Debug)]
-struct BJTParameters {
-    // Forward beta
-    bf: f64,
-    // Reverse beta
-    br: f64,
-    // Base resistor in model
-    rb: f64,
-    // Emitter resistor in model
-    re: f64,
-    // Collector resistor in model
-    rc: f64,
-    // Reverse bias saturation current
-    is: f64,
-    // Ideality factor
-    n: f64,
-    transistor_type: TransistorType,
+/// Mechanical resonance of a speaker as seen electrically
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct SpeakerResonance {
+    // resistance at the resonance peak
+    res: f64,
+    // compliance as inductance, moving mass as capacitance
+    lces: f64,
+    cmes: f64,
 }
 
-// Computed parameters from other params
-impl BJTParameters {
-    // Forward alpha
-    fn af(&self) -> f64 {
-        self.bf / (1.0 + self.bf)
-    }
-    // Reverse alpha
-    fn ar(&self) -> f64 {
-        self.br / (1.0 + self.br)
-    }
-    // Series resistance from base-collector
-    fn rsbc(&self) -> f64 {
-        self.rb + self.rc
-    }
-    // Series resistance from base-emitter
-    fn rsbe(&self) -> f64 {
-        self.rb + self.re
+impl SpeakerResonance {
+    fn frequency(&self) -> f64 {
+        1.0 / (2.0 * std::f64::consts::PI * (self.lces * self.cmes).sqrt())
     }
 }
 
-impl Default for BJTParameters {
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct SpeakerParameters {
+    // voice coil resistance and inductance
+    re: f64,
+    le: f64,
+    resonance: Option<SpeakerResonance>,
+}
+
+impl Default for SpeakerParameters {
     fn default() -> Self {
-        // Default transistor approximates 2N3904
+        // 8 ohm woofer with a resonance around 65Hz
         Self {
-            bf: 200.0,
-            br: 20.0,
-            rb: 5.8376,
-            re: 2.65711,
-            rc: 0.0001,
-            is: 6.734e-15,
-            n: 1.24,
-            transistor_type: TransistorType::NPN,
+            re: 6.0,
+            le: 0.5e-3,
+            resonance: Some(SpeakerResonance {
+                res: 40.0,
+                lces: 15e-3,
+                cmes: 400e-6,
+            }),
         }
     }
 }
 
 #[derive(Debug)]
-struct BJTReserved {
-    l: [usize; 4],
-    dyn_pnc_ieq: usize,
-    dyn_pnc_geq: usize,
-    dyn_pne_ieq: usize,
-    dyn_pne_geq: usize,
-}
-
-#[derive(Debug)]
-struct BJT {
-    pin: [usize; 3],
-    pnc: JunctionPN,
-    pne: JunctionPN,
-    params: BJTParameters,
-    reserved: Option<BJTReserved>,
+struct Speaker {
+    params: SpeakerParameters,
+    l0: usize,
+    l1: usize,
+    // voice coil, into the resonance branch if there is one
+    coil: Option<Resistor>,
+    // parallel resistor, inductor and capacitor to l1
+    branch: Option<(Resistor, Inductor, Capacitor)>,
 }
 
-impl BJT {
-    fn new(b: usize, c: usize, e: usize, params: BJTParameters) -> Self {
-        let pne = JunctionPN::new(params.is / params.af(), params.n);
-        let pnc = JunctionPN::new(params.is / params.ar(), params.n);
+impl Speaker {
+    fn new(l0: usize, l1: usize, params: SpeakerParameters) -> Self {
         Self {
-            pin: [b, c, e],
-            pnc,
-            pne,
             params,
+            l0,
+            l1,
+            coil: None,
+            branch: None,
+        }
+    }
+
+    fn parts_mut(&mut self) -> Vec<&mut dyn Component> {
+        let mut res: Vec<&mut dyn Component> = vec![];
+        if let Some(coil) = &mut self.coil {
+            res.push(coil);
+        }
+        if let Some((r, l, c)) = &mut self.branch {
+            res.extend([r as &mut dyn Component, l, c]);
+        }
+        res
+    }
+}
+
+impl Component for Speaker {
+    fn reserve(&mut self, m: &mut MNASystem) {
+        let (re, le) = (self.params.re, self.params.le);
+        let coil_end = match &self.params.resonance {
+            Some(res) => {
+                let mid = m.reserve();
+                self.branch = Some((
+                    Resistor::new(res.res, mid, self.l1),
+                    Inductor::new(res.lces, mid, self.l1),
+                    Capacitor::new(res.cmes, mid, self.l1),
+                ));
+                mid
+            }
+            None => self.l1,
+        };
+        self.coil = Some(Resistor::with_inductance(re, le, self.l0, coil_end));
+        for part in self.parts_mut() {
+            part.reserve(m);
+        }
+    }
+
+    fn stamp(&self, m: &mut MNASystem) {
+        if let Some(coil) = &self.coil {
+            coil.stamp(m);
+        }
+        if let Some((r, l, c)) = &self.branch {
+            r.stamp(m);
+            l.stamp(m);
+            c.stamp(m);
+        }
+    }
+
+    fn update(&mut self, m: &mut MNASystem) {
+        for part in self.parts_mut() {
+            part.update(m);
+        }
+    }
+
+    fn scale_time(&mut self, m: &mut MNASystem, t_old_per_new: f64) {
+        for part in self.parts_mut() {
+            part.scale_time(m, t_old_per_new);
+        }
+    }
+
+    fn time_constant(&self, _m: &MNASystem) -> Option<f64> {
+        // voice coil L/R, and the resonance period over 2 pi
+        let coil =
+            (self.params.re > 0.0 && self.params.le > 0.0).then(|| self.params.le / self.params.re);
+        let resonance = self
+            .params
+            .resonance
+            .as_ref()
+            .map(|r| 1.0 / (2.0 * std::f64::consts::PI * r.frequency()));
+        coil.into_iter().chain(resonance).reduce(f64::min)
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::Speaker {
+            nets: [self.l0, self.l1],
+            params: self.params.clone(),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct VoltageProbeReserved {
+    l2: usize,
+}
+
+#[derive(Debug)]
+struct VoltageProbe {
+    // probe a differential voltage
+    // also forces this voltage to actually get solved :)
+    l0: usize,
+    l1: usize,
+    // name of the output, "v:probe" when not given
+    name: Option<String>,
+    reserved: Option<VoltageProbeReserved>,
+}
+
+impl VoltageProbe {
+    fn new(l0: usize, l1: usize) -> Self {
+        Self {
+            l0,
+            l1,
+            name: None,
             reserved: None,
         }
     }
+
+    /// Probe whose output is called `name`, and registered under it
+    fn new_named(l0: usize, l1: usize, name: &str) -> Self {
+        Self {
+            name: Some(name.to_string()),
+            ..Self::new(l0, l1)
+        }
+    }
 }
 
-impl Component for BJT {
+impl Component for VoltageProbe {
     fn reserve(&mut self, m: &mut MNASystem) {
-        self.reserved = Some(BJTReserved {
-            l: [m.reserve(), m.reserve(), m.reserve(), m.reserve()],
-            dyn_pnc_ieq: m.reserve_dynamic(),
-            dyn_pnc_geq: m.reserve_dynamic(),
-            dyn_pne_ieq: m.reserve_dynamic(),
-            dyn_pne_geq: m.reserve_dynamic(),
+        let l2 = m.reserve();
+        if let Some(name) = &self.name {
+            m.register_probe(name, l2);
+        }
+        self.reserved = Some(VoltageProbeReserved { l2 });
+    }
+    fn stamp(&self, m: &mut MNASystem) {
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let (l0, l1, l2) = (self.l0, self.l1, reserved.l2);
+
+        // vp + vn - vd = 0     so      vp = vd - vn
+        m.stamp_static(1., l2, l0, "+1");
+        m.stamp_static(-1., l2, l1, "-1");
+        m.stamp_static(-1., l2, l2, "-1");
+        let name = self.name.as_deref().unwrap_or("v:probe");
+        m.nodes[l2] = MNANodeInfo::new_voltage_with_name(name);
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::VoltageProbe {
+            name: self.name.clone(),
+            nets: [self.l0, self.l1],
+        })
+    }
+}
+
+/// Named ammeter, its branch current is registered as a probe
+#[derive(Debug)]
+struct CurrentProbe {
+    name: String,
+    ammeter: Ammeter,
+}
+
+impl CurrentProbe {
+    fn new(l0: usize, l1: usize, name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ammeter: Ammeter::new(l0, l1),
+        }
+    }
+}
+
+impl Component for CurrentProbe {
+    fn reserve(&mut self, m: &mut MNASystem) {
+        self.ammeter.reserve(m);
+        let reserved = self.ammeter.reserved.as_ref().expect("need reserved");
+        m.register_probe(&self.name, reserved.l2);
+    }
+
+    fn stamp(&self, m: &mut MNASystem) {
+        self.ammeter.stamp(m);
+        let reserved = self.ammeter.reserved.as_ref().expect("need reserved");
+        m.nodes[reserved.l2] = MNANodeInfo::new_current(&self.name);
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::CurrentProbe {
+            name: self.name.clone(),
+            nets: [self.ammeter.l0, self.ammeter.l1],
+        })
+    }
+}
+
+#[derive(Debug)]
+struct AmmeterReserved {
+    l2: usize,
+}
+
+#[derive(Debug)]
+struct Ammeter {
+    // zero volt source, its branch current is the current from l0 to l1
+    l0: usize,
+    l1: usize,
+    reserved: Option<AmmeterReserved>,
+}
+
+impl Ammeter {
+    fn new(l0: usize, l1: usize) -> Self {
+        Self {
+            l0,
+            l1,
+            reserved: None,
+        }
+    }
+}
+
+impl Component for Ammeter {
+    fn reserve(&mut self, m: &mut MNASystem) {
+        self.reserved = Some(AmmeterReserved { l2: m.reserve() });
+    }
+
+    fn stamp(&self, m: &mut MNASystem) {
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let (l0, l1, l2) = (self.l0, self.l1, reserved.l2);
+        // current leaves l0 and enters l1, with vl0 - vl1 = 0
+        m.stamp_static(1., l0, l2, "+1");
+        m.stamp_static(-1., l1, l2, "-1");
+        m.stamp_static(1., l2, l0, "+1");
+        m.stamp_static(-1., l2, l1, "-1");
+        m.nodes[l2] = MNANodeInfo::new_current(&format!("i:A:{},{}", l0, l1));
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::Ammeter {
+            nets: [self.l0, self.l1],
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct OpAmpParameters {
+    // Open-loop gain
+    gain: f64,
+    // Positive and negative output rails
+    vcc: f64,
+    vee: f64,
+}
+
+impl Default for OpAmpParameters {
+    fn default() -> Self {
+        // Default op-amp is a generic part on a +-15V supply
+        Self {
+            gain: 1e5,
+            vcc: 15.0,
+            vee: -15.0,
+        }
+    }
+}
+
+/// Region an op-amp output is linearized in
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OpAmpRegion {
+    Linear,
+    High,
+    Low,
+}
+
+#[derive(Debug)]
+struct OpAmpReserved {
+    l: usize,
+    // for the finite gain model
+    dyn_gp: usize,
+    dyn_gn: usize,
+    dyn_v0: usize,
+}
+
+#[derive(Debug)]
+struct OpAmp {
+    // ideal (nullor) op-amp, the output drives whatever keeps the inputs equal
+    in_p: usize,
+    in_n: usize,
+    out: usize,
+    // finite gain model clamped to the rails instead of ideal
+    params: Option<OpAmpParameters>,
+    region: OpAmpRegion,
+    reserved: Option<OpAmpReserved>,
+}
+
+impl OpAmp {
+    fn new(in_p: usize, in_n: usize, out: usize) -> Self {
+        Self {
+            in_p,
+            in_n,
+            out,
+            params: None,
+            region: OpAmpRegion::Linear,
+            reserved: None,
+        }
+    }
+
+    /// Op-amp with finite gain whose output saturates at the rails
+    fn with_params(in_p: usize, in_n: usize, out: usize, params: OpAmpParameters) -> Self {
+        Self {
+            params: Some(params),
+            ..Self::new(in_p, in_n, out)
+        }
+    }
+
+    /// Linearized output `g * (v+ - v-) + v0` as (g, v0)
+    fn linearized(&self) -> (f64, f64) {
+        match (&self.params, self.region) {
+            (None, _) => (0.0, 0.0),
+            (Some(params), OpAmpRegion::Linear) => (params.gain, 0.0),
+            (Some(params), OpAmpRegion::High) => (0.0, params.vcc),
+            (Some(params), OpAmpRegion::Low) => (0.0, params.vee),
+        }
+    }
+}
+
+impl Component for OpAmp {
+    fn reserve(&mut self, m: &mut MNASystem) {
+        self.reserved = Some(OpAmpReserved {
+            l: m.reserve(),
+            dyn_gp: m.reserve_dynamic(),
+            dyn_gn: m.reserve_dynamic(),
+            dyn_v0: m.reserve_dynamic(),
         });
     }
 
     fn stamp(&self, m: &mut MNASystem) {
-        // The basic idea here is the same as with diodes
-        // except we do it once for each junction.
-        //
-        // With the transfer currents sourced from the
-        // diode currents, NPN then looks like this:
+        // Inputs draw no current, output current is free:
         //
-        // 0 |  .  .  .  .  . 1-ar 1-af | vB
-        // 1 |  .  .  .  .  .   -1  +af | vC
-        // 2 |  .  .  .  .  .  +ar   -1 | vE
-        // 3 |  .  .  . gc  .   -1    . | v:Qbc  = ic
-        // 4 |  .  .  .  . ge    .   -1 | v:Qbe  = ie
-        // 5 | -1 +1  . +1  . rsbc    . | i:Qbc
-        // 6 | -1  . +1  . +1    . rsbe | i:Qbe
-        //     ------------------------
-        //      0  1  2  3  4    5    6
+        // |  .  .  .  . | v+
+        // |  .  .  .  . | v-
+        // |  .  .  . +1 | vout
+        // | +1 -1  .  . | i:OA = 0
         //
-        // For PNP version, we simply flip the junctions
-        // by changing signs of (3,5),(5,3) and (4,6),(6,4).
+        // Only works with negative feedback, otherwise the
+        // matrix is singular.
         //
-        // Also just like diodes, we have junction series
-        // resistances, rather than terminal resistances.
+        // With finite gain the last row instead drives the
+        // output from the linearized differential input:
         //
-        // This works just as well, but should be kept
-        // in mind when fitting particular transistors.
+        // | -g +g +1  . | i:OA = v0
         //
-        // Cheat sheet:
-        // nets[0] pin[0]
-        // nets[1] pin[1]
-        // nets[2] pin[2]
-        // nets[3] l[0]
-        // nets[4] l[1]
-        // nets[5] l[2]
-        // nets[6] l[3]
+        // where g is the gain (linear) or 0 (at a rail).
         let reserved = self.reserved.as_ref().expect("need reserved");
-        let (pin, l, params) = (self.pin, reserved.l, &self.params);
-        let dyn_pnc_ieq = reserved.dyn_pnc_ieq;
-        let dyn_pnc_geq = reserved.dyn_pnc_geq;
-        let dyn_pne_ieq = reserved.dyn_pne_ieq;
-        let dyn_pne_geq = reserved.dyn_pne_geq;
-        let pnp = self.params.transistor_type == TransistorType::PNP;
-        // diode currents to external base
-        m.stamp_static(1.0 - params.ar(), pin[0], l[2], "1-ar");
-        m.stamp_static(1.0 - params.af(), pin[0], l[3], "1-ar");
-        // diode currents to external collector and emitter
-        m.stamp_static(-1.0, pin[1], l[2], "-1");
-        m.stamp_static(-1.0, pin[2], l[3], "-1");
-        // series resistances
-        m.stamp_static(params.rsbc(), l[2], l[2], "rsbc");
-        m.stamp_static(params.rsbe(), l[3], l[3], "rsbe");
-        // current - junction connections
-        // for the PNP case we flip the signs of these
-        // to flip the diode junctions wrt. the above
-        if pnp {
-            m.stamp_static(-1.0, l[2], l[0], "-1");
-            m.stamp_static(1.0, l[0], l[2], "+1");
-            m.stamp_static(-1.0, l[3], l[1], "-1");
-            m.stamp_static(1.0, l[1], l[3], "+1");
+        let (in_p, in_n, out, l) = (self.in_p, self.in_n, self.out, reserved.l);
+        m.stamp_static(1., out, l, "+1");
+        if self.params.is_some() {
+            m.stamp_static(1., l, out, "+1");
+            m.add_dynamic_a(l, in_p, reserved.dyn_gp, "-g:OA");
+            m.add_dynamic_a(l, in_n, reserved.dyn_gn, "g:OA");
+            m.add_dynamic_b(
+                l,
+                reserved.dyn_v0,
+                &format!("v0:OA:{},{},{}", in_p, in_n, out),
+            );
         } else {
-            m.stamp_static(1.0, l[2], l[0], "+1");
-            m.stamp_static(-1.0, l[0], l[2], "-1");
-            m.stamp_static(1.0, l[3], l[1], "+1");
-            m.stamp_static(-1.0, l[1], l[3], "-1");
+            m.stamp_static(1., l, in_p, "+1");
+            m.stamp_static(-1., l, in_n, "-1");
+        }
+        m.nodes[l] = MNANodeInfo::new_current(&format!("i:OA:{},{},{}", in_p, in_n, out));
+        self.update_dynamic(m);
+    }
+
+    fn update_dynamic(&self, m: &mut MNASystem) {
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let (g, v0) = self.linearized();
+        m.set_dynamic(reserved.dyn_gp, -g);
+        m.set_dynamic(reserved.dyn_gn, g);
+        m.set_dynamic(reserved.dyn_v0, v0);
+    }
+
+    fn newton(&mut self, m: &mut MNASystem, _options: &SimOptions) -> bool {
+        let Some(params) = &self.params else {
+            return true;
+        };
+        // the model is piecewise linear, so the solution is exact
+        // once it lands in the region it was linearized for
+        let v = params.gain * (m.b[self.in_p].lu - m.b[self.in_n].lu);
+        let region = if v > params.vcc {
+            OpAmpRegion::High
+        } else if v < params.vee {
+            OpAmpRegion::Low
+        } else {
+            OpAmpRegion::Linear
+        };
+        if region == self.region {
+            return true;
+        }
+        self.region = region;
+        // Update dynamic variables since we relinearized
+        self.update_dynamic(m);
+        false
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::OpAmp {
+            nets: [self.in_p, self.in_n, self.out],
+            params: self.params.clone(),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct VCVSReserved {
+    l2: usize,
+}
+
+#[derive(Debug)]
+struct Vcvs {
+    // v(l0) - v(l1) = gain * (v(cp) - v(cn))
+    gain: f64,
+    cp: usize,
+    cn: usize,
+    l0: usize,
+    l1: usize,
+    reserved: Option<VCVSReserved>,
+}
+
+impl Vcvs {
+    fn new(gain: f64, cp: usize, cn: usize, l0: usize, l1: usize) -> Self {
+        Self {
+            gain,
+            cp,
+            cn,
+            l0,
+            l1,
+            reserved: None,
+        }
+    }
+}
+
+impl Component for Vcvs {
+    fn reserve(&mut self, m: &mut MNASystem) {
+        self.reserved = Some(VCVSReserved { l2: m.reserve() });
+    }
+
+    fn stamp(&self, m: &mut MNASystem) {
+        // same as a voltage source, with the control
+        // voltage taking the place of the fixed value
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let (gain, l0, l1, l2) = (self.gain, self.l0, self.l1, reserved.l2);
+        m.stamp_static(-1., l0, l2, "-1");
+        m.stamp_static(1., l1, l2, "+1");
+        m.stamp_static(1., l2, l0, "+1");
+        m.stamp_static(-1., l2, l1, "-1");
+        m.stamp_static(-gain, l2, self.cp, "-gain");
+        m.stamp_static(gain, l2, self.cn, "+gain");
+        m.nodes[l2] = MNANodeInfo::new_current(&format!("i:E:{},{}", l0, l1));
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::Vcvs {
+            gain: self.gain,
+            nets: [self.l0, self.l1, self.cp, self.cn],
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Vccs {
+    // current gm * (v(cp) - v(cn)) flows from l0 through the source to l1
+    gm: f64,
+    cp: usize,
+    cn: usize,
+    l0: usize,
+    l1: usize,
+}
+
+impl Vccs {
+    fn new(gm: f64, cp: usize, cn: usize, l0: usize, l1: usize) -> Self {
+        Self { gm, cp, cn, l0, l1 }
+    }
+}
+
+impl Component for Vccs {
+    fn stamp(&self, m: &mut MNASystem) {
+        // no extra net, the current is a cross conductance
+        let (gm, l0, l1, cp, cn) = (self.gm, self.l0, self.l1, self.cp, self.cn);
+        m.stamp_static(gm, l0, cp, "+gm");
+        m.stamp_static(-gm, l0, cn, "-gm");
+        m.stamp_static(-gm, l1, cp, "-gm");
+        m.stamp_static(gm, l1, cn, "+gm");
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::Vccs {
+            gm: self.gm,
+            nets: [self.l0, self.l1, self.cp, self.cn],
+        })
+    }
+}
+
+/// Zero volt branch from s0 to s1 whose current controls a source
+fn stamp_sense(m: &mut MNASystem, s0: usize, s1: usize, l: usize) {
+    m.stamp_static(1., s0, l, "+1");
+    m.stamp_static(-1., s1, l, "-1");
+    m.stamp_static(1., l, s0, "+1");
+    m.stamp_static(-1., l, s1, "-1");
+}
+
+#[derive(Debug)]
+struct CCCSReserved {
+    l2: usize,
+}
+
+#[derive(Debug)]
+struct Cccs {
+    // current gain * i(s0 -> s1) flows from l0 through the source to l1
+    gain: f64,
+    s0: usize,
+    s1: usize,
+    l0: usize,
+    l1: usize,
+    reserved: Option<CCCSReserved>,
+}
+
+impl Cccs {
+    fn new(gain: f64, s0: usize, s1: usize, l0: usize, l1: usize) -> Self {
+        Self {
+            gain,
+            s0,
+            s1,
+            l0,
+            l1,
+            reserved: None,
+        }
+    }
+}
+
+impl Component for Cccs {
+    fn reserve(&mut self, m: &mut MNASystem) {
+        self.reserved = Some(CCCSReserved { l2: m.reserve() });
+    }
+
+    fn stamp(&self, m: &mut MNASystem) {
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let (gain, l0, l1, l2) = (self.gain, self.l0, self.l1, reserved.l2);
+        stamp_sense(m, self.s0, self.s1, l2);
+        m.stamp_static(gain, l0, l2, "+gain");
+        m.stamp_static(-gain, l1, l2, "-gain");
+        m.nodes[l2] = MNANodeInfo::new_current(&format!("i:F:{},{}", self.s0, self.s1));
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::Cccs {
+            gain: self.gain,
+            nets: [self.l0, self.l1, self.s0, self.s1],
+        })
+    }
+}
+
+#[derive(Debug)]
+struct CCVSReserved {
+    l2: usize,
+    l3: usize,
+}
+
+#[derive(Debug)]
+struct Ccvs {
+    // v(l0) - v(l1) = r * i(s0 -> s1)
+    r: f64,
+    s0: usize,
+    s1: usize,
+    l0: usize,
+    l1: usize,
+    reserved: Option<CCVSReserved>,
+}
+
+impl Ccvs {
+    fn new(r: f64, s0: usize, s1: usize, l0: usize, l1: usize) -> Self {
+        Self {
+            r,
+            s0,
+            s1,
+            l0,
+            l1,
+            reserved: None,
+        }
+    }
+}
+
+impl Component for Ccvs {
+    fn reserve(&mut self, m: &mut MNASystem) {
+        self.reserved = Some(CCVSReserved {
+            l2: m.reserve(),
+            l3: m.reserve(),
+        });
+    }
+
+    fn stamp(&self, m: &mut MNASystem) {
+        // sense branch current in l2, output branch like a voltage source in l3
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let (r, l0, l1, l2, l3) = (self.r, self.l0, self.l1, reserved.l2, reserved.l3);
+        stamp_sense(m, self.s0, self.s1, l2);
+        m.stamp_static(-1., l0, l3, "-1");
+        m.stamp_static(1., l1, l3, "+1");
+        m.stamp_static(1., l3, l0, "+1");
+        m.stamp_static(-1., l3, l1, "-1");
+        m.stamp_static(-r, l3, l2, "-r");
+        m.nodes[l2] = MNANodeInfo::new_current(&format!("i:H:{},{}", self.s0, self.s1));
+        m.nodes[l3] = MNANodeInfo::new_current(&format!("i:H:{},{}", l0, l1));
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::Ccvs {
+            r: self.r,
+            nets: [self.l0, self.l1, self.s0, self.s1],
+        })
+    }
+}
+
+#[derive(Debug)]
+struct TransformerReserved {
+    l4: usize,
+}
+
+/// Ideal transformer, secondary voltage is `turns_ratio` times the primary
+#[derive(Debug)]
+struct Transformer {
+    turns_ratio: f64,
+    primary: (usize, usize),
+    secondary: (usize, usize),
+    reserved: Option<TransformerReserved>,
+}
+
+impl Transformer {
+    fn new(turns_ratio: f64, primary: (usize, usize), secondary: (usize, usize)) -> Self {
+        Self {
+            turns_ratio,
+            primary,
+            secondary,
+            reserved: None,
+        }
+    }
+}
+
+impl Component for Transformer {
+    fn reserve(&mut self, m: &mut MNASystem) {
+        self.reserved = Some(TransformerReserved { l4: m.reserve() });
+    }
+
+    fn stamp(&self, m: &mut MNASystem) {
+        // the extra net is the secondary current i, out of l2 into the
+        // circuit, which the primary draws n times over:
+        //
+        // |  .   .   .   .  +n | v0
+        // |  .   .   .   .  -n | v1
+        // |  .   .   .   .  -1 | v2
+        // |  .   .   .   .  +1 | v3
+        // | -n  +n  +1  -1   . | i
+        //
+        // so v2 - v3 = n*(v0 - v1) and power in equals power out
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let (n, (l0, l1), (l2, l3), l4) =
+            (self.turns_ratio, self.primary, self.secondary, reserved.l4);
+        m.stamp_static(n, l0, l4, "+n");
+        m.stamp_static(-n, l1, l4, "-n");
+        m.stamp_static(-1., l2, l4, "-1");
+        m.stamp_static(1., l3, l4, "+1");
+        m.stamp_static(-n, l4, l0, "-n");
+        m.stamp_static(n, l4, l1, "+n");
+        m.stamp_static(1., l4, l2, "+1");
+        m.stamp_static(-1., l4, l3, "-1");
+        m.nodes[l4] = MNANodeInfo::new_current(&format!("i:T:{},{}", l2, l3));
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::Transformer {
+            turns_ratio: self.turns_ratio,
+            nets: [
+                self.primary.0,
+                self.primary.1,
+                self.secondary.0,
+                self.secondary.1,
+            ],
+        })
+    }
+}
+
+/// Two inductors with mutual inductance `m` between them
+///
+/// The dotted ends are the `l0` of each inductor, so current into `a.l0`
+/// induces a positive voltage from `b.l0` to `b.l1`.
+#[derive(Debug)]
+struct CoupledInductors {
+    a: Inductor,
+    b: Inductor,
+    m: f64,
+}
+
+impl CoupledInductors {
+    fn new(a: Inductor, b: Inductor, m: f64) -> Self {
+        Self { a, b, m }
+    }
+}
+
+impl Component for CoupledInductors {
+    fn reserve(&mut self, m: &mut MNASystem) {
+        self.a.reserve(m);
+        self.b.reserve(m);
+    }
+
+    fn stamp(&self, m: &mut MNASystem) {
+        // each current row picks up the other current through M:
+        //
+        //  v1 - 2*t*(L*i1 + M*j1) = -(2*t*(L*i0 + M*j0) + v0)
+        //
+        // which for AC is v = jwL*i + jwM*j
+        self.a.stamp(m);
+        self.b.stamp(m);
+        let la = self.a.reserved.as_ref().expect("need reserved").l2;
+        let lb = self.b.reserved.as_ref().expect("need reserved").l2;
+        let txt = format!("-t*2*{}", format_unit_value(self.m, "H"));
+        m.stamp_timed(-2. * self.m, la, lb, &txt);
+        m.stamp_timed(-2. * self.m, lb, la, &txt);
+        self.update_dynamic(m);
+    }
+
+    fn update_dynamic(&self, m: &mut MNASystem) {
+        // inductor states with the flux from the other winding added
+        for (x, y) in [(&self.a, &self.b), (&self.b, &self.a)] {
+            let reserved = x.reserved.as_ref().expect("need reserved");
+            let flux = x.l * x.current + self.m * y.current;
+            let state = 2. * m.step_scale * flux + x.voltage;
+            m.set_dynamic(reserved.dyn_index, -state);
+        }
+    }
+
+    fn update(&mut self, m: &mut MNASystem) {
+        self.a.update(m);
+        self.b.update(m);
+        self.update_dynamic(m);
+    }
+
+    fn scale_time(&mut self, m: &mut MNASystem, t_old_per_new: f64) {
+        self.a.scale_time(m, t_old_per_new);
+        self.b.scale_time(m, t_old_per_new);
+        self.update_dynamic(m);
+    }
+
+    fn stored_energy(&self) -> Vec<(String, f64)> {
+        let (a, b) = (&self.a, &self.b);
+        let name = format!("K:{},{}:{},{}", a.l0, a.l1, b.l0, b.l1);
+        let e = 0.5 * a.l * a.current * a.current
+            + 0.5 * b.l * b.current * b.current
+            + self.m * a.current * b.current;
+        vec![(name, e)]
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::CoupledInductors {
+            l: [self.a.l, self.b.l],
+            m: self.m,
+            nets: [self.a.l0, self.a.l1, self.b.l0, self.b.l1],
+        })
+    }
+}
+
+#[derive(Debug)]
+struct VoltageFunctionReserved {
+    dyn_index: usize,
+    l2: usize,
+}
+
+struct VoltageFunction {
+    // probe a differential voltage
+    // also forces this voltage to actually get solved :)
+    v: f64,
+    // voltage at a given time, may keep state between calls
+    f: Box<dyn FnMut(f64) -> f64>,
+    // highest frequency in the signal, if known
+    freq: Option<f64>,
+    l0: usize,
+    l1: usize,
+    reserved: Option<VoltageFunctionReserved>,
+}
+
+impl std::fmt::Debug for VoltageFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VoltageFunction")
+            .field("v", &self.v)
+            .field("freq", &self.freq)
+            .field("l0", &self.l0)
+            .field("l1", &self.l1)
+            .field("reserved", &self.reserved)
+            .finish_non_exhaustive()
+    }
+}
+
+impl VoltageFunction {
+    /// Function source from a closure, eg. one capturing its parameters
+    fn from_closure(mut f: impl FnMut(f64) -> f64 + 'static, l0: usize, l1: usize) -> Self {
+        let v = f(0.0);
+        Self {
+            v,
+            f: Box::new(f),
+            freq: None,
+            l0,
+            l1,
+            reserved: None,
+        }
+    }
+
+    /// Function source whose highest frequency is known (eg. a sine)
+    fn with_frequency(
+        f: impl FnMut(f64) -> f64 + 'static,
+        freq: f64,
+        l0: usize,
+        l1: usize,
+    ) -> Self {
+        Self {
+            freq: Some(freq),
+            ..Self::from_closure(f, l0, l1)
+        }
+    }
+
+    /// Sine wave `offset + amp * sin(2 pi freq t)`
+    fn sine(amp: f64, freq: f64, offset: f64, l0: usize, l1: usize) -> Self {
+        let w = 2.0 * std::f64::consts::PI * freq;
+        Self::with_frequency(move |t| offset + amp * f64::sin(w * t), freq, l0, l1)
+    }
+
+    /// Square wave starting high, at `v_high` for `duty` of each period
+    fn pulse(v_low: f64, v_high: f64, period: f64, duty: f64, l0: usize, l1: usize) -> Self {
+        let f = move |t: f64| {
+            if (t / period).rem_euclid(1.0) < duty {
+                v_high
+            } else {
+                v_low
+            }
+        };
+        Self::with_frequency(f, 1.0 / period, l0, l1)
+    }
+}
+
+impl Component for VoltageFunction {
+    fn reserve(&mut self, m: &mut MNASystem) {
+        self.reserved = Some(VoltageFunctionReserved {
+            l2: m.reserve(),
+            dyn_index: m.reserve_dynamic(),
+        });
+    }
+
+    fn stamp(&self, m: &mut MNASystem) {
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let (dyn_index, l0, l1, l2) = (
+            reserved.dyn_index,
+            self.l0,
+            self.l1,
+            reserved.l2,
+        );
+
+        // this is identical to voltage source
+        // except voltage is dynanic
+        m.stamp_static(-1., l0, l2, "-1");
+        m.stamp_static(1., l1, l2, "+1");
+        m.stamp_static(1., l2, l0, "+1");
+        m.stamp_static(-1., l2, l1, "-1");
+
+        m.add_dynamic_b(l2, dyn_index, &format!("Vfn:{},{}", l0, l1));
+
+        m.nodes[l2] = MNANodeInfo::new_current(&format!("i:Vfn:{},{}", l0, l1));
+        self.update_dynamic(m);
+    }
+
+    fn update_dynamic(&self, m: &mut MNASystem) {
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        m.set_dynamic(reserved.dyn_index, self.v);
+    }
+    fn advance(&mut self, m: &mut MNASystem) {
+        self.v = (self.f)(m.time);
+        // Update dynamic variable since we changed state_var
+        self.update_dynamic(m);
+    }
+
+    fn max_frequency(&self) -> Option<f64> {
+        self.freq
+    }
+}
+
+/// Voltage source playing back audio samples, zero after the last one
+#[derive(Debug)]
+struct WavSource {
+    samples: Vec<f64>,
+    sample_rate: f64,
+    // volts for a full scale sample
+    amplitude: f64,
+    source: VoltageFunction,
+}
+
+impl WavSource {
+    fn new(samples: Vec<f64>, sample_rate: f64, amplitude: f64, l0: usize, l1: usize) -> Self {
+        let mut wav = Self {
+            samples,
+            sample_rate,
+            amplitude,
+            source: VoltageFunction::with_frequency(|_| 0.0, sample_rate / 2.0, l0, l1),
+        };
+        wav.source.v = wav.sample_at(0.0);
+        wav
+    }
+
+    /// Source playing the first channel of a 16 bit PCM WAV file
+    fn from_file(path: &str, amplitude: f64, l0: usize, l1: usize) -> io::Result<Self> {
+        let (rate, samples) = wav::read_wav(path)?;
+        Ok(Self::new(samples, rate as f64, amplitude, l0, l1))
+    }
+
+    /// Linearly interpolated voltage at time `t`
+    fn sample_at(&self, t: f64) -> f64 {
+        let x = t * self.sample_rate;
+        if x < 0.0 {
+            return 0.0;
+        }
+        let (i, frac) = (x.floor() as usize, x.fract());
+        let a = self.samples.get(i).copied().unwrap_or(0.0);
+        let b = self.samples.get(i + 1).copied().unwrap_or(0.0);
+        self.amplitude * (a + frac * (b - a))
+    }
+}
+
+impl Component for WavSource {
+    fn reserve(&mut self, m: &mut MNASystem) {
+        self.source.reserve(m);
+    }
+
+    fn stamp(&self, m: &mut MNASystem) {
+        self.source.stamp(m);
+    }
+
+    fn update_dynamic(&self, m: &mut MNASystem) {
+        self.source.update_dynamic(m);
+    }
+
+    fn advance(&mut self, m: &mut MNASystem) {
+        self.source.v = self.sample_at(m.time);
+        self.source.update_dynamic(m);
+    }
+
+    fn max_frequency(&self) -> Option<f64> {
+        self.source.max_frequency()
+    }
+}
+
+/// Output voltage of a behavioral source from its input voltages
+type BehavioralFn = Box<dyn Fn(&[f64]) -> f64>;
+
+/// Transfer curve of a waveshaper in the JSON model
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Shape {
+    // level * tanh(gain * x / level)
+    Tanh,
+    // gain * x limited to +-level
+    Clip,
+}
+
+impl Shape {
+    fn apply(self, x: f64, gain: f64, level: f64) -> f64 {
+        match self {
+            Shape::Tanh => level * f64::tanh(gain * x / level),
+            Shape::Clip => f64::clamp(gain * x, -level, level),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct BehavioralSourceReserved {
+    l2: usize,
+    // one slope for each input, then the offset
+    dyn_slopes: Vec<usize>,
+    dyn_offset: usize,
+}
+
+/// Voltage source driven by a function of node voltages (SPICE B-source)
+///
+/// `v(l0) - v(l1) = f(x)` where `x` holds the voltages of `inputs` in
+/// order, eg. `tanh` of one input for a soft clipper. Newton linearizes
+/// `f` with central differences around the last solution.
+struct BehavioralSource {
+    f: BehavioralFn,
+    inputs: Vec<usize>,
+    l0: usize,
+    l1: usize,
+    // input voltages of the present linearization
+    x: Vec<f64>,
+    // partial derivatives of f at x
+    slopes: Vec<f64>,
+    // f(x) minus the slopes times x
+    offset: f64,
+    // description for the JSON model, when f has one
+    model: Option<ModelComponent>,
+    reserved: Option<BehavioralSourceReserved>,
+}
+
+impl std::fmt::Debug for BehavioralSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BehavioralSource")
+            .field("inputs", &self.inputs)
+            .field("l0", &self.l0)
+            .field("l1", &self.l1)
+            .field("x", &self.x)
+            .field("slopes", &self.slopes)
+            .field("offset", &self.offset)
+            .field("model", &self.model)
+            .field("reserved", &self.reserved)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BehavioralSource {
+    fn new(f: impl Fn(&[f64]) -> f64 + 'static, inputs: Vec<usize>, l0: usize, l1: usize) -> Self {
+        let mut source = Self {
+            f: Box::new(f),
+            x: vec![0.0; inputs.len()],
+            slopes: vec![],
+            offset: 0.0,
+            inputs,
+            l0,
+            l1,
+            model: None,
+            reserved: None,
+        };
+        // initial state is linearized with all inputs at 0
+        source.linearize(vec![0.0; source.inputs.len()]);
+        source
+    }
+
+    /// Waveshaper of the voltage from `i0` to `i1`
+    fn waveshaper(
+        shape: Shape,
+        gain: f64,
+        level: f64,
+        (i0, i1): (usize, usize),
+        l0: usize,
+        l1: usize,
+    ) -> Self {
+        let f = move |x: &[f64]| shape.apply(x[0] - x[1], gain, level);
+        Self {
+            model: Some(ModelComponent::Waveshaper {
+                shape,
+                gain,
+                level,
+                nets: [l0, l1, i0, i1],
+            }),
+            ..Self::new(f, vec![i0, i1], l0, l1)
+        }
+    }
+
+    /// Partial derivatives of f at x, by central differences with step h
+    fn slopes_at(&self, x: &[f64], h: f64) -> Vec<f64> {
+        let mut probe = x.to_vec();
+        (0..x.len())
+            .map(|k| {
+                probe[k] = x[k] + h;
+                let up = (self.f)(&probe);
+                probe[k] = x[k] - h;
+                let down = (self.f)(&probe);
+                probe[k] = x[k];
+                (up - down) / (2.0 * h)
+            })
+            .collect()
+    }
+
+    fn linearize(&mut self, x: Vec<f64>) {
+        self.slopes = self.slopes_at(&x, BEHAVIORAL_STEP);
+        let dot: f64 = self.slopes.iter().zip(&x).map(|(g, v)| g * v).sum();
+        self.offset = (self.f)(&x) - dot;
+        self.x = x;
+    }
+}
+
+impl Component for BehavioralSource {
+    fn reserve(&mut self, m: &mut MNASystem) {
+        self.reserved = Some(BehavioralSourceReserved {
+            l2: m.reserve(),
+            dyn_slopes: self.inputs.iter().map(|_| m.reserve_dynamic()).collect(),
+            dyn_offset: m.reserve_dynamic(),
+        });
+    }
+
+    fn stamp(&self, m: &mut MNASystem) {
+        // voltage source with the linearized function in its row:
+        //
+        // |  .  .   . +1 | vl0
+        // |  .  .   . -1 | vl1
+        // |  .  .   .  . | vx
+        // | +1 -1  -g  . | i:B = f(x0) - g x0
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let (l0, l1, l2) = (self.l0, self.l1, reserved.l2);
+        m.stamp_static(-1., l0, l2, "-1");
+        m.stamp_static(1., l1, l2, "+1");
+        m.stamp_static(1., l2, l0, "+1");
+        m.stamp_static(-1., l2, l1, "-1");
+        for (input, index) in self.inputs.iter().zip(&reserved.dyn_slopes) {
+            m.add_dynamic_a(l2, *input, *index, &format!("-g:B:{}", input));
+        }
+        m.add_dynamic_b(l2, reserved.dyn_offset, &format!("v0:B:{},{}", l0, l1));
+        m.nodes[l2] = MNANodeInfo::new_current(&format!("i:B:{},{}", l0, l1));
+        self.update_dynamic(m);
+    }
+
+    fn update_dynamic(&self, m: &mut MNASystem) {
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        for (g, index) in self.slopes.iter().zip(&reserved.dyn_slopes) {
+            m.set_dynamic(*index, -g);
+        }
+        m.set_dynamic(reserved.dyn_offset, self.offset);
+    }
+
+    fn newton(&mut self, m: &mut MNASystem, options: &SimOptions) -> bool {
+        let x: Vec<f64> = self.inputs.iter().map(|n| m.b[*n].lu).collect();
+        let done = x
+            .iter()
+            .zip(&self.x)
+            .all(|(a, b)| f64::abs(a - b) < options.v_tolerance);
+        if !done {
+            self.linearize(x);
+            self.update_dynamic(m);
+        }
+        done
+    }
+
+    fn jacobian_error(&self, m: &MNASystem) -> Option<f64> {
+        let l2 = self.reserved.as_ref().expect("need reserved").l2;
+        // slopes with a coarser step than the linearization
+        let slopes = self.slopes_at(&self.x, JACOBIAN_STEP);
+        // expected row entries, summed where inputs share a net with the
+        // outputs, relative to the unit entries of the source
+        let mut nets = self.inputs.clone();
+        nets.extend([self.l0, self.l1]);
+        nets.sort();
+        nets.dedup();
+        let scale = 1.0 + slopes.iter().map(|g| g.abs()).sum::<f64>();
+        let err_g = nets
+            .iter()
+            .map(|c| {
+                let unit = f64::from(*c == self.l0) - f64::from(*c == self.l1);
+                let inputs: f64 = self
+                    .inputs
+                    .iter()
+                    .zip(&slopes)
+                    .filter(|(n, _)| *n == c)
+                    .map(|(_, g)| g)
+                    .sum();
+                f64::abs(m.assembled_a(l2, *c) - unit + inputs) / scale
+            })
+            .fold(0.0, f64::max);
+        // offset has to put the linear model through f(x)
+        let f = (self.f)(&self.x);
+        let dot: f64 = slopes.iter().zip(&self.x).map(|(g, v)| g * v).sum();
+        let (_, b) = m.assembled_row(l2);
+        let err_b = f64::abs(b - (f - dot)) / f64::max(f.abs() + dot.abs(), BEHAVIORAL_STEP);
+        Some(f64::max(err_g, err_b))
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        self.model.clone()
+    }
+}
+
+#[derive(Debug)]
+struct JunctionPN {
+    // variables
+    geq: f64,
+    ieq: f64,
+    veq: f64,
+    // parameters
+    is: f64,
+    n: f64,
+    nvt: f64,
+    rnvt: f64,
+    vcrit: f64,
+    g_min: f64,
+}
+
+impl JunctionPN {
+    fn new(is: f64, n: f64) -> Self {
+        let nvt = n * V_THERMAL;
+        // initial state is linearized at v=0
+        Self {
+            geq: is / (n * V_THERMAL) + G_MIN,
+            ieq: 0.0,
+            veq: 0.0,
+            is,
+            n,
+            nvt,
+            rnvt: 1. / nvt,
+            vcrit: nvt * f64::ln(nvt / (is * f64::sqrt(2.0))),
+            g_min: G_MIN,
+        }
+    }
+
+    /// Follow thermal voltage and leakage changed in the options
+    fn set_options(&mut self, options: &SimOptions) {
+        self.g_min = options.g_min;
+        let nvt = self.n * options.v_thermal;
+        if nvt != self.nvt {
+            self.nvt = nvt;
+            self.rnvt = 1. / nvt;
+            self.vcrit = nvt * f64::ln(nvt / (self.is * f64::sqrt(2.0)));
+        }
+    }
+
+    fn linearize(&mut self, v: f64) {
+        // linearize junction at the specified voltage
+        //
+        // ideally we could handle series resistance here as well
+        // to avoid putting it on a separate node, but not sure how
+        // to make that work as it looks like we'd need Lambert-W then
+        let e = self.is * f64::exp(v * self.rnvt);
+        let i = e - self.is + self.g_min * v;
+        let g = e * self.rnvt + self.g_min;
+
+        self.geq = g;
+        self.ieq = v * g - i;
+        self.veq = v;
+    }
+
+    // returns true if junction is good enough
+    fn newton(&mut self, v: f64, options: &SimOptions) -> bool {
+        self.set_options(options);
+        let dv = v - self.veq;
+        if f64::abs(dv) < options.v_tolerance {
+            return true;
+        }
+        // check critical voltage and adjust voltage if over
+        let vv = if v > self.vcrit && self.veq <= 0.0 {
+            // coming from reverse bias, a step relative to veq would crawl
+            // (this is the SPICE pnjlim rule for that case)
+            self.nvt * f64::ln(v * self.rnvt)
+        } else if v > self.vcrit {
+            // this formula comes from Qucs documentation
+            // https://qucs.sourceforge.net/tech/node16.html#SECTION00431000000000000000
+            self.veq + self.nvt * f64::ln(f64::max(self.is, 1.0 + dv * self.rnvt))
+        } else {
+            v
+        };
+        self.linearize(vv);
+        false
+    }
+
+    /// Junction current from the device equation (with g_min leakage)
+    fn current(&self, v: f64) -> f64 {
+        self.is * (f64::exp(v * self.rnvt) - 1.0) + self.g_min * v
+    }
+
+    /// Tangent (geq, ieq) of the device current at veq, by central differences
+    fn tangent(&self) -> (f64, f64) {
+        let (v, h) = (self.veq, JACOBIAN_STEP * self.nvt);
+        let g = (self.current(v + h) - self.current(v - h)) / (2.0 * h);
+        (g, g * v - self.current(v))
+    }
+}
+
+/// Relative mismatch of a stamped (geq, ieq) against the expected tangent
+///
+/// Conductances are compared directly and currents at `v`, relative to
+/// the expected current but at least `scale`.
+fn tangent_error(stamped: (f64, f64), expected: (f64, f64), v: f64, scale: f64) -> f64 {
+    let err_g = f64::abs(stamped.0 - expected.0) / expected.0;
+    // linear model has to pass through the operating point
+    let i = expected.0 * v - expected.1;
+    let err_i = f64::abs(stamped.0 * v - stamped.1 - i) / f64::max(f64::abs(i), scale);
+    f64::max(err_g, err_i)
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct DiodeParameters {
+    // Series resistor in model
+    rs: f64,
+    // Reverse bias saturation current
+    is: f64,
+    // Ideality factor
+    n: f64,
+    // Highest forward current the model is trusted for
+    i_max: f64,
+}
+
+impl Default for DiodeParameters {
+    fn default() -> Self {
+        // Default diode approximates 1N4148
+        Self {
+            rs: 10.0,
+            is: 35.0e-12,
+            n: 1.24,
+            i_max: 0.3,
+        }
+    }
+}
+
+impl DiodeParameters {
+    /// Small-signal Schottky diode, roughly BAT54 (about 0.3V at 10mA)
+    fn schottky() -> Self {
+        Self {
+            rs: 1.0,
+            is: 2e-7,
+            n: 1.05,
+            i_max: 0.2,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct DiodeReserved {
+    l2: usize,
+    l3: usize,
+    dyn_index0: usize,
+    dyn_index1: usize,
+}
+
+#[derive(Debug)]
+struct Diode {
+    l0: usize,
+    l1: usize,
+    pn: JunctionPN,
+    rs: f64,
+    i_max: f64,
+    reserved: Option<DiodeReserved>,
+}
+
+impl Diode {
+    fn new(l0: usize, l1: usize, params: DiodeParameters) -> Self {
+        let pn = JunctionPN::new(params.is, params.n);
+        Self {
+            l0,
+            l1,
+            rs: params.rs,
+            i_max: params.i_max,
+            pn,
+            reserved: None,
+        }
+    }
+}
+
+impl Component for Diode {
+    fn reserve(&mut self, m: &mut MNASystem) {
+        self.reserved = Some(DiodeReserved {
+            l2: m.reserve(),
+            l3: m.reserve(),
+            dyn_index0: m.reserve_dynamic(),
+            dyn_index1: m.reserve_dynamic(),
+        });
+    }
+
+    fn stamp(&self, m: &mut MNASystem) {
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let (l0, l1, l2, l3) = (self.l0, self.l1, reserved.l2, reserved.l3);
+
+        // Diode could be built with 3 extra nodes:
+        //
+        // |  .  .    .       . +1 | V+
+        // |  .  .    .       . -1 | V-
+        // |  .  .  grs    -grs -1 | v:D
+        // |  .  . -grs grs+geq  . | v:pn = ieq
+        // | -1 +1   +1       .  . | i:pn
+        //
+        // Here grs is the 1/rs series conductance.
+        //
+        // This gives us the junction voltage (v:pn) and
+        // current (i:pn) and the composite voltage (v:D).
+        //
+        // The i:pn row is an ideal transformer connecting
+        // the floating diode to the ground-referenced v:D
+        // where we connect the series resistance to v:pn
+        // that solves the diode equation with Newton.
+        //
+        // We can then add the 3rd row to the bottom 2 with
+        // multipliers 1 and -rs = -1/grs and drop it:
+        //
+        // |  .  .   . +1 | V+
+        // |  .  .   . -1 | V-
+        // |  .  . geq -1 | v:pn = ieq
+        // | -1 +1  +1 rs | i:pn
+        //
+        // Note that only the v:pn row here is non-linear.
+        //
+        // We could even do away without the separate row for
+        // the current, which would lead to the following:
+        //
+        // | +grs -grs     -grs |
+        // | -grs +grs     +grs |
+        // | -grs +grs +grs+geq | = ieq
+        //
+        // In practice we keep the current row since it's
+        // nice to have it as an output anyway.
+        //
+        m.stamp_static(-1.0, l3, l0, "-1");
+        m.stamp_static(1.0, l3, l1, "+1");
+        m.stamp_static(1.0, l3, l2, "+1");
+        m.stamp_static(1.0, l0, l3, "+1");
+        m.stamp_static(-1.0, l1, l3, "-1");
+        m.stamp_static(-1.0, l2, l3, "-1");
+        m.stamp_static(self.rs, l3, l3, "rs:pn");
+        m.add_dynamic_a(l2, l2, reserved.dyn_index0, "gm:D");
+        m.add_dynamic_b(l2, reserved.dyn_index1, &format!("i0:D:{},{}", l0, l1));
+        m.nodes[l2] = MNANodeInfo::new_voltage_with_name(&format!("v:D:{},{}", l0, l1));
+        m.nodes[l3] = MNANodeInfo::new_current(&format!("i:D:{},{}", l0, l1));
+        self.update_dynamic(m);
+    }
+
+    fn update_dynamic(&self, m: &mut MNASystem) {
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        m.set_dynamic(reserved.dyn_index0, self.pn.geq);
+        m.set_dynamic(reserved.dyn_index1, self.pn.ieq);
+    }
+
+    fn newton(&mut self, m: &mut MNASystem, options: &SimOptions) -> bool {
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let done = self.pn.newton(m.b[reserved.l2].lu, options);
+        // Update dynamic variables since we relinearized
+        self.update_dynamic(m);
+        done
+    }
+
+    fn newton_residual(&self, m: &MNASystem) -> Option<(usize, f64)> {
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        Some((reserved.l2, m.b[reserved.l2].lu - self.pn.veq))
+    }
+
+    fn jacobian_error(&self, m: &MNASystem) -> Option<f64> {
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let pn = &self.pn;
+        Some(tangent_error(
+            m.assembled_row(reserved.l2),
+            pn.tangent(),
+            pn.veq,
+            pn.is,
+        ))
+    }
+
+    fn noise_sources(&self, m: &MNASystem, _options: &SimOptions) -> Vec<(usize, usize, f64)> {
+        // shot noise 2qI in parallel with the junction
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let i = m.b[reserved.l3].lu;
+        vec![(reserved.l2, 0, 2.0 * Q_ELECTRON * i.abs())]
+    }
+
+    fn range_warnings(&self, m: &MNASystem) -> Vec<String> {
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let i = m.b[reserved.l3].lu;
+        if i > self.i_max {
+            vec![format!(
+                "diode {},{}: forward current {:.3}A above {}",
+                self.l0,
+                self.l1,
+                i,
+                format_unit_value(self.i_max, "A")
+            )]
+        } else {
+            vec![]
+        }
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::Diode {
+            nets: [self.l0, self.l1],
+            params: DiodeParameters {
+                rs: self.rs,
+                is: self.pn.is,
+                n: self.pn.n,
+                i_max: self.i_max,
+            },
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+enum LedColor {
+    Red,
+    Green,
+    Blue,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct LedParameters {
+    color: LedColor,
+    // Current for full brightness, where the forward voltage is specified
+    i_nominal: f64,
+    // Series resistor in model
+    rs: f64,
+    // Ideality factor
+    n: f64,
+}
+
+impl Default for LedParameters {
+    fn default() -> Self {
+        // Default LED is a plain 5mm red indicator
+        Self::new(LedColor::Red)
+    }
+}
+
+impl LedParameters {
+    /// Plain 5mm indicator of this color
+    fn new(color: LedColor) -> Self {
+        Self {
+            color,
+            i_nominal: 20e-3,
+            rs: 2.0,
+            n: 2.0,
+        }
+    }
+
+    /// Forward voltage at nominal current
+    fn vf(&self) -> f64 {
+        match self.color {
+            LedColor::Red => 1.8,
+            LedColor::Green => 2.1,
+            LedColor::Blue => 3.0,
+        }
+    }
+
+    /// Junction parameters giving vf at i_nominal
+    fn diode(&self) -> DiodeParameters {
+        let v_junction = self.vf() - self.rs * self.i_nominal;
+        DiodeParameters {
+            rs: self.rs,
+            is: self.i_nominal / f64::exp(v_junction / (self.n * V_THERMAL)),
+            n: self.n,
+            i_max: 2.5 * self.i_nominal,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LedReserved {
+    l: usize,
+}
+
+#[derive(Debug)]
+struct Led {
+    // diode from l0 (anode) to l1 (cathode)
+    diode: Diode,
+    params: LedParameters,
+    reserved: Option<LedReserved>,
+}
+
+impl Led {
+    fn new(l0: usize, l1: usize, params: LedParameters) -> Self {
+        Self {
+            diode: Diode::new(l0, l1, params.diode()),
+            params,
+            reserved: None,
+        }
+    }
+}
+
+impl Component for Led {
+    fn reserve(&mut self, m: &mut MNASystem) {
+        self.diode.reserve(m);
+        self.reserved = Some(LedReserved { l: m.reserve() });
+    }
+
+    fn stamp(&self, m: &mut MNASystem) {
+        // Brightness row copies the diode current, and its node
+        // info scales that relative to the nominal current:
+        //
+        // | i:D lum |
+        // |  -1  +1 | lum:LED = 0
+        self.diode.stamp(m);
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let diode = self.diode.reserved.as_ref().expect("need reserved");
+        let (l0, l1, l) = (self.diode.l0, self.diode.l1, reserved.l);
+        m.stamp_static(-1., l, diode.l3, "-1");
+        m.stamp_static(1., l, l, "+1");
+        m.nodes[diode.l2] = MNANodeInfo::new_voltage_with_name(&format!("v:LED:{},{}", l0, l1));
+        m.nodes[diode.l3] = MNANodeInfo::new_current(&format!("i:LED:{},{}", l0, l1));
+        m.nodes[l] = MNANodeInfo::new_current_with_scale(
+            &format!("lum:LED:{},{}", l0, l1),
+            1.0 / self.params.i_nominal,
+        );
+    }
+
+    fn update_dynamic(&self, m: &mut MNASystem) {
+        self.diode.update_dynamic(m);
+    }
+
+    fn newton(&mut self, m: &mut MNASystem, options: &SimOptions) -> bool {
+        self.diode.newton(m, options)
+    }
+
+    fn newton_residual(&self, m: &MNASystem) -> Option<(usize, f64)> {
+        self.diode.newton_residual(m)
+    }
+
+    fn jacobian_error(&self, m: &MNASystem) -> Option<f64> {
+        self.diode.jacobian_error(m)
+    }
+
+    fn range_warnings(&self, m: &MNASystem) -> Vec<String> {
+        self.diode.range_warnings(m)
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::Led {
+            nets: [self.diode.l0, self.diode.l1],
+            params: self.params.clone(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct ZenerParameters {
+    // Reverse voltage where breakdown starts
+    vz: f64,
+    // Breakdown current scale, reverse voltage is vz + nvt * ln(1 + i / i_knee)
+    i_knee: f64,
+    // Ideality factor of the breakdown junction
+    n_bv: f64,
+    // Forward conduction
+    diode: DiodeParameters,
+}
+
+impl Default for ZenerParameters {
+    fn default() -> Self {
+        // Default zener approximates 1N4733 (5.1V)
+        Self {
+            vz: 5.1,
+            i_knee: 1e-3,
+            n_bv: 1.0,
+            diode: DiodeParameters {
+                rs: 1.0,
+                ..DiodeParameters::default()
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ZenerReserved {
+    dyn_geq: usize,
+    dyn_ieq: usize,
+}
+
+#[derive(Debug)]
+struct Zener {
+    // normal diode from l0 (anode) to l1 (cathode) for forward conduction
+    diode: Diode,
+    // second junction across the same nodes, turned on by v < -vz
+    breakdown: JunctionPN,
+    params: ZenerParameters,
+    reserved: Option<ZenerReserved>,
+}
+
+impl Zener {
+    fn new(l0: usize, l1: usize, params: ZenerParameters) -> Self {
+        Self {
+            diode: Diode::new(l0, l1, params.diode.clone()),
+            // starts linearized at the onset of breakdown
+            breakdown: JunctionPN::new(params.i_knee, params.n_bv),
+            params,
+            reserved: None,
+        }
+    }
+}
+
+impl Component for Zener {
+    fn reserve(&mut self, m: &mut MNASystem) {
+        self.diode.reserve(m);
+        self.reserved = Some(ZenerReserved {
+            dyn_geq: m.reserve_dynamic(),
+            dyn_ieq: m.reserve_dynamic(),
+        });
+    }
+
+    fn stamp(&self, m: &mut MNASystem) {
+        // Breakdown junction sits on the same v:pn row as the
+        // forward one, with its voltage x = -v - vz, so its
+        // current in the forward direction is
+        //
+        //   -(geq * x - ieq) = geq * v + geq * vz + ieq
+        //
+        // which adds geq to the row and moves the rest to the rhs.
+        self.diode.stamp(m);
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let diode = self.diode.reserved.as_ref().expect("need reserved");
+        let (l0, l1) = (self.diode.l0, self.diode.l1);
+        m.add_dynamic_a(diode.l2, diode.l2, reserved.dyn_geq, "gm:Z");
+        m.add_dynamic_b(diode.l2, reserved.dyn_ieq, &format!("i0:Z:{},{}", l0, l1));
+        m.nodes[diode.l2] = MNANodeInfo::new_voltage_with_name(&format!("v:Z:{},{}", l0, l1));
+        m.nodes[diode.l3] = MNANodeInfo::new_current(&format!("i:Z:{},{}", l0, l1));
+        self.update_dynamic(m);
+    }
+
+    fn update_dynamic(&self, m: &mut MNASystem) {
+        self.diode.update_dynamic(m);
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let pn = &self.breakdown;
+        m.set_dynamic(reserved.dyn_geq, pn.geq);
+        m.set_dynamic(reserved.dyn_ieq, -(pn.geq * self.params.vz + pn.ieq));
+    }
+
+    fn newton(&mut self, m: &mut MNASystem, options: &SimOptions) -> bool {
+        let diode = self.diode.reserved.as_ref().expect("need reserved");
+        let v = m.b[diode.l2].lu;
+        // both junctions need to be relinearized, so no short-circuit here
+        let done_f = self.diode.newton(m, options);
+        let done_bv = self.breakdown.newton(-v - self.params.vz, options);
+        // Update dynamic variables since we relinearized
+        self.update_dynamic(m);
+        done_f && done_bv
+    }
+
+    fn newton_residual(&self, m: &MNASystem) -> Option<(usize, f64)> {
+        // both junctions share the diode's v:pn row
+        self.diode.newton_residual(m)
+    }
+
+    fn jacobian_error(&self, m: &MNASystem) -> Option<f64> {
+        // both junctions add up on the v:pn row, breakdown mirrored as in stamp
+        let diode = self.diode.reserved.as_ref().expect("need reserved");
+        let (pn, bv) = (&self.diode.pn, &self.breakdown);
+        let (g_f, i_f) = pn.tangent();
+        let (g_bv, i_bv) = bv.tangent();
+        let expected = (g_f + g_bv, i_f - (g_bv * self.params.vz + i_bv));
+        Some(tangent_error(
+            m.assembled_row(diode.l2),
+            expected,
+            pn.veq,
+            f64::max(pn.is, bv.is),
+        ))
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::Zener {
+            nets: [self.diode.l0, self.diode.l1],
+            params: self.params.clone(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum TransistorType {
+    #[serde(rename = "NPN")]
+    Npn,
+    #[serde(rename = "PNP")]
+    Pnp,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct BJTParameters {
+    // Forward beta
+    bf: f64,
+    // Reverse beta
+    br: f64,
+    // Base resistor in model
+    rb: f64,
+    // Emitter resistor in model
+    re: f64,
+    // Collector resistor in model
+    rc: f64,
+    // Reverse bias saturation current
+    is: f64,
+    // Ideality factor
+    n: f64,
+    // Highest collector-emitter voltage the model is trusted for
+    vce_max: f64,
+    // Base-emitter and base-collector depletion capacitances
+    cje: f64,
+    cjc: f64,
+    // Forward and reverse transit times, for diffusion capacitance
+    tf: f64,
+    tr: f64,
+    transistor_type: TransistorType,
+}
+
+// Computed parameters from other params
+impl BJTParameters {
+    // Forward alpha
+    fn af(&self) -> f64 {
+        self.bf / (1.0 + self.bf)
+    }
+    // Reverse alpha
+    fn ar(&self) -> f64 {
+        self.br / (1.0 + self.br)
+    }
+    // Series resistance from base-collector
+    fn rsbc(&self) -> f64 {
+        self.rb + self.rc
+    }
+    // Series resistance from base-emitter
+    fn rsbe(&self) -> f64 {
+        self.rb + self.re
+    }
+}
+
+impl Default for BJTParameters {
+    fn default() -> Self {
+        // Default transistor approximates 2N3904
+        Self {
+            bf: 200.0,
+            br: 20.0,
+            rb: 5.8376,
+            re: 2.65711,
+            rc: 0.0001,
+            is: 6.734e-15,
+            n: 1.24,
+            vce_max: 40.0,
+            cje: 4.493e-12,
+            cjc: 3.638e-12,
+            tf: 301.2e-12,
+            tr: 239.5e-9,
+            transistor_type: TransistorType::Npn,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct BJTReserved {
+    l: [usize; 4],
+    dyn_pnc_ieq: usize,
+    dyn_pnc_geq: usize,
+    dyn_pne_ieq: usize,
+    dyn_pne_geq: usize,
+    // charge of base-collector and base-emitter junctions, if any
+    charges: Vec<ChargeStorage>,
+}
+
+#[derive(Debug)]
+struct Bjt {
+    pin: [usize; 3],
+    pnc: JunctionPN,
+    pne: JunctionPN,
+    params: BJTParameters,
+    reserved: Option<BJTReserved>,
+}
+
+impl Bjt {
+    fn new(b: usize, c: usize, e: usize, params: BJTParameters) -> Self {
+        let pne = JunctionPN::new(params.is / params.af(), params.n);
+        let pnc = JunctionPN::new(params.is / params.ar(), params.n);
+        Self {
+            pin: [b, c, e],
+            pnc,
+            pne,
+            params,
+            reserved: None,
+        }
+    }
+}
+
+impl Component for Bjt {
+    fn reserve(&mut self, m: &mut MNASystem) {
+        let l = [m.reserve(), m.reserve(), m.reserve(), m.reserve()];
+        let [b, c, e] = self.pin;
+        let p = &self.params;
+        // depletion charge goes with the junction voltage and diffusion
+        // charge with the junction current, which flows base to c or e
+        let mut charges = vec![];
+        for (cj, t, pin, current, name) in
+            [(p.cjc, p.tr, c, l[2], "bc"), (p.cje, p.tf, e, l[3], "be")]
+        {
+            if cj == 0.0 && t == 0.0 {
+                continue;
+            }
+            let terms = vec![(b, cj), (pin, -cj), (current, t)];
+            let name = format!("Q:{},{},{}:{}", b, c, e, name);
+            let mut charge = ChargeStorage::new(terms, b, pin, &name);
+            charge.reserve(m);
+            charges.push(charge);
+        }
+        self.reserved = Some(BJTReserved {
+            l,
+            dyn_pnc_ieq: m.reserve_dynamic(),
+            dyn_pnc_geq: m.reserve_dynamic(),
+            dyn_pne_ieq: m.reserve_dynamic(),
+            dyn_pne_geq: m.reserve_dynamic(),
+            charges,
+        });
+    }
+
+    fn stamp(&self, m: &mut MNASystem) {
+        // The basic idea here is the same as with diodes
+        // except we do it once for each junction.
+        //
+        // With the transfer currents sourced from the
+        // diode currents, NPN then looks like this:
+        //
+        // 0 |  .  .  .  .  . 1-ar 1-af | vB
+        // 1 |  .  .  .  .  .   -1  +af | vC
+        // 2 |  .  .  .  .  .  +ar   -1 | vE
+        // 3 |  .  .  . gc  .   -1    . | v:Qbc  = ic
+        // 4 |  .  .  .  . ge    .   -1 | v:Qbe  = ie
+        // 5 | -1 +1  . +1  . rsbc    . | i:Qbc
+        // 6 | -1  . +1  . +1    . rsbe | i:Qbe
+        //     ------------------------
+        //      0  1  2  3  4    5    6
+        //
+        // For PNP version, we simply flip the junctions
+        // by changing signs of (3,5),(5,3) and (4,6),(6,4).
+        //
+        // Also just like diodes, we have junction series
+        // resistances, rather than terminal resistances.
+        //
+        // This works just as well, but should be kept
+        // in mind when fitting particular transistors.
+        //
+        // Cheat sheet:
+        // nets[0] pin[0]
+        // nets[1] pin[1]
+        // nets[2] pin[2]
+        // nets[3] l[0]
+        // nets[4] l[1]
+        // nets[5] l[2]
+        // nets[6] l[3]
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let (pin, l, params) = (self.pin, reserved.l, &self.params);
+        let dyn_pnc_ieq = reserved.dyn_pnc_ieq;
+        let dyn_pnc_geq = reserved.dyn_pnc_geq;
+        let dyn_pne_ieq = reserved.dyn_pne_ieq;
+        let dyn_pne_geq = reserved.dyn_pne_geq;
+        let pnp = self.params.transistor_type == TransistorType::Pnp;
+        // diode currents to external base
+        m.stamp_static(1.0 - params.ar(), pin[0], l[2], "1-ar");
+        m.stamp_static(1.0 - params.af(), pin[0], l[3], "1-ar");
+        // diode currents to external collector and emitter
+        m.stamp_static(-1.0, pin[1], l[2], "-1");
+        m.stamp_static(-1.0, pin[2], l[3], "-1");
+        // series resistances
+        m.stamp_static(params.rsbc(), l[2], l[2], "rsbc");
+        m.stamp_static(params.rsbe(), l[3], l[3], "rsbe");
+        // current - junction connections
+        // for the PNP case we flip the signs of these
+        // to flip the diode junctions wrt. the above
+        if pnp {
+            m.stamp_static(-1.0, l[2], l[0], "-1");
+            m.stamp_static(1.0, l[0], l[2], "+1");
+            m.stamp_static(-1.0, l[3], l[1], "-1");
+            m.stamp_static(1.0, l[1], l[3], "+1");
+        } else {
+            m.stamp_static(1.0, l[2], l[0], "+1");
+            m.stamp_static(-1.0, l[0], l[2], "-1");
+            m.stamp_static(1.0, l[3], l[1], "+1");
+            m.stamp_static(-1.0, l[1], l[3], "-1");
+        }
+        // external voltages to collector current
+        m.stamp_static(-1.0, l[2], pin[0], "-1");
+        m.stamp_static(1.0, l[2], pin[1], "+1");
+        // external voltages to emitter current
+        m.stamp_static(-1.0, l[3], pin[0], "-1");
+        m.stamp_static(1.0, l[3], pin[2], "+1");
+        // source transfer currents to external pins
+        m.stamp_static(params.ar(), pin[2], l[2], "+ar");
+        m.stamp_static(params.af(), pin[1], l[3], "+af");
+        // dynamic variables
+        m.add_dynamic_a(l[0], l[0], dyn_pnc_geq, "gm:Qbc");
+        m.add_dynamic_b(
+            l[0],
+            dyn_pnc_ieq,
+            &format!("i0:Q:{},{},{}:cb", pin[0], pin[1], pin[2]),
+        );
+        m.add_dynamic_a(l[1], l[1], dyn_pne_geq, "gm:Qbe");
+        m.add_dynamic_b(
+            l[1],
+            dyn_pne_ieq,
+            &format!("i0:Q:{},{},{}:eb", pin[0], pin[1], pin[2]),
+        );
+        // voltage and current infos
+        m.nodes[l[0]] = MNANodeInfo::new_voltage_with_name(&format!(
+            "v:Q:{},{},{}:{}",
+            pin[0],
+            pin[1],
+            pin[2],
+            if pnp { "cb" } else { "bc" }
+        ));
+        m.nodes[l[1]] = MNANodeInfo::new_voltage_with_name(&format!(
+            "v:Q:{},{},{}:{}",
+            pin[0],
+            pin[1],
+            pin[2],
+            if pnp { "eb" } else { "be" }
+        ));
+        for charge in &reserved.charges {
+            charge.stamp(m);
+        }
+        m.nodes[l[2]] = MNANodeInfo::new_current_with_scale(
+            &format!("i:Q:{},{},{}:bc", pin[0], pin[1], pin[2],),
+            1.0 - params.ar(),
+        );
+        m.nodes[l[3]] = MNANodeInfo::new_current_with_scale(
+            &format!("i:Q:{},{},{}:be", pin[0], pin[1], pin[2],),
+            1.0 - params.af(),
+        );
+        self.update_dynamic(m);
+    }
+
+    fn update_dynamic(&self, m: &mut MNASystem) {
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        m.set_dynamic(reserved.dyn_pnc_ieq, self.pnc.ieq);
+        m.set_dynamic(reserved.dyn_pnc_geq, self.pnc.geq);
+        m.set_dynamic(reserved.dyn_pne_ieq, self.pne.ieq);
+        m.set_dynamic(reserved.dyn_pne_geq, self.pne.geq);
+    }
+
+    fn update(&mut self, m: &mut MNASystem) {
+        let reserved = self.reserved.as_mut().expect("need reserved");
+        for charge in &mut reserved.charges {
+            charge.update(m);
+        }
+        self.update_dynamic(m);
+    }
+
+    fn scale_time(&mut self, m: &mut MNASystem, t_old_per_new: f64) {
+        let reserved = self.reserved.as_mut().expect("need reserved");
+        for charge in &mut reserved.charges {
+            charge.scale_time(m, t_old_per_new);
+        }
+    }
+
+    fn newton(&mut self, m: &mut MNASystem, options: &SimOptions) -> bool {
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        // both junctions need to be relinearized, so no short-circuit here
+        let done_c = self.pnc.newton(m.b[reserved.l[0]].lu, options);
+        let done_e = self.pne.newton(m.b[reserved.l[1]].lu, options);
+        // Update dynamic variables since we relinearized
+        self.update_dynamic(m);
+        done_c && done_e
+    }
+
+    fn jacobian_error(&self, m: &MNASystem) -> Option<f64> {
+        let l = self.reserved.as_ref().expect("need reserved").l;
+        let [c, e] = [(l[0], &self.pnc), (l[1], &self.pne)]
+            .map(|(r, pn)| tangent_error(m.assembled_row(r), pn.tangent(), pn.veq, pn.is));
+        Some(f64::max(c, e))
+    }
+
+    fn noise_sources(&self, m: &MNASystem, _options: &SimOptions) -> Vec<(usize, usize, f64)> {
+        // shot noise 2qI across each junction, which the transfer
+        // currents carry on to the collector and emitter
+        let l = self.reserved.as_ref().expect("need reserved").l;
+        [(l[0], l[2]), (l[1], l[3])]
+            .iter()
+            .map(|(v, i)| (*v, 0, 2.0 * Q_ELECTRON * m.b[*i].lu.abs()))
+            .collect()
+    }
+
+    fn range_warnings(&self, m: &MNASystem) -> Vec<String> {
+        let [b, c, e] = self.pin;
+        let vce = m.b[c].lu - m.b[e].lu;
+        // for PNP the collector sits below the emitter
+        let vce = match self.params.transistor_type {
+            TransistorType::Npn => vce,
+            TransistorType::Pnp => -vce,
+        };
+        if f64::abs(vce) > self.params.vce_max {
+            vec![format!(
+                "transistor {},{},{}: Vce {:.3}V beyond {}",
+                b,
+                c,
+                e,
+                vce,
+                format_unit_value(self.params.vce_max, "V")
+            )]
+        } else {
+            vec![]
+        }
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::Bjt {
+            nets: self.pin,
+            params: self.params.clone(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum MosfetType {
+    #[serde(rename = "NMOS")]
+    Nmos,
+    #[serde(rename = "PMOS")]
+    Pmos,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct MosfetParameters {
+    // Threshold voltage, magnitude for both NMOS and PMOS
+    vt: f64,
+    // Transconductance parameter (A/V^2)
+    kp: f64,
+    // Channel length modulation (1/V)
+    lambda: f64,
+    mosfet_type: MosfetType,
+}
+
+impl Default for MosfetParameters {
+    fn default() -> Self {
+        // Default MOSFET approximates 2N7000
+        Self {
+            vt: 2.1,
+            kp: 0.1,
+            lambda: 0.01,
+            mosfet_type: MosfetType::Nmos,
+        }
+    }
+}
+
+impl MosfetParameters {
+    /// Level 1 (square law) NMOS drain current with its derivatives
+    /// (id, gm, gds) for vds >= 0
+    fn forward_current(&self, vgs: f64, vds: f64) -> (f64, f64, f64) {
+        let vov = vgs - self.vt;
+        let clm = 1.0 + self.lambda * vds;
+        if vov <= 0.0 {
+            // cutoff
+            (0.0, 0.0, 0.0)
+        } else if vds < vov {
+            // linear region
+            let i = self.kp * (vov * vds - 0.5 * vds * vds);
+            (
+                i * clm,
+                self.kp * vds * clm,
+                self.kp * (vov - vds) * clm + i * self.lambda,
+            )
+        } else {
+            // saturation
+            let i = 0.5 * self.kp * vov * vov;
+            (i * clm, self.kp * vov * clm, i * self.lambda)
+        }
+    }
+
+    /// Drain current with derivatives (id, gm, gds) at any bias, including
+    /// `g_min` leakage from drain to source
+    fn drain_current(&self, vgs: f64, vds: f64, g_min: f64) -> (f64, f64, f64) {
+        // PMOS is NMOS with all voltages and currents flipped
+        let sign = match self.mosfet_type {
+            MosfetType::Nmos => 1.0,
+            MosfetType::Pmos => -1.0,
+        };
+        let (vgs, vds) = (sign * vgs, sign * vds);
+        // the channel is symmetric, swap drain and source when reversed
+        let (i, gm, gds) = if vds >= 0.0 {
+            self.forward_current(vgs, vds)
+        } else {
+            let (i, gm, gds) = self.forward_current(vgs - vds, -vds);
+            (-i, -gm, gm + gds)
+        };
+        (sign * i + g_min * sign * vds, gm, gds + g_min)
+    }
+}
+
+#[derive(Debug)]
+struct MosfetReserved {
+    l: usize,
+    dyn_gm: usize,
+    dyn_gds: usize,
+    dyn_gs: usize,
+    dyn_ieq: usize,
+}
+
+#[derive(Debug)]
+struct Mosfet {
+    // gate, drain, source
+    pin: [usize; 3],
+    params: MosfetParameters,
+    // linearization point
+    vgs: f64,
+    vds: f64,
+    gm: f64,
+    gds: f64,
+    ieq: f64,
+    // drain to source leakage, from the simulator options
+    g_min: f64,
+    reserved: Option<MosfetReserved>,
+}
+
+impl Mosfet {
+    fn new(g: usize, d: usize, s: usize, params: MosfetParameters) -> Self {
+        let mut res = Self {
+            pin: [g, d, s],
+            params,
+            vgs: 0.0,
+            vds: 0.0,
+            gm: 0.0,
+            gds: 0.0,
+            ieq: 0.0,
+            g_min: G_MIN,
+            reserved: None,
+        };
+        res.linearize(0.0, 0.0);
+        res
+    }
+
+    /// Tangent (gm, gds, ieq) of the drain current at the linearization
+    /// point, by central differences
+    fn tangent(&self) -> (f64, f64, f64) {
+        let (vgs, vds, h) = (self.vgs, self.vds, JACOBIAN_STEP);
+        let id = |vgs, vds| self.params.drain_current(vgs, vds, self.g_min).0;
+        let gm = (id(vgs + h, vds) - id(vgs - h, vds)) / (2.0 * h);
+        let gds = (id(vgs, vds + h) - id(vgs, vds - h)) / (2.0 * h);
+        (gm, gds, id(vgs, vds) - gm * vgs - gds * vds)
+    }
+
+    fn linearize(&mut self, vgs: f64, vds: f64) {
+        let (i, gm, gds) = self.params.drain_current(vgs, vds, self.g_min);
+        self.vgs = vgs;
+        self.vds = vds;
+        self.gm = gm;
+        self.gds = gds;
+        self.ieq = i - gm * vgs - gds * vds;
+    }
+}
+
+impl Component for Mosfet {
+    fn reserve(&mut self, m: &mut MNASystem) {
+        self.reserved = Some(MosfetReserved {
+            l: m.reserve(),
+            dyn_gm: m.reserve_dynamic(),
+            dyn_gds: m.reserve_dynamic(),
+            dyn_gs: m.reserve_dynamic(),
+            dyn_ieq: m.reserve_dynamic(),
+        });
+    }
+
+    fn stamp(&self, m: &mut MNASystem) {
+        // Drain current gets its own row, linearized as
+        // id = gm * vgs + gds * vds + ieq
+        //
+        // |  .   .        .  . | vG
+        // |  .   .        . +1 | vD
+        // |  .   .        . -1 | vS
+        // | gm gds -gm-gds  -1 | i:M = -ieq
+        //
+        // The gate draws no current, so its row is empty.
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        let ([g, d, s], l) = (self.pin, reserved.l);
+        m.stamp_static(1.0, d, l, "+1");
+        m.stamp_static(-1.0, s, l, "-1");
+        m.stamp_static(-1.0, l, l, "-1");
+        m.add_dynamic_a(l, g, reserved.dyn_gm, "gm:M");
+        m.add_dynamic_a(l, d, reserved.dyn_gds, "gds:M");
+        m.add_dynamic_a(l, s, reserved.dyn_gs, "-gm-gds:M");
+        m.add_dynamic_b(l, reserved.dyn_ieq, &format!("-i0:M:{},{},{}", g, d, s));
+        m.nodes[l] = MNANodeInfo::new_current(&format!("i:M:{},{},{}", g, d, s));
+        self.update_dynamic(m);
+    }
+
+    fn update_dynamic(&self, m: &mut MNASystem) {
+        let reserved = self.reserved.as_ref().expect("need reserved");
+        m.set_dynamic(reserved.dyn_gm, self.gm);
+        m.set_dynamic(reserved.dyn_gds, self.gds);
+        m.set_dynamic(reserved.dyn_gs, -self.gm - self.gds);
+        m.set_dynamic(reserved.dyn_ieq, -self.ieq);
+    }
+
+    fn newton(&mut self, m: &mut MNASystem, options: &SimOptions) -> bool {
+        let [g, d, s] = self.pin.map(|n| m.b[n].lu);
+        let (vgs, vds) = (g - s, d - s);
+        let tol = options.v_tolerance;
+        // a new leakage (eg. from gmin stepping) needs relinearizing
+        let leakage = options.g_min != self.g_min;
+        self.g_min = options.g_min;
+        if !leakage && f64::abs(vgs - self.vgs) < tol && f64::abs(vds - self.vds) < tol {
+            return true;
+        }
+        self.linearize(vgs, vds);
+        // Update dynamic variables since we relinearized
+        self.update_dynamic(m);
+        false
+    }
+
+    fn jacobian_error(&self, m: &MNASystem) -> Option<f64> {
+        let ([g, d, s], l) = (self.pin, self.reserved.as_ref().expect("need reserved").l);
+        let (gm, gds, ieq) = self.tangent();
+        let (_, b) = m.assembled_row(l);
+        // expected row entries, and node voltages with the source as
+        // reference, both summed or equal where pins share a net
+        let expected = [(g, gm), (d, gds), (s, -gm - gds)];
+        let volts = [(g, self.vgs), (d, self.vds), (s, 0.0)];
+        let mut nets = self.pin.to_vec();
+        nets.sort();
+        nets.dedup();
+        // conductances relative to the device, at least g_min
+        let scale = f64::max(gm.abs() + gds.abs(), self.g_min);
+        let (mut err_g, mut stamped_i) = (0.0, -b);
+        for c in nets {
+            let a = m.assembled_a(l, c);
+            let e: f64 = expected.iter().filter(|x| x.0 == c).map(|x| x.1).sum();
+            err_g = f64::max(err_g, f64::abs(a - e) / scale);
+            stamped_i += a * volts.iter().find(|x| x.0 == c).map_or(0.0, |x| x.1);
+        }
+        // linear model has to pass through the operating point
+        let i = gm * self.vgs + gds * self.vds + ieq;
+        let err_i = f64::abs(stamped_i - i) / f64::max(i.abs(), self.g_min);
+        Some(f64::max(err_g, err_i))
+    }
+
+    fn model(&self) -> Option<ModelComponent> {
+        Some(ModelComponent::Mosfet {
+            nets: self.pin,
+            params: self.params.clone(),
+        })
+    }
+}
+
+type ComponentList = Vec<Box<dyn Component>>;
+
+/// One component of the JSON circuit model, with the nets it connects to
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+enum ModelComponent {
+    Resistor {
+        r: f64,
+        #[serde(default)]
+        l: f64,
+        nets: [usize; 2],
+    },
+    Potentiometer {
+        total_r: f64,
+        wiper: f64,
+        taper: Taper,
+        nets: [usize; 3],
+    },
+    Switch {
+        closed: bool,
+        r_on: f64,
+        r_off: f64,
+        nets: [usize; 2],
+    },
+    VoltageControlledSwitch {
+        v_threshold: f64,
+        hysteresis: f64,
+        // starts open unless given
+        #[serde(default)]
+        closed: bool,
+        r_on: f64,
+        r_off: f64,
+        // switch from, to, control +, control -
+        nets: [usize; 4],
+    },
+    Capacitor {
+        c: f64,
+        // volts at the start of transient analysis, from DC when left out
+        #[serde(default)]
+        ic: Option<f64>,
+        nets: [usize; 2],
+    },
+    Inductor {
+        l: f64,
+        // amps from the first net to the second at the start of transient analysis
+        #[serde(default)]
+        ic: Option<f64>,
+        nets: [usize; 2],
+    },
+    Cable {
+        // meters
+        length: f64,
+        #[serde(default)]
+        params: CableParameters,
+        nets: [usize; 2],
+    },
+    VoltageSource {
+        v: f64,
+        #[serde(default)]
+        ac: f64,
+        nets: [usize; 2],
+        #[serde(default)]
+        taps: Vec<(usize, f64)>,
+    },
+    CurrentSource {
+        i: f64,
+        nets: [usize; 2],
+    },
+    Ammeter {
+        nets: [usize; 2],
+    },
+    Speaker {
+        nets: [usize; 2],
+        #[serde(default)]
+        params: SpeakerParameters,
+    },
+    // voltage across the nets, "v:probe" unless named
+    VoltageProbe {
+        #[serde(default)]
+        name: Option<String>,
+        nets: [usize; 2],
+    },
+    // ammeter whose current is named
+    CurrentProbe {
+        name: String,
+        nets: [usize; 2],
+    },
+    OpAmp {
+        // non-inverting input, inverting input, output
+        nets: [usize; 3],
+        // finite gain model, ideal when left out
+        #[serde(default)]
+        params: Option<OpAmpParameters>,
+    },
+    #[serde(rename = "VCVS")]
+    Vcvs {
+        gain: f64,
+        // output +, output -, control +, control -
+        nets: [usize; 4],
+    },
+    #[serde(rename = "VCCS")]
+    Vccs {
+        gm: f64,
+        // output +, output -, control +, control -
+        nets: [usize; 4],
+    },
+    #[serde(rename = "CCCS")]
+    Cccs {
+        gain: f64,
+        // output +, output -, sense from, sense to
+        nets: [usize; 4],
+    },
+    #[serde(rename = "CCVS")]
+    Ccvs {
+        r: f64,
+        // output +, output -, sense from, sense to
+        nets: [usize; 4],
+    },
+    // behavioral source with a fixed transfer curve
+    Waveshaper {
+        shape: Shape,
+        gain: f64,
+        // output limit in volts
+        level: f64,
+        // output +, output -, input +, input -
+        nets: [usize; 4],
+    },
+    Transformer {
+        turns_ratio: f64,
+        // primary +, primary -, secondary +, secondary -
+        nets: [usize; 4],
+    },
+    CoupledInductors {
+        l: [f64; 2],
+        m: f64,
+        // first inductor from, to, second inductor from, to
+        nets: [usize; 4],
+    },
+    Diode {
+        nets: [usize; 2],
+        #[serde(default)]
+        params: DiodeParameters,
+    },
+    // diode with the Schottky preset parameters
+    Schottky {
+        nets: [usize; 2],
+    },
+    Led {
+        // anode, cathode
+        nets: [usize; 2],
+        #[serde(default)]
+        params: LedParameters,
+    },
+    Zener {
+        // anode, cathode
+        nets: [usize; 2],
+        #[serde(default)]
+        params: ZenerParameters,
+    },
+    #[serde(rename = "BJT")]
+    Bjt {
+        // base, collector, emitter
+        nets: [usize; 3],
+        #[serde(default)]
+        params: BJTParameters,
+    },
+    Mosfet {
+        // gate, drain, source
+        nets: [usize; 3],
+        #[serde(default)]
+        params: MosfetParameters,
+    },
+}
+
+impl ModelComponent {
+    /// Copy with resistance, capacitance and inductance values passed through `draw`
+    fn with_tolerance(&self, draw: &mut impl FnMut(f64) -> f64) -> Self {
+        let mut res = self.clone();
+        match &mut res {
+            ModelComponent::Resistor { r, .. } => *r = draw(*r),
+            ModelComponent::Capacitor { c, .. } => *c = draw(*c),
+            ModelComponent::Inductor { l, .. } => *l = draw(*l),
+            _ => {}
+        }
+        res
+    }
+
+    /// Circuit nets used, including taps of merged sources
+    fn nets(&self) -> Vec<usize> {
+        match self {
+            ModelComponent::Vcvs { nets, .. }
+            | ModelComponent::Vccs { nets, .. }
+            | ModelComponent::VoltageControlledSwitch { nets, .. }
+            | ModelComponent::Cccs { nets, .. }
+            | ModelComponent::Ccvs { nets, .. }
+            | ModelComponent::Waveshaper { nets, .. }
+            | ModelComponent::Transformer { nets, .. }
+            | ModelComponent::CoupledInductors { nets, .. } => nets.to_vec(),
+            ModelComponent::Resistor { nets, .. }
+            | ModelComponent::Switch { nets, .. }
+            | ModelComponent::Capacitor { nets, .. }
+            | ModelComponent::Inductor { nets, .. }
+            | ModelComponent::Cable { nets, .. }
+            | ModelComponent::CurrentSource { nets, .. }
+            | ModelComponent::Ammeter { nets }
+            | ModelComponent::Speaker { nets, .. }
+            | ModelComponent::VoltageProbe { nets, .. }
+            | ModelComponent::CurrentProbe { nets, .. }
+            | ModelComponent::Diode { nets, .. }
+            | ModelComponent::Schottky { nets }
+            | ModelComponent::Led { nets, .. }
+            | ModelComponent::Zener { nets, .. } => nets.to_vec(),
+            ModelComponent::Potentiometer { nets, .. }
+            | ModelComponent::OpAmp { nets, .. }
+            | ModelComponent::Bjt { nets, .. }
+            | ModelComponent::Mosfet { nets, .. } => nets.to_vec(),
+            ModelComponent::VoltageSource { nets, taps, .. } => nets
+                .iter()
+                .copied()
+                .chain(taps.iter().map(|t| t.0))
+                .collect(),
+        }
+    }
+
+    /// Fresh simulator component for this description
+    fn build(&self) -> Box<dyn Component> {
+        match self {
+            ModelComponent::Resistor { r, l, nets } => {
+                Box::new(Resistor::with_inductance(*r, *l, nets[0], nets[1]))
+            }
+            ModelComponent::Potentiometer {
+                total_r,
+                wiper,
+                taper,
+                nets,
+            } => {
+                let mut pot = Potentiometer::new(*total_r, *taper, nets[0], nets[1], nets[2]);
+                pot.set_wiper(*wiper);
+                Box::new(pot)
+            }
+            ModelComponent::Switch {
+                closed,
+                r_on,
+                r_off,
+                nets,
+            } => Box::new(Switch {
+                closed: *closed,
+                r_on: *r_on,
+                r_off: *r_off,
+                ..Switch::new(*closed, nets[0], nets[1])
+            }),
+            ModelComponent::VoltageControlledSwitch {
+                v_threshold,
+                hysteresis,
+                closed,
+                r_on,
+                r_off,
+                nets,
+            } => {
+                let mut vcs = VoltageControlledSwitch::new(
+                    nets[2],
+                    nets[3],
+                    *v_threshold,
+                    *hysteresis,
+                    nets[0],
+                    nets[1],
+                );
+                vcs.switch = Switch {
+                    closed: *closed,
+                    r_on: *r_on,
+                    r_off: *r_off,
+                    ..vcs.switch
+                };
+                Box::new(vcs)
+            }
+            ModelComponent::Capacitor { c, ic, nets } => match ic {
+                Some(v0) => Box::new(Capacitor::with_ic(*c, *v0, nets[0], nets[1])),
+                None => Box::new(Capacitor::new(*c, nets[0], nets[1])),
+            },
+            ModelComponent::Inductor { l, ic, nets } => match ic {
+                Some(i0) => Box::new(Inductor::with_ic(*l, *i0, nets[0], nets[1])),
+                None => Box::new(Inductor::new(*l, nets[0], nets[1])),
+            },
+            ModelComponent::Cable {
+                length,
+                params,
+                nets,
+            } => Box::new(Cable::new(*length, nets[0], nets[1], params.clone())),
+            ModelComponent::VoltageSource { v, ac, nets, taps } => Box::new(VoltageSource {
+                taps: taps.clone(),
+                ..VoltageSource::with_ac(*v, *ac, nets[0], nets[1])
+            }),
+            ModelComponent::CurrentSource { i, nets } => {
+                Box::new(CurrentSource::new(*i, nets[0], nets[1]))
+            }
+            ModelComponent::Ammeter { nets } => Box::new(Ammeter::new(nets[0], nets[1])),
+            ModelComponent::Speaker { nets, params } => {
+                Box::new(Speaker::new(nets[0], nets[1], params.clone()))
+            }
+            ModelComponent::VoltageProbe { name, nets } => match name {
+                Some(name) => Box::new(VoltageProbe::new_named(nets[0], nets[1], name)),
+                None => Box::new(VoltageProbe::new(nets[0], nets[1])),
+            },
+            ModelComponent::CurrentProbe { name, nets } => {
+                Box::new(CurrentProbe::new(nets[0], nets[1], name))
+            }
+            ModelComponent::OpAmp { nets, params } => match params {
+                Some(params) => Box::new(OpAmp::with_params(
+                    nets[0],
+                    nets[1],
+                    nets[2],
+                    params.clone(),
+                )),
+                None => Box::new(OpAmp::new(nets[0], nets[1], nets[2])),
+            },
+            ModelComponent::Vcvs { gain, nets } => {
+                Box::new(Vcvs::new(*gain, nets[2], nets[3], nets[0], nets[1]))
+            }
+            ModelComponent::Vccs { gm, nets } => {
+                Box::new(Vccs::new(*gm, nets[2], nets[3], nets[0], nets[1]))
+            }
+            ModelComponent::Cccs { gain, nets } => {
+                Box::new(Cccs::new(*gain, nets[2], nets[3], nets[0], nets[1]))
+            }
+            ModelComponent::Ccvs { r, nets } => {
+                Box::new(Ccvs::new(*r, nets[2], nets[3], nets[0], nets[1]))
+            }
+            ModelComponent::Waveshaper {
+                shape,
+                gain,
+                level,
+                nets,
+            } => Box::new(BehavioralSource::waveshaper(
+                *shape,
+                *gain,
+                *level,
+                (nets[2], nets[3]),
+                nets[0],
+                nets[1],
+            )),
+            ModelComponent::Transformer { turns_ratio, nets } => Box::new(Transformer::new(
+                *turns_ratio,
+                (nets[0], nets[1]),
+                (nets[2], nets[3]),
+            )),
+            ModelComponent::CoupledInductors { l, m, nets } => Box::new(CoupledInductors::new(
+                Inductor::new(l[0], nets[0], nets[1]),
+                Inductor::new(l[1], nets[2], nets[3]),
+                *m,
+            )),
+            ModelComponent::Diode { nets, params } => {
+                Box::new(Diode::new(nets[0], nets[1], params.clone()))
+            }
+            ModelComponent::Schottky { nets } => {
+                Box::new(Diode::new(nets[0], nets[1], DiodeParameters::schottky()))
+            }
+            ModelComponent::Led { nets, params } => {
+                Box::new(Led::new(nets[0], nets[1], params.clone()))
+            }
+            ModelComponent::Zener { nets, params } => {
+                Box::new(Zener::new(nets[0], nets[1], params.clone()))
+            }
+            ModelComponent::Bjt { nets, params } => {
+                Box::new(Bjt::new(nets[0], nets[1], nets[2], params.clone()))
+            }
+            ModelComponent::Mosfet { nets, params } => {
+                Box::new(Mosfet::new(nets[0], nets[1], nets[2], params.clone()))
+            }
+        }
+    }
+}
+
+/// Electrical circuit model, independent of any schematic, for example:
+///
+/// ```json
+/// {
+///     "nets": [ "GND", "in", "out" ],
+///     "components": [
+///         { "type": "VoltageSource", "v": 5.0, "nets": [ 1, 0 ] },
+///         { "type": "Resistor", "r": 1000.0, "nets": [ 1, 2 ] }
+///     ]
+/// }
+/// ```
+///
+/// Net 0 is ground. Reserved nets of components are not part of the model.
+/// Missing device parameters take their defaults, so `"params": {
+/// "transistor_type": "PNP" }` is a default PNP transistor.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Model {
+    nets: Vec<String>,
+    components: Vec<ModelComponent>,
+}
+
+impl Model {
+    fn to_json(&self) -> Result<String, SimError> {
+        serde_json::to_string_pretty(self).map_err(|e| SimError::BadModel(e.to_string()))
+    }
+
+    fn from_json(text: &str) -> Result<Self, SimError> {
+        serde_json::from_str(text).map_err(|e| SimError::BadModel(e.to_string()))
+    }
+
+    /// Simulator with all components added, not yet built
+    fn build(&self) -> Result<Simulator, SimError> {
+        let mut sim = Simulator::new(self.nets.len());
+        for (i, c) in self.components.iter().enumerate() {
+            if let Some(net) = c.nets().into_iter().find(|n| *n >= self.nets.len()) {
+                return Err(SimError::BadModel(format!(
+                    "component {} uses net {} of {}",
+                    i,
+                    net,
+                    self.nets.len()
+                )));
+            }
+            sim.add_component(c.build());
+        }
+        sim.merge_series_sources();
+        // reserving resizes the system, so the names go on afterwards
+        for (node, name) in sim.system.nodes.iter_mut().zip(&self.nets) {
+            *node = MNANodeInfo::new_voltage_with_name(name);
+        }
+        Ok(sim)
+    }
+}
+
+/// Time at which waveform first crosses `threshold`, interpolated between samples
+fn crossing_time(times: &[f64], samples: &[f64], threshold: f64) -> Option<f64> {
+    for i in 1..samples.len().min(times.len()) {
+        let (s0, s1) = (samples[i - 1] - threshold, samples[i] - threshold);
+        if s0 == 0.0 {
+            return Some(times[i - 1]);
+        }
+        if s0 * s1 < 0.0 || s1 == 0.0 {
+            let frac = s0 / (s0 - s1);
+            return Some(times[i - 1] + frac * (times[i] - times[i - 1]));
+        }
+    }
+    None
+}
+
+/// Delay from waveform `a` crossing `threshold` to waveform `b` crossing it
+fn propagation_delay(times: &[f64], a: &[f64], b: &[f64], threshold: f64) -> Option<f64> {
+    Some(crossing_time(times, b, threshold)? - crossing_time(times, a, threshold)?)
+}
+
+/// Value of every recorded column at time `t`, labeled and linearly interpolated
+///
+/// Returns None if `t` is outside the recorded times.
+fn marker(
+    times: &[f64],
+    rows: &[Vec<f64>],
+    names: &[String],
+    t: f64,
+) -> Option<Vec<(String, f64)>> {
+    let n = times.len().min(rows.len());
+    if n == 0 || t < times[0] || t > times[n - 1] {
+        return None;
+    }
+    let i = times[..n].partition_point(|time| *time < t);
+    let (lo, hi) = (i.saturating_sub(1), i);
+    let frac = if hi == lo || times[hi] == times[lo] {
+        1.0
+    } else {
+        (t - times[lo]) / (times[hi] - times[lo])
+    };
+    Some(
+        names
+            .iter()
+            .zip(rows[lo].iter().zip(&rows[hi]))
+            .map(|(name, (a, b))| (name.clone(), a + frac * (b - a)))
+            .collect(),
+    )
+}
+
+/// Source value and recorded values at each point of a DC sweep
+type DcCurve = Vec<(f64, Vec<f64>)>;
+
+/// Values from `start` to `stop` inclusive in steps of `step`
+fn sweep_values(start: f64, stop: f64, step: f64) -> Result<Vec<f64>, SimError> {
+    if step == 0.0 || (stop - start) * step < 0.0 {
+        return Err(SimError::BadConfig(format!(
+            "step {} does not go from {} to {}",
+            step, start, stop
+        )));
+    }
+    let points = ((stop - start) / step).round() as usize;
+    Ok((0..=points).map(|k| start + k as f64 * step).collect())
+}
+
+/// Frequencies from `f_start` to `f_stop` with `points_per_decade` per decade
+///
+/// The range has to be finite with 0 < `f_start` <= `f_stop`.
+fn log_frequencies(
+    f_start: f64,
+    f_stop: f64,
+    points_per_decade: usize,
+) -> Result<Vec<f64>, SimError> {
+    if !(f_start > 0.0 && f_stop >= f_start && f_stop.is_finite()) || points_per_decade == 0 {
+        return Err(SimError::BadConfig(format!(
+            "cannot sweep {} to {} Hz at {} points per decade",
+            f_start, f_stop, points_per_decade
+        )));
+    }
+    let ppd = points_per_decade as f64;
+    // slack so a whole number of decades keeps its last point
+    let points = ((f_stop / f_start).log10() * ppd + 1e-9).floor() as usize;
+    Ok((0..=points)
+        .map(|k| f_start * f64::powf(10.0, k as f64 / ppd))
+        .collect())
+}
+
+/// Check recent Newton states for a repeating pattern
+///
+/// Each state is the solution plus the dynamic variables, since the
+/// linearization of a component can keep moving while the solution
+/// barely changes. Returns the period if the last full period of states
+/// repeats the one before it, meaning the Newton loop is stuck in a cycle.
+fn find_cycle(history: &VecDeque<(Vec<f64>, Vec<f64>)>, tolerance: f64) -> Option<usize> {
+    let n = history.len();
+    let same = |a: &(Vec<f64>, Vec<f64>), b: &(Vec<f64>, Vec<f64>)| {
+        let solution =
+            a.0.iter()
+                .zip(b.0.iter())
+                .all(|(x, y)| f64::abs(x - y) < tolerance);
+        let vars =
+            a.1.iter()
+                .zip(b.1.iter())
+                .all(|(x, y)| f64::abs(x - y) <= tolerance * f64::max(f64::abs(*x), f64::abs(*y)));
+        solution && vars
+    };
+    (1..=DC_CYCLE_MAX_PERIOD)
+        .filter(|period| 2 * period <= n)
+        .find(|period| {
+            (0..*period).all(|j| same(&history[n - 1 - j], &history[n - 1 - j - period]))
+        })
+}
+
+/// Analysis to run, as given in a JSON run config
+#[derive(Debug, PartialEq)]
+enum Analysis {
+    Dc,
+    Transient {
+        t_end: f64,
+        dt: f64,
+    },
+    Ac {
+        f_start: f64,
+        f_stop: f64,
+        points_per_decade: usize,
+        // node that probes are relative to, for a transfer function
+        drive: Option<usize>,
+    },
+    /// Matrix Market export of A (to the output) and b
+    Mtx {
+        b: String,
+    },
+    /// JSON model of the circuit with its stimulus
+    Model,
+    /// Formula for each probe, for small resistor and source circuits
+    Symbolic,
+    /// DC contribution of each independent source to each probe
+    Superposition,
+    /// DC value of one probe with R, L and C values drawn within tolerance
+    MonteCarlo {
+        runs: usize,
+        seed: u64,
+        tolerance: f64,
+        // normal with the tolerance at three sigma, instead of uniform
+        normal: bool,
+    },
+}
+
+/// Probe of a JSON run config, a node number or a probe or net name
+#[derive(Debug, PartialEq)]
+enum ProbeRef {
+    Node(usize),
+    Name(String),
+}
+
+/// Scalar measurement of a probed waveform in a transient run config
+#[derive(Debug, PartialEq)]
+enum Measure {
+    Rms(ProbeRef),
+    PeakToPeak(ProbeRef),
+    Thd {
+        probe: ProbeRef,
+        fundamental: f64,
+    },
+    // from `from` crossing the threshold to `to` crossing it
+    Delay {
+        from: ProbeRef,
+        to: ProbeRef,
+        threshold: f64,
+    },
+    // every probe at this time
+    Marker(f64),
+}
+
+/// Time-varying stimulus, in place of the DC and AC values
+#[derive(Debug, PartialEq)]
+enum Wave {
+    Sine {
+        amp: f64,
+        freq: f64,
+        offset: f64,
+    },
+    Pulse {
+        low: f64,
+        high: f64,
+        period: f64,
+        duty: f64,
+    },
+    // 16 bit PCM WAV file, `amplitude` volts at full scale
+    Wav {
+        path: String,
+        amplitude: f64,
+    },
+}
+
+/// Source from a JSON run config, drives `node` against ground
+#[derive(Debug, PartialEq)]
+struct Stimulus {
+    node: usize,
+    dc: f64,
+    ac: f64,
+    wave: Option<Wave>,
+}
+
+/// Analysis directives loaded from JSON, for example:
+///
+/// ```json
+/// {
+///     "analysis": { "type": "transient", "t_end": 1e-3, "dt": 1e-5 },
+///     "stimulus": [ { "node": 1, "dc": 5.0 } ],
+///     "probes": [ 2 ],
+///     "output": "out/rc.csv"
+/// }
+/// ```
+///
+/// Numbers can also be given with units, eg. `"t_end": "1ms"`. Other
+/// analysis types are `"dc"`, `"ac"` (with `f_start`, `f_stop`
+/// and `points_per_decade`, and a `drive` node to write gain in dB
+/// relative to it instead of magnitude), `"symbolic"` (a formula per
+/// probe, for circuits of only resistors and sources), `"superposition"`
+/// (a row per source by component index, then their sum and the total),
+/// `"monte_carlo"` (the DC value of one probe over `runs` circuits with
+/// resistors, capacitors and inductors drawn within `tolerance` from a
+/// `seed`, the `distribution` is `uniform` or `normal`), `"model"` (the
+/// JSON model with the stimulus sources added) and `"mtx"` (with a `b`
+/// file name). Probes are node numbers, or names of probes or nets, and
+/// can be left out for `model` and `mtx`. Stimulus can also have an `ac`
+/// amplitude, or be a `sine` (with `amp`, `freq` and `offset`), `pulse`
+/// (with `low`, `high`, `period` and `duty`) or `wav` file (with an
+/// `amplitude` for full scale) instead. Transient output has a column per probe named after its node, eg. `out [V]`,
+/// or is streamed a step per line when the output ends in `.jsonl`.
+/// Transients can also print a `measure` list of probe measurements, eg.
+/// `{ "type": "thd", "probe": "out", "fundamental": 1000 }`, with types
+/// `rms`, `peak_to_peak`, `thd` and `delay` (from a `from` probe crossing
+/// the `threshold` to a `to` probe crossing it, `none` if either doesn't)
+/// and `marker` (every probe at a `time`).
+/// Runs with `sim model.json config.json`, or `sim a.json b.json config.json`
+/// to overlay two variants of a circuit.
+#[derive(Debug, PartialEq)]
+struct RunConfig {
+    analysis: Analysis,
+    stimulus: Vec<Stimulus>,
+    probes: Vec<ProbeRef>,
+    // printed after a transient run
+    measure: Vec<Measure>,
+    output: String,
+}
+
+/// Number, or a string with a unit suffix like "10k" or "1ms"
+fn config_f64(v: &serde_json::Value, key: &str) -> Result<f64, SimError> {
+    v[key]
+        .as_f64()
+        .or_else(|| v[key].as_str().and_then(parse_unit_value))
+        .ok_or_else(|| SimError::BadConfig(format!("expected number for \"{}\"", key)))
+}
+
+/// Like `config_f64`, with a default when the key is left out
+fn config_f64_or(v: &serde_json::Value, key: &str, default: f64) -> Result<f64, SimError> {
+    match v[key].is_null() {
+        true => Ok(default),
+        false => config_f64(v, key),
+    }
+}
+
+fn config_probe(p: &serde_json::Value) -> Result<ProbeRef, SimError> {
+    match (p.as_u64(), p.as_str()) {
+        (Some(n), _) => Ok(ProbeRef::Node(n as usize)),
+        (_, Some(name)) => Ok(ProbeRef::Name(name.to_string())),
+        _ => Err(SimError::BadConfig(
+            "probes must be node numbers or names".into(),
+        )),
+    }
+}
+
+fn config_usize(v: &serde_json::Value, key: &str) -> Result<usize, SimError> {
+    v[key]
+        .as_u64()
+        .map(|n| n as usize)
+        .ok_or_else(|| SimError::BadConfig(format!("expected integer for \"{}\"", key)))
+}
+
+impl RunConfig {
+    fn from_json(text: &str) -> Result<Self, SimError> {
+        let v: serde_json::Value =
+            serde_json::from_str(text).map_err(|e| SimError::BadConfig(e.to_string()))?;
+        let a = &v["analysis"];
+        let analysis = match a["type"].as_str() {
+            Some("dc") => Analysis::Dc,
+            Some("transient") => Analysis::Transient {
+                t_end: config_f64(a, "t_end")?,
+                dt: config_f64(a, "dt")?,
+            },
+            Some("ac") => Analysis::Ac {
+                f_start: config_f64(a, "f_start")?,
+                f_stop: config_f64(a, "f_stop")?,
+                points_per_decade: config_usize(a, "points_per_decade")?,
+                drive: match a["drive"].is_null() {
+                    true => None,
+                    false => Some(config_usize(a, "drive")?),
+                },
+            },
+            Some("symbolic") => Analysis::Symbolic,
+            Some("superposition") => Analysis::Superposition,
+            Some("monte_carlo") => Analysis::MonteCarlo {
+                runs: config_usize(a, "runs")?,
+                seed: config_usize(a, "seed")? as u64,
+                tolerance: config_f64(a, "tolerance")?,
+                normal: match a["distribution"].as_str() {
+                    None | Some("uniform") => false,
+                    Some("normal") => true,
+                    Some(d) => {
+                        return Err(SimError::BadConfig(format!("unknown distribution {}", d)));
+                    }
+                },
+            },
+            Some("model") => Analysis::Model,
+            Some("mtx") => Analysis::Mtx {
+                b: a["b"]
+                    .as_str()
+                    .ok_or_else(|| SimError::BadConfig("expected \"b\" file name".into()))?
+                    .to_string(),
+            },
+            t => {
+                return Err(SimError::BadConfig(format!(
+                    "unknown analysis type {:?}",
+                    t
+                )));
+            }
+        };
+        let mut stimulus = vec![];
+        for s in v["stimulus"].as_array().unwrap_or(&vec![]) {
+            let (sine, pulse) = (&s["sine"], &s["pulse"]);
+            let wave = match (sine.is_object(), pulse.is_object(), s["wav"].as_str()) {
+                (true, _, _) => Some(Wave::Sine {
+                    amp: config_f64(sine, "amp")?,
+                    freq: config_f64(sine, "freq")?,
+                    offset: config_f64_or(sine, "offset", 0.0)?,
+                }),
+                (_, true, _) => Some(Wave::Pulse {
+                    low: config_f64_or(pulse, "low", 0.0)?,
+                    high: config_f64(pulse, "high")?,
+                    period: config_f64(pulse, "period")?,
+                    duty: config_f64_or(pulse, "duty", 0.5)?,
+                }),
+                (_, _, Some(path)) => Some(Wave::Wav {
+                    path: path.to_string(),
+                    amplitude: config_f64_or(s, "amplitude", 1.0)?,
+                }),
+                _ => None,
+            };
+            stimulus.push(Stimulus {
+                node: config_usize(s, "node")?,
+                dc: config_f64_or(s, "dc", 0.0)?,
+                ac: config_f64_or(s, "ac", 0.0)?,
+                wave,
+            });
+        }
+        let no_probes = vec![];
+        let probes = v["probes"]
+            .as_array()
+            .or(matches!(analysis, Analysis::Mtx { .. } | Analysis::Model).then_some(&no_probes))
+            .ok_or_else(|| SimError::BadConfig("expected list of \"probes\"".into()))?
+            .iter()
+            .map(config_probe)
+            .collect::<Result<_, _>>()?;
+        let mut measure = vec![];
+        for m in v["measure"].as_array().unwrap_or(&vec![]) {
+            measure.push(match m["type"].as_str() {
+                Some("rms") => Measure::Rms(config_probe(&m["probe"])?),
+                Some("peak_to_peak") => Measure::PeakToPeak(config_probe(&m["probe"])?),
+                Some("thd") => Measure::Thd {
+                    probe: config_probe(&m["probe"])?,
+                    fundamental: config_f64(m, "fundamental")?,
+                },
+                Some("delay") => Measure::Delay {
+                    from: config_probe(&m["from"])?,
+                    to: config_probe(&m["to"])?,
+                    threshold: config_f64(m, "threshold")?,
+                },
+                Some("marker") => Measure::Marker(config_f64(m, "time")?),
+                t => {
+                    return Err(SimError::BadConfig(format!(
+                        "unknown measurement type {:?}",
+                        t
+                    )));
+                }
+            });
+        }
+        let output = v["output"]
+            .as_str()
+            .ok_or_else(|| SimError::BadConfig("expected \"output\" file name".into()))?
+            .to_string();
+        Ok(Self {
+            analysis,
+            stimulus,
+            probes,
+            measure,
+            output,
+        })
+    }
+
+    fn load(path: &str) -> Result<Self, SimError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| SimError::BadConfig(format!("{}: {}", path, e)))?;
+        Self::from_json(&text)
+    }
+}
+
+/// Polynomial in symbols, maps exponent of each symbol to coefficient
+type Poly = std::collections::BTreeMap<Vec<u32>, i64>;
+
+/// Largest system (without ground) that symbolic solving will expand
+const SYMBOLIC_MAX_SIZE: usize = 8;
+
+fn poly_add(a: &mut Poly, b: &Poly, sign: i64) {
+    for (e, c) in b {
+        let v = a.entry(e.clone()).or_insert(0);
+        *v += sign * c;
+        if *v == 0 {
+            a.remove(e);
+        }
+    }
+}
+
+fn poly_mul(a: &Poly, b: &Poly) -> Poly {
+    let mut res = Poly::new();
+    for (ea, ca) in a {
+        for (eb, cb) in b {
+            let e: Vec<u32> = ea.iter().zip(eb).map(|(x, y)| x + y).collect();
+            poly_add(&mut res, &Poly::from([(e, ca * cb)]), 1);
+        }
+    }
+    res
+}
+
+/// Determinant by expansion along the first of the remaining rows
+fn poly_det(a: &[Vec<Poly>], rows: &[usize], cols: &[usize]) -> Poly {
+    if rows.is_empty() {
+        let nsym = a
+            .first()
+            .and_then(|r| r.first())
+            .map_or(0, |p| p.keys().next().map_or(0, |e| e.len()));
+        return Poly::from([(vec![0; nsym], 1)]);
+    }
+    let mut res = Poly::new();
+    for (i, c) in cols.iter().enumerate() {
+        let entry = &a[rows[0]][*c];
+        if entry.is_empty() {
+            continue;
+        }
+        let sub_cols: Vec<usize> = cols.iter().filter(|x| *x != c).copied().collect();
+        let minor = poly_det(a, &rows[1..], &sub_cols);
+        let sign = if i % 2 == 0 { 1 } else { -1 };
+        poly_add(&mut res, &poly_mul(entry, &minor), sign);
+    }
+    res
+}
+
+/// Format polynomial like "R1k+2*R2k^2"
+fn poly_to_string(p: &Poly, symbols: &[String]) -> String {
+    let mut res = String::new();
+    for (e, c) in p.iter().rev() {
+        let factors: Vec<String> = e
+            .iter()
+            .zip(symbols)
+            .filter(|(n, _)| **n > 0)
+            .map(|(n, s)| {
+                if *n == 1 {
+                    s.clone()
+                } else {
+                    format!("{}^{}", s, n)
+                }
+            })
+            .collect();
+        let sign = if *c < 0 {
+            "-"
+        } else if res.is_empty() {
+            ""
+        } else {
+            "+"
+        };
+        let c = c.abs();
+        let term = match (c, factors.is_empty()) {
+            (_, true) => c.to_string(),
+            (1, false) => factors.join("*"),
+            (_, false) => format!("{}*{}", c, factors.join("*")),
+        };
+        res += &format!("{}{}", sign, term);
+    }
+    if res.is_empty() {
+        String::from("0")
+    } else {
+        res
+    }
+}
+
+/// Turn num/den in conductances into a tidy ratio in resistances
+fn conductance_ratio(num: &Poly, den: &Poly, symbols: &[String]) -> String {
+    let nsym = symbols.len();
+    // multiply through by R^k where k is highest power of G
+    let mut max_e = vec![0; nsym];
+    for e in num.keys().chain(den.keys()) {
+        for (m, x) in max_e.iter_mut().zip(e) {
+            *m = u32::max(*m, *x);
+        }
+    }
+    let flip = |p: &Poly| -> Poly {
+        p.iter()
+            .map(|(e, c)| (e.iter().zip(&max_e).map(|(x, m)| m - x).collect(), *c))
+            .collect()
+    };
+    let (mut num, mut den) = (flip(num), flip(den));
+    // divide out common factors of symbols and coefficients
+    let mut min_e = vec![u32::MAX; nsym];
+    let mut content = 0;
+    for (e, c) in num.iter().chain(den.iter()) {
+        for (m, x) in min_e.iter_mut().zip(e) {
+            *m = u32::min(*m, *x);
+        }
+        let (mut a, mut b) = (content, c.abs());
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        content = a;
+    }
+    if den.values().all(|c| *c < 0) {
+        content = -content;
+    }
+    let reduce = |p: &Poly| -> Poly {
+        p.iter()
+            .map(|(e, c)| {
+                (
+                    e.iter().zip(&min_e).map(|(x, m)| x - m).collect(),
+                    c / content,
+                )
+            })
+            .collect()
+    };
+    (num, den) = (reduce(&num), reduce(&den));
+    let paren = |p: &Poly| {
+        let s = poly_to_string(p, symbols);
+        if p.len() > 1 { format!("({})", s) } else { s }
+    };
+    if num == den {
+        return String::from("1");
+    }
+    if den == Poly::from([(vec![0; nsym], 1)]) {
+        return poly_to_string(&num, symbols);
+    }
+    format!("{}/{}", paren(&num), paren(&den))
+}
+
+impl MNASystem {
+    /// Experimental symbolic DC solution for a node of a small linear circuit
+    ///
+    /// Reads the stamp annotations, so it only knows resistors (as
+    /// their value, eg. "R1k") and sources (eg. "5V"). Returns None
+    /// for anything else, or if the circuit is too big to expand.
+    fn symbolic_node(&self, node: usize) -> Option<String> {
+        let n = self.net_size;
+        if n < 2 || n - 1 > SYMBOLIC_MAX_SIZE {
+            return None;
+        }
+        // parse terms like "+R1k-1" into (sign, symbol) pairs
+        let parse = |txt: &str| -> Option<Vec<(i64, Option<String>)>> {
+            let mut terms = vec![];
+            let mut rest = txt;
+            while !rest.is_empty() {
+                let sign = match rest.as_bytes()[0] {
+                    b'+' => 1,
+                    b'-' => -1,
+                    _ => return None,
+                };
+                let end = rest[1..].find(['+', '-']).map_or(rest.len(), |i| i + 1);
+                let token = &rest[1..end];
+                match token {
+                    "1" => terms.push((sign, None)),
+                    t if t.starts_with('R') => terms.push((sign, Some(t.to_string()))),
+                    _ => return None,
+                }
+                rest = &rest[end..];
+            }
+            Some(terms)
+        };
+        let mut symbols: Vec<String> = vec![];
+        let mut parsed = vec![];
+        for r in 1..n {
+            let mut row = vec![];
+            for c in 1..n {
+                let terms = parse(&self.a_matrix[r][c].txt)?;
+                for (_, sym) in terms.iter().filter(|(_, sym)| sym.is_some()) {
+                    let sym = sym.clone().unwrap();
+                    if !symbols.contains(&sym) {
+                        symbols.push(sym);
+                    }
+                }
+                row.push(terms);
+            }
+            parsed.push(row);
+        }
+        symbols.sort();
+        let nsym = symbols.len();
+        let a: Vec<Vec<Poly>> = parsed
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|terms| {
+                        let mut p = Poly::new();
+                        for (sign, sym) in terms {
+                            let mut e = vec![0; nsym];
+                            if let Some(sym) = sym {
+                                e[symbols.iter().position(|s| s == sym).unwrap()] = 1;
+                            }
+                            poly_add(&mut p, &Poly::from([(e, 1)]), *sign);
+                        }
+                        p
+                    })
+                    .collect()
+            })
+            .collect();
+        let all: Vec<usize> = (0..n - 1).collect();
+        let det = poly_det(&a, &all, &all);
+        if det.is_empty() {
+            return None;
+        }
+        // Cramer's rule, expanding the replaced column along the sources
+        // (rows of A and b are pivoted together so the order is irrelevant)
+        let k = node - 1;
+        let mut terms = vec![];
+        for j in 0..n - 1 {
+            let b = &self.b[j + 1];
+            if b.txt.is_empty() {
+                continue;
+            }
+            if !b.g_dyn.is_empty() {
+                return None;
+            }
+            let rows: Vec<usize> = all.iter().filter(|r| **r != j).copied().collect();
+            let cols: Vec<usize> = all.iter().filter(|c| **c != k).copied().collect();
+            let mut minor = poly_det(&a, &rows, &cols);
+            if (j + k) % 2 == 1 {
+                minor = minor.into_iter().map(|(e, c)| (e, -c)).collect();
+            }
+            if !minor.is_empty() {
+                terms.push(format!(
+                    "{} * {}",
+                    b.txt,
+                    conductance_ratio(&minor, &det, &symbols)
+                ));
+            }
+        }
+        let rhs = if terms.is_empty() {
+            String::from("0")
+        } else {
+            terms.join(" + ")
+        };
+        Some(format!("{} = {}", self.nodes[node].name, rhs))
+    }
+}
+
+/// Seeded random numbers for randomized analyses (splitmix64)
+///
+/// Analyses take the generator as an argument instead of reaching for
+/// global randomness, so a run is reproduced exactly from its seed.
+#[derive(Clone, Debug)]
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1)
+    fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Standard normal (Box-Muller)
+    fn normal(&mut self) -> f64 {
+        let u = 1.0 - self.uniform();
+        let v = self.uniform();
+        (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+    }
+
+    /// Component value uniformly within relative tolerance, eg. 0.05 for 5%
+    fn tolerance(&mut self, nominal: f64, tol: f64) -> f64 {
+        nominal * (1.0 + tol * (2.0 * self.uniform() - 1.0))
+    }
+
+    /// Component value normally distributed, with the tolerance at three sigma
+    fn tolerance_normal(&mut self, nominal: f64, tol: f64) -> f64 {
+        nominal * (1.0 + tol / 3.0 * self.normal())
+    }
+}
+
+/// DC value of `node` over `runs` circuits drawn by `build` from `rng`
+fn monte_carlo(
+    runs: usize,
+    node: usize,
+    rng: &mut Rng,
+    mut build: impl FnMut(&mut Rng) -> Result<Simulator, SimError>,
+) -> Result<Vec<f64>, SimError> {
+    let mut res = Vec::with_capacity(runs);
+    for _ in 0..runs {
+        let mut sim = build(rng)?;
+        sim.build_system();
+        sim.solve_dc()?;
+        res.push(sim.system.b[node].lu);
+    }
+    Ok(res)
+}
+
+/// Same analysis run on two circuit variants, one row per step or frequency
+#[derive(Debug)]
+struct Comparison {
+    a: Vec<Vec<f64>>,
+    b: Vec<Vec<f64>>,
+}
+
+impl Comparison {
+    /// Transient of both variants, recording their tagged nodes
+    fn transient(
+        a: &mut Simulator,
+        b: &mut Simulator,
+        t_end: f64,
+        dt: f64,
+    ) -> Result<Self, SimError> {
+        Ok(Self {
+            a: a.run_transient(t_end, dt)?,
+            b: b.run_transient(t_end, dt)?,
+        })
+    }
+
+    /// AC magnitude response of both variants at all nodes
+    fn ac(
+        a: &mut Simulator,
+        b: &mut Simulator,
+        f_start: f64,
+        f_stop: f64,
+        points_per_decade: usize,
+    ) -> Result<Self, SimError> {
+        let magnitudes = |points: Vec<AcPoint>| -> Vec<Vec<f64>> {
+            points
+                .into_iter()
+                .map(|(_, x)| x.iter().map(|v| v.norm()).collect())
+                .collect()
+        };
+        Ok(Self {
+            a: magnitudes(a.run_ac(f_start, f_stop, points_per_decade)?),
+            b: magnitudes(b.run_ac(f_start, f_stop, points_per_decade)?),
+        })
+    }
+
+    /// Difference trace a - b
+    fn difference(&self) -> Vec<Vec<f64>> {
+        self.a
+            .iter()
+            .zip(&self.b)
+            .map(|(a, b)| a.iter().zip(b).map(|(a, b)| a - b).collect())
+            .collect()
+    }
+
+    /// Only the columns of these nodes, in this order
+    fn probes(self, nodes: &[usize]) -> Self {
+        let pick = |rows: Vec<Vec<f64>>| -> Vec<Vec<f64>> {
+            rows.into_iter()
+                .map(|row| nodes.iter().filter_map(|n| row.get(*n).copied()).collect())
+                .collect()
+        };
+        Self {
+            a: pick(self.a),
+            b: pick(self.b),
+        }
+    }
+
+    /// Overlay as CSV with a, b and difference columns for each name
+    ///
+    /// There is a line for each `x` that either variant has a row for,
+    /// values a row doesn't have are left empty.
+    fn to_csv(&self, x_name: &str, x: &[f64], names: &[String]) -> String {
+        let mut header = vec![csv_field(x_name)];
+        for prefix in ["a", "b", "diff"] {
+            header.extend(
+                names
+                    .iter()
+                    .map(|n| csv_field(&format!("{}:{}", prefix, n))),
+            );
+        }
+        let mut lines = vec![header.join(",")];
+        let difference = self.difference();
+        let rows = self.a.len().max(self.b.len());
+        for (i, x) in x.iter().enumerate().take(rows) {
+            let mut values = vec![x.to_string()];
+            for rows in [&self.a, &self.b, &difference] {
+                let row = rows.get(i).map_or(&[][..], |r| r.as_slice());
+                values.extend(
+                    (0..names.len()).map(|j| row.get(j).map_or(String::new(), |v| v.to_string())),
+                );
+            }
+            lines.push(values.join(","));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    /// Run the analysis of a config on both variants, overlay goes to its output
+    fn run_config(
+        a: &mut Simulator,
+        b: &mut Simulator,
+        config: &RunConfig,
+    ) -> Result<(), SimError> {
+        for sim in [&mut *a, &mut *b] {
+            sim.add_stimulus(&config.stimulus)?;
+            sim.build_system();
+        }
+        let probes = &a.probe_nodes(&config.probes)?;
+        let text = match config.analysis {
+            Analysis::Dc
+            | Analysis::Mtx { .. }
+            | Analysis::Model
+            | Analysis::Symbolic
+            | Analysis::Superposition
+            | Analysis::MonteCarlo { .. } => {
+                return Err(SimError::BadConfig(String::from(
+                    "comparing needs a transient or ac analysis",
+                )));
+            }
+            Analysis::Transient { t_end, dt } => {
+                for sim in [&mut *a, &mut *b] {
+                    sim.tagged.clear();
+                    for p in probes {
+                        sim.tag_node(*p);
+                    }
+                }
+                let names: Vec<String> = a.tagged.iter().map(|p| format!("v({})", p)).collect();
+                let comparison = Self::transient(a, b, t_end, dt)?;
+                let times: Vec<f64> = (1..=comparison.a.len()).map(|i| i as f64 * dt).collect();
+                comparison.to_csv("time", &times, &names)
+            }
+            Analysis::Ac { drive: Some(_), .. } => {
+                return Err(SimError::BadConfig(String::from(
+                    "comparing does not take an ac drive",
+                )));
+            }
+            Analysis::Ac {
+                f_start,
+                f_stop,
+                points_per_decade,
+                drive: None,
+            } => {
+                let names: Vec<String> = probes.iter().map(|p| format!("v({})", p)).collect();
+                let comparison = Self::ac(a, b, f_start, f_stop, points_per_decade)?.probes(probes);
+                let freqs = log_frequencies(f_start, f_stop, points_per_decade)?;
+                comparison.to_csv("freq", &freqs, &names)
+            }
+        };
+        std::fs::write(&config.output, text)
+            .map_err(|e| SimError::Output(format!("{}: {}", config.output, e)))
+    }
+}
+
+/// Streams recorded steps as JSON Lines, eg. {"time":0.001,"v2":1.5}
+struct JsonLinesWriter<W: Write> {
+    out: W,
+    names: Vec<String>,
+}
+
+impl<W: Write> JsonLinesWriter<W> {
+    fn new(out: W, names: Vec<String>) -> Self {
+        Self { out, names }
+    }
+
+    /// Write and flush one step, so consumers see it right away
+    fn write_step(&mut self, time: f64, values: &[f64]) -> Result<(), SimError> {
+        let mut line = serde_json::Map::new();
+        line.insert(String::from("time"), time.into());
+        for (name, v) in self.names.iter().zip(values) {
+            line.insert(name.clone(), (*v).into());
+        }
+        writeln!(self.out, "{}", serde_json::Value::Object(line))
+            .and_then(|_| self.out.flush())
+            .map_err(|e| SimError::Output(e.to_string()))
+    }
+}
+
+/// Buffered output file, errors name the file
+fn create_output(path: &str) -> Result<io::BufWriter<std::fs::File>, SimError> {
+    std::fs::File::create(path)
+        .map(io::BufWriter::new)
+        .map_err(|e| SimError::Output(format!("{}: {}", path, e)))
+}
+
+/// Quote a CSV field if it has separators or quotes in it
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Write transient results as CSV, time first and then one column per node
+///
+/// Header cells carry the unit, eg. `v2 [V]`, and values are multiplied
+/// by the node scale so they read in that unit.
+fn write_csv<W: Write>(
+    nodes: &[MNANodeInfo],
+    rows: &[Vec<f64>],
+    times: &[f64],
+    w: &mut W,
+) -> io::Result<()> {
+    let mut header = vec![String::from("time [s]")];
+    for node in nodes {
+        let name = match node.info_type {
+            InfoType::Voltage => format!("{} [V]", node.name),
+            InfoType::Current => format!("{} [A]", node.name),
+        };
+        header.push(csv_field(&name));
+    }
+    writeln!(w, "{}", header.join(","))?;
+    for (t, row) in times.iter().zip(rows) {
+        let mut line = vec![t.to_string()];
+        line.extend(
+            nodes
+                .iter()
+                .zip(row)
+                .map(|(node, v)| (v * node.scale).to_string()),
+        );
+        writeln!(w, "{}", line.join(","))?;
+    }
+    Ok(())
+}
+
+/// Frequency and complex solution vector of one AC sweep point
+type AcPoint = (f64, Vec<Complex<f64>>);
+
+/// Magnitude and phase in radians of every node at each AC sweep point
+fn ac_polar(points: &[AcPoint]) -> Vec<(f64, Vec<(f64, f64)>)> {
+    points
+        .iter()
+        .map(|(f, x)| (*f, x.iter().map(|v| v.to_polar()).collect()))
+        .collect()
+}
+
+/// Transfer function from node `drive` to node `probe_node` of an AC sweep
+///
+/// Returns (frequency, gain in dB, phase in degrees) for each point, with
+/// the phase in -180..180.
+fn bode(points: &[AcPoint], probe_node: usize, drive: usize) -> Vec<(f64, f64, f64)> {
+    points
+        .iter()
+        .map(|(f, x)| {
+            let (mag, phase) = (x[probe_node] / x[drive]).to_polar();
+            (*f, 20.0 * mag.log10(), phase.to_degrees())
+        })
+        .collect()
+}
+
+/// Result of a superposition study for one node
+#[derive(Debug)]
+struct Superposition {
+    // component index and node voltage with only that source active
+    contributions: Vec<(usize, f64)>,
+    // node voltage with all sources active
+    total: f64,
+}
+
+impl Superposition {
+    /// Sum of all individual contributions
+    fn sum(&self) -> f64 {
+        self.contributions.iter().map(|(_, v)| v).sum()
+    }
+}
+
+/// Ramp for gmin stepping, the DC fallback when plain Newton fails
+///
+/// Every voltage node gets a conductance to ground that starts at `g_start`
+/// and is divided by `factor` each solve, with each operating point used as
+/// the starting guess for the next, until it is below the `g_min` option.
+#[derive(Clone, Debug, PartialEq)]
+struct GminStepping {
+    g_start: f64,
+    factor: f64,
+    // most ramp steps tried before the final solve without extra conductance
+    max_steps: usize,
+}
+
+impl Default for GminStepping {
+    fn default() -> Self {
+        Self {
+            g_start: 1e-2,
+            factor: 10.0,
+            max_steps: 20,
+        }
+    }
+}
+
+/// Ramp for source stepping, the DC fallback after gmin stepping
+///
+/// Independent sources are scaled from 0 up to full value in `steps` equal
+/// steps, with each operating point used as the starting guess for the next.
+#[derive(Clone, Debug, PartialEq)]
+struct SourceStepping {
+    steps: usize,
+}
+
+impl Default for SourceStepping {
+    fn default() -> Self {
+        Self { steps: 20 }
+    }
+}
+
+struct Simulator {
+    components: ComponentList,
+    // number of circuit nodes before any reserved nets
+    size: usize,
+    time_step: f64,
+    system: MNASystem,
+    // nodes of interest to record, all nodes when empty
+    tagged: Vec<usize>,
+    // pinned nodes with the matrix and rhs values they replaced
+    pins: Vec<(usize, f64, f64)>,
+    // fallback for DC operating points that Newton cannot find alone
+    gmin_stepping: GminStepping,
+    source_stepping: SourceStepping,
+    options: SimOptions,
+    // failed Newton solves, for finding the offending component
+    report: ConvergenceReport,
+}
+
+#[allow(unused)]
+impl Simulator {
+    fn new(nodes: usize) -> Self {
+        let mut system = MNASystem::default();
+        system.set_size(nodes);
+        Self {
+            components: vec![],
+            size: nodes,
+            time_step: 0.0,
+            system,
+            tagged: vec![],
+            pins: vec![],
+            gmin_stepping: GminStepping::default(),
+            source_stepping: SourceStepping::default(),
+            options: SimOptions::default(),
+            report: ConvergenceReport::default(),
+        }
+    }
+
+    /// Simulator for a system and components from `netlist::parse_spice`
+    fn from_netlist(system: MNASystem, components: Vec<Box<dyn Component>>) -> Self {
+        let mut sim = Self::new(system.net_size);
+        for c in components {
+            sim.add_component(c);
+        }
+        // reserving resizes the system, which forgets the node names
+        for (node, info) in sim.system.nodes.iter_mut().zip(system.nodes) {
+            *node = info;
+        }
+        sim
+    }
+
+    /// Add component, reserving its internal nets and dynamic variables
+    fn add_component(&mut self, mut component: Box<dyn Component>) {
+        component.reserve(&mut self.system);
+        self.components.push(component);
+    }
+
+    /// Mark node as an output of interest, recording is limited to tagged nodes
+    fn tag_node(&mut self, node: usize) {
+        if !self.tagged.contains(&node) {
+            self.tagged.push(node);
+        }
+    }
+
+    /// Current values of tagged nodes in tag order, or all nodes if none are tagged
+    fn record(&self) -> Vec<f64> {
+        if self.tagged.is_empty() {
+            return self.system.solution();
+        }
+        self.tagged.iter().map(|n| self.system.b[*n].lu).collect()
+    }
+
+    /// Developer check of nonlinear stamps against numerical Jacobians
+    ///
+    /// Returns index and relative error of every component whose
+    /// current linearization is off by more than `tolerance`.
+    fn check_jacobians(&self, tolerance: f64) -> Vec<(usize, f64)> {
+        self.components
+            .iter()
+            .enumerate()
+            .filter_map(|(i, c)| Some((i, c.jacobian_error(&self.system)?)))
+            .filter(|(_, err)| *err > tolerance)
+            .collect()
+    }
+
+    /// Warnings for devices driven outside their validated range at the current solution
+    fn range_warnings(&self) -> Vec<String> {
+        self.components
+            .iter()
+            .flat_map(|c| c.range_warnings(&self.system))
+            .collect()
+    }
+
+    /// Node info of the recorded columns, matching `record`
+    fn recorded_nodes(&self) -> Vec<MNANodeInfo> {
+        if self.tagged.is_empty() {
+            return self.system.nodes.clone();
+        }
+        self.tagged
+            .iter()
+            .map(|n| self.system.nodes[*n].clone())
+            .collect()
+    }
+
+    /// Names of the recorded columns, matching `record`
+    fn recorded_names(&self) -> Vec<String> {
+        self.recorded_nodes().into_iter().map(|n| n.name).collect()
+    }
+
+    /// Energy in every capacitor and inductor at the last solve or step
+    fn stored_energy(&self) -> Vec<(String, f64)> {
+        self.components
+            .iter()
+            .flat_map(|c| c.stored_energy())
+            .collect()
+    }
+
+    /// Operating point dump, node values followed by stored energies
+    fn operating_point(&self) -> String {
+        let mut lines = vec![];
+        for (info, v) in self.system.nodes.iter().zip(self.system.solution()).skip(1) {
+            let unit = match info.info_type {
+                InfoType::Current => "A",
+                _ => "V",
+            };
+            lines.push(format!("{} = {:.6} {}", info.name, v * info.scale, unit));
+        }
+        for (name, e) in self.stored_energy() {
+            lines.push(format!("E({}) = {:.4e} J", name, e));
+        }
+        lines.join("\n")
+    }
+
+    /// Stamp all components and prepare for DC analysis
+    fn build_system(&mut self) {
+        for c in &self.components {
+            c.stamp(&mut self.system);
+        }
+        self.time_step = 0.0;
+        self.report = ConvergenceReport::default();
+        self.system.init_lu(0.0);
+        self.system.plan_band();
+    }
+
+    /// Circuit model of all components, failing on any without one
+    fn model(&self) -> Result<Model, SimError> {
+        let components = self
+            .components
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                c.model()
+                    .ok_or_else(|| SimError::BadModel(format!("component {} has no model", i)))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Model {
+            nets: self.system.nodes[..self.size]
+                .iter()
+                .map(|n| n.name.clone())
+                .collect(),
+            components,
+        })
+    }
+
+    /// Merge chains of voltage sources whose shared node touches nothing else
+    ///
+    /// Runs when loading a model, before `build_system`. Each merge drops one branch current from
+    /// the system, the shared node keeps its voltage as a tap of the merged
+    /// source. Returns number of merges.
+    fn merge_series_sources(&mut self) -> usize {
+        let mut merged = 0;
+        loop {
+            // stamp to see which rows and columns touch each node
+            for c in &self.components {
+                c.stamp(&mut self.system);
+            }
+            let a = &self.system.a_matrix;
+            let used = |r: usize, c: usize| {
+                let cell = &a[r][c];
+                cell.g != 0.0 || cell.g_timed != 0.0 || !cell.g_dyn.is_empty()
+            };
+            let sources: Vec<(usize, &VoltageSource)> = self
+                .components
+                .iter()
+                .enumerate()
+                .filter_map(|(i, c)| Some((i, c.voltage_source()?)))
+                .collect();
+            let mut found = None;
+            'search: for (n, (i, si)) in sources.iter().enumerate() {
+                for (j, sj) in &sources[n + 1..] {
+                    for m in [si.l0, si.l1] {
+                        if m == 0 || m >= self.size || (m != sj.l0 && m != sj.l1) {
+                            continue;
+                        }
+                        let branches = [si, sj].map(|s| s.reserved.as_ref().map(|r| r.l2));
+                        let only_branches = (0..self.system.net_size)
+                            .filter(|k| used(m, *k) || used(*k, m))
+                            .all(|k| branches.contains(&Some(k)));
+                        if let Some(s) = si.merge_series(sj, m).filter(|_| only_branches) {
+                            found = Some((*i, *j, s));
+                            break 'search;
+                        }
+                    }
+                }
+            }
+            // start over with a fresh system either way
+            self.system = MNASystem::default();
+            self.system.set_size(self.size);
+            let done = found.is_none();
+            if let Some((i, j, s)) = found {
+                self.components.remove(j);
+                self.components[i] = Box::new(s);
+                merged += 1;
+            }
+            for c in &mut self.components {
+                c.reserve(&mut self.system);
+            }
+            if done {
+                return merged;
+            }
+        }
+    }
+
+    /// Relinearize every component, returns the index of the last one
+    /// that has not settled or None when all have converged
+    fn newton(&mut self) -> Option<usize> {
+        let mut unsettled = None;
+        for (i, c) in self.components.iter_mut().enumerate() {
+            if !c.newton(&mut self.system, &self.options) {
+                unsettled = Some(i);
+            }
+        }
+        unsettled
+    }
+
+    /// Add a Newton solve that stopped at `unsettled` to the report,
+    /// `prev` is the solution from the iteration before
+    fn report_failure(&mut self, iterations: u32, unsettled: Option<usize>, prev: &[f64]) {
+        let m = &self.system;
+        let max_delta = (1..m.nodes.len())
+            .filter(|i| matches!(m.nodes[*i].info_type, InfoType::Voltage))
+            .map(|i| f64::abs(m.b[i].lu - prev[i]))
+            .fold(0.0, f64::max);
+        let node = unsettled
+            .and_then(|c| self.components[c].newton_residual(m))
+            .map(|(node, residual)| (m.nodes[node].name.clone(), residual));
+        self.report.failures.push(NewtonFailure {
+            time: m.time,
+            iterations,
+            component: unsettled,
+            node,
+            max_delta,
+        });
+    }
+
+    fn update(&mut self) {
+        for c in &mut self.components {
+            c.update(&mut self.system);
+        }
+    }
+
+    /// Let components follow a change of the simulation time
+    fn advance(&mut self) {
+        for c in &mut self.components {
+            c.advance(&mut self.system);
+        }
+    }
+
+    /// Suggest a transient timestep from source frequencies and time constants
+    ///
+    /// Takes a fraction of the shortest source period or reactive time
+    /// constant, whichever is faster. Needs a built system, returns None
+    /// if nothing in the circuit sets a time scale.
+    fn suggest_time_step(&self) -> Option<f64> {
+        let periods = self
+            .components
+            .iter()
+            .filter_map(|c| c.max_frequency())
+            .map(|f| 1.0 / f);
+        let taus = self
+            .components
+            .iter()
+            .filter_map(|c| c.time_constant(&self.system));
+        periods
+            .chain(taus)
+            .reduce(f64::min)
+            .map(|t| t / STEPS_PER_PERIOD)
+    }
+
+    /// AC small-signal sweep, returns solution per frequency
+    ///
+    /// Finds the DC operating point first, then sweeps logarithmically
+    /// from `f_start` to `f_stop`. Sources with an AC amplitude drive it.
+    fn run_ac(
+        &mut self,
+        f_start: f64,
+        f_stop: f64,
+        points_per_decade: usize,
+    ) -> Result<Vec<AcPoint>, SimError> {
+        let freqs = log_frequencies(f_start, f_stop, points_per_decade)?;
+        self.solve_dc()?;
+        let mut res = vec![];
+        for f in freqs {
+            let w = 2.0 * std::f64::consts::PI * f;
+            let x = self.system.solve_ac(w, &self.system.ac)?;
+            res.push((f, x));
+        }
+        Ok(res)
+    }
+
+    /// Small-signal impedance between nodes `a` and `b` at frequency `freq`
+    ///
+    /// Injects a 1A test current into `a` (out of `b`) with all other
+    /// sources turned off, so the voltage across is the impedance.
+    fn impedance_at(&mut self, a: usize, b: usize, freq: f64) -> Result<Complex<f64>, SimError> {
+        self.solve_dc()?;
+        let mut excitation = vec![0.0; self.system.net_size];
+        excitation[a] += 1.0;
+        excitation[b] -= 1.0;
+        let w = 2.0 * std::f64::consts::PI * freq;
+        let x = self.system.solve_ac(w, &excitation)?;
+        Ok(x[a] - x[b])
+    }
+
+    /// Output noise density at `node` in V/sqrt(Hz), from a DC operating point
+    ///
+    /// Resistors add thermal noise 4kT/R and diode and transistor junctions
+    /// add shot noise 2qI of their junction current. Sources are taken as
+    /// uncorrelated, so their contributions add in power.
+    fn run_noise(
+        &mut self,
+        node: usize,
+        f_start: f64,
+        f_stop: f64,
+        points_per_decade: usize,
+    ) -> Result<Vec<(f64, f64)>, SimError> {
+        let freqs = log_frequencies(f_start, f_stop, points_per_decade)?;
+        self.solve_dc()?;
+        let sources: Vec<(usize, usize, f64)> = self
+            .components
+            .iter()
+            .flat_map(|c| c.noise_sources(&self.system, &self.options))
+            .filter(|(a, b, density)| a != b && *density > 0.0)
+            .collect();
+        let mut res = vec![];
+        for f in freqs {
+            let w = 2.0 * std::f64::consts::PI * f;
+            let mut total = 0.0;
+            for (a, b, density) in &sources {
+                let mut excitation = vec![0.0; self.system.net_size];
+                excitation[*a] += 1.0;
+                excitation[*b] -= 1.0;
+                let x = self.system.solve_ac(w, &excitation)?;
+                total += x[node].norm_sqr() * density;
+            }
+            res.push((f, total.sqrt()));
+        }
+        Ok(res)
+    }
+
+    /// Change timestep, rescaling the state of reactive components
+    fn set_time_step(&mut self, dt: f64) {
+        self.system.init_lu(1.0 / dt);
+        for c in &mut self.components {
+            c.scale_time(&mut self.system, self.time_step / dt);
+        }
+        self.time_step = dt;
+    }
+
+    /// Iterate Newton at the current time step, false if it never settles
+    ///
+    /// Nothing is committed, so the step can be retried with another dt.
+    fn settle(&mut self) -> Result<bool, SimError> {
+        let mut prev = self.system.solution();
+        let mut unsettled = None;
+        for _ in 0..self.options.max_iter {
+            prev = self.system.solution();
+            self.system.update_pre();
+            self.system.solve()?;
+            unsettled = self.newton();
+            if unsettled.is_none() {
+                return Ok(true);
+            }
+        }
+        self.report_failure(self.options.max_iter, unsettled, &prev);
+        Ok(false)
+    }
+
+    /// Advance time by one step, iterating Newton until components settle
+    ///
+    /// Sources follow the new time before the step is solved, so the
+    /// solution belongs to the time after the step.
+    fn tick(&mut self) -> Result<(), SimError> {
+        self.system.time += self.time_step;
+        self.advance();
+        if !self.settle()? {
+            return Err(SimError::NoConvergence(self.options.max_iter));
+        }
+        self.update();
+        Ok(())
+    }
+
+    /// Transient analysis with the time step adapted to the circuit
+    ///
+    /// Each step is solved once in full and then as two committed half
+    /// steps. The largest node voltage difference between the two estimates
+    /// the local error: above `tolerance` the next step is halved, well
+    /// under it the step doubles, within `dt_min..dt_max`. A full step that
+    /// doesn't converge is retried at half size. Returns the time and
+    /// recorded values of every committed step.
+    fn run_transient_adaptive(
+        &mut self,
+        t_end: f64,
+        dt_min: f64,
+        dt_max: f64,
+        tolerance: f64,
+    ) -> Result<(Vec<f64>, Vec<Vec<f64>>), SimError> {
+        if self.time_step == 0.0 {
+            self.solve_dc()?;
+        }
+        let end = self.system.time + t_end;
+        let (mut times, mut rows) = (vec![], vec![]);
+        let mut dt = dt_min;
+        while end - self.system.time > 0.5 * dt_min {
+            let h = f64::min(dt, end - self.system.time);
+            let start = self.system.time;
+            self.set_time_step(h);
+            // trial full step, which isn't committed
+            self.system.time = start + h;
+            self.advance();
+            let settled = self.settle()?;
+            self.system.time = start;
+            if !settled {
+                if h < 2.0 * dt_min {
+                    return Err(SimError::NoConvergence(self.options.max_iter));
+                }
+                dt = h / 2.0;
+                continue;
+            }
+            let full = self.system.solution();
+            self.set_time_step(h / 2.0);
+            for _ in 0..2 {
+                self.system.time += h / 2.0;
+                self.advance();
+                if !self.settle()? {
+                    return Err(SimError::NoConvergence(self.options.max_iter));
+                }
+                self.update();
+                times.push(self.system.time);
+                rows.push(self.record());
+            }
+            let half = self.system.solution();
+            let err = (1..self.size)
+                .map(|i| f64::abs(full[i] - half[i]))
+                .fold(0.0, f64::max);
+            dt = if err > tolerance {
+                f64::max(h / 2.0, dt_min)
+            } else if err < 0.25 * tolerance {
+                f64::min(2.0 * h, dt_max)
+            } else {
+                h
+            };
+        }
+        Ok((times, rows))
+    }
+
+    /// Transient analysis from the DC operating point, returns recorded values per step
+    fn run_transient(&mut self, t_end: f64, dt: f64) -> Result<Vec<Vec<f64>>, SimError> {
+        let mut rows = Vec::with_capacity((t_end / dt).round() as usize);
+        self.run_transient_with(t_end, dt, |_, x| {
+            rows.push(x.to_vec());
+            Ok(())
+        })?;
+        Ok(rows)
+    }
+
+    /// Transient analysis recorded exactly on a sample grid, eg. 48kHz for audio
+    ///
+    /// Steps internally by `dt` and linearly interpolates the recorded values
+    /// onto sample times, returns the sample times and their values.
+    fn run_transient_sampled(
+        &mut self,
+        t_end: f64,
+        sample_rate: f64,
+        dt: f64,
+    ) -> Result<(Vec<f64>, Vec<Vec<f64>>), SimError> {
+        if self.time_step == 0.0 {
+            self.solve_dc()?;
+        }
+        let start = self.system.time;
+        let samples = (t_end * sample_rate).round() as usize;
+        let sample_time = |k: usize| start + k as f64 / sample_rate;
+        let (mut times, mut rows) = (vec![], vec![]);
+        let mut prev = (start, self.record());
+        let mut k = 1;
+        // run whole internal steps past the last sample
+        let t_run = (samples as f64 / sample_rate / dt).ceil() * dt;
+        self.run_transient_with(t_run, dt, |t, x| {
+            while k <= samples && sample_time(k) <= t {
+                let frac = (sample_time(k) - prev.0) / (t - prev.0);
+                let row = prev.1.iter().zip(x).map(|(a, b)| a + frac * (b - a));
+                times.push(sample_time(k));
+                rows.push(row.collect());
+                k += 1;
+            }
+            prev = (t, x.to_vec());
+            Ok(())
+        })?;
+        Ok((times, rows))
+    }
+
+    /// Transient analysis of `node` written to a WAV file, one step per sample
+    fn run_transient_wav(
+        &mut self,
+        node: usize,
+        t_end: f64,
+        sample_rate: u32,
+        path: &str,
+    ) -> Result<(), SimError> {
+        let column = match self.tagged.is_empty() {
+            true => Some(node),
+            false => self.tagged.iter().position(|n| *n == node),
+        }
+        .ok_or_else(|| SimError::BadConfig(format!("node {} is not recorded", node)))?;
+        let mut samples = vec![];
+        self.run_transient_with(t_end, 1.0 / sample_rate as f64, |_, x| {
+            samples.push(x[column]);
+            Ok(())
+        })?;
+        wav::write_wav(&samples, sample_rate, path)
+            .map_err(|e| SimError::Output(format!("{}: {}", path, e)))
+    }
+
+    /// Transient analysis handing time and recorded values of each step to `on_step`
+    fn run_transient_with(
+        &mut self,
+        t_end: f64,
+        dt: f64,
+        mut on_step: impl FnMut(f64, &[f64]) -> Result<(), SimError>,
+    ) -> Result<(), SimError> {
+        if self.time_step == 0.0 {
+            self.solve_dc()?;
+        }
+        self.set_time_step(dt);
+        let steps = (t_end / dt).round() as usize;
+        for _ in 0..steps {
+            self.tick()?;
+            on_step(self.system.time, &self.record())?;
+        }
+        Ok(())
+    }
+
+    /// Add the config's sources, then run its analysis and write probes to its output file
+    fn run_config(&mut self, config: &RunConfig) -> Result<(), SimError> {
+        self.add_stimulus(&config.stimulus)?;
+        self.build_system();
+        let probes = &self.probe_nodes(&config.probes)?;
+        let mut lines = vec![];
+        match config.analysis {
+            Analysis::Dc => {
+                self.solve_dc()?;
+                let header: Vec<String> = probes.iter().map(|p| format!("v({})", p)).collect();
+                lines.push(header.join(","));
+                let x = self.system.solution();
+                let values: Vec<String> = probes.iter().map(|p| x[*p].to_string()).collect();
+                lines.push(values.join(","));
+            }
+            Analysis::Transient { t_end, dt } => {
+                self.tagged.clear();
+                for p in probes {
+                    self.tag_node(*p);
+                }
+                let mut out = create_output(&config.output)?;
+                let (mut times, mut rows) = (vec![], vec![]);
+                if config.output.ends_with(".jsonl") {
+                    let mut writer = JsonLinesWriter::new(out, self.recorded_names());
+                    self.run_transient_with(t_end, dt, |t, x| {
+                        times.push(t);
+                        rows.push(x.to_vec());
+                        writer.write_step(t, x)
+                    })?;
+                } else {
+                    self.run_transient_with(t_end, dt, |t, x| {
+                        times.push(t);
+                        rows.push(x.to_vec());
+                        Ok(())
+                    })?;
+                    write_csv(&self.recorded_nodes(), &rows, &times, &mut out)
+                        .and_then(|_| out.flush())
+                        .map_err(|e| SimError::Output(e.to_string()))?;
+                }
+                for m in &config.measure {
+                    println!("{}", self.measurement(m, &times, &rows)?);
+                }
+                return Ok(());
+            }
+            Analysis::Ac {
+                f_start,
+                f_stop,
+                points_per_decade,
+                drive,
+            } => {
+                let points = self.run_ac(f_start, f_stop, points_per_decade)?;
+                let mut header = vec![String::from("freq")];
+                for p in probes {
+                    match drive {
+                        Some(_) => header.push(format!("gain({})", p)),
+                        None => header.push(format!("mag({})", p)),
+                    }
+                    header.push(format!("phase({})", p));
+                }
+                lines.push(header.join(","));
+                // gain in dB or magnitude, then phase in degrees for each probe
+                let columns: Vec<Vec<(f64, f64)>> = match drive {
+                    Some(drive) => {
+                        let transfer: Vec<_> =
+                            probes.iter().map(|p| bode(&points, *p, drive)).collect();
+                        (0..points.len())
+                            .map(|i| transfer.iter().map(|t| (t[i].1, t[i].2)).collect())
+                            .collect()
+                    }
+                    None => ac_polar(&points)
+                        .into_iter()
+                        .map(|(_, x)| {
+                            probes
+                                .iter()
+                                .map(|p| (x[*p].0, x[*p].1.to_degrees()))
+                                .collect()
+                        })
+                        .collect(),
+                };
+                for ((f, _), row) in points.iter().zip(columns) {
+                    let mut values = vec![f.to_string()];
+                    for (a, phase) in row {
+                        values.push(a.to_string());
+                        values.push(phase.to_string());
+                    }
+                    lines.push(values.join(","));
+                }
+            }
+            Analysis::MonteCarlo {
+                runs,
+                seed,
+                tolerance,
+                normal,
+            } => {
+                let &[node] = probes.as_slice() else {
+                    return Err(SimError::BadConfig(String::from(
+                        "monte carlo takes one probe",
+                    )));
+                };
+                let model = self.model()?;
+                let values = monte_carlo(runs, node, &mut Rng::new(seed), |rng| {
+                    let mut draw = |v: f64| match normal {
+                        true => rng.tolerance_normal(v, tolerance),
+                        false => rng.tolerance(v, tolerance),
+                    };
+                    let components = model
+                        .components
+                        .iter()
+                        .map(|c| c.with_tolerance(&mut draw))
+                        .collect();
+                    Model {
+                        nets: model.nets.clone(),
+                        components,
+                    }
+                    .build()
+                })?;
+                lines.push(format!("run,v({})", node));
+                for (k, v) in values.iter().enumerate() {
+                    lines.push(format!("{},{}", k, v));
+                }
+            }
+            Analysis::Superposition => {
+                let results = probes
+                    .iter()
+                    .map(|p| self.superposition(*p))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut header = vec![String::from("source")];
+                header.extend(probes.iter().map(|p| format!("v({})", p)));
+                lines.push(header.join(","));
+                // a row per source by component index, then the sum and the total
+                let sources = results.first().map_or(vec![], |r| r.contributions.clone());
+                for (k, (source, _)) in sources.iter().enumerate() {
+                    let mut values = vec![source.to_string()];
+                    values.extend(results.iter().map(|r| r.contributions[k].1.to_string()));
+                    lines.push(values.join(","));
+                }
+                let mut sum = vec![String::from("sum")];
+                sum.extend(results.iter().map(|r| r.sum().to_string()));
+                lines.push(sum.join(","));
+                let mut total = vec![String::from("total")];
+                total.extend(results.iter().map(|r| r.total.to_string()));
+                lines.push(total.join(","));
+            }
+            Analysis::Symbolic => {
+                for p in probes {
+                    let formula = self
+                        .system
+                        .symbolic_node(*p)
+                        .ok_or_else(|| SimError::BadConfig(format!("no formula for node {}", p)))?;
+                    lines.push(formula);
+                }
+            }
+            Analysis::Mtx { ref b } => {
+                // system linearized at the operating point
+                self.solve_dc()?;
+                let (mut a_out, mut b_out) = (create_output(&config.output)?, create_output(b)?);
+                return self
+                    .system
+                    .write_mtx(&mut a_out, &mut b_out)
+                    .and_then(|_| a_out.flush())
+                    .and_then(|_| b_out.flush())
+                    .map_err(|e| SimError::Output(e.to_string()));
+            }
+            Analysis::Model => lines.push(self.model()?.to_json()?),
+        }
+        let mut text = lines.join("\n");
+        text.push('\n');
+        std::fs::write(&config.output, text)
+            .map_err(|e| SimError::Output(format!("{}: {}", config.output, e)))
+    }
+
+    /// Nodes of run config probes, names are looked up as probes and then nets
+    fn probe_nodes(&self, probes: &[ProbeRef]) -> Result<Vec<usize>, SimError> {
+        probes
+            .iter()
+            .map(|p| match p {
+                ProbeRef::Node(n) => Ok(*n),
+                ProbeRef::Name(name) => self
+                    .system
+                    .probe(name)
+                    .or_else(|| self.system.nodes.iter().position(|n| n.name == *name))
+                    .ok_or_else(|| SimError::BadConfig(format!("no probe or net {}", name))),
+            })
+            .collect()
+    }
+
+    /// Recorded values of a probe over transient rows, with its name
+    fn probe_column(
+        &self,
+        probe: &ProbeRef,
+        rows: &[Vec<f64>],
+    ) -> Result<(String, Vec<f64>), SimError> {
+        let node = self.probe_nodes(std::slice::from_ref(probe))?[0];
+        let column = match self.tagged.is_empty() {
+            true => Some(node),
+            false => self.tagged.iter().position(|n| *n == node),
+        }
+        .ok_or_else(|| SimError::BadConfig(format!("node {} is not recorded", node)))?;
+        let name = self.recorded_names()[column].clone();
+        Ok((name, rows.iter().map(|row| row[column]).collect()))
+    }
+
+    /// Result line of a run config measurement over recorded transient rows
+    fn measurement(
+        &self,
+        m: &Measure,
+        times: &[f64],
+        rows: &[Vec<f64>],
+    ) -> Result<String, SimError> {
+        match m {
+            Measure::Rms(probe) => {
+                let (name, x) = self.probe_column(probe, rows)?;
+                Ok(format!("rms({}) = {}", name, measure::rms(&x)))
+            }
+            Measure::PeakToPeak(probe) => {
+                let (name, x) = self.probe_column(probe, rows)?;
+                Ok(format!(
+                    "peak_to_peak({}) = {}",
+                    name,
+                    measure::peak_to_peak(&x)
+                ))
+            }
+            Measure::Thd { probe, fundamental } => {
+                let (name, x) = self.probe_column(probe, rows)?;
+                let n = times.len();
+                if n < 2 {
+                    return Err(SimError::BadConfig(String::from(
+                        "thd needs at least two steps",
+                    )));
+                }
+                let sample_rate = (n - 1) as f64 / (times[n - 1] - times[0]);
+                let thd = measure::thd(&x, *fundamental, sample_rate);
+                Ok(format!("thd({}) = {}", name, thd))
+            }
+            Measure::Delay {
+                from,
+                to,
+                threshold,
+            } => {
+                let (a_name, a) = self.probe_column(from, rows)?;
+                let (b_name, b) = self.probe_column(to, rows)?;
+                let delay = propagation_delay(times, &a, &b, *threshold)
+                    .map_or(String::from("none"), |d| d.to_string());
+                Ok(format!("delay({}, {}) = {}", a_name, b_name, delay))
+            }
+            Measure::Marker(t) => {
+                let values = marker(times, rows, &self.recorded_names(), *t).ok_or_else(|| {
+                    SimError::BadConfig(format!("marker at {} is outside the run", t))
+                })?;
+                let values: Vec<String> = values
+                    .iter()
+                    .map(|(name, v)| format!("{} = {}", name, v))
+                    .collect();
+                Ok(format!("marker({}): {}", t, values.join(", ")))
+            }
+        }
+    }
+
+    /// Add a voltage source to ground for each stimulus of a run config
+    fn add_stimulus(&mut self, stimulus: &[Stimulus]) -> Result<(), SimError> {
+        for s in stimulus {
+            let source: Box<dyn Component> = match s.wave {
+                Some(Wave::Sine { amp, freq, offset }) => {
+                    Box::new(VoltageFunction::sine(amp, freq, offset, s.node, 0))
+                }
+                Some(Wave::Pulse {
+                    low,
+                    high,
+                    period,
+                    duty,
+                }) => Box::new(VoltageFunction::pulse(low, high, period, duty, s.node, 0)),
+                Some(Wave::Wav {
+                    ref path,
+                    amplitude,
+                }) => Box::new(
+                    WavSource::from_file(path, amplitude, s.node, 0)
+                        .map_err(|e| SimError::BadConfig(format!("{}: {}", path, e)))?,
+                ),
+                None => Box::new(VoltageSource::with_ac(s.dc, s.ac, s.node, 0)),
+            };
+            self.add_component(source);
+        }
+        Ok(())
+    }
+
+    /// Hold node at voltage `v` until unpinned, for what-if analysis
+    ///
+    /// Acts like an ideal source to ground, without adding to the netlist.
+    /// Needs a built system, solve again to see the effect.
+    fn pin_node(&mut self, node: usize, v: f64) {
+        self.unpin_node(node);
+        let (a, b) = (self.system.a_cell(node, node).g, self.system.b_row(node).g);
+        self.pins.push((node, a, b));
+        self.system.a_cell(node, node).g = a + G_PIN;
+        self.system.b_row(node).g = b + G_PIN * v;
+        self.system.init_lu(self.system.step_scale);
+    }
+
+    /// Release a pinned node, restoring the circuit exactly
+    fn unpin_node(&mut self, node: usize) {
+        if let Some(i) = self.pins.iter().position(|(n, _, _)| *n == node) {
+            let (_, a, b) = self.pins.remove(i);
+            self.system.a_cell(node, node).g = a;
+            self.system.b_row(node).g = b;
+            self.system.init_lu(self.system.step_scale);
+        }
+    }
+
+    /// Move the wiper of potentiometer `index` while simulating
+    ///
+    /// Returns false if that component has no wiper.
+    fn set_wiper(&mut self, index: usize, frac: f64) -> bool {
+        let moved = self.components[index].move_wiper(&mut self.system, frac);
+        if moved {
+            self.system.init_lu(self.system.step_scale);
+        }
+        moved
+    }
+
+    /// Open or close switch `index` while simulating
+    ///
+    /// Returns false if that component is not a switch.
+    fn set_switch(&mut self, index: usize, closed: bool) -> bool {
+        let switched = self.components[index].set_closed(&mut self.system, closed);
+        if switched {
+            self.system.init_lu(self.system.step_scale);
+        }
+        switched
+    }
+
+    /// Scale every independent source, returns indices of the sources
+    fn scale_sources(&mut self, scale: f64) -> Vec<usize> {
+        let mut sources = vec![];
+        for (i, c) in self.components.iter_mut().enumerate() {
+            if c.scale_source(&mut self.system, scale) {
+                sources.push(i);
+            }
+        }
+        self.system.init_lu(self.system.step_scale);
+        sources
+    }
+
+    /// DC contribution of each independent source alone to `node`
+    ///
+    /// Other sources are zeroed while one is active. Contributions only
+    /// add up to the total for linear circuits.
+    fn superposition(&mut self, node: usize) -> Result<Superposition, SimError> {
+        let sources = self.scale_sources(0.0);
+        let mut contributions = vec![];
+        for &i in &sources {
+            self.components[i].scale_source(&mut self.system, 1.0);
+            self.system.init_lu(self.system.step_scale);
+            self.solve_dc()?;
+            contributions.push((i, self.system.b[node].lu));
+            self.components[i].scale_source(&mut self.system, 0.0);
+        }
+        self.scale_sources(1.0);
+        self.solve_dc()?;
+        Ok(Superposition {
+            contributions,
+            total: self.system.b[node].lu,
+        })
+    }
+
+    /// DC transfer curve of source `source` stepped from `start` to `stop`
+    ///
+    /// Each point is solved starting from the one before, so sweep away
+    /// from where Newton finds it easy. Returns the source value and the
+    /// recorded values at each point, the source is left at `stop`.
+    fn run_dc_sweep(
+        &mut self,
+        source: usize,
+        start: f64,
+        stop: f64,
+        step: f64,
+    ) -> Result<DcCurve, SimError> {
+        let mut rows = vec![];
+        for value in sweep_values(start, stop, step)? {
+            self.set_source(source, value)?;
+            self.solve_dc()?;
+            rows.push((value, self.record()));
+        }
+        Ok(rows)
+    }
+
+    /// Family of DC sweeps of `inner`, one for each value of `outer`
+    ///
+    /// Ranges are `(start, stop, step)` as for `run_dc_sweep`, eg. collector
+    /// voltage inside base current for transistor output curves. Each curve
+    /// starts from the last point of the one before.
+    fn run_dc_sweep2(
+        &mut self,
+        outer: usize,
+        outer_range: (f64, f64, f64),
+        inner: usize,
+        inner_range: (f64, f64, f64),
+    ) -> Result<Vec<(f64, DcCurve)>, SimError> {
+        let (start, stop, step) = inner_range;
+        // check the inner range before solving anything
+        sweep_values(start, stop, step)?;
+        let mut curves = vec![];
+        for value in sweep_values(outer_range.0, outer_range.1, outer_range.2)? {
+            self.set_source(outer, value)?;
+            curves.push((value, self.run_dc_sweep(inner, start, stop, step)?));
+        }
+        Ok(curves)
+    }
+
+    /// Change the value of source `index` and prepare the system to solve
+    fn set_source(&mut self, index: usize, value: f64) -> Result<(), SimError> {
+        if !self.components[index].set_source(&mut self.system, value) {
+            return Err(SimError::BadConfig(format!(
+                "component {} is not a source",
+                index
+            )));
+        }
+        self.system.init_lu(self.system.step_scale);
+        Ok(())
+    }
+
+    /// Find the DC operating point, returns number of Newton iterations used
+    ///
+    /// Circuits without a stable operating point (latches, oscillators) make
+    /// Newton bounce between states; that is reported as `NoDcSolution`
+    /// rather than running out the iteration limit.
+    ///
+    /// When Newton fails the operating point is retried with gmin stepping
+    /// and then source stepping, and the original error is kept if both
+    /// fail too.
+    fn solve_dc(&mut self) -> Result<u32, SimError> {
+        match self.newton_dc() {
+            Err(e @ (SimError::NoConvergence(_) | SimError::NoDcSolution(_))) => self
+                .gmin_step()
+                .or_else(|_| self.source_step())
+                .map_err(|_| e),
+            res => res,
+        }
+    }
+
+    /// Add conductance `g` from every voltage node to ground
+    fn add_shunts(&mut self, g: f64) {
+        for i in 1..self.system.nodes.len() {
+            if matches!(self.system.nodes[i].info_type, InfoType::Voltage) {
+                self.system.a_cell(i, i).g += g;
+            }
+        }
+        self.system.init_lu(self.system.step_scale);
+    }
+
+    /// Find the DC operating point by ramping down extra node conductance,
+    /// returns the total number of Newton iterations
+    fn gmin_step(&mut self) -> Result<u32, SimError> {
+        let schedule = self.gmin_stepping.clone();
+        let mut total = 0;
+        let mut g = schedule.g_start;
+        for _ in 0..schedule.max_steps {
+            if g < self.options.g_min {
+                break;
+            }
+            self.add_shunts(g);
+            let res = self.newton_dc();
+            self.add_shunts(-g);
+            total += res?;
+            g /= schedule.factor;
+        }
+        Ok(total + self.newton_dc()?)
+    }
+
+    /// Find the DC operating point by ramping up the independent sources,
+    /// returns the total number of Newton iterations
+    fn source_step(&mut self) -> Result<u32, SimError> {
+        let steps = self.source_stepping.steps.max(1);
+        let mut total = 0;
+        for k in 0..=steps {
+            self.scale_sources(k as f64 / steps as f64);
+            match self.newton_dc() {
+                Ok(iter) => total += iter,
+                Err(e) => {
+                    self.scale_sources(1.0);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(total)
+    }
+
+    /// Newton loop for the DC operating point from the present linearization
+    fn newton_dc(&mut self) -> Result<u32, SimError> {
+        let mut history = VecDeque::new();
+        let mut prev = self.system.solution();
+        let mut unsettled = None;
+        for iter in 0..self.options.max_iter {
+            prev = self.system.solution();
+            self.system.update_pre();
+            self.system.solve()?;
+            unsettled = self.newton();
+            if unsettled.is_none() {
+                self.update();
+                return Ok(iter + 1);
+            }
+            history.push_back((self.system.solution(), self.system.vars.clone()));
+            if history.len() > 2 * DC_CYCLE_MAX_PERIOD {
+                history.pop_front();
+            }
+            if let Some(period) = find_cycle(&history, self.options.v_tolerance) {
+                self.report_failure(iter + 1, unsettled, &prev);
+                return Err(SimError::NoDcSolution(period));
+            }
+        }
+        self.report_failure(self.options.max_iter, unsettled, &prev);
+        Err(SimError::NoConvergence(self.options.max_iter))
+    }
+}
+
+/// Simulator for the circuit model in a JSON file, or a SPICE netlist
+fn load_model(path: &str) -> Result<Simulator, SimError> {
+    let text = std::fs::read_to_string(path).map_err(|e| SimError::BadModel(e.to_string()))?;
+    if path.ends_with(".cir") || path.ends_with(".sp") {
+        let (system, components) = netlist::parse_spice(&text).map_err(SimError::BadModel)?;
+        Ok(Simulator::from_netlist(system, components))
+    } else {
+        Model::from_json(&text)?.build()
+    }
+}
+
+/// Operating point of the circuit model in a JSON file
+fn model_operating_point(path: &str) -> Result<String, SimError> {
+    let mut sim = load_model(path)?;
+    sim.build_system();
+    if let Err(e) = sim.solve_dc() {
+        eprint!("{}", sim.report);
+        return Err(e);
+    }
+    Ok(sim.operating_point())
+}
+
+fn main() {
+    // circuit model exported from the schematic editor, or a SPICE netlist
+    if let Some(path) = std::env::args().nth(1) {
+        // analysis of a run config written to its output, or else the operating point
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        let result = match args.as_slice() {
+            [config] => {
+                RunConfig::load(config).and_then(|config| load_model(&path)?.run_config(&config))
+            }
+            // second variant of the circuit to compare against
+            [other, config] => RunConfig::load(config).and_then(|config| {
+                Comparison::run_config(&mut load_model(&path)?, &mut load_model(other)?, &config)
+            }),
+            _ => model_operating_point(&path).map(|op| println!("{}", op)),
+        };
+        if let Err(e) = result {
+            eprintln!("Could not simulate {}: {}", path, e);
+        }
+        return;
+    }
+    let mut s = MNASystem::default();
+    s.set_size(3);
+
+    println!("Hello from sim.rs");
+    println!("{:?}", s);
+    println!("Resistor is {}", format_unit_value(1500.0, " Ohms"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_cmp::approx_eq;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_system() -> Result<(), String> {
+        let mut s = MNASystem::default();
+        s.set_size(5);
+        assert_eq!(s.a_matrix.len(), 5);
+        for row in s.a_matrix {
+            assert_eq!(row.width, 5);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_lu_divider() -> Result<(), String> {
+        // 6V across 1k over 2k, stamped straight into the system
+        let mut m = MNASystem::default();
+        m.set_size(3);
+        let mut source = VoltageSource::new(6.0, 1, 0);
+        source.reserve(&mut m);
+        source.stamp(&mut m);
+        Resistor::new(1e3, 1, 2).stamp(&mut m);
+        Resistor::new(2e3, 2, 0).stamp(&mut m);
+        m.init_lu(0.0);
+        m.update_pre();
+        m.solve().map_err(|e| e.to_string())?;
+        assert!(approx_eq!(f64, m.b[1].lu, 6.0, epsilon = 1e-12));
+        assert!(approx_eq!(f64, m.b[2].lu, 4.0, epsilon = 1e-12));
+        // source branch current, 2mA out of node 1
+        assert!(approx_eq!(f64, m.b[3].lu, 2e-3, epsilon = 1e-12));
+        assert_eq!(m.stats.last_path, LuPath::General);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pn() -> Result<(), String> {
+        // Similar to 1N4148 (but just PN junction)
+        let mut pn = JunctionPN::new(/*is=*/ 35.0e-12, /*n=*/ 1.24);
+        let options = SimOptions::default();
+        // vcrit is point where current increases faster than voltage as voltage increases
+        assert!(approx_eq!(f64, pn.vcrit, 0.6542963597947701, ulps = 100));
+        // Check ieq for a couple voltages
+        pn.newton(0.5, &options);
+        assert!(approx_eq!(f64, pn.ieq, 0.002760783529589722, ulps = 100));
+        pn.newton(0.4, &options);
+        assert!(approx_eq!(f64, pn.ieq, 0.0000976127760265226, ulps = 100));
+        // 0.4 should just take 1 newton step (below vcrit)
+        let mut done = pn.newton(0.4, &options);
+        assert!(done);
+        // 0.8 takes more than 2 iterations because of qucs current thing
+        done = pn.newton(0.8, &options);
+        assert!(!done);
+        done = pn.newton(0.8, &options);
+        assert!(!done);
+        // But with more iterations it should converge
+        for _i in 0..10 {
+            done = pn.newton(0.8, &options);
+            if done {
+                break;
+            }
+        }
+        assert!(done);
+        Ok(())
+    }
+
+    #[test]
+    fn test_component_polymorphism() -> Result<(), String> {
+        let mut s = MNASystem::default();
+        s.set_size(3);
+        let c1 = Resistor::new(100.0, 0, 1);
+        let c2 = Resistor::new(100.0, 1, 2);
+        let c3 = Capacitor::new(0.1, 1, 2);
+        let c4 = Diode::new(0, 1, DiodeParameters::default());
+        println!("{:?}", &c1);
+        println!("{:?}", &c4);
+        let mut v: Vec<Box<dyn Component>> = vec![Box::new(c1), Box::new(c2), Box::new(c3), Box::new(c4)];
+        // Reserve all components
+        for c in &mut v {
+            c.reserve(&mut s);
+        }
+        // Stamp into matrix
+        for c in &mut v {
+            c.stamp(&mut s);
+        }
+        Ok(())
+    }
+
+    // Ideal switch from `node` to ground that closes when `ctl` is high
+    struct ComparatorSwitch {
+        node: usize,
+        ctl: usize,
+        closed: bool,
+        dyn_index: usize,
+    }
+
+    impl ComparatorSwitch {
+        fn new(node: usize, ctl: usize) -> Self {
+            Self {
+                node,
+                ctl,
+                closed: false,
+                dyn_index: 0,
+            }
+        }
+    }
+
+    impl Component for ComparatorSwitch {
+        fn reserve(&mut self, m: &mut MNASystem) {
+            self.dyn_index = m.reserve_dynamic();
+        }
+        fn stamp(&self, m: &mut MNASystem) {
+            m.add_dynamic_a(self.node, self.node, self.dyn_index, "g:sw");
+            self.update_dynamic(m);
+        }
+        fn update_dynamic(&self, m: &mut MNASystem) {
+            m.set_dynamic(self.dyn_index, if self.closed { 1.0 } else { G_MIN });
+        }
+        fn newton(&mut self, m: &mut MNASystem, _options: &SimOptions) -> bool {
+            let closed = m.b[self.ctl].lu > 0.5;
+            if closed == self.closed {
+                return true;
+            }
+            self.closed = closed;
+            self.update_dynamic(m);
+            false
+        }
+    }
+
+    // Conductor to ground carrying atan(v - v0), so the current saturates.
+    // Started far from v0, Newton overshoots onto the flat part of the curve.
+    struct SoftLimiter {
+        node: usize,
+        v0: f64,
+        veq: f64,
+        dyn_g: usize,
+        dyn_i: usize,
+    }
+
+    impl SoftLimiter {
+        fn new(node: usize, v0: f64) -> Self {
+            Self {
+                node,
+                v0,
+                veq: 0.0,
+                dyn_g: 0,
+                dyn_i: 0,
+            }
+        }
+    }
+
+    impl Component for SoftLimiter {
+        fn reserve(&mut self, m: &mut MNASystem) {
+            self.dyn_g = m.reserve_dynamic();
+            self.dyn_i = m.reserve_dynamic();
+        }
+        fn stamp(&self, m: &mut MNASystem) {
+            m.add_dynamic_a(self.node, self.node, self.dyn_g, "g:lim");
+            m.add_dynamic_b(self.node, self.dyn_i, "i:lim");
+            self.update_dynamic(m);
+        }
+        fn update_dynamic(&self, m: &mut MNASystem) {
+            let x = self.veq - self.v0;
+            let g = 1.0 / (1.0 + x * x);
+            m.set_dynamic(self.dyn_g, g);
+            m.set_dynamic(self.dyn_i, g * self.veq - x.atan());
+        }
+        fn newton(&mut self, m: &mut MNASystem, options: &SimOptions) -> bool {
+            // keep the overshoots finite
+            let v = m.b[self.node].lu.clamp(-1e3, 1e3);
+            let done = (v - self.veq).abs() < options.v_tolerance;
+            self.veq = v;
+            self.update_dynamic(m);
+            done
+        }
+    }
+
+    #[test]
+    fn test_dc_solution() -> Result<(), String> {
+        // Diode forward biased through 1k from 5V
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageSource::new(5.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1000.0, 1, 2)));
+        sim.add_component(Box::new(Diode::new(2, 0, DiodeParameters::default())));
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        let v = sim.system.b[2].lu;
+        assert!(v > 0.5 && v < 0.8);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ac_cable() -> Result<(), String> {
+        // 10k source (like a guitar pickup) into 1M load at node 2
+        let cable_out = |length: Option<f64>| -> Result<Vec<f64>, String> {
+            let mut sim = Simulator::new(if length.is_some() { 4 } else { 3 });
+            sim.add_component(Box::new(VoltageSource::with_ac(0.0, 1.0, 1, 0)));
+            sim.add_component(Box::new(Resistor::new(10e3, 1, 2)));
+            let out = match length {
+                Some(length) => {
+                    let params = CableParameters::default();
+                    sim.add_component(Box::new(Cable::new(length, 2, 3, params)));
+                    3
+                }
+                None => 2,
+            };
+            sim.add_component(Box::new(Resistor::new(1e6, out, 0)));
+            sim.build_system();
+            let res = sim.run_ac(100.0, 100e3, 1).map_err(|e| e.to_string())?;
+            Ok(res.iter().map(|(_, x)| x[out].norm()).collect())
+        };
+        let wire = cable_out(None)?;
+        let cable = cable_out(Some(10.0))?;
+        assert_eq!(wire.len(), 4);
+        // low frequency passes about the same
+        assert!(approx_eq!(f64, wire[0], cable[0], epsilon = 1e-3));
+        // 1nF of cable with 10k source has corner near 16kHz
+        assert!(approx_eq!(f64, wire[3], 1e6 / (1e6 + 10e3), epsilon = 1e-6));
+        assert!(cable[3] < 0.2 * wire[3]);
+        // same cable loaded from a model, as the schematic editor exports it
+        let json = r#"{
+            "nets": ["GND", "n1", "n2", "n3"],
+            "components": [
+                { "type": "VoltageSource", "v": 0.0, "ac": 1.0, "nets": [1, 0] },
+                { "type": "Resistor", "r": 10e3, "nets": [1, 2] },
+                { "type": "Cable", "length": 10.0, "nets": [2, 3] },
+                { "type": "Resistor", "r": 1e6, "nets": [3, 0] }
+            ]
+        }"#;
+        let model = Model::from_json(json).map_err(|e| e.to_string())?;
+        let mut sim = model.build().map_err(|e| e.to_string())?;
+        sim.build_system();
+        let res = sim.run_ac(100.0, 100e3, 1).map_err(|e| e.to_string())?;
+        for ((_, x), expected) in res.iter().zip(&cable) {
+            assert!(approx_eq!(f64, x[3].norm(), *expected, epsilon = 1e-9));
+        }
+        assert!(res[3].1[3].norm() < 0.2 * wire[3]);
+        assert_eq!(sim.model().map_err(|e| e.to_string())?, model);
+        Ok(())
+    }
+
+    #[test]
+    fn test_impedance_parallel_rc() -> Result<(), String> {
+        let (r, c, f) = (1e3, 1e-6, 200.0);
+        let mut sim = Simulator::new(2);
+        sim.add_component(Box::new(Resistor::new(r, 1, 0)));
+        sim.add_component(Box::new(Capacitor::new(c, 1, 0)));
+        sim.build_system();
+        let z = sim.impedance_at(1, 0, f).map_err(|e| e.to_string())?;
+        let w = 2.0 * std::f64::consts::PI * f;
+        let expected = r / Complex::new(1.0, w * r * c);
+        assert!(approx_eq!(f64, z.re, expected.re, epsilon = 1e-6));
+        assert!(approx_eq!(f64, z.im, expected.im, epsilon = 1e-6));
+        // same impedance seen from the other terminal
+        let z = sim.impedance_at(0, 1, f).map_err(|e| e.to_string())?;
+        assert!(approx_eq!(f64, z.re, expected.re, epsilon = 1e-6));
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_config() -> Result<(), String> {
+        let output = std::env::temp_dir().join("circuit_test_run_config.csv");
+        let output = output.to_str().ok_or("bad temp path")?;
+        let json = format!(
+            r#"{{
+                "analysis": {{ "type": "transient", "t_end": 1e-3, "dt": 1e-4 }},
+                "stimulus": [ {{ "node": 1, "dc": 2.0 }} ],
+                "probes": [ 1, 2 ],
+                "output": "{}"
+            }}"#,
+            output
+        );
+        let config = RunConfig::from_json(&json).map_err(|e| e.to_string())?;
+        assert_eq!(
+            config.analysis,
+            Analysis::Transient {
+                t_end: 1e-3,
+                dt: 1e-4
+            }
+        );
+        // divider with a capacitor that has already settled
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Resistor::new(1e3, 2, 0)));
+        sim.add_component(Box::new(Capacitor::new(1e-6, 2, 0)));
+        sim.run_config(&config).map_err(|e| e.to_string())?;
+        let text = std::fs::read_to_string(output).map_err(|e| e.to_string())?;
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 11);
+        assert_eq!(lines[0], "time [s],v1 [V],v2 [V]");
+        let last: Vec<f64> = lines[10].split(',').map(|x| x.parse().unwrap()).collect();
+        assert!(approx_eq!(f64, last[0], 1e-3, epsilon = 1e-12));
+        assert!(approx_eq!(f64, last[1], 2.0, epsilon = 1e-9));
+        assert!(approx_eq!(f64, last[2], 1.0, epsilon = 1e-9));
+        // numbers can have units
+        let config = RunConfig::from_json(
+            r#"{
+                "analysis": { "type": "transient", "t_end": "1ms", "dt": "10u" },
+                "stimulus": [ { "node": 1, "dc": "4k7" } ],
+                "probes": [ 1 ],
+                "output": "out.csv"
+            }"#,
+        )
+        .map_err(|e| e.to_string())?;
+        assert_eq!(
+            config.analysis,
+            Analysis::Transient {
+                t_end: 1e-3,
+                dt: 10e-6
+            }
+        );
+        assert!(approx_eq!(
+            f64,
+            config.stimulus[0].dc,
+            4700.0,
+            epsilon = 1e-9
+        ));
+        let err = RunConfig::from_json(
+            r#"{ "analysis": { "type": "transient", "t_end": "1K", "dt": 1 } }"#,
+        );
+        assert!(matches!(err, Err(SimError::BadConfig(_))));
+        // measurements of a probe, by name or node
+        let json = format!(
+            r#"{{
+                "analysis": {{ "type": "transient", "t_end": "10m", "dt": "10u" }},
+                "stimulus": [ {{ "node": 1, "sine": {{ "amp": 1.0, "freq": 1000 }} }} ],
+                "probes": [ "v1", "v2" ],
+                "measure": [
+                    {{ "type": "rms", "probe": "v2" }},
+                    {{ "type": "peak_to_peak", "probe": 2 }},
+                    {{ "type": "thd", "probe": "v2", "fundamental": 1000 }}
+                ],
+                "output": "{}"
+            }}"#,
+            output
+        );
+        let config = RunConfig::from_json(&json).map_err(|e| e.to_string())?;
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Resistor::new(1e3, 2, 0)));
+        sim.run_config(&config).map_err(|e| e.to_string())?;
+        let text = std::fs::read_to_string(output).map_err(|e| e.to_string())?;
+        let (mut times, mut rows) = (vec![], vec![]);
+        for line in text.lines().skip(1) {
+            let values: Vec<f64> = line.split(',').map(|x| x.parse().unwrap()).collect();
+            times.push(values[0]);
+            rows.push(values[1..].to_vec());
+        }
+        let results: Vec<String> = config
+            .measure
+            .iter()
+            .map(|m| sim.measurement(m, &times, &rows))
+            .collect::<Result<_, _>>()
+            .map_err(|e| e.to_string())?;
+        let value = |k: usize| -> f64 { results[k].split(" = ").nth(1).unwrap().parse().unwrap() };
+        assert!(results[0].starts_with("rms(v2) = "));
+        assert!(approx_eq!(f64, value(0), 0.5 / 2f64.sqrt(), epsilon = 1e-6));
+        assert!(approx_eq!(f64, value(1), 1.0, epsilon = 1e-9));
+        assert!(value(2) < 1e-6);
+        // the divider halves the sine, so it crosses 0.25V later
+        let delay = |threshold: f64| {
+            let m = Measure::Delay {
+                from: ProbeRef::Name(String::from("v1")),
+                to: ProbeRef::Node(2),
+                threshold,
+            };
+            sim.measurement(&m, &times, &rows)
+                .map_err(|e| e.to_string())
+        };
+        let text = delay(0.25)?;
+        assert!(text.starts_with("delay(v1, v2) = "));
+        let d: f64 = text.split(" = ").nth(1).unwrap().parse().unwrap();
+        let w = 2.0 * std::f64::consts::PI * 1000.0;
+        let expected = (f64::asin(0.5) - f64::asin(0.25)) / w;
+        assert!(approx_eq!(f64, d, expected, epsilon = 1e-7));
+        assert_eq!(delay(0.75)?, "delay(v1, v2) = none");
+        // markers read every probe between steps
+        let m = Measure::Marker(0.255e-3);
+        let text = sim
+            .measurement(&m, &times, &rows)
+            .map_err(|e| e.to_string())?;
+        let v2 = 0.5 * (rows[24][1] + rows[25][1]);
+        assert_eq!(
+            text,
+            format!(
+                "marker(0.000255): v1 = {}, v2 = {}",
+                0.5 * (rows[24][0] + rows[25][0]),
+                v2
+            )
+        );
+        let m = Measure::Marker(1.0);
+        let err = sim.measurement(&m, &times, &rows);
+        assert!(matches!(err, Err(SimError::BadConfig(_))));
+        // only probed nodes can be measured
+        let m = Measure::Rms(ProbeRef::Node(0));
+        let err = sim.measurement(&m, &times, &rows);
+        assert!(matches!(err, Err(SimError::BadConfig(_))));
+        // missing pieces are reported
+        let err = RunConfig::from_json(r#"{ "analysis": { "type": "noise" } }"#);
+        assert!(matches!(err, Err(SimError::BadConfig(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jacobians() -> Result<(), String> {
+        // junction linearization across forward and reverse bias
+        let mut pn = JunctionPN::new(35.0e-12, 1.24);
+        for v in [-1.0, -0.1, 0.0, 0.3, 0.6, 0.7] {
+            pn.linearize(v);
+            let err = tangent_error((pn.geq, pn.ieq), pn.tangent(), v, pn.is);
+            assert!(err < 1e-6, "v = {}", v);
+        }
+        // diode and transistor at their operating points
+        let mut sim = Simulator::new(4);
+        sim.add_component(Box::new(VoltageSource::new(5.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Resistor::new(100e3, 1, 3)));
+        let params = BJTParameters::default();
+        sim.add_component(Box::new(Bjt::new(3, 2, 0, params)));
+        sim.add_component(Box::new(Zener::new(0, 1, ZenerParameters::default())));
+        sim.add_component(Box::new(Diode::new(2, 0, DiodeParameters::default())));
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        assert_eq!(sim.check_jacobians(1e-6), vec![]);
+        // a stamp that is off gets caught, diode geq is the second last variable
+        let n = sim.system.vars.len();
+        sim.system.vars[n - 2] *= 1.1;
+        let errors = sim.check_jacobians(1e-6);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 5);
+        assert!(errors[0].1 > 0.05);
+        // MOSFETs in the linear region and in saturation
+        let mut sim = Simulator::new(5);
+        sim.add_component(Box::new(VoltageSource::new(5.0, 1, 0)));
+        sim.add_component(Box::new(VoltageSource::new(3.0, 2, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 3)));
+        sim.add_component(Box::new(Resistor::new(10.0, 1, 4)));
+        let params = MosfetParameters::default();
+        sim.add_component(Box::new(Mosfet::new(1, 3, 0, params.clone())));
+        sim.add_component(Box::new(Mosfet::new(2, 4, 0, params.clone())));
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        let (v3, v4) = (sim.system.b[3].lu, sim.system.b[4].lu);
+        assert!(v3 < 5.0 - params.vt && v4 > 3.0 - params.vt);
+        assert_eq!(sim.check_jacobians(1e-6), vec![]);
+        // gm of the last MOSFET is its first dynamic variable
+        let n = sim.system.vars.len();
+        sim.system.vars[n - 4] *= 1.1;
+        let errors = sim.check_jacobians(1e-6);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 5);
+        assert!(errors[0].1 > 0.05);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resistor_inductance() -> Result<(), String> {
+        // 100 ohm with 10uH leads, corner near 1.6MHz
+        let (r, l) = (100.0, 10e-6);
+        let impedance = |with_l: bool, f: f64| -> Result<f64, String> {
+            let mut sim = Simulator::new(2);
+            let resistor = if with_l {
+                Resistor::with_inductance(r, l, 1, 0)
+            } else {
+                Resistor::new(r, 1, 0)
+            };
+            sim.add_component(Box::new(resistor));
+            sim.build_system();
+            let z = sim.impedance_at(1, 0, f).map_err(|e| e.to_string())?;
+            Ok(z.norm())
+        };
+        // well below the corner it is just a resistor
+        assert!(approx_eq!(f64, impedance(true, 1e3)?, r, epsilon = 1e-3));
+        // ideal stays flat
+        assert!(approx_eq!(f64, impedance(false, 100e6)?, r, epsilon = 1e-6));
+        // above the corner it rises
+        let w = 2.0 * std::f64::consts::PI * 100e6;
+        let expected = f64::hypot(r, w * l);
+        assert!(approx_eq!(
+            f64,
+            impedance(true, 100e6)?,
+            expected,
+            epsilon = 1e-6
+        ));
+        assert!(impedance(true, 10e6)? > 3.0 * r);
+        assert!(impedance(true, 100e6)? > 5.0 * impedance(true, 10e6)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tagged_nodes() -> Result<(), String> {
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageFunction::from_closure(step_5v, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Capacitor::new(1e-6, 2, 0)));
+        sim.build_system();
+        sim.tag_node(2);
+        sim.tag_node(1);
+        sim.tag_node(2);
+        let rows = sim.run_transient(1e-3, 1e-4).map_err(|e| e.to_string())?;
+        assert_eq!(rows.len(), 10);
+        assert!(rows.iter().all(|row| row.len() == 2));
+        // in tag order, capacitor charging from the source
+        let last = &rows[9];
+        assert_eq!(last[1], 5.0);
+        assert!(last[0] > 0.0 && last[0] < 5.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_symbolic_divider() -> Result<(), String> {
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageSource::new(5.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Resistor::new(2e3, 2, 0)));
+        sim.build_system();
+        assert_eq!(
+            sim.system.symbolic_node(2),
+            Some(String::from("v2 = 5V * R2k/(R1k+R2k)"))
+        );
+        assert_eq!(
+            sim.system.symbolic_node(1),
+            Some(String::from("v1 = 5V * 1"))
+        );
+        // still the same after numeric solving pivoted the rows
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        assert_eq!(
+            sim.system.symbolic_node(2),
+            Some(String::from("v2 = 5V * R2k/(R1k+R2k)"))
+        );
+        // capacitors are not supported
+        sim.add_component(Box::new(Capacitor::new(1e-6, 2, 0)));
+        sim.build_system();
+        assert_eq!(sim.system.symbolic_node(2), None);
+        // a run config writes the formula of each probe
+        let output = std::env::temp_dir().join("circuit_test_symbolic.txt");
+        let output = output.to_str().ok_or("bad temp path")?;
+        let json = format!(
+            r#"{{
+                "analysis": {{ "type": "symbolic" }},
+                "stimulus": [ {{ "node": 1, "dc": 5.0 }} ],
+                "probes": [ 1, 2 ],
+                "output": "{}"
+            }}"#,
+            output
+        );
+        let config = RunConfig::from_json(&json).map_err(|e| e.to_string())?;
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Resistor::new(2e3, 2, 0)));
+        sim.run_config(&config).map_err(|e| e.to_string())?;
+        let text = std::fs::read_to_string(output).map_err(|e| e.to_string())?;
+        assert_eq!(text, "v1 = 5V * 1\nv2 = 5V * R2k/(R1k+R2k)\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_superposition() -> Result<(), String> {
+        // 5V and 2V sources mixed into node 3 through 1k and 2k, 3k to ground
+        let mut sim = Simulator::new(4);
+        sim.add_component(Box::new(VoltageSource::new(5.0, 1, 0)));
+        sim.add_component(Box::new(VoltageSource::new(2.0, 2, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 3)));
+        sim.add_component(Box::new(Resistor::new(2e3, 2, 3)));
+        sim.add_component(Box::new(Resistor::new(3e3, 3, 0)));
+        sim.build_system();
+        let result = sim.superposition(3).map_err(|e| e.to_string())?;
+        assert_eq!(result.contributions.len(), 2);
+        // each source through its divider with the other two resistors in parallel
+        let (g1, g2, g3) = (1.0 / 1e3, 1.0 / 2e3, 1.0 / 3e3);
+        assert!(approx_eq!(
+            f64,
+            result.contributions[0].1,
+            5.0 * g1 / (g1 + g2 + g3),
+            epsilon = 1e-9
+        ));
+        assert!(approx_eq!(
+            f64,
+            result.contributions[1].1,
+            2.0 * g2 / (g1 + g2 + g3),
+            epsilon = 1e-9
+        ));
+        assert!(approx_eq!(f64, result.sum(), result.total, epsilon = 1e-9));
+        // a run config writes the same for its stimulus
+        let output = std::env::temp_dir().join("circuit_test_superposition.csv");
+        let output = output.to_str().ok_or("bad temp path")?;
+        let json = format!(
+            r#"{{
+                "analysis": {{ "type": "superposition" }},
+                "stimulus": [ {{ "node": 1, "dc": 5.0 }}, {{ "node": 2, "dc": 2.0 }} ],
+                "probes": [ 3 ],
+                "output": "{}"
+            }}"#,
+            output
+        );
+        let config = RunConfig::from_json(&json).map_err(|e| e.to_string())?;
+        let mut sim = Simulator::new(4);
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 3)));
+        sim.add_component(Box::new(Resistor::new(2e3, 2, 3)));
+        sim.add_component(Box::new(Resistor::new(3e3, 3, 0)));
+        sim.run_config(&config).map_err(|e| e.to_string())?;
+        let text = std::fs::read_to_string(output).map_err(|e| e.to_string())?;
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "source,v(3)");
+        for (line, (name, v)) in lines[1..].iter().zip([
+            ("3", result.contributions[0].1),
+            ("4", result.contributions[1].1),
+            ("sum", result.sum()),
+            ("total", result.total),
+        ]) {
+            let (source, value) = line.split_once(',').ok_or("no value")?;
+            assert_eq!(source, name);
+            assert!(approx_eq!(f64, value.parse().unwrap(), v, epsilon = 1e-9));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_dc_sweep() -> Result<(), String> {
+        // source straight across a diode without series resistance
+        let params = DiodeParameters {
+            rs: 0.0,
+            ..DiodeParameters::default()
+        };
+        let mut sim = Simulator::new(2);
+        sim.add_component(Box::new(VoltageSource::new(0.0, 1, 0)));
+        sim.add_component(Box::new(Diode::new(1, 0, params.clone())));
+        sim.build_system();
+        let curve = sim
+            .run_dc_sweep(0, 0.3, 0.6, 0.05)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(curve.len(), 7);
+        assert!(approx_eq!(f64, curve[6].0, 0.6, epsilon = 1e-12));
+        // source current at row 2, growing by the same factor every step
+        let ratio = (0.05 / (params.n * V_THERMAL)).exp();
+        for pair in curve.windows(2) {
+            let (a, b) = (pair[0].1[2], pair[1].1[2]);
+            assert!(
+                approx_eq!(f64, b / a, ratio, epsilon = 1e-3 * ratio),
+                "{} {}",
+                a,
+                b
+            );
+        }
+        assert!(approx_eq!(f64, sim.system.b[1].lu, 0.6, epsilon = 1e-9));
+        // resistors can't be swept, and the step has to head for stop
+        assert!(sim.run_dc_sweep(1, 0.0, 1.0, 0.1).is_err());
+        assert!(sim.run_dc_sweep(0, 0.0, 1.0, -0.1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dc_sweep2() -> Result<(), String> {
+        // base current into node 1, collector held by a source on node 2
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(CurrentSource::new(0.0, 1, 0)));
+        sim.add_component(Box::new(VoltageSource::new(0.0, 2, 0)));
+        sim.add_component(Box::new(Bjt::new(1, 2, 0, BJTParameters::default())));
+        sim.build_system();
+        let curves = sim
+            .run_dc_sweep2(0, (10e-6, 30e-6, 10e-6), 1, (1.0, 5.0, 1.0))
+            .map_err(|e| e.to_string())?;
+        assert_eq!(curves.len(), 3);
+        // collector current is the source branch current at row 3
+        let ic: Vec<Vec<f64>> = curves
+            .iter()
+            .map(|(_, curve)| curve.iter().map(|(_, x)| x[3]).collect())
+            .collect();
+        for curve in &ic {
+            assert_eq!(curve.len(), 5);
+            assert!(
+                curve.windows(2).all(|w| w[1] >= w[0] - 1e-12),
+                "{:?}",
+                curve
+            );
+        }
+        // curves are bf times the base current step apart
+        let spacing = BJTParameters::default().bf * 10e-6;
+        for pair in ic.windows(2) {
+            for (a, b) in pair[0].iter().zip(&pair[1]) {
+                assert!(
+                    approx_eq!(f64, b - a, spacing, epsilon = 0.02 * spacing),
+                    "{} {}",
+                    a,
+                    b
+                );
+            }
+        }
+        assert!(
+            sim.run_dc_sweep2(0, (0.0, 1e-5, 1e-6), 1, (5.0, 1.0, 1.0))
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_noise() -> Result<(), String> {
+        // 10k from node 1 to ground, then 10nF across it
+        let r = 10e3;
+        let mut sim = Simulator::new(2);
+        sim.add_component(Box::new(Resistor::new(r, 1, 0)));
+        sim.build_system();
+        let johnson = (4.0 * Q_ELECTRON * V_THERMAL * r).sqrt();
+        let points = sim
+            .run_noise(1, 10.0, 100e3, 1)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(points.len(), 5);
+        for (f, v) in &points {
+            assert!(
+                approx_eq!(f64, *v, johnson, epsilon = 1e-3 * johnson),
+                "{} {}",
+                f,
+                v
+            );
+        }
+        // about 12.9nV/sqrt(Hz) at room temperature
+        assert!(johnson > 12e-9 && johnson < 14e-9, "{}", johnson);
+        let mut sim = Simulator::new(2);
+        sim.add_component(Box::new(Resistor::new(r, 1, 0)));
+        sim.add_component(Box::new(Capacitor::new(10e-9, 1, 0)));
+        sim.build_system();
+        // capacitor shunts the noise above the corner
+        let f = 10.0 / (2.0 * std::f64::consts::PI * r * 10e-9);
+        let points = sim.run_noise(1, f, f, 1).map_err(|e| e.to_string())?;
+        let expected = johnson / (1.0f64 + 100.0).sqrt();
+        assert!(approx_eq!(
+            f64,
+            points[0].1,
+            expected,
+            epsilon = 1e-3 * expected
+        ));
+        // 1mA into a diode, shot noise across its small signal resistance
+        let params = DiodeParameters {
+            rs: 0.0,
+            ..DiodeParameters::default()
+        };
+        let mut sim = Simulator::new(2);
+        sim.add_component(Box::new(CurrentSource::new(1e-3, 1, 0)));
+        sim.add_component(Box::new(Diode::new(1, 0, params.clone())));
+        sim.build_system();
+        let points = sim.run_noise(1, 1e3, 1e3, 1).map_err(|e| e.to_string())?;
+        let expected = (2.0 * Q_ELECTRON * 1e-3).sqrt() * params.n * V_THERMAL / 1e-3;
+        assert!(approx_eq!(
+            f64,
+            points[0].1,
+            expected,
+            epsilon = 1e-2 * expected
+        ));
+        // sweeps that would never end are refused up front
+        for (f_start, f_stop, ppd) in [
+            (0.0, 1e3, 1),
+            (-10.0, 1e3, 1),
+            (1e3, 10.0, 1),
+            (10.0, 1e3, 0),
+        ] {
+            let err = sim.run_noise(1, f_start, f_stop, ppd).unwrap_err();
+            assert!(matches!(err, SimError::BadConfig(_)), "{:?}", err);
+        }
+        assert!(log_frequencies(10.0, f64::INFINITY, 1).is_err());
+        assert_eq!(
+            log_frequencies(10.0, 1e3, 2)
+                .map_err(|e| e.to_string())?
+                .len(),
+            5
+        );
+        assert_eq!(
+            log_frequencies(10.0, 500.0, 1).map_err(|e| e.to_string())?,
+            vec![10.0, 100.0]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_behavioral_source() -> Result<(), String> {
+        // tanh soft clipper from node 1 to node 2, loaded by 1k
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageSource::new(0.0, 1, 0)));
+        sim.add_component(Box::new(BehavioralSource::new(
+            |x| x[0].tanh(),
+            vec![1],
+            2,
+            0,
+        )));
+        sim.add_component(Box::new(Resistor::new(1e3, 2, 0)));
+        sim.build_system();
+        let curve = sim
+            .run_dc_sweep(0, -5.0, 5.0, 0.5)
+            .map_err(|e| e.to_string())?;
+        for (v_in, x) in &curve {
+            assert!(
+                approx_eq!(f64, x[2], v_in.tanh(), epsilon = 1e-6),
+                "{} {}",
+                v_in,
+                x[2]
+            );
+        }
+        // small signals pass, large ones saturate just short of 1V
+        assert!(approx_eq!(
+            f64,
+            curve[11].1[2],
+            0.5f64.tanh(),
+            epsilon = 1e-6
+        ));
+        assert!(curve[20].1[2] > 0.999 && curve[20].1[2] < 1.0);
+        assert!(curve[0].1[2] < -0.999 && curve[0].1[2] > -1.0);
+        // stamped slope and offset match tanh, and a bad slope is caught
+        sim.run_dc_sweep(0, 0.0, 0.5, 0.5)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(sim.check_jacobians(1e-6), vec![]);
+        let n = sim.system.vars.len();
+        sim.system.vars[n - 2] *= 1.1;
+        let errors = sim.check_jacobians(1e-6);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 1);
+        assert!(errors[0].1 > 0.01);
+        // ring modulator multiplying two inputs
+        let mut sim = Simulator::new(4);
+        sim.add_component(Box::new(VoltageSource::new(2.0, 1, 0)));
+        sim.add_component(Box::new(VoltageSource::new(-3.0, 2, 0)));
+        sim.add_component(Box::new(BehavioralSource::new(
+            |x| x[0] * x[1],
+            vec![1, 2],
+            3,
+            0,
+        )));
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        assert!(approx_eq!(f64, sim.system.b[3].lu, -6.0, epsilon = 1e-6));
+        // waveshapers from the JSON model, clipping 10V at 2V
+        for (shape, expected) in [("tanh", 2.0 * 5f64.tanh()), ("clip", 2.0)] {
+            let json = format!(
+                r#"{{
+                    "nets": ["GND", "in", "out"],
+                    "components": [
+                        {{ "type": "VoltageSource", "v": 10.0, "nets": [1, 0] }},
+                        {{ "type": "Waveshaper", "shape": "{}", "gain": 1.0, "level": 2.0,
+                           "nets": [2, 0, 1, 0] }}
+                    ]
+                }}"#,
+                shape
+            );
+            let model = Model::from_json(&json).map_err(|e| e.to_string())?;
+            let mut sim = model.build().map_err(|e| e.to_string())?;
+            assert_eq!(sim.model().map_err(|e| e.to_string())?, model);
+            sim.build_system();
+            sim.solve_dc().map_err(|e| e.to_string())?;
+            assert!(approx_eq!(
+                f64,
+                sim.system.b[2].lu,
+                expected,
+                epsilon = 1e-6
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_series_sources() -> Result<(), String> {
+        // two 5V sources stacked on node 2, loaded by a 1k/1k divider
+        let build = || {
+            let mut sim = Simulator::new(4);
+            sim.add_component(Box::new(VoltageSource::new(5.0, 1, 2)));
+            sim.add_component(Box::new(VoltageSource::new(5.0, 2, 0)));
+            sim.add_component(Box::new(Resistor::new(1e3, 1, 3)));
+            sim.add_component(Box::new(Resistor::new(1e3, 3, 0)));
+            sim
+        };
+        let mut plain = build();
+        plain.build_system();
+        plain.solve_dc().map_err(|e| e.to_string())?;
+        // loading the model merges the stack into one source
+        let mut merged = build()
+            .model()
+            .map_err(|e| e.to_string())?
+            .build()
+            .map_err(|e| e.to_string())?;
+        assert_eq!(merged.components.len(), 3);
+        let source = merged.components[0]
+            .voltage_source()
+            .ok_or("not a source")?;
+        assert_eq!((source.v, source.l0, source.l1), (10.0, 1, 0));
+        assert_eq!(merged.system.nodes.len(), plain.system.nodes.len() - 1);
+        merged.build_system();
+        merged.solve_dc().map_err(|e| e.to_string())?;
+        for n in 0..4 {
+            assert!(approx_eq!(
+                f64,
+                merged.system.b[n].lu,
+                plain.system.b[n].lu,
+                epsilon = 1e-9
+            ));
+        }
+        assert!(approx_eq!(f64, merged.system.b[2].lu, 5.0, epsilon = 1e-9));
+        // nothing left to merge
+        assert_eq!(merged.merge_series_sources(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_round_trip() -> Result<(), String> {
+        // diode clipper driving a transistor follower
+        let mut sim = Simulator::new(5);
+        sim.add_component(Box::new(VoltageSource::new(5.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Diode::new(2, 0, DiodeParameters::default())));
+        sim.add_component(Box::new(Capacitor::new(1e-6, 2, 0)));
+        sim.add_component(Box::new(VoltageSource::new(9.0, 3, 0)));
+        sim.add_component(Box::new(Bjt::new(2, 3, 4, BJTParameters::default())));
+        sim.add_component(Box::new(Resistor::with_inductance(1e3, 1e-6, 4, 0)));
+        sim.add_component(Box::new(VoltageControlledSwitch::new(1, 0, 2.5, 0.5, 3, 4)));
+        let model = sim.model().map_err(|e| e.to_string())?;
+        let json = model.to_json().map_err(|e| e.to_string())?;
+        let reloaded = Model::from_json(&json).map_err(|e| e.to_string())?;
+        assert_eq!(reloaded, model);
+        assert_eq!(reloaded.nets[4], "v4");
+        let mut copy = reloaded.build().map_err(|e| e.to_string())?;
+        assert_eq!(copy.model().map_err(|e| e.to_string())?, model);
+        for s in [&mut sim, &mut copy] {
+            s.build_system();
+            s.solve_dc().map_err(|e| e.to_string())?;
+        }
+        assert_eq!(copy.system.nodes.len(), sim.system.nodes.len());
+        for (a, b) in copy.system.solution().iter().zip(sim.system.solution()) {
+            assert!(approx_eq!(f64, *a, b, epsilon = 1e-9));
+        }
+        // nets outside the model are caught before building
+        let mut bad = Model::from_json(&json).map_err(|e| e.to_string())?;
+        bad.components
+            .push(ModelComponent::Ammeter { nets: [4, 7] });
+        assert!(matches!(bad.build(), Err(SimError::BadModel(_))));
+        // a run config writes the model with its stimulus, no probes needed
+        let output = std::env::temp_dir().join("circuit_test_model_export.json");
+        let output = output.to_str().ok_or("bad temp path")?;
+        let config = RunConfig::from_json(&format!(
+            r#"{{
+                "analysis": {{ "type": "model" }},
+                "stimulus": [ {{ "node": 1, "dc": 5.0 }} ],
+                "output": "{}"
+            }}"#,
+            output
+        ))
+        .map_err(|e| e.to_string())?;
+        let mut divider = Simulator::new(3);
+        divider.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        divider.add_component(Box::new(Resistor::new(1e3, 2, 0)));
+        divider.run_config(&config).map_err(|e| e.to_string())?;
+        let text = std::fs::read_to_string(output).map_err(|e| e.to_string())?;
+        let exported = Model::from_json(&text).map_err(|e| e.to_string())?;
+        assert_eq!(exported.components.len(), 3);
+        let mut copy = exported.build().map_err(|e| e.to_string())?;
+        copy.build_system();
+        copy.solve_dc().map_err(|e| e.to_string())?;
+        assert!(approx_eq!(
+            f64,
+            copy.system.solution()[2],
+            2.5,
+            epsilon = 1e-9
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_defaults() -> Result<(), String> {
+        // device parameters can be left out, or given in part
+        let model = Model::from_json(
+            r#"{
+                "nets": ["GND", "VCC", "b", "e"],
+                "components": [
+                    { "type": "Diode", "nets": [2, 0] },
+                    { "type": "BJT", "nets": [2, 1, 3], "params": { "transistor_type": "PNP" } }
+                ]
+            }"#,
+        )
+        .map_err(|e| e.to_string())?;
+        assert_eq!(
+            model.components[0],
+            ModelComponent::Diode {
+                nets: [2, 0],
+                params: DiodeParameters::default()
+            }
+        );
+        assert_eq!(
+            model.components[1],
+            ModelComponent::Bjt {
+                nets: [2, 1, 3],
+                params: BJTParameters {
+                    transistor_type: TransistorType::Pnp,
+                    ..BJTParameters::default()
+                }
+            }
+        );
+        // net names from the model are kept by the simulator
+        let mut sim = model.build().map_err(|e| e.to_string())?;
+        sim.build_system();
+        let names: Vec<&str> = sim.system.nodes[..4]
+            .iter()
+            .map(|n| n.name.as_str())
+            .collect();
+        assert_eq!(names, ["GND", "VCC", "b", "e"]);
+        assert_eq!(sim.model().map_err(|e| e.to_string())?.nets, model.nets);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bjt_rolloff() -> Result<(), String> {
+        // common emitter amp, 1k source and 1k collector resistor
+        let amp = |params: BJTParameters| -> Result<(f64, f64), String> {
+            let mut sim = Simulator::new(6);
+            sim.add_component(Box::new(VoltageSource::new(9.0, 1, 0)));
+            sim.add_component(Box::new(VoltageSource::with_ac(0.0, 1.0, 2, 0)));
+            sim.add_component(Box::new(Capacitor::new(10e-6, 2, 3)));
+            sim.add_component(Box::new(Resistor::new(1e3, 3, 4)));
+            sim.add_component(Box::new(Resistor::new(430e3, 1, 4)));
+            sim.add_component(Box::new(Resistor::new(1e3, 1, 5)));
+            sim.add_component(Box::new(Bjt::new(4, 5, 0, params)));
+            sim.build_system();
+            let res = sim.run_ac(1e3, 1e8, 1).map_err(|e| e.to_string())?;
+            let gain = |i: usize| res[i].1[5].norm();
+            Ok((gain(0), gain(5)))
+        };
+        let (low, high) = amp(BJTParameters::default())?;
+        assert!(low > 20.0, "gain {}", low);
+        // junction capacitances roll off the gain at 100MHz
+        assert!(high < 0.1 * low, "gain {} at 1kHz, {} at 100MHz", low, high);
+        // without them it would stay flat
+        let (flat_low, flat_high) = amp(BJTParameters {
+            cje: 0.0,
+            cjc: 0.0,
+            tf: 0.0,
+            tr: 0.0,
+            ..BJTParameters::default()
+        })?;
+        assert!(approx_eq!(f64, flat_low, low, epsilon = 1e-3 * low));
+        assert!(approx_eq!(f64, flat_high, low, epsilon = 1e-2 * low));
+        Ok(())
+    }
+
+    #[test]
+    fn test_mosfet_saturation() -> Result<(), String> {
+        // gate at 3V, drain at 5V, so vds > vgs - vt
+        let params = MosfetParameters::default();
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageSource::new(3.0, 1, 0)));
+        sim.add_component(Box::new(VoltageSource::new(5.0, 2, 0)));
+        sim.add_component(Box::new(Mosfet::new(1, 2, 0, params.clone())));
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        let names = sim.recorded_names();
+        let current = names
+            .iter()
+            .position(|n| n == "i:M:1,2,0")
+            .ok_or("no drain current")?;
+        let vov: f64 = 3.0 - params.vt;
+        let expected = 0.5 * params.kp * vov.powi(2) * (1.0 + params.lambda * 5.0);
+        let solution = sim.system.solution();
+        assert!(approx_eq!(f64, solution[current], expected, epsilon = 1e-9));
+        // PMOS mirror image: source at 5V, gate 3V below it, drain at 0V
+        let params = MosfetParameters {
+            mosfet_type: MosfetType::Pmos,
+            ..MosfetParameters::default()
+        };
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageSource::new(2.0, 1, 0)));
+        sim.add_component(Box::new(VoltageSource::new(5.0, 2, 0)));
+        sim.add_component(Box::new(Mosfet::new(1, 0, 2, params)));
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        let current = sim
+            .recorded_names()
+            .iter()
+            .position(|n| n == "i:M:1,0,2")
+            .ok_or("no drain current")?;
+        assert!(approx_eq!(
+            f64,
+            sim.system.solution()[current],
+            -expected,
+            epsilon = 1e-9
+        ));
+        // linearization matches the device equation across regions
+        for (vgs, vds) in [(3.0, 0.5), (3.0, -0.5), (1.0, 2.0), (4.0, 6.0)] {
+            let params = MosfetParameters::default();
+            let (i, gm, gds) = params.drain_current(vgs, vds, G_MIN);
+            let h = 1e-6;
+            let (i_g, _, _) = params.drain_current(vgs + h, vds, G_MIN);
+            let (i_d, _, _) = params.drain_current(vgs, vds + h, G_MIN);
+            assert!(approx_eq!(f64, (i_g - i) / h, gm, epsilon = 1e-4));
+            assert!(approx_eq!(f64, (i_d - i) / h, gds, epsilon = 1e-4));
+        }
+        // leakage in cutoff follows the g_min option
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageSource::new(0.0, 1, 0)));
+        sim.add_component(Box::new(VoltageSource::new(5.0, 2, 0)));
+        sim.add_component(Box::new(Mosfet::new(1, 2, 0, MosfetParameters::default())));
+        sim.options.g_min = 1e-6;
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        let current = sim
+            .recorded_names()
+            .iter()
+            .position(|n| n == "i:M:1,2,0")
+            .ok_or("no drain current")?;
+        let leak = sim.system.solution()[current];
+        assert!(approx_eq!(f64, leak, 5e-6, epsilon = 1e-12));
+        Ok(())
+    }
+
+    #[test]
+    fn test_current_source() -> Result<(), String> {
+        // 2mA into 1k gives 2V, no extra net needed
+        let mut sim = Simulator::new(2);
+        sim.add_component(Box::new(CurrentSource::new(2e-3, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 0)));
+        sim.build_system();
+        assert_eq!(sim.system.nodes.len(), 2);
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        assert!(approx_eq!(
+            f64,
+            sim.system.solution()[1],
+            2.0,
+            epsilon = 1e-9
+        ));
+        // sources add up, and scaling only changes each one's own share
+        let mut sim = Simulator::new(2);
+        sim.add_component(Box::new(CurrentSource::new(2e-3, 1, 0)));
+        sim.add_component(Box::new(CurrentSource::new(1e-3, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 0)));
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        assert!(approx_eq!(
+            f64,
+            sim.system.solution()[1],
+            3.0,
+            epsilon = 1e-9
+        ));
+        sim.scale_sources(0.5);
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        assert!(approx_eq!(
+            f64,
+            sim.system.solution()[1],
+            1.5,
+            epsilon = 1e-9
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_opamp_non_inverting() -> Result<(), String> {
+        // 0.5V in, Rf = 10k, Rg = 1k gives gain 1 + Rf/Rg = 11
+        let (rf, rg) = (10e3, 1e3);
+        let mut sim = Simulator::new(4);
+        sim.add_component(Box::new(VoltageSource::new(0.5, 1, 0)));
+        sim.add_component(Box::new(OpAmp::new(1, 3, 2)));
+        sim.add_component(Box::new(Resistor::new(rf, 2, 3)));
+        sim.add_component(Box::new(Resistor::new(rg, 3, 0)));
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        let v = sim.system.solution();
+        assert!(approx_eq!(f64, v[2], 0.5 * (1.0 + rf / rg), epsilon = 1e-9));
+        // virtual short between the inputs
+        assert!(approx_eq!(f64, v[3], v[1], epsilon = 1e-12));
+        Ok(())
+    }
+
+    #[test]
+    fn test_opamp_comparator() -> Result<(), String> {
+        // open loop against a 2.5V reference, on a 0..5V supply
+        let params = OpAmpParameters {
+            vcc: 5.0,
+            vee: 0.0,
+            ..OpAmpParameters::default()
+        };
+        for (vin, expected) in [(2.0, 0.0), (2.499, 0.0), (2.501, 5.0), (3.0, 5.0)] {
+            let mut sim = Simulator::new(4);
+            sim.add_component(Box::new(VoltageSource::new(vin, 1, 0)));
+            sim.add_component(Box::new(VoltageSource::new(2.5, 2, 0)));
+            sim.add_component(Box::new(OpAmp::with_params(1, 2, 3, params.clone())));
+            sim.add_component(Box::new(Resistor::new(10e3, 3, 0)));
+            sim.build_system();
+            sim.solve_dc().map_err(|e| e.to_string())?;
+            assert_eq!(sim.system.solution()[3], expected);
+        }
+        // with feedback it stays linear, follower gain is A / (1 + A)
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageSource::new(1.0, 1, 0)));
+        sim.add_component(Box::new(OpAmp::with_params(1, 2, 2, params.clone())));
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        let a = params.gain;
+        assert!(approx_eq!(
+            f64,
+            sim.system.solution()[2],
+            a / (1.0 + a),
+            epsilon = 1e-12
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_schottky_forward_voltage() -> Result<(), String> {
+        // forward voltage at 10mA, driven by a current source
+        let forward_voltage = |params: DiodeParameters| -> Result<f64, String> {
+            let mut sim = Simulator::new(2);
+            sim.add_component(Box::new(CurrentSource::new(10e-3, 1, 0)));
+            sim.add_component(Box::new(Diode::new(1, 0, params)));
+            sim.build_system();
+            sim.solve_dc().map_err(|e| e.to_string())?;
+            Ok(sim.system.solution()[1])
+        };
+        let schottky = forward_voltage(DiodeParameters::schottky())?;
+        let silicon = forward_voltage(DiodeParameters::default())?;
+        assert!(
+            schottky > 0.25 && schottky < 0.4,
+            "Schottky at {}",
+            schottky
+        );
+        assert!(silicon - schottky > 0.3, "1N4148 at {}", silicon);
+        Ok(())
+    }
+
+    #[test]
+    fn test_led_brightness() -> Result<(), String> {
+        // red LED through 150R from 5V, close to its nominal 20mA
+        let params = LedParameters::new(LedColor::Red);
+        let mut brightness = vec![];
+        for r in [150.0, 330.0] {
+            let mut sim = Simulator::new(3);
+            sim.add_component(Box::new(VoltageSource::new(5.0, 1, 0)));
+            sim.add_component(Box::new(Resistor::new(r, 1, 2)));
+            sim.add_component(Box::new(Led::new(2, 0, params.clone())));
+            sim.build_system();
+            sim.solve_dc().map_err(|e| e.to_string())?;
+            let names = sim.recorded_names();
+            let find = |name: &str| names.iter().position(|n| n == name).ok_or(name.to_string());
+            let (current, lum) = (find("i:LED:2,0")?, find("lum:LED:2,0")?);
+            let v = sim.system.solution();
+            let i = (5.0 - v[2]) / r;
+            assert!(approx_eq!(f64, v[current], i, epsilon = 1e-9));
+            if r == 150.0 {
+                assert!(v[2] > 1.7 && v[2] < 1.9, "forward voltage {}", v[2]);
+                assert!(i > 18e-3 && i < 24e-3, "current {}", i);
+            }
+            // relative brightness follows the current
+            let info = &sim.system.nodes[lum];
+            let relative = v[lum] * info.scale;
+            assert!(approx_eq!(
+                f64,
+                relative,
+                i / params.i_nominal,
+                epsilon = 1e-9
+            ));
+            brightness.push(relative);
+        }
+        assert!(brightness[1] < 0.6 * brightness[0]);
+        // bluer LEDs need more voltage
+        assert!(LedParameters::new(LedColor::Blue).vf() > LedParameters::new(LedColor::Green).vf());
+        Ok(())
+    }
+
+    #[test]
+    fn test_zener_clamp() -> Result<(), String> {
+        // 12V through 1k into the cathode, about 7mA of breakdown current
+        let params = ZenerParameters::default();
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageSource::new(12.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Zener::new(0, 2, params.clone())));
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        let v = sim.system.solution()[2];
+        assert!(v > params.vz && v < params.vz + 0.2, "clamped at {}", v);
+        assert!(sim.check_jacobians(1e-3).is_empty());
+        // forward biased it is a plain diode
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageSource::new(12.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Zener::new(2, 0, params)));
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        let v = sim.system.solution()[2];
+        assert!(v > 0.5 && v < 1.0, "forward voltage {}", v);
+        Ok(())
+    }
+
+    #[test]
+    fn test_vcvs_gain() -> Result<(), String> {
+        // gain of 5 from a 0.3V input, output holds up under load
+        for load in [100.0, 10e3] {
+            let mut sim = Simulator::new(3);
+            sim.add_component(Box::new(VoltageSource::new(0.3, 1, 0)));
+            sim.add_component(Box::new(Resistor::new(10e3, 1, 0)));
+            sim.add_component(Box::new(Vcvs::new(5.0, 1, 0, 2, 0)));
+            sim.add_component(Box::new(Resistor::new(load, 2, 0)));
+            sim.build_system();
+            sim.solve_dc().map_err(|e| e.to_string())?;
+            assert!(approx_eq!(
+                f64,
+                sim.system.solution()[2],
+                1.5,
+                epsilon = 1e-12
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_vccs_transconductor() -> Result<(), String> {
+        // 1mS from a 2V control, pulled from ground into a 4.7k load
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageSource::new(2.0, 1, 0)));
+        sim.add_component(Box::new(Vccs::new(1e-3, 1, 0, 0, 2)));
+        sim.add_component(Box::new(Resistor::new(4.7e3, 2, 0)));
+        sim.build_system();
+        assert_eq!(sim.system.nodes.len(), 4);
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        assert!(approx_eq!(
+            f64,
+            sim.system.solution()[2],
+            9.4,
+            epsilon = 1e-9
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_current_controlled_sources() -> Result<(), String> {
+        // 5mA sensed between 1 and 2, CCCS mirrors it twice into 3,
+        // CCVS turns it into 2.5V at 4
+        let mut sim = Simulator::new(6);
+        sim.add_component(Box::new(VoltageSource::new(5.0, 1, 0)));
+        sim.add_component(Box::new(Cccs::new(2.0, 1, 2, 0, 3)));
+        sim.add_component(Box::new(Ccvs::new(500.0, 2, 5, 4, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 5, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 3, 0)));
+        sim.add_component(Box::new(Resistor::new(10e3, 4, 0)));
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        let v = sim.system.solution();
+        // two sense branches in series leave all 5V on the resistor
+        assert!(approx_eq!(f64, v[5], 5.0, epsilon = 1e-9));
+        assert!(approx_eq!(f64, v[3], 10.0, epsilon = 1e-9));
+        assert!(approx_eq!(f64, v[4], 2.5, epsilon = 1e-9));
+        let names = sim.recorded_names();
+        let sensed = names
+            .iter()
+            .position(|n| n == "i:F:1,2")
+            .ok_or("no sense")?;
+        assert!(approx_eq!(f64, v[sensed], 5e-3, epsilon = 1e-12));
+        Ok(())
+    }
+
+    #[test]
+    fn test_transformer() -> Result<(), String> {
+        // 1:2 step up from 1V into a 100 ohm load
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageSource::new(1.0, 1, 0)));
+        sim.add_component(Box::new(Transformer::new(2.0, (1, 0), (2, 0))));
+        sim.add_component(Box::new(Resistor::new(100.0, 2, 0)));
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        let v = sim.system.solution();
+        assert!(approx_eq!(f64, v[2], 2.0, epsilon = 1e-12));
+        // 20mA out of the secondary takes 40mA from the source
+        let names = sim.recorded_names();
+        let current = |prefix: &str| -> Result<f64, String> {
+            let i = names
+                .iter()
+                .position(|n| n.starts_with(prefix))
+                .ok_or(format!("no {}", prefix))?;
+            Ok(v[i].abs())
+        };
+        assert!(approx_eq!(f64, current("i:T")?, 20e-3, epsilon = 1e-12));
+        assert!(approx_eq!(f64, current("i:V")?, 40e-3, epsilon = 1e-12));
+        // coupled inductors with half the inductance as mutual
+        // inductance give half the voltage on the open winding
+        let mut sim = Simulator::new(4);
+        sim.add_component(Box::new(VoltageSource::with_ac(0.0, 1.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(10.0, 1, 2)));
+        sim.add_component(Box::new(CoupledInductors::new(
+            Inductor::new(1e-3, 2, 0),
+            Inductor::new(1e-3, 3, 0),
+            0.5e-3,
+        )));
+        sim.add_component(Box::new(Resistor::new(1e9, 3, 0)));
+        sim.build_system();
+        let res = sim.run_ac(1e3, 1e4, 1).map_err(|e| e.to_string())?;
+        for (_, x) in &res {
+            assert!(approx_eq!(f64, (x[3] / x[2]).re, 0.5, epsilon = 1e-6));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_banded_ladder() -> Result<(), String> {
+        // 40 section RC ladder driven by a 1V step
+        let sections = 40;
+        let mut sim = Simulator::new(sections + 2);
+        sim.add_component(Box::new(VoltageSource::new(1.0, 1, 0)));
+        for k in 1..=sections {
+            sim.add_component(Box::new(Resistor::new(1e3, k, k + 1)));
+            sim.add_component(Box::new(Capacitor::new(1e-8, k + 1, 0)));
+        }
+        sim.build_system();
+        sim.set_time_step(1e-6);
+        for _ in 0..20 {
+            sim.tick().map_err(|e| e.to_string())?;
+        }
+        let stats = &sim.system.stats;
+        assert_eq!(stats.general_solves, 0);
+        // the net order is planned in build_system and kept for every step
+        assert_eq!(stats.band_plans, 1);
+        assert!(matches!(stats.last_path, LuPath::Banded { lower, upper } if lower + upper < 8));
+        // the same system through both paths
+        sim.system.update_pre();
+        sim.system.solve().map_err(|e| e.to_string())?;
+        let banded = sim.system.solution();
+        sim.system.update_pre();
+        sim.system.lu_factor().map_err(|e| e.to_string())?;
+        sim.system.lu_forward();
+        sim.system.lu_solve();
+        for (a, b) in banded.iter().zip(sim.system.solution()).skip(1) {
+            assert!(approx_eq!(f64, *a, b, epsilon = 1e-9));
+        }
+        // charge has only spread partway down the ladder
+        assert!(banded[2] > banded[sections + 1]);
+        // small circuits keep using the general path
+        let mut small = Simulator::new(3);
+        small.add_component(Box::new(VoltageSource::new(5.0, 1, 0)));
+        small.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        small.add_component(Box::new(Resistor::new(1e3, 2, 0)));
+        small.build_system();
+        small.solve_dc().map_err(|e| e.to_string())?;
+        assert_eq!(small.system.stats.last_path, LuPath::General);
+        Ok(())
+    }
+
+    /// RC ladder driven by a 1V step, after `steps` time steps
+    fn rc_ladder(sections: usize, steps: usize) -> Result<Simulator, String> {
+        let mut sim = Simulator::new(sections + 2);
+        sim.add_component(Box::new(VoltageSource::new(1.0, 1, 0)));
+        for k in 1..=sections {
+            sim.add_component(Box::new(Resistor::new(1e3, k, k + 1)));
+            sim.add_component(Box::new(Capacitor::new(1e-8, k + 1, 0)));
+        }
+        sim.build_system();
+        sim.set_time_step(1e-6);
+        for _ in 0..steps {
+            sim.tick().map_err(|e| e.to_string())?;
+        }
+        Ok(sim)
+    }
+
+    /// Time to solve the current system by each path, as (sparse, banded, general)
+    ///
+    /// The general LU goes last since it pivots rows and adds fill, and is
+    /// left out (as None) for systems too big to factor densely in a test.
+    fn time_solve_paths(
+        system: &mut MNASystem,
+        general: bool,
+    ) -> Result<(Duration, Duration, Option<Duration>), String> {
+        let mut time = |solve: &mut dyn FnMut(&mut MNASystem) -> bool| {
+            let start = Instant::now();
+            system.update_pre();
+            solve(system).then(|| start.elapsed())
+        };
+        let sparse = time(&mut |s| s.solve_sparse().is_some()).ok_or("no sparse solve")?;
+        let banded = time(&mut |s| s.solve_banded().is_some()).ok_or("no banded solve")?;
+        let general = general
+            .then(|| {
+                time(&mut |s| {
+                    let factored = s.lu_factor().is_ok();
+                    s.lu_forward();
+                    s.lu_solve();
+                    factored
+                })
+                .ok_or("no general solve")
+            })
+            .transpose()?;
+        Ok((sparse, banded, general))
+    }
+
+    #[test]
+    fn test_sparse_ladder() -> Result<(), String> {
+        // 500 section RC ladder, far too big for the dense LU every step
+        let sections = 500;
+        let mut sim = rc_ladder(sections, 20)?;
+        let stats = &sim.system.stats;
+        assert_eq!(stats.general_solves + stats.banded_solves, 0);
+        assert!(stats.sparse_solves >= 20);
+        // fill in grows with the ladder, a dense LU fills the whole matrix
+        let n = sim.system.net_size;
+        assert!(matches!(stats.last_path, LuPath::Sparse { fill } if fill < 4 * n));
+        // same answer as the banded path
+        sim.system.update_pre();
+        sim.system.solve().map_err(|e| e.to_string())?;
+        let sparse = sim.system.solution();
+        sim.system.update_pre();
+        sim.system.solve_banded().ok_or("no banded solve")?;
+        for (a, b) in sparse.iter().zip(sim.system.solution()).skip(1) {
+            assert!(approx_eq!(f64, *a, b, epsilon = 1e-9));
+        }
+        assert!(sparse[2] > sparse[10] && sparse[10] > sparse[sections + 1]);
+        Ok(())
+    }
+
+    /// Benchmark, run with `cargo test --bin sim -- --ignored`
+    #[test]
+    #[ignore]
+    fn bench_solve_paths() -> Result<(), String> {
+        // sparse and banded on a big ladder, where the general LU takes
+        // tens of seconds, then all three on a smaller ladder
+        let mut sim = rc_ladder(500, 1)?;
+        let n = sim.system.net_size;
+        let (sparse, banded, _) = time_solve_paths(&mut sim.system, false)?;
+        println!("{} nets: sparse {:?}, banded {:?}", n, sparse, banded);
+        let mut sim = rc_ladder(100, 1)?;
+        let (sparse, banded, general) = time_solve_paths(&mut sim.system, true)?;
+        let general = general.ok_or("no general timing")?;
+        println!(
+            "{} nets: sparse {:?}, banded {:?}, general {:?}",
+            sim.system.net_size, sparse, banded, general
+        );
+        assert!(sparse < general && banded < general);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pivot_order_cache() -> Result<(), String> {
+        // RC ladder with a diode, big enough for the sparse path
+        let sections = 60;
+        let mut sim = Simulator::new(sections + 2);
+        sim.add_component(Box::new(VoltageFunction::sine(1.0, 1e3, 0.0, 1, 0)));
+        for k in 1..=sections {
+            sim.add_component(Box::new(Resistor::new(1e3, k, k + 1)));
+            sim.add_component(Box::new(Capacitor::new(1e-8, k + 1, 0)));
+        }
+        let params = DiodeParameters::default();
+        sim.add_component(Box::new(Diode::new(sections + 1, 0, params)));
+        sim.build_system();
+        assert!(sim.system.net_size >= SPARSE_MIN_SIZE);
+        sim.set_time_step(1e-5);
+        for _ in 0..50 {
+            sim.tick().map_err(|e| e.to_string())?;
+        }
+        // every step reused the first pivot order
+        let stats = &sim.system.stats;
+        assert!(stats.sparse_solves >= 50);
+        assert_eq!(stats.pivot_searches, 1);
+        // which is what a fresh search finds for the current values
+        // with the structure of A taken once
+        assert_eq!(stats.sparse_patterns, 1);
+        sim.system.update_sparse();
+        let pattern = sim.system.sparse_pattern.as_ref().ok_or("no pattern")?;
+        let fresh = pattern.matrix.factor().ok_or("singular")?;
+        let cached = sim.system.pivot_order.as_ref().ok_or("no pivot order")?;
+        assert_eq!(fresh.order(), cached);
+        // a different structure needs a new search
+        let smaller = {
+            let mut small = Simulator::new(sections + 1);
+            for k in 1..=sections {
+                small.add_component(Box::new(Resistor::new(1e3, k, k + 1)));
+                small.add_component(Box::new(Capacitor::new(1e-8, k + 1, 0)));
+            }
+            small.add_component(Box::new(VoltageSource::new(1.0, 1, 0)));
+            small.build_system();
+            small.system.init_lu(1e5);
+            small.system.update_pre();
+            small.system.update_sparse();
+            small.system.sparse_pattern.ok_or("no pattern")?.matrix
+        };
+        assert!(smaller.refactor(cached).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_monte_carlo_seed() -> Result<(), String> {
+        // divider of two 5% resistors
+        let divider = |rng: &mut Rng| {
+            let mut sim = Simulator::new(3);
+            sim.add_component(Box::new(VoltageSource::new(5.0, 1, 0)));
+            sim.add_component(Box::new(Resistor::new(rng.tolerance(1e3, 0.05), 1, 2)));
+            sim.add_component(Box::new(Resistor::new(rng.tolerance(1e3, 0.05), 2, 0)));
+            Ok(sim)
+        };
+        let run = |seed| -> Result<Vec<u64>, String> {
+            let v = monte_carlo(20, 2, &mut Rng::new(seed), divider).map_err(|e| e.to_string())?;
+            assert!(v.iter().all(|v| (2.375..=2.625).contains(v)));
+            Ok(v.iter().map(|v| v.to_bits()).collect())
+        };
+        assert_eq!(run(42)?, run(42)?);
+        assert_ne!(run(42)?, run(43)?);
+        // normal draws center on zero with unit spread
+        let mut rng = Rng::new(7);
+        let draws: Vec<f64> = (0..10000).map(|_| rng.normal()).collect();
+        let mean = draws.iter().sum::<f64>() / draws.len() as f64;
+        let var = draws.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / draws.len() as f64;
+        assert!(
+            mean.abs() < 0.05 && (var - 1.0).abs() < 0.05,
+            "{} {}",
+            mean,
+            var
+        );
+        // a run config draws its resistors from the seed
+        let output = std::env::temp_dir().join("circuit_test_monte_carlo.csv");
+        let output = output.to_str().ok_or("bad temp path")?;
+        let values = |distribution: &str| -> Result<Vec<f64>, String> {
+            let json = format!(
+                r#"{{
+                    "analysis": {{ "type": "monte_carlo", "runs": 20, "seed": 42,
+                                   "tolerance": 0.05, "distribution": "{}" }},
+                    "stimulus": [ {{ "node": 1, "dc": 5.0 }} ],
+                    "probes": [ 2 ],
+                    "output": "{}"
+                }}"#,
+                distribution, output
+            );
+            let config = RunConfig::from_json(&json).map_err(|e| e.to_string())?;
+            let mut sim = Simulator::new(3);
+            sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+            sim.add_component(Box::new(Resistor::new(1e3, 2, 0)));
+            sim.run_config(&config).map_err(|e| e.to_string())?;
+            let text = std::fs::read_to_string(output).map_err(|e| e.to_string())?;
+            let mut lines = text.lines();
+            assert_eq!(lines.next(), Some("run,v(2)"));
+            lines
+                .map(|line| {
+                    let (_, v) = line.split_once(',').ok_or("bad row")?;
+                    v.parse().map_err(|_| String::from("bad value"))
+                })
+                .collect()
+        };
+        let uniform = values("uniform")?;
+        assert_eq!(uniform.len(), 20);
+        assert!(uniform.iter().all(|v| (2.375..=2.625).contains(v)));
+        assert_eq!(uniform, values("uniform")?);
+        let normal = values("normal")?;
+        assert_ne!(normal, uniform);
+        assert!(normal.iter().all(|v| (2.3..=2.7).contains(v)));
+        assert!(values("triangle").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_variants() -> Result<(), String> {
+        // RC lowpass driven by a 5V step, output at node 2
+        let lowpass = |c: f64| {
+            let mut sim = Simulator::new(3);
+            sim.add_component(Box::new(VoltageFunction::from_closure(step_5v, 1, 0)));
+            sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+            sim.add_component(Box::new(Capacitor::new(c, 2, 0)));
+            sim.tag_node(2);
+            sim.build_system();
+            sim
+        };
+        let same = Comparison::transient(&mut lowpass(1e-6), &mut lowpass(1e-6), 5e-3, 1e-4)
+            .map_err(|e| e.to_string())?;
+        assert!(same.difference().iter().flatten().all(|d| *d == 0.0));
+        let differ = Comparison::transient(&mut lowpass(1e-6), &mut lowpass(2e-6), 5e-3, 1e-4)
+            .map_err(|e| e.to_string())?;
+        assert!(differ.difference().iter().flatten().any(|d| d.abs() > 0.1));
+        let times: Vec<f64> = (1..=50).map(|i| i as f64 * 1e-4).collect();
+        let csv = differ.to_csv("time", &times, &[String::from("v(2)")]);
+        assert!(csv.starts_with("time,a:v(2),b:v(2),diff:v(2)\n"));
+        assert_eq!(csv.lines().count(), 51);
+        // rows missing on one side, or short of names, leave empty fields
+        let ragged = Comparison {
+            a: vec![vec![1.0, 2.0], vec![3.0]],
+            b: vec![vec![1.5, 2.0]],
+        };
+        let names = [String::from("x"), String::from("y")];
+        let csv = ragged.to_csv("time", &[0.0, 1.0, 2.0], &names);
+        assert_eq!(csv.lines().nth(1), Some("0,1,2,1.5,2,-0.5,0"));
+        assert_eq!(csv.lines().nth(2), Some("1,3,,,,,"));
+        assert_eq!(csv.lines().count(), 3);
+        assert_eq!(ragged.to_csv("time", &[0.0], &names).lines().count(), 2);
+        // frequency responses too, driven by a unit AC source
+        let ac_lowpass = |c: f64| {
+            let mut sim = Simulator::new(3);
+            sim.add_component(Box::new(VoltageSource::with_ac(0.0, 1.0, 1, 0)));
+            sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+            sim.add_component(Box::new(Capacitor::new(c, 2, 0)));
+            sim.build_system();
+            sim
+        };
+        let same = Comparison::ac(&mut ac_lowpass(1e-6), &mut ac_lowpass(1e-6), 10.0, 1e4, 5)
+            .map_err(|e| e.to_string())?;
+        assert!(same.difference().iter().flatten().all(|d| *d == 0.0));
+        let differ = Comparison::ac(&mut ac_lowpass(1e-6), &mut ac_lowpass(2e-6), 10.0, 1e4, 5)
+            .map_err(|e| e.to_string())?;
+        assert!(differ.difference().iter().any(|d| d[2].abs() > 0.1));
+        // both variants through a run config, into one overlay file
+        let output = std::env::temp_dir().join("circuit_test_compare.csv");
+        let config = RunConfig::from_json(&format!(
+            r#"{{
+                "analysis": {{ "type": "ac", "f_start": 10, "f_stop": 1e4, "points_per_decade": 5 }},
+                "stimulus": [ {{ "node": 1, "ac": 1.0 }} ],
+                "probes": [ 2 ],
+                "output": {:?}
+            }}"#,
+            output
+        ))
+        .map_err(|e| e.to_string())?;
+        let rc = |c: f64| {
+            let mut sim = Simulator::new(3);
+            sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+            sim.add_component(Box::new(Capacitor::new(c, 2, 0)));
+            sim
+        };
+        Comparison::run_config(&mut rc(1e-6), &mut rc(2e-6), &config).map_err(|e| e.to_string())?;
+        let text = std::fs::read_to_string(&output).map_err(|e| e.to_string())?;
+        assert!(text.starts_with("freq,a:v(2),b:v(2),diff:v(2)\n"));
+        assert_eq!(text.lines().count(), 17);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stored_energy() -> Result<(), String> {
+        // 1uF charged to 5V through 1k, 1mH carrying 5mA
+        let mut sim = Simulator::new(4);
+        sim.add_component(Box::new(VoltageSource::new(5.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Capacitor::new(1e-6, 2, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 3)));
+        sim.add_component(Box::new(Inductor::new(1e-3, 3, 0)));
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        let energy = sim.stored_energy();
+        assert_eq!(energy[0].0, "C:2,0");
+        assert!(approx_eq!(f64, energy[0].1, 12.5e-6, epsilon = 1e-12));
+        assert_eq!(energy[1].0, "L:3,0");
+        assert!(approx_eq!(f64, energy[1].1, 12.5e-9, epsilon = 1e-15));
+        let dump = sim.operating_point();
+        assert!(dump.contains("v2 = 5.000000 V"));
+        assert!(dump.contains("E(C:2,0) = 1.2500e-5 J"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pot_taper() -> Result<(), String> {
+        let mut linear = Potentiometer::new(10e3, Taper::Linear, 0, 2, 1);
+        let mut log = Potentiometer::new(10e3, Taper::Log, 0, 2, 1);
+        linear.set_wiper(0.5);
+        log.set_wiper(0.5);
+        let (r0, r1) = linear.resistances();
+        assert!(approx_eq!(f64, r0, 5e3, epsilon = 1e-9));
+        assert!(approx_eq!(f64, r1, 5e3, epsilon = 1e-9));
+        let (r0, r1) = log.resistances();
+        assert!(approx_eq!(f64, r0, 1e3, epsilon = 1e-9));
+        assert!(approx_eq!(f64, r1, 9e3, epsilon = 1e-9));
+        // ends of travel are the same for both
+        log.set_wiper(1.0);
+        assert!(approx_eq!(f64, log.resistances().0, 10e3, epsilon = 1e-9));
+        // volume control, 1V in at l1 and ground at l0
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageSource::new(1.0, 1, 0)));
+        let mut volume = Potentiometer::new(10e3, Taper::Log, 0, 2, 1);
+        volume.set_wiper(0.5);
+        sim.add_component(Box::new(volume));
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        assert!(approx_eq!(f64, sim.system.b[2].lu, 0.1, epsilon = 1e-9));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pot_sweep() -> Result<(), String> {
+        // 10V across a 10k pot, 10k load from wiper to ground
+        let (total, load) = (10e3, 10e3);
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageSource::new(10.0, 1, 0)));
+        sim.add_component(Box::new(Potentiometer::new(total, Taper::Linear, 1, 2, 0)));
+        sim.add_component(Box::new(Resistor::new(load, 2, 0)));
+        sim.build_system();
+        for k in 0..=10 {
+            let wiper = k as f64 / 10.0;
+            assert!(sim.set_wiper(1, wiper));
+            sim.solve_dc().map_err(|e| e.to_string())?;
+            let top = f64::max(total * wiper, POT_R_MIN);
+            let bottom = f64::max(total * (1.0 - wiper), POT_R_MIN);
+            let lower = 1.0 / (1.0 / bottom + 1.0 / load);
+            let expected = 10.0 * lower / (top + lower);
+            assert!(approx_eq!(
+                f64,
+                sim.system.b[2].lu,
+                expected,
+                epsilon = 1e-9
+            ));
+        }
+        // other components have no wiper
+        assert!(!sim.set_wiper(2, 0.5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_switch() -> Result<(), String> {
+        // 10V over a 1k/1k divider, the switch adds another 1k below
+        let mut sim = Simulator::new(4);
+        sim.add_component(Box::new(VoltageSource::new(10.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Resistor::new(1e3, 2, 0)));
+        sim.add_component(Box::new(Switch::new(false, 2, 3)));
+        sim.add_component(Box::new(Resistor::new(1e3, 3, 0)));
+        sim.add_component(Box::new(Capacitor::new(1e-9, 2, 0)));
+        sim.tag_node(2);
+        sim.build_system();
+        for (closed, expected) in [(false, 5.0), (true, 10.0 / 3.0), (false, 5.0)] {
+            assert!(sim.set_switch(3, closed));
+            let rows = sim.run_transient(1e-4, 1e-6).map_err(|e| e.to_string())?;
+            // settles well within the run, the 1nF time constant is 0.5us
+            let last = rows.last().ok_or("no steps")?[0];
+            assert!(approx_eq!(f64, last, expected, epsilon = 1e-3), "{}", last);
         }
-        // external voltages to collector current
-        m.stamp_static(-1.0, l[2], pin[0], "-1");
-        m.stamp_static(1.0, l[2], pin[1], "+1");
-        // external voltages to emitter current
-        m.stamp_static(-1.0, l[3], pin[0], "-1");
-        m.stamp_static(1.0, l[3], pin[2], "+1");
-        // source transfer currents to external pins
-        m.stamp_static(params.ar(), pin[2], l[2], "+ar");
-        m.stamp_static(params.af(), pin[1], l[3], "+af");
-        // dynamic variables
-        m.add_dynamic_a(l[0], l[0], dyn_pnc_geq, &format!("gm:Qbc"));
-        m.add_dynamic_b(
-            l[0],
-            dyn_pnc_ieq,
-            &format!("i0:Q:{},{},{}:cb", pin[0], pin[1], pin[2]),
-        );
-        m.add_dynamic_a(l[1], l[1], dyn_pne_geq, &format!("gm:Qbe"));
-        m.add_dynamic_b(
-            l[1],
-            dyn_pne_ieq,
-            &format!("i0:Q:{},{},{}:eb", pin[0], pin[1], pin[2]),
+        assert!(!sim.set_switch(2, true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_voltage_controlled_switch() -> Result<(), String> {
+        // control ramps 0 to 10V and back over 2ms, switch connects 1V to a load
+        fn triangle(t: f64) -> f64 {
+            10.0 * (1.0 - f64::abs(t / 1e-3 - 1.0))
+        }
+        let mut sim = Simulator::new(4);
+        sim.add_component(Box::new(VoltageFunction::from_closure(triangle, 1, 0)));
+        sim.add_component(Box::new(VoltageSource::new(1.0, 2, 0)));
+        sim.add_component(Box::new(VoltageControlledSwitch::new(1, 0, 5.0, 2.0, 2, 3)));
+        sim.add_component(Box::new(Resistor::new(1e3, 3, 0)));
+        sim.tag_node(1);
+        sim.tag_node(3);
+        sim.build_system();
+        let rows = sim.run_transient(2e-3, 1e-5).map_err(|e| e.to_string())?;
+        // control voltage at the first step of each new switch state
+        let mut changes = vec![];
+        for pair in rows.windows(2) {
+            let (was, now) = (pair[0][1] > 0.5, pair[1][1] > 0.5);
+            if was != now {
+                changes.push((now, pair[1][0]));
+            }
+        }
+        assert_eq!(changes.len(), 2);
+        // closes rising past 6V, opens falling past 4V, a step late at most
+        let (closed, v) = changes[0];
+        assert!(closed && v > 6.0 && v < 6.3, "closed at {}", v);
+        let (closed, v) = changes[1];
+        assert!(!closed && v < 4.0 && v > 3.7, "opened at {}", v);
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_lines() -> Result<(), String> {
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageFunction::from_closure(step_5v, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Capacitor::new(1e-6, 2, 0)));
+        sim.tag_node(1);
+        sim.tag_node(2);
+        sim.build_system();
+        let mut out = vec![];
+        let mut writer = JsonLinesWriter::new(&mut out, sim.recorded_names());
+        sim.run_transient_with(1e-3, 1e-4, |t, x| writer.write_step(t, x))
+            .map_err(|e| e.to_string())?;
+        let text = String::from_utf8(out).map_err(|e| e.to_string())?;
+        assert_eq!(text.lines().count(), 10);
+        for (i, line) in text.lines().enumerate() {
+            let v: serde_json::Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+            let keys: Vec<&String> = v.as_object().ok_or("not an object")?.keys().collect();
+            assert_eq!(keys, vec!["time", "v1", "v2"]);
+            assert!(approx_eq!(
+                f64,
+                v["time"].as_f64().unwrap(),
+                (i + 1) as f64 * 1e-4,
+                epsilon = 1e-12
+            ));
+        }
+        // run configs stream to a .jsonl output
+        let output = std::env::temp_dir().join("circuit_test_run_config.jsonl");
+        let output = output.to_str().ok_or("bad temp path")?;
+        let json = format!(
+            r#"{{
+                "analysis": {{ "type": "transient", "t_end": 1e-3, "dt": 1e-4 }},
+                "stimulus": [ {{ "node": 1, "dc": 2.0 }} ],
+                "probes": [ 2 ],
+                "output": "{}"
+            }}"#,
+            output
         );
-        // voltage and current infos
-        m.nodes[l[0]] = MNANodeInfo::new_voltage_with_name(&format!(
-            "v:Q:{},{},{}:{}",
-            pin[0],
-            pin[1],
-            pin[2],
-            if pnp { "cb" } else { "bc" }
+        let config = RunConfig::from_json(&json).map_err(|e| e.to_string())?;
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Capacitor::new(1e-6, 2, 0)));
+        sim.run_config(&config).map_err(|e| e.to_string())?;
+        let text = std::fs::read_to_string(output).map_err(|e| e.to_string())?;
+        assert_eq!(text.lines().count(), 10);
+        let last: serde_json::Value = serde_json::from_str(text.lines().last().ok_or("no steps")?)
+            .map_err(|e| e.to_string())?;
+        assert!(approx_eq!(
+            f64,
+            last["time"].as_f64().unwrap(),
+            1e-3,
+            epsilon = 1e-12
         ));
-        m.nodes[l[1]] = MNANodeInfo::new_voltage_with_name(&format!(
-            "v:Q:{},{},{}:{}",
-            pin[0],
-            pin[1],
-            pin[2],
-            if pnp { "eb" } else { "be" }
+        assert!(last["v2"].as_f64().unwrap() > 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_adaptive_time_step() -> Result<(), String> {
+        // 5V step into 1k and 1uF, clamped by a diode: a slow RC charge
+        // that turns fast once the diode conducts
+        let circuit = || {
+            let mut sim = Simulator::new(3);
+            sim.add_component(Box::new(VoltageFunction::from_closure(step_5v, 1, 0)));
+            sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+            sim.add_component(Box::new(Capacitor::new(1e-6, 2, 0)));
+            sim.add_component(Box::new(Diode::new(2, 0, DiodeParameters::default())));
+            sim.tag_node(2);
+            sim.build_system();
+            sim
+        };
+        let t_end = 5e-3;
+        let dt = 1e-6;
+        let mut fixed = circuit();
+        let reference = fixed.run_transient(t_end, dt).map_err(|e| e.to_string())?;
+        let mut adaptive = circuit();
+        let (times, rows) = adaptive
+            .run_transient_adaptive(t_end, dt, 1e-4, 1e-3)
+            .map_err(|e| e.to_string())?;
+        assert!(approx_eq!(
+            f64,
+            *times.last().unwrap(),
+            t_end,
+            epsilon = 1e-12
         ));
-        m.nodes[l[2]] = MNANodeInfo::new_current_with_scale(
-            &format!("i:Q:{},{},{}:bc", pin[0], pin[1], pin[2],),
-            1.0 - params.ar(),
+        assert!(
+            times.len() * 5 < reference.len(),
+            "{} adaptive steps",
+            times.len()
         );
-        m.nodes[l[3]] = MNANodeInfo::new_current_with_scale(
-            &format!("i:Q:{},{},{}:be", pin[0], pin[1], pin[2],),
-            1.0 - params.af(),
+        // steps get short around the diode turning on and long after
+        let steps: Vec<f64> = times.windows(2).map(|w| w[1] - w[0]).collect();
+        let (shortest, longest) = steps.iter().fold((f64::MAX, 0.0), |(a, b), s| {
+            (f64::min(a, *s), f64::max(b, *s))
+        });
+        assert!(longest > 10.0 * shortest);
+        // same waveform as the fixed small step
+        for (t, row) in times.iter().zip(&rows) {
+            let k = (t / dt).round() as usize - 1;
+            assert!(
+                f64::abs(row[0] - reference[k][0]) < 0.02,
+                "at {}: {} vs {}",
+                t,
+                row[0],
+                reference[k][0]
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_csv() -> Result<(), String> {
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageFunction::from_closure(step_5v, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Capacitor::new(1e-6, 2, 0)));
+        sim.build_system();
+        let (mut times, mut rows) = (vec![], vec![]);
+        sim.run_transient_with(1e-3, 1e-4, |t, x| {
+            times.push(t);
+            rows.push(x.to_vec());
+            Ok(())
+        })
+        .map_err(|e| e.to_string())?;
+        let mut out = vec![];
+        write_csv(&sim.system.nodes, &rows, &times, &mut out).map_err(|e| e.to_string())?;
+        let text = String::from_utf8(out).map_err(|e| e.to_string())?;
+        let mut lines = text.lines();
+        let header = lines.next().ok_or("no header")?;
+        assert!(header.starts_with("time [s],v0 [V],v1 [V],v2 [V],"));
+        // branch current names have commas, so they are quoted
+        assert!(header.contains("\"i:Vfn:1,0 [A]\""));
+        let first: Vec<f64> = lines
+            .next()
+            .ok_or("no data")?
+            .split(',')
+            .map(|v| v.parse::<f64>().map_err(|e| e.to_string()))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(first.len(), sim.system.nodes.len() + 1);
+        assert!(approx_eq!(f64, first[0], 1e-4, epsilon = 1e-12));
+        for (i, node) in sim.system.nodes.iter().enumerate() {
+            assert_eq!(first[i + 1], rows[0][i] * node.scale);
+        }
+        assert_eq!(text.lines().count(), 11);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pin_node() -> Result<(), String> {
+        // 9V across three 1k resistors in series
+        let mut sim = Simulator::new(4);
+        sim.add_component(Box::new(VoltageSource::new(9.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Resistor::new(1e3, 2, 3)));
+        sim.add_component(Box::new(Resistor::new(1e3, 3, 0)));
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        let before = sim.system.solution();
+        assert!(approx_eq!(f64, before[3], 3.0, epsilon = 1e-9));
+        sim.pin_node(2, 2.0);
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        assert!(approx_eq!(f64, sim.system.b[2].lu, 2.0, epsilon = 1e-6));
+        assert!(approx_eq!(f64, sim.system.b[3].lu, 1.0, epsilon = 1e-6));
+        // source side is unaffected
+        assert!(approx_eq!(f64, sim.system.b[1].lu, 9.0, epsilon = 1e-9));
+        sim.unpin_node(2);
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        let after = sim.system.solution();
+        for (a, b) in before.iter().zip(&after) {
+            assert!(approx_eq!(f64, *a, *b, epsilon = 1e-12));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_speaker_impedance() -> Result<(), String> {
+        let params = SpeakerParameters::default();
+        let f0 = params.resonance.as_ref().unwrap().frequency();
+        let mut sim = Simulator::new(2);
+        sim.add_component(Box::new(Speaker::new(1, 0, params)));
+        sim.build_system();
+        // sweep 10Hz to 1kHz, 50 points per decade
+        let mut peak = (0.0, 0.0);
+        for i in 0..=100 {
+            let f = 10.0 * 10f64.powf(i as f64 / 50.0);
+            let z = sim.impedance_at(1, 0, f).map_err(|e| e.to_string())?.norm();
+            if z > peak.1 {
+                peak = (f, z);
+            }
+        }
+        assert!((peak.0 / f0 - 1.0).abs() < 0.05, "peak at {}Hz", peak.0);
+        // voice coil plus the branch resistance at resonance
+        let z = sim.impedance_at(1, 0, f0).map_err(|e| e.to_string())?;
+        assert!(approx_eq!(f64, z.re, 46.0, epsilon = 1e-6));
+        // just the coil without a resonance
+        let mut sim = Simulator::new(2);
+        let coil = SpeakerParameters {
+            resonance: None,
+            ..SpeakerParameters::default()
+        };
+        sim.add_component(Box::new(Speaker::new(1, 0, coil)));
+        sim.build_system();
+        let z = sim.impedance_at(1, 0, 1e3).map_err(|e| e.to_string())?;
+        assert!(approx_eq!(f64, z.re, 6.0, epsilon = 1e-6));
+        assert!(approx_eq!(
+            f64,
+            z.im,
+            2.0 * std::f64::consts::PI * 1e3 * 0.5e-3,
+            epsilon = 1e-6
+        ));
+        // loaded from a model, the faster of coil and resonance sets the time step
+        let model = Model::from_json(
+            r#"{
+                "nets": [ "GND", "out" ],
+                "components": [ { "type": "Speaker", "nets": [ 1, 0 ] } ]
+            }"#,
+        )
+        .map_err(|e| e.to_string())?;
+        let mut sim = model.build().map_err(|e| e.to_string())?;
+        assert_eq!(sim.model().map_err(|e| e.to_string())?, model);
+        sim.build_system();
+        let tau = f64::min(0.5e-3 / 6.0, 1.0 / (2.0 * std::f64::consts::PI * f0));
+        let dt = sim.suggest_time_step().ok_or("no time step")?;
+        assert!(approx_eq!(f64, dt, tau / STEPS_PER_PERIOD, epsilon = 1e-12));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sample_rate_lock() -> Result<(), String> {
+        let rate = 48000.0;
+        let lowpass = || {
+            let mut sim = Simulator::new(3);
+            sim.add_component(Box::new(VoltageFunction::from_closure(step_5v, 1, 0)));
+            sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+            sim.add_component(Box::new(Capacitor::new(0.1e-6, 2, 0)));
+            sim.tag_node(2);
+            sim.build_system();
+            sim
+        };
+        for dt in [7e-6, 1.3e-6] {
+            let (times, rows) = lowpass()
+                .run_transient_sampled(1e-3, rate, dt)
+                .map_err(|e| e.to_string())?;
+            assert_eq!(times.len(), 48);
+            for (k, t) in times.iter().enumerate() {
+                assert_eq!(*t, (k + 1) as f64 / rate);
+            }
+            // RC charging curve, the trapezoidal rule centers the edge
+            // of the source half a step in
+            for (t, v) in times.iter().zip(&rows) {
+                let expected = 5.0 * (1.0 - (-(t - 0.5 * dt) / 100e-6).exp());
+                assert!((v[0] - expected).abs() < 0.02, "{} vs {}", v[0], expected);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_ammeter() -> Result<(), String> {
+        // 5V into 1k then 4k, sensing between the resistors
+        let mut sim = Simulator::new(4);
+        sim.add_component(Box::new(VoltageSource::new(5.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Ammeter::new(2, 3)));
+        sim.add_component(Box::new(Resistor::new(4e3, 3, 0)));
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        let names = sim.recorded_names();
+        let branch = names
+            .iter()
+            .position(|n| n == "i:A:2,3")
+            .ok_or("no current")?;
+        assert!(approx_eq!(f64, sim.record()[branch], 1e-3, epsilon = 1e-12));
+        // no voltage drop across it
+        assert!(approx_eq!(
+            f64,
+            sim.system.b[2].lu,
+            sim.system.b[3].lu,
+            epsilon = 1e-12
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_named_probes() -> Result<(), String> {
+        // 5V into 1k then 4k, named probes across and through the 4k
+        let mut sim = Simulator::new(4);
+        sim.add_component(Box::new(VoltageSource::new(5.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(CurrentProbe::new(2, 3, "load current")));
+        sim.add_component(Box::new(Resistor::new(4e3, 3, 0)));
+        sim.add_component(Box::new(VoltageProbe::new_named(3, 0, "out")));
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        let out = sim.system.probe("out").ok_or("no probe")?;
+        assert_eq!(sim.system.nodes[out].name, "out");
+        assert!(approx_eq!(f64, sim.system.b[out].lu, 4.0, epsilon = 1e-12));
+        let current = sim.system.probe("load current").ok_or("no probe")?;
+        assert!(approx_eq!(
+            f64,
+            sim.system.b[current].lu,
+            1e-3,
+            epsilon = 1e-12
+        ));
+        // recorded columns carry the names
+        sim.tag_node(out);
+        sim.tag_node(current);
+        assert_eq!(sim.recorded_names(), vec!["out", "load current"]);
+        assert_eq!(sim.system.probe("missing"), None);
+        // run configs probe by name, from a model that keeps the probes
+        let model = sim.model().map_err(|e| e.to_string())?;
+        let output = std::env::temp_dir().join("circuit_test_named_probes.csv");
+        let output = output.to_str().ok_or("bad temp path")?;
+        let json = format!(
+            r#"{{
+                "analysis": {{ "type": "dc" }},
+                "probes": [ "out", "load current", "v1", 2 ],
+                "output": "{}"
+            }}"#,
+            output
         );
-        self.update_dynamic(m);
+        let config = RunConfig::from_json(&json).map_err(|e| e.to_string())?;
+        let mut sim = model.build().map_err(|e| e.to_string())?;
+        sim.run_config(&config).map_err(|e| e.to_string())?;
+        let text = std::fs::read_to_string(output).map_err(|e| e.to_string())?;
+        let values: Vec<f64> = text
+            .lines()
+            .nth(1)
+            .ok_or("no values")?
+            .split(',')
+            .map(|v| v.parse::<f64>().map_err(|e| e.to_string()))
+            .collect::<Result<_, _>>()?;
+        for (v, expected) in values.iter().zip([4.0, 1e-3, 5.0, 4.0]) {
+            assert!(approx_eq!(f64, *v, expected, epsilon = 1e-12));
+        }
+        let json = r#"{ "analysis": { "type": "dc" }, "probes": [ "missing" ], "output": "x" }"#;
+        let config = RunConfig::from_json(json).map_err(|e| e.to_string())?;
+        let mut sim = model.build().map_err(|e| e.to_string())?;
+        assert!(matches!(
+            sim.run_config(&config),
+            Err(SimError::BadConfig(_))
+        ));
+        Ok(())
     }
 
-    fn update_dynamic(&self, m: &mut MNASystem) {
-        let reserved = self.reserved.as_ref().expect("need reserved");
-        m.set_dynamic(reserved.dyn_pnc_ieq, self.pnc.ieq);
-        m.set_dynamic(reserved.dyn_pnc_geq, self.pnc.geq);
-        m.set_dynamic(reserved.dyn_pne_ieq, self.pne.ieq);
-        m.set_dynamic(reserved.dyn_pne_geq, self.pne.geq);
+    #[test]
+    fn test_range_warnings() -> Result<(), String> {
+        let diode_sim = |r: f64| -> Result<Vec<String>, String> {
+            let mut sim = Simulator::new(3);
+            sim.add_component(Box::new(VoltageSource::new(5.0, 1, 0)));
+            sim.add_component(Box::new(Resistor::new(r, 1, 2)));
+            sim.add_component(Box::new(Diode::new(2, 0, DiodeParameters::default())));
+            sim.build_system();
+            sim.solve_dc().map_err(|e| e.to_string())?;
+            Ok(sim.range_warnings())
+        };
+        // about 4mA is fine
+        assert!(diode_sim(1e3)?.is_empty());
+        // straight from 5V through 1 ohm is not
+        let warnings = diode_sim(1.0)?;
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("diode 2,0: forward current"));
+        Ok(())
     }
 
-    fn newton(&mut self, m: &mut MNASystem) -> bool {
-        let reserved = self.reserved.as_ref().expect("need reserved");
-        self.pnc.newton(m.b[reserved.l[0]].lu) && self.pne.newton(m.b[reserved.l[1]].lu)
+    #[test]
+    fn test_marker() -> Result<(), String> {
+        let names = vec![String::from("a"), String::from("b")];
+        let times = [1.0, 2.0, 3.0];
+        let rows = vec![vec![0.0, 10.0], vec![2.0, 20.0], vec![2.0, 0.0]];
+        let values = marker(&times, &rows, &names, 1.25).ok_or("no marker")?;
+        assert_eq!(values, vec![("a".into(), 0.5), ("b".into(), 12.5)]);
+        // on a sample and at the ends
+        let values = marker(&times, &rows, &names, 2.0).ok_or("no marker")?;
+        assert_eq!(values[1].1, 20.0);
+        assert_eq!(
+            marker(&times, &rows, &names, 3.0).ok_or("no marker")?[1].1,
+            0.0
+        );
+        assert_eq!(marker(&times, &rows, &names, 3.5), None);
+        // labels come from the tagged nodes
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageSource::new(1.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Capacitor::new(1e-6, 2, 0)));
+        sim.build_system();
+        sim.tag_node(2);
+        let rows = sim.run_transient(1e-3, 1e-4).map_err(|e| e.to_string())?;
+        let times: Vec<f64> = (1..=rows.len()).map(|i| i as f64 * 1e-4).collect();
+        let values = marker(&times, &rows, &sim.recorded_names(), 5e-4).ok_or("no marker")?;
+        assert_eq!(values[0].0, "v2");
+        Ok(())
     }
-}
 
-type ComponentList = Vec<Box<dyn Component>>;
+    fn ramp_10ms(t: f64) -> f64 {
+        f64::min(t / 10e-3, 1.0)
+    }
 
-struct NetList {
-    components: ComponentList,
-    time_step: f64,
-    nets: usize,
-    states: usize,
-    system: MNASystem,
-}
+    #[test]
+    fn test_propagation_delay() -> Result<(), String> {
+        // slow ramp through RC lags by the low frequency group delay RC
+        let (r, c, dt) = (1e3, 100e-9, 1e-6);
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageFunction::from_closure(ramp_10ms, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(r, 1, 2)));
+        sim.add_component(Box::new(Capacitor::new(c, 2, 0)));
+        sim.build_system();
+        sim.tag_node(1);
+        sim.tag_node(2);
+        let rows = sim.run_transient(8e-3, dt).map_err(|e| e.to_string())?;
+        let times: Vec<f64> = (1..=rows.len()).map(|i| i as f64 * dt).collect();
+        let input: Vec<f64> = rows.iter().map(|row| row[0]).collect();
+        let output: Vec<f64> = rows.iter().map(|row| row[1]).collect();
+        let delay = propagation_delay(&times, &input, &output, 0.5).ok_or("no crossing")?;
+        assert!(approx_eq!(f64, delay, r * c, epsilon = 1e-6));
+        // interpolation between samples
+        let t = crossing_time(&[0.0, 1.0, 2.0], &[0.0, 0.0, 1.0], 0.25);
+        assert_eq!(t, Some(1.25));
+        assert_eq!(crossing_time(&[0.0, 1.0], &[0.0, 0.1], 0.5), None);
+        Ok(())
+    }
 
-#[allow(unused)]
-impl NetList {
-    fn new(nodes: usize) -> Self {
-        Self {
-            components: vec![],
-            time_step: 0.0,
-            nets: nodes,
-            states: 0,
-            system: MNASystem::default(),
+    fn step_5v(t: f64) -> f64 {
+        if t > 0.0 { 5.0 } else { 0.0 }
+    }
+
+    #[test]
+    fn test_sine_pulse_sources() -> Result<(), String> {
+        // 1kHz sine of 2V around 1V, at quarter periods
+        let mut sine = VoltageFunction::sine(2.0, 1e3, 1.0, 1, 0);
+        assert_eq!(sine.freq, Some(1e3));
+        for (k, expected) in [1.0, 3.0, 1.0, -1.0, 1.0].iter().enumerate() {
+            let v = (sine.f)(k as f64 * 0.25e-3);
+            assert!(approx_eq!(f64, v, *expected, epsilon = 1e-9));
         }
+        // 1ms pulse that is high for the first quarter
+        let mut pulse = VoltageFunction::pulse(0.0, 5.0, 1e-3, 0.25, 1, 0);
+        assert_eq!(pulse.v, 5.0);
+        for (t, expected) in [
+            (0.24e-3, 5.0),
+            (0.26e-3, 0.0),
+            (0.99e-3, 0.0),
+            (1.01e-3, 5.0),
+        ] {
+            assert_eq!((pulse.f)(t), expected);
+        }
+        // driving a circuit, row k is at (k + 1) * 0.1ms
+        let mut sim = Simulator::new(2);
+        sim.add_component(Box::new(VoltageFunction::pulse(0.0, 5.0, 1e-3, 0.25, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 0)));
+        sim.tag_node(1);
+        sim.build_system();
+        let rows = sim.run_transient(1e-3, 1e-4).map_err(|e| e.to_string())?;
+        let v: Vec<f64> = rows.iter().map(|row| row[0]).collect();
+        assert_eq!(v[1], 5.0);
+        assert_eq!(v[2], 0.0);
+        assert_eq!(v[9], 5.0);
+        // the same pulse and a sine from a run config
+        let output = std::env::temp_dir().join("circuit_test_wave_stimulus.csv");
+        let output = output.to_str().ok_or("bad temp path")?;
+        let json = format!(
+            r#"{{
+                "analysis": {{ "type": "transient", "t_end": 1e-3, "dt": 1e-4 }},
+                "stimulus": [
+                    {{ "node": 1, "pulse": {{ "high": 5.0, "period": 1e-3, "duty": 0.25 }} }},
+                    {{ "node": 2, "sine": {{ "amp": 2.0, "freq": 1e3, "offset": 1.0 }} }}
+                ],
+                "probes": [ 1, 2 ],
+                "output": "{}"
+            }}"#,
+            output
+        );
+        let config = RunConfig::from_json(&json).map_err(|e| e.to_string())?;
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 2, 0)));
+        sim.run_config(&config).map_err(|e| e.to_string())?;
+        let text = std::fs::read_to_string(output).map_err(|e| e.to_string())?;
+        let rows: Vec<Vec<f64>> = text
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').map(|x| x.parse().unwrap()).collect())
+            .collect();
+        let pulse: Vec<f64> = rows.iter().map(|row| row[1]).collect();
+        assert_eq!(pulse, v);
+        for row in &rows {
+            let expected = 1.0 + 2.0 * f64::sin(2.0 * std::f64::consts::PI * 1e3 * row[0]);
+            assert!(approx_eq!(f64, row[2], expected, epsilon = 1e-9));
+        }
+        Ok(())
     }
-    fn add_component(component: &mut dyn Component) {}
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use float_cmp::approx_eq;
+    #[test]
+    fn test_closure_source() -> Result<(), String> {
+        // sine with amplitude and frequency from variables
+        let (amplitude, freq) = (2.5, 50.0);
+        let mut sim = Simulator::new(2);
+        sim.add_component(Box::new(VoltageFunction::with_frequency(
+            move |t| amplitude * (2.0 * std::f64::consts::PI * freq * t).sin(),
+            freq,
+            1,
+            0,
+        )));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 0)));
+        sim.tag_node(1);
+        sim.build_system();
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        // last row is the quarter-period peak
+        let rows = sim.run_transient(0.005, 1e-4).map_err(|e| e.to_string())?;
+        assert!(approx_eq!(
+            f64,
+            rows[rows.len() - 1][0],
+            amplitude,
+            epsilon = 1e-6
+        ));
+        // closures can keep state too, this one counts its calls
+        let mut calls = 0.0;
+        let mut sim = Simulator::new(2);
+        sim.add_component(Box::new(VoltageFunction::from_closure(
+            move |_| {
+                calls += 1.0;
+                calls
+            },
+            1,
+            0,
+        )));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 0)));
+        sim.tag_node(1);
+        sim.build_system();
+        let rows = sim.run_transient(3e-3, 1e-3).map_err(|e| e.to_string())?;
+        assert!(rows.last().unwrap()[0] > 1.0);
+        Ok(())
+    }
 
     #[test]
-    fn test_format_unit_value() -> Result<(), String> {
-        assert_eq!(format_unit_value(1.5, " Ohms"), "1.5 Ohms");
-        assert_eq!(format_unit_value(15.0, " Ohms"), "15 Ohms");
-        assert_eq!(format_unit_value(1500.0, " Ohms"), "1.5k Ohms");
-        assert_eq!(format_unit_value(150000.0, " Ohms"), "150k Ohms");
-        assert_eq!(format_unit_value(1500000.0, " Ohms"), "1.5M Ohms");
-        assert_eq!(format_unit_value(0.015, " Ohms"), "15m Ohms");
-        assert_eq!(format_unit_value(0.0015, " Ohms"), "1.5m Ohms");
-        assert_eq!(format_unit_value(0.00015, " Ohms"), "150u Ohms");
+    fn test_rc_charge_curve() -> Result<(), String> {
+        // 5V step into 1k and 1uF, tau = 1ms
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageFunction::from_closure(step_5v, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Capacitor::new(1e-6, 2, 0)));
+        sim.tag_node(2);
+        sim.build_system();
+        let dt = 1e-5;
+        let rows = sim.run_transient(5e-3, dt).map_err(|e| e.to_string())?;
+        assert_eq!(rows.len(), 500);
+        // trapezoidal rule centers the edge of the source half a step in
+        for (k, row) in rows.iter().enumerate().skip(1) {
+            let t = (k + 1) as f64 * dt - 0.5 * dt;
+            let expected = 5.0 * (1.0 - (-t / 1e-3).exp());
+            assert!(approx_eq!(f64, row[0], expected, epsilon = 2e-3));
+        }
         Ok(())
     }
 
     #[test]
-    fn test_system() -> Result<(), String> {
-        let mut s = MNASystem::default();
-        s.set_size(5);
-        assert_eq!(s.a_matrix.len(), 5);
-        for row in s.a_matrix {
-            assert_eq!(row.len(), 5);
+    fn test_lr_current_rise() -> Result<(), String> {
+        // 5V step into 100R and 10mH, tau = 0.1ms, final current 50mA
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageFunction::from_closure(step_5v, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(100.0, 1, 2)));
+        sim.add_component(Box::new(Inductor::new(10e-3, 2, 0)));
+        sim.build_system();
+        let current = sim
+            .recorded_names()
+            .iter()
+            .position(|n| n == "i:L:2,0")
+            .ok_or("no inductor current")?;
+        let dt = 1e-6;
+        let rows = sim.run_transient(1e-3, dt).map_err(|e| e.to_string())?;
+        for (k, row) in rows.iter().enumerate().skip(1) {
+            let t = (k + 1) as f64 * dt - 0.5 * dt;
+            let expected = 0.05 * (1.0 - (-t / 1e-4).exp());
+            assert!(approx_eq!(f64, row[current], expected, epsilon = 2e-5));
+            // same current through the resistor
+            let ir = (row[1] - row[2]) / 100.0;
+            assert!(approx_eq!(f64, ir, row[current], epsilon = 1e-9));
         }
         Ok(())
     }
 
     #[test]
-    fn test_pn() -> Result<(), String> {
-        // Similar to 1N4148 (but just PN junction)
-        let mut pn = JunctionPN::new(/*is=*/ 35.0e-12, /*n=*/ 1.24);
-        // vcrit is point where current increases faster than voltage as voltage increases
-        assert!(approx_eq!(f64, pn.vcrit, 0.6542963597947701, ulps = 100));
-        // Check ieq for a couple voltages
-        pn.newton(0.5);
-        assert!(approx_eq!(f64, pn.ieq, 0.002760783529589722, ulps = 100));
-        pn.newton(0.4);
-        assert!(approx_eq!(f64, pn.ieq, 0.0000976127760265226, ulps = 100));
-        // 0.4 should just take 1 newton step (below vcrit)
-        let mut done = pn.newton(0.4);
-        assert!(done);
-        // 0.8 takes more than 2 iterations because of qucs current thing
-        done = pn.newton(0.8);
-        assert!(!done);
-        done = pn.newton(0.8);
-        assert!(!done);
-        // But with more iterations it should converge
-        for _i in 0..10 {
-            done = pn.newton(0.8);
-            if done {
-                break;
-            }
+    fn test_capacitor_time_step_reduction() -> Result<(), String> {
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageFunction::from_closure(step_5v, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Capacitor::new(1e-6, 2, 0)));
+        sim.build_system();
+        let rows = sim.run_transient(0.5e-3, 1e-5).map_err(|e| e.to_string())?;
+        let mut v = rows.last().ok_or("no steps")?[2];
+        // keep charging while the step shrinks by 1000x each time
+        let mut dt = 1e-5;
+        for _ in 0..8 {
+            dt /= 1000.0;
+            let rows = sim
+                .run_transient(10.0 * dt, dt)
+                .map_err(|e| e.to_string())?;
+            let v_new = rows.last().ok_or("no steps")?[2];
+            assert!(v_new.is_finite());
+            // still charging (up to roundoff), no jumps
+            assert!(v_new > v - 1e-9 && v_new < v + 1e-3);
+            v = v_new;
         }
-        assert!(done);
+        // and back to a normal step, continuing on the charge curve
+        let rows = sim.run_transient(0.5e-3, 1e-5).map_err(|e| e.to_string())?;
+        let v_end = rows.last().ok_or("no steps")?[2];
+        assert!(approx_eq!(
+            f64,
+            v_end,
+            5.0 * (1.0 - (-1.0f64).exp()),
+            epsilon = 0.05
+        ));
         Ok(())
     }
 
     #[test]
-    fn test_component_polymorphism() -> Result<(), String> {
-        let mut s = MNASystem::default();
-        s.set_size(3);
-        let c1 = Resistor::new(100.0, 0, 1);
-        let c2 = Resistor::new(100.0, 1, 2);
-        let c3 = Capacitor::new(0.1, 1, 2);
-        let c4 = Diode::new(0, 1, DiodeParameters::default());
-        println!("{:?}", &c1);
-        println!("{:?}", &c4);
-        let mut v: Vec<Box<dyn Component>> = vec![Box::new(c1), Box::new(c2), Box::new(c3), Box::new(c4)];
-        // Reserve all components
-        for c in &mut v {
-            c.reserve(&mut s);
+    fn test_transient_wav() -> Result<(), String> {
+        let path = std::env::temp_dir().join("circuit_test_transient.wav");
+        let path = path.to_str().ok_or("bad temp path")?;
+        // 440Hz through a divider, probed at the output
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageFunction::sine(2.0, 440.0, 0.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Resistor::new(1e3, 2, 0)));
+        sim.build_system();
+        sim.tag_node(2);
+        sim.run_transient_wav(2, 0.1, 8000, path)
+            .map_err(|e| e.to_string())?;
+        let data = std::fs::read(path).map_err(|e| e.to_string())?;
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        let u32_at =
+            |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32_at(4) as usize, data.len() - 8);
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        // PCM, mono, 8kHz, 16 bit
+        assert_eq!((u16_at(20), u16_at(22)), (1, 1));
+        assert_eq!(u32_at(24), 8000);
+        assert_eq!(u32_at(28), 16000);
+        assert_eq!((u16_at(32), u16_at(34)), (2, 16));
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(u32_at(40), 2 * 800);
+        assert_eq!(data.len(), 44 + 2 * 800);
+        // normalized to full scale
+        let peak = (0..800)
+            .map(|k| (u16_at(44 + 2 * k) as i16).unsigned_abs())
+            .max();
+        assert_eq!(peak, Some(i16::MAX as u16));
+        // untagged nodes are not recorded
+        assert!(sim.run_transient_wav(1, 0.01, 8000, path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_wav_source() -> Result<(), String> {
+        let path = std::env::temp_dir().join("circuit_test_wav_source.wav");
+        let path = path.to_str().ok_or("bad temp path")?;
+        // 50ms of 440Hz at 8kHz
+        let w = 2.0 * std::f64::consts::PI * 440.0;
+        let samples: Vec<f64> = (0..400).map(|k| f64::sin(w * k as f64 / 8000.0)).collect();
+        wav::write_wav(&samples, 8000, path).map_err(|e| e.to_string())?;
+        let source = WavSource::from_file(path, 2.0, 1, 0).map_err(|e| e.to_string())?;
+        assert_eq!(source.sample_rate, 8000.0);
+        assert_eq!(source.samples.len(), 400);
+        // through a unity gain buffer into a load
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(source));
+        sim.add_component(Box::new(Vcvs::new(1.0, 1, 0, 2, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 2, 0)));
+        sim.build_system();
+        sim.tag_node(2);
+        let (mut worst, dt): (f64, f64) = (0.0, 1.0 / 32000.0);
+        sim.run_transient_with(0.05, dt, |t, x| {
+            worst = worst.max((x[0] - 2.0 * f64::sin(w * t)).abs());
+            Ok(())
+        })
+        .map_err(|e| e.to_string())?;
+        // linear interpolation error is about (w / rate)^2 / 8 of the amplitude
+        assert!(worst < 2.0 * 0.02, "error {}", worst);
+        // zero once the samples run out
+        let rows = sim
+            .run_transient(0.01, 1.0 / 8000.0)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(rows.last().ok_or("no steps")?[0], 0.0);
+        // a run config plays the file on a sample grid
+        let output = std::env::temp_dir().join("circuit_test_wav_stimulus.csv");
+        let output = output.to_str().ok_or("bad temp path")?;
+        let json = format!(
+            r#"{{
+                "analysis": {{ "type": "transient", "t_end": 0.01, "dt": 1.25e-4 }},
+                "stimulus": [ {{ "node": 1, "wav": "{}", "amplitude": 2.0 }} ],
+                "probes": [ 1 ],
+                "output": "{}"
+            }}"#,
+            path, output
+        );
+        let config = RunConfig::from_json(&json).map_err(|e| e.to_string())?;
+        let mut sim = Simulator::new(2);
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 0)));
+        sim.run_config(&config).map_err(|e| e.to_string())?;
+        let text = std::fs::read_to_string(output).map_err(|e| e.to_string())?;
+        for (k, line) in text.lines().skip(1).enumerate() {
+            let v: f64 = line.split(',').nth(1).ok_or("no value")?.parse().unwrap();
+            assert!(approx_eq!(f64, v, 2.0 * samples[k + 1], epsilon = 1e-3));
         }
-        // Stamp into matrix
-        for c in &mut v {
-            c.stamp(&mut s);
+        // missing files are reported
+        let config =
+            RunConfig::from_json(&json.replace(path, "no/such.wav")).map_err(|e| e.to_string())?;
+        let mut sim = Simulator::new(2);
+        assert!(matches!(
+            sim.run_config(&config),
+            Err(SimError::BadConfig(_))
+        ));
+        Ok(())
+    }
+
+    fn sine_1k(t: f64) -> f64 {
+        f64::sin(2.0 * std::f64::consts::PI * 1000.0 * t)
+    }
+
+    #[test]
+    fn test_suggest_time_step() -> Result<(), String> {
+        // 1kHz sine into a slow RC (10ms)
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageFunction::with_frequency(
+            sine_1k, 1000.0, 1, 0,
+        )));
+        sim.add_component(Box::new(Resistor::new(10e3, 1, 2)));
+        sim.add_component(Box::new(Capacitor::new(1e-6, 2, 0)));
+        sim.build_system();
+        let dt = sim.suggest_time_step().ok_or("no timestep")?;
+        assert!(dt > 10e-6 && dt < 100e-6);
+        assert!(approx_eq!(f64, dt, 50e-6, ulps = 10));
+        // fast RC (1us) takes over
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageFunction::with_frequency(
+            sine_1k, 1000.0, 1, 0,
+        )));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 2)));
+        sim.add_component(Box::new(Capacitor::new(1e-9, 2, 0)));
+        sim.build_system();
+        let dt = sim.suggest_time_step().ok_or("no timestep")?;
+        assert!(approx_eq!(f64, dt, 1e-6 / STEPS_PER_PERIOD, ulps = 10));
+        // purely resistive circuit has no time scale
+        let mut sim = Simulator::new(2);
+        sim.add_component(Box::new(VoltageSource::new(1.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1e3, 1, 0)));
+        sim.build_system();
+        assert_eq!(sim.suggest_time_step(), None);
+        Ok(())
+    }
+
+    /// Read Matrix Market file back into (rows, cols, entries)
+    fn parse_mtx(text: &str) -> (usize, usize, Vec<(usize, usize, f64)>) {
+        let mut lines = text.lines().filter(|line| !line.starts_with('%'));
+        let size: Vec<usize> = lines
+            .next()
+            .unwrap()
+            .split_whitespace()
+            .map(|x| x.parse().unwrap())
+            .collect();
+        let entries = lines
+            .map(|line| {
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                (
+                    parts[0].parse().unwrap(),
+                    parts[1].parse().unwrap(),
+                    parts[2].parse().unwrap(),
+                )
+            })
+            .collect();
+        (size[0], size[1], entries)
+    }
+
+    #[test]
+    fn test_matrix_market() -> Result<(), String> {
+        let divider = || {
+            let mut sim = Simulator::new(3);
+            sim.add_component(Box::new(VoltageSource::new(5.0, 1, 0)));
+            sim.add_component(Box::new(Resistor::new(1000.0, 1, 2)));
+            sim.add_component(Box::new(Resistor::new(2000.0, 2, 0)));
+            sim
+        };
+        let mut sim = divider();
+        sim.build_system();
+        let (mut out_a, mut out_b) = (vec![], vec![]);
+        sim.system
+            .write_mtx(&mut out_a, &mut out_b)
+            .map_err(|e| e.to_string())?;
+        let text_a = String::from_utf8(out_a).map_err(|e| e.to_string())?;
+        let text_b = String::from_utf8(out_b).map_err(|e| e.to_string())?;
+        assert!(text_a.starts_with("%%MatrixMarket matrix coordinate real general"));
+        let (rows, cols, entries) = parse_mtx(&text_a);
+        // nets 1, 2 and the source current (ground dropped)
+        assert_eq!((rows, cols), (3, 3));
+        assert_eq!(entries.len(), 6);
+        for (r, c, v) in entries {
+            assert_eq!(v, sim.system.a_matrix[r][c].value(&sim.system.vars));
         }
+        let b: Vec<f64> = text_b
+            .lines()
+            .filter(|line| !line.starts_with('%'))
+            .skip(1)
+            .map(|line| line.parse().unwrap())
+            .collect();
+        assert_eq!(b, vec![0.0, 0.0, 5.0]);
+        // a run config writes the same files after solving, pivoting in
+        // the LU does not change them
+        let dir = std::env::temp_dir();
+        let path_a = dir.join("circuit_test_mtx_a.mtx");
+        let path_b = dir.join("circuit_test_mtx_b.mtx");
+        let (path_a, path_b) = (
+            path_a.to_str().ok_or("bad temp path")?,
+            path_b.to_str().ok_or("bad temp path")?,
+        );
+        let json = format!(
+            r#"{{ "analysis": {{ "type": "mtx", "b": "{}" }}, "output": "{}" }}"#,
+            path_b, path_a
+        );
+        let config = RunConfig::from_json(&json).map_err(|e| e.to_string())?;
+        assert!(config.probes.is_empty());
+        let mut sim = divider();
+        sim.run_config(&config).map_err(|e| e.to_string())?;
+        assert_ne!(sim.system.rows, vec![0, 1, 2, 3]);
+        let read = |path| std::fs::read_to_string(path).map_err(|e| e.to_string());
+        assert_eq!(read(path_a)?, text_a);
+        assert_eq!(read(path_b)?, text_b);
         Ok(())
     }
-}
 
-fn main() {
-    let mut s = MNASystem::default();
-    s.set_size(3);
-    let c1 = Resistor::new(100.0, 0, 1);
-    let c2 = Resistor::new(100.0, 1, 2);
-    let c3 = Capacitor::new(0.1, 1, 2);
+    #[test]
+    fn test_dc_bistable() -> Result<(), String> {
+        // Latch from two cross-coupled switches with pull-ups. Newton flips
+        // both switches at once, so it bounces between both-open and
+        // both-closed instead of settling in one of the two stable states.
+        let mut sim = Simulator::new(4);
+        sim.add_component(Box::new(VoltageSource::new(1.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1000.0, 1, 2)));
+        sim.add_component(Box::new(Resistor::new(1000.0, 1, 3)));
+        sim.add_component(Box::new(ComparatorSwitch::new(2, 3)));
+        sim.add_component(Box::new(ComparatorSwitch::new(3, 2)));
+        sim.build_system();
+        assert_eq!(sim.solve_dc(), Err(SimError::NoDcSolution(2)));
+        Ok(())
+    }
 
-    println!("Hello from sim.rs");
-    println!("{:?}", s);
-    println!("Resistor is {}", format_unit_value(1500.0, " Ohms"));
+    #[test]
+    fn test_gmin_stepping() -> Result<(), String> {
+        // Plain Newton from 0V jumps past 3V onto the flat part of the
+        // limiter and ends up bouncing between the clamps
+        let build = || {
+            let mut sim = Simulator::new(2);
+            sim.add_component(Box::new(SoftLimiter::new(1, 3.0)));
+            sim.build_system();
+            sim
+        };
+        let mut sim = build();
+        sim.gmin_stepping.max_steps = 0;
+        assert_eq!(sim.solve_dc(), Err(SimError::NoDcSolution(2)));
+        // Starting with 1S to ground keeps every step on the steep part
+        let mut sim = build();
+        sim.gmin_stepping.g_start = 1.0;
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        assert!(approx_eq!(f64, sim.system.b[1].lu, 3.0, epsilon = 1e-6));
+        Ok(())
+    }
+
+    #[test]
+    fn test_source_stepping() -> Result<(), String> {
+        // Latch with unequal dividers on the switch controls. At full supply
+        // both controls are above the threshold, so Newton closes both
+        // switches at once and bounces.
+        let mut sim = Simulator::new(4);
+        sim.add_component(Box::new(VoltageSource::new(1.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1000.0, 1, 2)));
+        sim.add_component(Box::new(Resistor::new(2000.0, 2, 0)));
+        sim.add_component(Box::new(Resistor::new(1000.0, 1, 3)));
+        sim.add_component(Box::new(Resistor::new(1500.0, 3, 0)));
+        sim.add_component(Box::new(ComparatorSwitch::new(2, 3)));
+        sim.add_component(Box::new(ComparatorSwitch::new(3, 2)));
+        sim.build_system();
+        sim.gmin_stepping.max_steps = 0;
+        sim.source_stepping.steps = 1;
+        assert_eq!(sim.solve_dc(), Err(SimError::NoDcSolution(2)));
+        // Ramping the supply lets node 2 cross first and latch node 3 low
+        sim.source_stepping.steps = 20;
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        let v2 = sim.system.b[2].lu;
+        let v3 = sim.system.b[3].lu;
+        assert!(approx_eq!(f64, v2, 2.0 / 3.0, epsilon = 1e-6));
+        assert!(v3 < 1e-3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_convergence_report() -> Result<(), String> {
+        // Two iterations are not enough for a diode starting from 0V
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageSource::new(5.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1000.0, 1, 2)));
+        sim.add_component(Box::new(Diode::new(2, 0, DiodeParameters::default())));
+        sim.build_system();
+        sim.options.max_iter = 2;
+        sim.gmin_stepping.max_steps = 0;
+        sim.source_stepping.steps = 1;
+        assert_eq!(sim.solve_dc(), Err(SimError::NoConvergence(2)));
+        let failure = &sim.report.failures[0];
+        assert_eq!(failure.iterations, 2);
+        assert_eq!(failure.component, Some(2));
+        let (name, residual) = failure.node.clone().ok_or("no node in report")?;
+        assert_eq!(name, "v:D:2,0");
+        assert!(residual.abs() > V_TOLERANCE);
+        assert!(failure.max_delta > V_TOLERANCE);
+        assert!(
+            sim.report
+                .to_string()
+                .contains("component 2 not settled at v:D:2,0")
+        );
+        // with the full budget it settles
+        sim.options.max_iter = MAX_ITER;
+        sim.solve_dc().map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_sim_options() -> Result<(), String> {
+        // Diode forward biased through 1k from 5V at two tolerances
+        let solve = |options: SimOptions| -> Result<(u32, f64), String> {
+            let mut sim = Simulator::new(3);
+            sim.add_component(Box::new(VoltageSource::new(5.0, 1, 0)));
+            sim.add_component(Box::new(Resistor::new(1000.0, 1, 2)));
+            sim.add_component(Box::new(Diode::new(2, 0, DiodeParameters::default())));
+            sim.options = options;
+            sim.build_system();
+            let iter = sim.solve_dc().map_err(|e| e.to_string())?;
+            Ok((iter, sim.system.b[2].lu))
+        };
+        let loose = SimOptions {
+            v_tolerance: 1e-2,
+            ..SimOptions::default()
+        };
+        let tight = SimOptions {
+            v_tolerance: 1e-9,
+            ..SimOptions::default()
+        };
+        let (iter_loose, v_loose) = solve(loose)?;
+        let (iter_tight, v_tight) = solve(tight)?;
+        assert!(iter_tight > iter_loose);
+        assert!(f64::abs(v_tight - v_loose) < 1e-2);
+        // the iteration budget comes from the options too
+        let few = SimOptions {
+            max_iter: 1,
+            ..SimOptions::default()
+        };
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageSource::new(5.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1000.0, 1, 2)));
+        sim.add_component(Box::new(Diode::new(2, 0, DiodeParameters::default())));
+        sim.options = few;
+        sim.build_system();
+        assert!(sim.settle() == Ok(false));
+        // and a transient step that doesn't settle is an error
+        sim.set_time_step(1e-5);
+        assert_eq!(sim.tick(), Err(SimError::NoConvergence(1)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ac_rc_corner() -> Result<(), String> {
+        // RC low-pass is 3dB down with 45 degrees lag at 1/(2 pi RC)
+        let (r, c) = (1e3, 100e-9);
+        let fc = 1.0 / (2.0 * std::f64::consts::PI * r * c);
+        let mut sim = Simulator::new(3);
+        sim.add_component(Box::new(VoltageSource::with_ac(0.0, 1.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(r, 1, 2)));
+        sim.add_component(Box::new(Capacitor::new(c, 2, 0)));
+        sim.build_system();
+        let res = ac_polar(&sim.run_ac(fc / 100.0, fc, 2).map_err(|e| e.to_string())?);
+        assert_eq!(res.len(), 5);
+        let (f, nodes) = &res[4];
+        assert!(approx_eq!(f64, *f, fc, epsilon = 1e-6));
+        let (mag, phase) = nodes[2];
+        assert!(approx_eq!(f64, 20.0 * mag.log10(), -3.0103, epsilon = 1e-3));
+        assert!(approx_eq!(f64, phase.to_degrees(), -45.0, epsilon = 1e-6));
+        // a decade and more below it passes flat
+        let (mag, _) = res[0].1[2];
+        assert!(approx_eq!(f64, mag, 1.0, epsilon = 1e-3));
+        // a range without a log sweep is an error instead of a hang
+        for (f_start, f_stop, ppd) in [(0.0, fc, 2), (fc, fc / 10.0, 2), (fc, fc, 0)] {
+            let err = sim.run_ac(f_start, f_stop, ppd).unwrap_err();
+            assert!(matches!(err, SimError::BadConfig(_)), "{:?}", err);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_bode_integrator() -> Result<(), String> {
+        // Inverting integrator, 10M across the capacitor sets the DC
+        // gain and puts the corner far below the sweep
+        let (r, c) = (10e3, 100e-9);
+        let mut sim = Simulator::new(4);
+        sim.add_component(Box::new(VoltageSource::with_ac(0.0, 0.5, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(r, 1, 2)));
+        sim.add_component(Box::new(OpAmp::new(0, 2, 3)));
+        sim.add_component(Box::new(Capacitor::new(c, 2, 3)));
+        sim.add_component(Box::new(Resistor::new(10e6, 2, 3)));
+        sim.build_system();
+        let points = sim.run_ac(100.0, 10e3, 1).map_err(|e| e.to_string())?;
+        let res = bode(&points, 3, 1);
+        assert_eq!(res.len(), 3);
+        // a run config with the input as drive writes the same transfer function
+        let output = std::env::temp_dir().join("circuit_test_bode.csv");
+        let output = output.to_str().ok_or("bad temp path")?;
+        let json = format!(
+            r#"{{
+                "analysis": {{ "type": "ac", "f_start": 100, "f_stop": 10e3,
+                               "points_per_decade": 1, "drive": 1 }},
+                "probes": [ 3 ],
+                "output": "{}"
+            }}"#,
+            output
+        );
+        let config = RunConfig::from_json(&json).map_err(|e| e.to_string())?;
+        let mut sim = Simulator::new(4);
+        sim.add_component(Box::new(VoltageSource::with_ac(0.0, 0.5, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(r, 1, 2)));
+        sim.add_component(Box::new(OpAmp::new(0, 2, 3)));
+        sim.add_component(Box::new(Capacitor::new(c, 2, 3)));
+        sim.add_component(Box::new(Resistor::new(10e6, 2, 3)));
+        sim.run_config(&config).map_err(|e| e.to_string())?;
+        let text = std::fs::read_to_string(output).map_err(|e| e.to_string())?;
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), res.len() + 1);
+        assert_eq!(lines[0], "freq,gain(3),phase(3)");
+        for (line, (f, gain, phase)) in lines[1..].iter().zip(&res) {
+            assert_eq!(*line, format!("{},{},{}", f, gain, phase));
+        }
+        for w in res.windows(2) {
+            assert!(approx_eq!(f64, w[1].1 - w[0].1, -20.0, epsilon = 1e-3));
+        }
+        // unity gain where 1/(wRC) = 1, leading by 90 degrees
+        let f1 = 1.0 / (2.0 * std::f64::consts::PI * r * c);
+        let (f, gain, phase) = res[1];
+        assert!(approx_eq!(
+            f64,
+            gain,
+            20.0 * f64::log10(f1 / f),
+            epsilon = 1e-3
+        ));
+        assert!(approx_eq!(f64, phase, 90.0, epsilon = 0.1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_initial_conditions() -> Result<(), String> {
+        // Capacitor charged to 5V with nothing else connected holds it,
+        // starting transient directly without a DC solve
+        let dt = 1e-6;
+        let mut sim = Simulator::new(2);
+        sim.add_component(Box::new(Capacitor::with_ic(1e-6, 5.0, 1, 0)));
+        sim.build_system();
+        sim.set_time_step(dt);
+        for _ in 0..100 {
+            sim.tick().map_err(|e| e.to_string())?;
+        }
+        assert!(approx_eq!(f64, sim.system.b[1].lu, 5.0, epsilon = 1e-9));
+        // through 1k it discharges from 5V instead of the 0V DC solution
+        let (r, c) = (1e3, 1e-6);
+        let mut sim = Simulator::new(2);
+        sim.add_component(Box::new(Capacitor::with_ic(c, 5.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(r, 1, 0)));
+        sim.build_system();
+        let rows = sim.run_transient(r * c, dt).map_err(|e| e.to_string())?;
+        let v = rows[rows.len() - 1][1];
+        assert!(approx_eq!(
+            f64,
+            v,
+            5.0 / std::f64::consts::E,
+            epsilon = 1e-3
+        ));
+        // inductor current decays through 1 ohm with tau = L/R
+        let l = 1e-3;
+        let mut sim = Simulator::new(2);
+        sim.add_component(Box::new(Inductor::with_ic(l, 1.0, 1, 0)));
+        sim.add_component(Box::new(Resistor::new(1.0, 1, 0)));
+        sim.build_system();
+        let rows = sim.run_transient(l, dt).map_err(|e| e.to_string())?;
+        // the current circulates back up through the resistor, so node 1 is negative
+        let i = -rows[rows.len() - 1][1];
+        assert!(approx_eq!(
+            f64,
+            i,
+            1.0 / std::f64::consts::E,
+            epsilon = 2e-3
+        ));
+        // the same discharge loaded from a model, which keeps the initial condition
+        let model = Model::from_json(
+            r#"{
+                "nets": [ "GND", "top" ],
+                "components": [
+                    { "type": "Capacitor", "c": 1e-6, "ic": 5.0, "nets": [ 1, 0 ] },
+                    { "type": "Resistor", "r": 1000.0, "nets": [ 1, 0 ] }
+                ]
+            }"#,
+        )
+        .map_err(|e| e.to_string())?;
+        let mut sim = model.build().map_err(|e| e.to_string())?;
+        assert_eq!(sim.model().map_err(|e| e.to_string())?, model);
+        sim.build_system();
+        let rows = sim.run_transient(r * c, dt).map_err(|e| e.to_string())?;
+        assert!(approx_eq!(f64, rows[rows.len() - 1][1], v, epsilon = 1e-12));
+        Ok(())
+    }
 }
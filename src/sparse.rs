@@ -0,0 +1,368 @@
+//! Sparse LU factorization for large systems
+//!
+//! Only stored entries cost memory and time. Pivots are picked with the
+//! Markowitz criterion, the entry whose elimination touches the fewest
+//! other entries, among the entries large enough to keep it stable.
+
+/// Pivots are at least this fraction of the largest entry in their column
+const PIVOT_THRESHOLD: f64 = 0.1;
+
+/// Shortest rows and columns searched for each pivot
+const PIVOT_SEARCH: usize = 4;
+
+/// Square matrix stored as compressed rows
+#[derive(Clone, Debug, PartialEq)]
+pub struct SparseMatrix {
+    n: usize,
+    // entries of row r are at row_start[r]..row_start[r + 1]
+    row_start: Vec<usize>,
+    cols: Vec<usize>,
+    values: Vec<f64>,
+}
+
+impl SparseMatrix {
+    /// Matrix from (row, column, value) entries, duplicates are summed
+    pub fn from_entries(n: usize, entries: impl IntoIterator<Item = (usize, usize, f64)>) -> Self {
+        let mut rows = vec![vec![]; n];
+        for (r, c, v) in entries {
+            rows[r].push((c, v));
+        }
+        let mut matrix = Self {
+            n,
+            row_start: vec![0],
+            cols: vec![],
+            values: vec![],
+        };
+        for mut row in rows {
+            row.sort_by_key(|(c, _)| *c);
+            let start = matrix.cols.len();
+            for (c, v) in row {
+                if matrix.cols.len() > start && matrix.cols.last() == Some(&c) {
+                    *matrix.values.last_mut().expect("entry") += v;
+                } else {
+                    matrix.cols.push(c);
+                    matrix.values.push(v);
+                }
+            }
+            matrix.row_start.push(matrix.cols.len());
+        }
+        matrix
+    }
+
+    /// Stored values row by row, for new values in the same structure
+    pub fn values_mut(&mut self) -> &mut [f64] {
+        &mut self.values
+    }
+
+    /// Stored (row, column, value) entries, row by row
+    pub fn entries(&self) -> impl Iterator<Item = (usize, usize, f64)> + '_ {
+        (0..self.n).flat_map(move |r| self.row(r).into_iter().map(move |(c, v)| (r, c, v)))
+    }
+
+    /// Stored (column, value) entries of row `r`
+    fn row(&self, r: usize) -> Vec<(usize, f64)> {
+        let range = self.row_start[r]..self.row_start[r + 1];
+        self.cols[range.clone()]
+            .iter()
+            .copied()
+            .zip(self.values[range].iter().copied())
+            .collect()
+    }
+
+    /// LU factors with threshold Markowitz pivoting, None if singular
+    pub fn factor(&self) -> Option<SparseLu> {
+        self.eliminate(|e, _| e.markowitz_pivot())
+    }
+
+    /// LU factors in the pivot order of an earlier factorization
+    ///
+    /// Skips the pivot search. None when this matrix has a different
+    /// structure or one of the pivots is no longer large enough, then a
+    /// full `factor` is needed.
+    pub fn refactor(&self, order: &PivotOrder) -> Option<SparseLu> {
+        if order.row_start != self.row_start || order.cols != self.cols {
+            return None;
+        }
+        self.eliminate(|e, k| {
+            let (r, c) = order.pivots[k];
+            Some((r, c, e.stable(r, c)?))
+        })
+    }
+
+    /// Gaussian elimination taking the k-th pivot from `choose`
+    fn eliminate(
+        &self,
+        mut choose: impl FnMut(&Elimination, usize) -> Option<(usize, usize, f64)>,
+    ) -> Option<SparseLu> {
+        let n = self.n;
+        let rows: Vec<Vec<(usize, f64)>> = (0..n).map(|r| self.row(r)).collect();
+        let mut col_rows = vec![vec![]; n];
+        for (r, row) in rows.iter().enumerate() {
+            for (c, _) in row {
+                col_rows[*c].push(r);
+            }
+        }
+        let mut e = Elimination {
+            col_count: col_rows.iter().map(Vec::len).collect(),
+            rows,
+            col_rows,
+            active: vec![true; n],
+            active_col: vec![true; n],
+        };
+        let mut lu = SparseLu {
+            order: PivotOrder {
+                row_start: self.row_start.clone(),
+                cols: self.cols.clone(),
+                pivots: vec![],
+            },
+            upper: vec![],
+            lower: vec![],
+            fill: 0,
+        };
+        for k in 0..n {
+            let (pr, pc, pv) = choose(&e, k)?;
+            let pivot_row: Vec<(usize, f64)> = e.rows[pr]
+                .iter()
+                .copied()
+                .filter(|(c, _)| *c != pc)
+                .collect();
+            let mut factors = vec![];
+            for r in e.col_rows[pc].clone() {
+                if !e.active[r] || r == pr {
+                    continue;
+                }
+                let at = e.rows[r].binary_search_by_key(&pc, |(c, _)| *c).ok()?;
+                let f = e.rows[r].remove(at).1 / pv;
+                let (merged, fill) = subtract_row(&e.rows[r], f, &pivot_row);
+                for c in fill {
+                    e.col_rows[c].push(r);
+                    e.col_count[c] += 1;
+                    lu.fill += 1;
+                }
+                e.rows[r] = merged;
+                factors.push((r, f));
+            }
+            e.active[pr] = false;
+            e.active_col[pc] = false;
+            for (c, _) in &e.rows[pr] {
+                e.col_count[*c] -= 1;
+            }
+            lu.order.pivots.push((pr, pc));
+            lu.upper.push((pv, pivot_row));
+            lu.lower.push(factors);
+        }
+        Some(lu)
+    }
+}
+
+/// Matrix part way through elimination, rows and columns not pivoted yet are active
+struct Elimination {
+    // sorted (column, value) entries of each row
+    rows: Vec<Vec<(usize, f64)>>,
+    // rows with an entry in each column, including rows pivoted already
+    col_rows: Vec<Vec<usize>>,
+    // active rows with an entry in each column
+    col_count: Vec<usize>,
+    active: Vec<bool>,
+    active_col: Vec<bool>,
+}
+
+impl Elimination {
+    fn entry(&self, r: usize, c: usize) -> Option<f64> {
+        let at = self.rows[r]
+            .binary_search_by_key(&c, |(col, _)| *col)
+            .ok()?;
+        Some(self.rows[r][at].1)
+    }
+
+    /// Largest magnitude in column `c` of the active rows
+    fn col_max(&self, c: usize) -> f64 {
+        self.col_rows[c]
+            .iter()
+            .filter(|r| self.active[**r])
+            .filter_map(|r| self.entry(*r, c))
+            .fold(0.0, |m: f64, v| m.max(v.abs()))
+    }
+
+    /// Value at (r, c) if it is big enough to pivot on
+    fn stable(&self, r: usize, c: usize) -> Option<f64> {
+        let v = self.entry(r, c)?;
+        (v != 0.0 && v.abs() >= PIVOT_THRESHOLD * self.col_max(c)).then_some(v)
+    }
+
+    /// Cheapest stable pivot as (row, column, value)
+    //
+    // A few of the shortest rows and columns are searched first, everything
+    // else only when none of those has an entry large enough. The cost of a
+    // pivot is (row entries - 1) * (column entries - 1), ties go to the
+    // entry that is largest relative to its column.
+    fn markowitz_pivot(&self) -> Option<(usize, usize, f64)> {
+        let (rows, col_rows, active) = (&self.rows, &self.col_rows, &self.active);
+        let active_rows = || (0..rows.len()).filter(|r| active[*r]);
+        let active_cols = || (0..col_rows.len()).filter(|c| self.active_col[*c]);
+        let min_row = active_rows().map(|r| rows[r].len()).min()?;
+        let min_col = active_cols().map(|c| self.col_count[c]).min()?;
+        let mut short: Vec<(usize, usize)> = active_rows()
+            .filter(|r| rows[*r].len() == min_row)
+            .take(PIVOT_SEARCH)
+            .flat_map(|r| rows[r].iter().map(move |(c, _)| (r, *c)))
+            .collect();
+        for c in active_cols()
+            .filter(|c| self.col_count[*c] == min_col)
+            .take(PIVOT_SEARCH)
+        {
+            short.extend(col_rows[c].iter().filter(|r| active[**r]).map(|r| (*r, c)));
+        }
+        let all = active_rows().flat_map(|r| rows[r].iter().map(move |(c, _)| (r, *c)));
+        let pick = |candidates: &mut dyn Iterator<Item = (usize, usize)>| {
+            let mut best: Option<(usize, usize, f64, usize, f64)> = None;
+            for (r, c) in candidates {
+                let col_max = self.col_max(c);
+                let stable = |v: &f64| *v != 0.0 && v.abs() >= PIVOT_THRESHOLD * col_max;
+                let Some(v) = self.entry(r, c).filter(stable) else {
+                    continue;
+                };
+                let cost = (rows[r].len() - 1) * (self.col_count[c] - 1);
+                let ratio = v.abs() / col_max;
+                if best.is_none_or(|(_, _, _, bcost, bratio)| {
+                    cost < bcost || (cost == bcost && ratio > bratio)
+                }) {
+                    best = Some((r, c, v, cost, ratio));
+                }
+            }
+            best.map(|(r, c, v, _, _)| (r, c, v))
+        };
+        pick(&mut short.into_iter()).or_else(|| pick(&mut all.into_iter()))
+    }
+}
+
+/// `row - f * pivot_row` for sorted rows, with the columns that are new in `row`
+fn subtract_row(
+    row: &[(usize, f64)],
+    f: f64,
+    pivot_row: &[(usize, f64)],
+) -> (Vec<(usize, f64)>, Vec<usize>) {
+    let mut merged = Vec::with_capacity(row.len() + pivot_row.len());
+    let mut fill = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < row.len() || j < pivot_row.len() {
+        match (row.get(i), pivot_row.get(j)) {
+            (Some(a), Some(b)) if a.0 == b.0 => {
+                merged.push((a.0, a.1 - f * b.1));
+                i += 1;
+                j += 1;
+            }
+            (Some(a), Some(b)) if a.0 < b.0 => {
+                merged.push(*a);
+                i += 1;
+            }
+            (Some(a), None) => {
+                merged.push(*a);
+                i += 1;
+            }
+            (_, Some(b)) => {
+                merged.push((b.0, -f * b.1));
+                fill.push(b.0);
+                j += 1;
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+    (merged, fill)
+}
+
+/// Pivots picked for a matrix structure, see `SparseMatrix::refactor`
+#[derive(Clone, Debug, PartialEq)]
+pub struct PivotOrder {
+    // structure of the factored matrix, like in `SparseMatrix`
+    row_start: Vec<usize>,
+    cols: Vec<usize>,
+    // (row, column) of each pivot
+    pivots: Vec<(usize, usize)>,
+}
+
+/// LU factors of a `SparseMatrix` in pivot order
+#[derive(Debug)]
+pub struct SparseLu {
+    order: PivotOrder,
+    // pivot value and the other (column, value) entries of its row
+    upper: Vec<(f64, Vec<(usize, f64)>)>,
+    // (row, factor) of the rows eliminated by each pivot
+    lower: Vec<Vec<(usize, f64)>>,
+    // entries created by elimination
+    fill: usize,
+}
+
+impl SparseLu {
+    /// (row, column) of each pivot in elimination order
+    pub fn pivots(&self) -> &[(usize, usize)] {
+        &self.order.pivots
+    }
+
+    /// Pivot order for factoring matrices of the same structure
+    pub fn order(&self) -> &PivotOrder {
+        &self.order
+    }
+
+    /// Number of entries elimination added to the matrix
+    pub fn fill(&self) -> usize {
+        self.fill
+    }
+
+    /// Solution of A x = b
+    pub fn solve(&self, b: &[f64]) -> Vec<f64> {
+        let mut y = b.to_vec();
+        for ((pr, _), factors) in self.pivots().iter().zip(&self.lower) {
+            for (r, f) in factors {
+                y[*r] -= f * y[*pr];
+            }
+        }
+        let mut x = vec![0.0; y.len()];
+        for ((pr, pc), (pv, row)) in self.pivots().iter().zip(&self.upper).rev() {
+            let sum: f64 = row.iter().map(|(c, v)| v * x[*c]).sum();
+            x[*pc] = (y[*pr] - sum) / pv;
+        }
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_cmp::approx_eq;
+
+    #[test]
+    fn test_sparse_lu() -> Result<(), String> {
+        // arrowhead matrix, eliminating the full first row and column
+        // first would fill in everything
+        let n = 6;
+        let mut entries = vec![];
+        for i in 0..n {
+            entries.push((i, i, 4.0));
+            if i > 0 {
+                entries.push((0, i, 1.0));
+                entries.push((i, 0, 1.0));
+            }
+        }
+        let a = SparseMatrix::from_entries(n, entries.clone());
+        let lu = a.factor().ok_or("singular")?;
+        assert_eq!(lu.fill(), 0);
+        let x_true: Vec<f64> = (0..n).map(|i| i as f64 - 2.0).collect();
+        let mut b = vec![0.0; n];
+        for (r, c, v) in &entries {
+            b[*r] += v * x_true[*c];
+        }
+        for (x, t) in lu.solve(&b).iter().zip(&x_true) {
+            assert!(approx_eq!(f64, *x, *t, epsilon = 1e-12));
+        }
+        // zero diagonal needs a pivot off the diagonal, like a voltage source
+        let a = SparseMatrix::from_entries(2, [(0, 1, 1.0), (1, 0, 1.0), (1, 1, 1e-3)]);
+        let x = a.factor().ok_or("singular")?.solve(&[2.0, 3.0]);
+        assert!(approx_eq!(f64, x[1], 2.0, epsilon = 1e-12));
+        assert!(approx_eq!(f64, x[0], 3.0 - 2e-3, epsilon = 1e-12));
+        // duplicates add up, so these cancel out
+        let a = SparseMatrix::from_entries(2, [(0, 0, 1.0), (1, 1, 1.0), (1, 1, -1.0)]);
+        assert!(a.factor().is_none());
+        Ok(())
+    }
+}
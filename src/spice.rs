@@ -0,0 +1,114 @@
+//! Writer for SPICE netlists from JSON circuit models
+//!
+//! Takes the model the schematic editor exports (see `Model` in sim.rs),
+//! so the editor and the simulator write the same netlists.
+
+use crate::units::format_unit_value;
+use serde_json::Value;
+
+/// Value with an engineering suffix, SPICE reads "M" as milli so mega is "Meg"
+fn spice_value(v: f64) -> String {
+    let s = format_unit_value(v, "");
+    match s.strip_suffix('M') {
+        Some(m) => format!("{}Meg", m),
+        None => s,
+    }
+}
+
+/// Model parameters present in `params`, as SPICE `KEY=VALUE` pairs
+fn model_params(params: &Value, keys: &[(&str, &str)]) -> String {
+    keys.iter()
+        .filter_map(|(key, spice)| Some(format!("{}={}", spice, params.get(key)?.as_f64()?)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// SPICE netlist for a JSON circuit model, eg. for ngspice or LTspice
+///
+/// Nodes are the model's net numbers, ground is 0. Components without a
+/// SPICE primitive are left in as comments.
+pub fn model_to_spice(model: &Value) -> String {
+    let mut lines = vec!["* exported circuit".to_string()];
+    let mut models = vec![];
+    let mut counts = std::collections::HashMap::new();
+    let mut name = |prefix: char| {
+        let n = counts.entry(prefix).or_insert(0);
+        *n += 1;
+        format!("{}{}", prefix, n)
+    };
+    let empty = vec![];
+    for c in model["components"].as_array().unwrap_or(&empty) {
+        let kind = c["type"].as_str().unwrap_or_default();
+        let nets: Vec<u64> = c["nets"]
+            .as_array()
+            .unwrap_or(&empty)
+            .iter()
+            .filter_map(Value::as_u64)
+            .collect();
+        let value = |key: &str| spice_value(c[key].as_f64().unwrap_or_default());
+        let line = match (kind, nets.as_slice()) {
+            ("Resistor", [a, b]) => format!("{} {} {} {}", name('R'), a, b, value("r")),
+            // log taper has no fixed split to write down
+            ("Potentiometer", [a, w, b]) if c["taper"] != "Log" => {
+                let r = c["total_r"].as_f64().unwrap_or_default();
+                let wiper = c["wiper"].as_f64().unwrap_or(0.5);
+                let top = format!("{} {} {} {}", name('R'), a, w, spice_value(r * wiper));
+                let bottom = spice_value(r * (1.0 - wiper));
+                format!("{}\n{} {} {} {}", top, name('R'), w, b, bottom)
+            }
+            ("Capacitor", [a, b]) => format!("{} {} {} {}", name('C'), a, b, value("c")),
+            ("Inductor", [a, b]) => format!("{} {} {} {}", name('L'), a, b, value("l")),
+            ("VoltageSource", [a, b]) => {
+                let mut line = format!("{} {} {} DC {}", name('V'), a, b, value("v"));
+                if c["ac"].as_f64().is_some_and(|ac| ac != 0.0) {
+                    line += &format!(" AC {}", value("ac"));
+                }
+                line
+            }
+            // current is pushed into the first net
+            ("CurrentSource", [a, b]) => format!("{} {} {} DC {}", name('I'), b, a, value("i")),
+            // zero volt source measures the current through it
+            ("Ammeter", [a, b]) => format!("{} {} {} DC 0", name('V'), a, b),
+            ("Diode", [a, k]) => {
+                let element = name('D');
+                let mut line = format!("{} {} {}", element, a, k);
+                if c["params"].is_object() {
+                    let keys = [("is", "IS"), ("n", "N"), ("rs", "RS")];
+                    let params = model_params(&c["params"], &keys);
+                    models.push(format!(".model {}_model D({})", element, params));
+                    line += &format!(" {}_model", element);
+                }
+                line
+            }
+            ("BJT", [b, col, e]) => {
+                let element = name('Q');
+                let mut line = format!("{} {} {} {}", element, col, b, e);
+                if c["params"].is_object() {
+                    let kind = c["params"]["transistor_type"].as_str().unwrap_or("NPN");
+                    let keys = [
+                        ("bf", "BF"),
+                        ("br", "BR"),
+                        ("is", "IS"),
+                        ("n", "NF"),
+                        ("rb", "RB"),
+                        ("re", "RE"),
+                        ("rc", "RC"),
+                        ("cje", "CJE"),
+                        ("cjc", "CJC"),
+                        ("tf", "TF"),
+                        ("tr", "TR"),
+                    ];
+                    let params = model_params(&c["params"], &keys);
+                    models.push(format!(".model {}_model {}({})", element, kind, params));
+                    line += &format!(" {}_model", element);
+                }
+                line
+            }
+            _ => format!("* {} on nets {:?} has no SPICE element", kind, nets),
+        };
+        lines.push(line);
+    }
+    lines.extend(models);
+    lines.push(".end".to_string());
+    lines.join("\n") + "\n"
+}
@@ -0,0 +1,176 @@
+//! Engineering notation for component values, eg. "4.7k" or "100n"
+
+const UNIT_VALUE_OFFSET: i32 = 4;
+const UNIT_VALUE_MAX: i32 = 8;
+const UNIT_VALUE_SUFFIXES: [&str; UNIT_VALUE_MAX as usize] =
+    ["p", "n", "u", "m", "", "k", "M", "G"];
+
+pub fn format_unit_value(v: f64, unit: &str) -> String {
+    let mut suff: i32 = UNIT_VALUE_OFFSET + (v.log10() as i32) / 3;
+    if v < 1.0 {
+        suff -= 1;
+    }
+    // the largest suffix is the last one
+    suff = suff.clamp(0, UNIT_VALUE_MAX - 1);
+    let vr = v / f64::powf(10.0, 3.0 * ((suff - UNIT_VALUE_OFFSET) as f64));
+    // Use as many decimals as needed, or none if not needed
+    format!("{:.}{}{}", vr, UNIT_VALUE_SUFFIXES[suff as usize], unit)
+}
+
+/// Length of the run of ASCII digits that `s` starts with
+fn digits(s: &str) -> usize {
+    s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len())
+}
+
+/// Power of ten for a suffix character, "R" marks a decimal point in
+/// the European style
+fn suffix_exponent(c: char) -> Option<i32> {
+    let c = match c {
+        'R' => return Some(0),
+        'µ' | 'μ' => 'u',
+        c => c,
+    };
+    let n = UNIT_VALUE_SUFFIXES
+        .iter()
+        .position(|x| x.starts_with(c) && !x.is_empty())?;
+    Some(3 * (n as i32 - UNIT_VALUE_OFFSET))
+}
+
+/// Suffix typed in the wrong case, eg. "K" for "k", which would
+/// otherwise pass as a unit and silently lose the scale
+fn wrong_case_suffix(c: char) -> bool {
+    let other = match c.is_ascii_uppercase() {
+        true => c.to_ascii_lowercase(),
+        false => c.to_ascii_uppercase(),
+    };
+    suffix_exponent(c).is_none() && suffix_exponent(other).is_some()
+}
+
+/// Read a value like the ones `format_unit_value` writes
+///
+/// Takes a number with an optional suffix and an optional unit, eg.
+/// "4.7k", "100 nF" or "2.2MΩ", plus the European notation that puts the
+/// suffix where the decimal point goes, eg. "4k7" or "1n5". Suffixes are
+/// case sensitive, a unit starting like a suffix in the wrong case ("10K")
+/// is taken as a typo. Returns None for anything else.
+pub fn parse_unit_value(s: &str) -> Option<f64> {
+    let s = s.trim();
+    // sign, digits and fraction
+    let mut end = usize::from(s.starts_with(['+', '-']));
+    let int_digits = digits(&s[end..]);
+    end += int_digits;
+    let mut frac_digits = 0;
+    if s[end..].starts_with('.') {
+        frac_digits = digits(&s[end + 1..]);
+        end += 1 + frac_digits;
+    }
+    if int_digits + frac_digits == 0 {
+        return None;
+    }
+    let mut mantissa = s[..end].to_string();
+    let mut exponent = 0;
+    // exponent, only taken when digits follow so "1E" stays a unit
+    let rest = &s[end..];
+    if let Some(e) = rest.strip_prefix(['e', 'E']) {
+        let sign = usize::from(e.starts_with(['+', '-']));
+        let n = digits(&e[sign..]);
+        if n > 0 {
+            exponent = e[..sign + n].parse().ok()?;
+            end += 1 + sign + n;
+        }
+    }
+    let mut rest = s[end..].trim_start();
+    let spaced = rest.len() < s.len() - end;
+    if let Some(c) = rest.chars().next()
+        && let Some(e) = suffix_exponent(c)
+    {
+        exponent += e;
+        rest = &rest[c.len_utf8()..];
+        // European notation, the suffix right after whole digits
+        let n = digits(rest);
+        if n > 0 {
+            if spaced || s[..end].contains(['.', 'e', 'E']) {
+                return None;
+            }
+            mantissa = format!("{}.{}", mantissa, &rest[..n]);
+            rest = &rest[n..];
+        }
+    }
+    // anything left is the unit, which can't start with another suffix
+    let unit = rest.trim();
+    let first = unit.chars().next();
+    if !unit.chars().all(char::is_alphabetic)
+        || first.and_then(suffix_exponent).is_some()
+        || first.is_some_and(wrong_case_suffix)
+    {
+        return None;
+    }
+    format!("{}e{}", mantissa, exponent).parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_unit_value() -> Result<(), String> {
+        assert_eq!(format_unit_value(1.5, " Ohms"), "1.5 Ohms");
+        assert_eq!(format_unit_value(15.0, " Ohms"), "15 Ohms");
+        assert_eq!(format_unit_value(1500.0, " Ohms"), "1.5k Ohms");
+        assert_eq!(format_unit_value(150000.0, " Ohms"), "150k Ohms");
+        assert_eq!(format_unit_value(1500000.0, " Ohms"), "1.5M Ohms");
+        assert_eq!(format_unit_value(0.015, " Ohms"), "15m Ohms");
+        assert_eq!(format_unit_value(0.0015, " Ohms"), "1.5m Ohms");
+        assert_eq!(format_unit_value(0.00015, " Ohms"), "150u Ohms");
+        // past the largest suffix the number grows instead
+        assert_eq!(format_unit_value(2.2e12, " Ohms"), "2200G Ohms");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_unit_value() -> Result<(), String> {
+        assert_eq!(parse_unit_value("4.7k"), Some(4700.0));
+        assert_eq!(parse_unit_value("100n"), Some(100e-9));
+        assert_eq!(parse_unit_value("2.2M"), Some(2.2e6));
+        assert_eq!(parse_unit_value(" 15 "), Some(15.0));
+        assert_eq!(parse_unit_value("1e3"), Some(1000.0));
+        // what gets formatted reads back
+        for v in [4.7e3, 100e-9, 2.2e6, 1.5, 0.015] {
+            let back = parse_unit_value(&format_unit_value(v, "")).ok_or("no value")?;
+            assert!(f64::abs(back - v) <= 1e-12 * v, "{}", v);
+        }
+        // every suffix, with and without units
+        let suffixes = [("p", 1e-12), ("n", 1e-9), ("u", 1e-6), ("m", 1e-3)];
+        let suffixes = suffixes
+            .into_iter()
+            .chain([("", 1.0), ("k", 1e3), ("M", 1e6), ("G", 1e9)]);
+        for (suffix, scale) in suffixes {
+            for unit in ["", "F", " Ohms", "Ω"] {
+                let text = format!("3.3{}{}", suffix, unit);
+                let v = parse_unit_value(&text).ok_or(text.clone())?;
+                assert!(f64::abs(v - 3.3 * scale) <= 1e-12 * scale, "{}", text);
+            }
+        }
+        assert_eq!(parse_unit_value("10 µF"), Some(10e-6));
+        assert_eq!(parse_unit_value("-5 mA"), Some(-5e-3));
+        assert_eq!(parse_unit_value("1.5e3 Hz"), Some(1500.0));
+        // European notation
+        assert_eq!(parse_unit_value("4k7"), Some(4700.0));
+        assert_eq!(parse_unit_value("1n5"), Some(1.5e-9));
+        assert_eq!(parse_unit_value("4R7"), Some(4.7));
+        assert_eq!(parse_unit_value("2M2Ω"), Some(2.2e6));
+        // malformed
+        for text in [
+            "", "k", "4.7%", "x4.7", "4.7.1k", "4k7k", "4.7k7", "4 k7", "1kk", "12 34",
+        ] {
+            assert_eq!(parse_unit_value(text), None, "{}", text);
+        }
+        // wrong case suffixes don't pass as units
+        for text in ["10K", "10 K", "10KΩ", "1N", "2.2g", "3U"] {
+            assert_eq!(parse_unit_value(text), None, "{}", text);
+        }
+        assert_eq!(parse_unit_value("50 Hz"), Some(50.0));
+        assert_eq!(parse_unit_value("5 V"), Some(5.0));
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+//! WAV files for listening to simulated signals
+
+use std::io::{self, Write};
+
+/// Write mono 16 bit PCM, scaled so the largest sample is full scale
+///
+/// Silence (all zero samples) is written as is.
+pub fn write_wav(samples: &[f64], sample_rate: u32, path: &str) -> io::Result<()> {
+    let peak = samples.iter().fold(0.0, |peak: f64, x| peak.max(x.abs()));
+    let scale = if peak > 0.0 {
+        i16::MAX as f64 / peak
+    } else {
+        0.0
+    };
+    let data_size = 2 * samples.len() as u32;
+    let mut out = Vec::with_capacity(44 + data_size as usize);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data_size).to_le_bytes());
+    out.extend_from_slice(b"WAVE");
+    // format chunk: PCM, 1 channel, 2 bytes per frame
+    out.extend_from_slice(b"fmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&sample_rate.to_le_bytes());
+    out.extend_from_slice(&(2 * sample_rate).to_le_bytes());
+    out.extend_from_slice(&2u16.to_le_bytes());
+    out.extend_from_slice(&16u16.to_le_bytes());
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data_size.to_le_bytes());
+    for x in samples {
+        out.extend_from_slice(&((x * scale).round() as i16).to_le_bytes());
+    }
+    std::fs::File::create(path)?.write_all(&out)
+}
+
+/// Little endian integer of `N` bytes at `at`, if the data is long enough
+fn le_bytes<const N: usize>(data: &[u8], at: usize) -> Option<[u8; N]> {
+    data.get(at..at + N)?.try_into().ok()
+}
+
+/// Read 16 bit PCM, returns the sample rate and the first channel scaled to full scale 1
+pub fn read_wav(path: &str) -> io::Result<(u32, Vec<f64>)> {
+    let data = std::fs::read(path)?;
+    let bad = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, msg));
+    if data.get(0..4) != Some(b"RIFF") || data.get(8..12) != Some(b"WAVE") {
+        return Err(bad("not a WAV file"));
+    }
+    // chunks can come in any order, eg. with metadata in between
+    let (mut format, mut samples) = (None, None);
+    let mut at = 12;
+    while let (Some(id), Some(size)) = (data.get(at..at + 4), le_bytes::<4>(&data, at + 4)) {
+        let size = u32::from_le_bytes(size) as usize;
+        let body = data
+            .get(at + 8..at + 8 + size)
+            .ok_or_else(|| bad("truncated chunk"))?;
+        match id {
+            b"fmt " => format = Some(body),
+            b"data" => samples = Some(body),
+            _ => {}
+        }
+        // chunks are padded to an even size
+        at += 8 + size + size % 2;
+    }
+    let (Some(format), Some(samples)) = (format, samples) else {
+        return Err(bad("missing fmt or data chunk"));
+    };
+    let field = |i| le_bytes::<2>(format, i).map(u16::from_le_bytes);
+    let (Some(1), Some(channels), Some(16)) = (field(0), field(2), field(14)) else {
+        return Err(bad("only 16 bit PCM is supported"));
+    };
+    let rate = le_bytes::<4>(format, 4)
+        .map(u32::from_le_bytes)
+        .ok_or_else(|| bad("short fmt chunk"))?;
+    let frame = 2 * channels.max(1) as usize;
+    let samples = samples
+        .chunks_exact(frame)
+        .map(|f| i16::from_le_bytes([f[0], f[1]]) as f64 / i16::MAX as f64)
+        .collect();
+    Ok((rate, samples))
+}